@@ -0,0 +1,89 @@
+use std::ops::Deref;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use penumbra_app::{AppActionHandler, VerificationPool};
+use penumbra_asset::{Value, STAKING_TOKEN_ASSET_ID};
+use penumbra_fee::Fee;
+use penumbra_keys::test_keys;
+use penumbra_shielded_pool::{Note, OutputPlan, SpendPlan};
+use penumbra_tct as tct;
+use penumbra_transaction::{
+    plan::TransactionPlan, Transaction, TransactionParameters, WitnessData,
+};
+use rand_core::OsRng;
+
+/// The number of transactions in the benchmarked block.
+const BLOCK_TRANSACTIONS: usize = 16;
+
+/// Builds a transaction with two spends and two outputs, so four proofs.
+fn transaction() -> Transaction {
+    let value = Value {
+        amount: 100u64.into(),
+        asset_id: *STAKING_TOKEN_ASSET_ID,
+    };
+    let notes: Vec<_> = (0..2)
+        .map(|_| Note::generate(&mut OsRng, &test_keys::ADDRESS_0, value))
+        .collect();
+
+    let mut sct = tct::Tree::new();
+    for note in &notes {
+        sct.insert(tct::Witness::Keep, note.commit()).unwrap();
+    }
+
+    let mut actions = Vec::new();
+    for note in &notes {
+        let position = sct.witness(note.commit()).unwrap().position();
+        actions.push(SpendPlan::new(&mut OsRng, note.clone(), position).into());
+        actions
+            .push(OutputPlan::new(&mut OsRng, value, test_keys::ADDRESS_1.deref().clone()).into());
+    }
+    let plan = TransactionPlan {
+        transaction_parameters: TransactionParameters {
+            expiry_height: 0,
+            fee: Fee::default(),
+            chain_id: "".into(),
+        },
+        actions,
+        detection_data: None,
+        memo: None,
+    };
+
+    let auth_data = plan
+        .authorize(OsRng, &test_keys::SPEND_KEY)
+        .expect("can authorize transaction");
+    let witness_data = WitnessData {
+        anchor: sct.root(),
+        state_commitment_proofs: notes
+            .iter()
+            .map(|note| (note.commit(), sct.witness(note.commit()).unwrap()))
+            .collect(),
+    };
+    plan.build(&test_keys::FULL_VIEWING_KEY, &witness_data, &auth_data)
+        .expect("can build transaction")
+}
+
+fn block_verification(c: &mut Criterion) {
+    let block: Vec<_> = (0..BLOCK_TRANSACTIONS).map(|_| transaction()).collect();
+    let runtime = tokio::runtime::Runtime::new().expect("can build runtime");
+
+    let mut group = c.benchmark_group("block verification");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(BLOCK_TRANSACTIONS as u64));
+    for threads in [1, 2, 4, 8] {
+        let pool = VerificationPool::new(threads).expect("can build pool");
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &pool, |b, pool| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    let checks = block.iter().map(|tx| tx.check_stateless(pool.clone()));
+                    futures::future::try_join_all(checks)
+                        .await
+                        .expect("block verifies");
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, block_verification);
+criterion_main!(benches);
@@ -89,6 +89,13 @@ pub enum RootCommand {
         /// Enable expensive RPCs, currently a no-op.
         #[clap(short, long, display_order = 500)]
         enable_expensive_rpc: bool,
+        /// The number of threads used to verify the proofs of transactions.
+        ///
+        /// Proofs are verified on this fixed set of threads, however many
+        /// transactions are being checked at once. Defaults to the number of
+        /// available cores.
+        #[clap(long, env = "PENUMBRA_PD_VERIFICATION_THREADS", display_order = 501)]
+        verification_threads: Option<usize>,
     },
 
     /// Generate, join, or reset a network.
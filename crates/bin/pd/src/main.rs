@@ -20,7 +20,7 @@ use pd::{
     },
 };
 use penumbra_app::app_version::check_and_update_app_version;
-use penumbra_app::{APP_VERSION, SUBSTORE_PREFIXES};
+use penumbra_app::{VerificationPool, APP_VERSION, SUBSTORE_PREFIXES};
 use rand::Rng;
 use rand_core::OsRng;
 use tendermint_config::net::Address as TendermintAddress;
@@ -62,6 +62,7 @@ async fn main() -> anyhow::Result<()> {
             metrics_bind,
             cometbft_addr,
             enable_expensive_rpc,
+            verification_threads,
         } => {
             // Use the given `grpc_bind` address if one was specified. If not, we will choose a
             // default depending on whether or not `grpc_auto_https` was set. See the
@@ -90,6 +91,11 @@ async fn main() -> anyhow::Result<()> {
                 )
             }
 
+            if let Some(threads) = verification_threads {
+                VerificationPool::init_shared(threads)
+                    .context("failed to configure the proof verification pool")?;
+            }
+
             // Unpack home directory. Accept an explicit path, but default
             // to a sane value if unspecified.
             let pd_home = match home {
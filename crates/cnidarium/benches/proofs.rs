@@ -0,0 +1,102 @@
+use cnidarium::{StateDelta, StateWrite, Storage, StorageOptions};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+const KEYS: u64 = 10_000;
+
+/// Loads a storage with `KEYS` keys in the `ibc` substore, committed over
+/// several versions.
+fn storage(
+    rt: &tokio::runtime::Runtime,
+    node_cache_capacity: usize,
+) -> (tempfile::TempDir, Storage) {
+    rt.block_on(async {
+        let tmpdir = tempfile::tempdir().expect("can create a temporary directory");
+        let options = StorageOptions {
+            node_cache_capacity,
+            ..Default::default()
+        };
+        let storage =
+            Storage::load_with_options(tmpdir.path().to_owned(), vec!["ibc".to_string()], options)
+                .await
+                .expect("can load storage");
+
+        for chunk in 0..10 {
+            let mut delta = StateDelta::new(storage.latest_snapshot());
+            for i in (chunk * KEYS / 10)..((chunk + 1) * KEYS / 10) {
+                delta.put_raw(format!("ibc/key_{i}"), i.to_be_bytes().to_vec());
+            }
+            storage.commit(delta).await.expect("can commit");
+        }
+        (tmpdir, storage)
+    })
+}
+
+/// Generates proofs for a fixed set of keys against the latest version, as a
+/// proof-serving RPC would for a hot version.
+fn hot_version_proofs(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().expect("can start a runtime");
+    let mut group = c.benchmark_group("hot_version_proofs");
+
+    for (name, capacity) in [("without_node_cache", 0), ("with_node_cache", 100_000)] {
+        let (_tmpdir, storage) = storage(&rt, capacity);
+        let snapshot = storage.latest_snapshot();
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || (0..100).map(|i| format!("ibc/key_{}", i * 97 % KEYS)),
+                |keys| {
+                    rt.block_on(async {
+                        for key in keys {
+                            snapshot
+                                .get_with_proof(key.into_bytes())
+                                .await
+                                .expect("can generate proof");
+                        }
+                    })
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        drop(snapshot);
+        rt.block_on(storage.release());
+    }
+
+    group.finish();
+}
+
+/// Generates proofs for keys that share most of their paths, one at a time
+/// and as a single batch.
+fn batched_proofs(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().expect("can start a runtime");
+    let mut group = c.benchmark_group("batched_proofs");
+    let (_tmpdir, storage) = storage(&rt, 0);
+    let snapshot = storage.latest_snapshot();
+    let keys: Vec<Vec<u8>> = (0..100)
+        .map(|i| format!("ibc/key_{i}").into_bytes())
+        .collect();
+
+    group.bench_function("get_with_proof", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for key in &keys {
+                    snapshot
+                        .get_with_proof(key.clone())
+                        .await
+                        .expect("can generate proof");
+                }
+            })
+        })
+    });
+    group.bench_function("multi_get_with_proof", |b| {
+        b.iter(|| {
+            rt.block_on(snapshot.multi_get_with_proof(&keys))
+                .expect("can generate proofs")
+        })
+    });
+
+    drop(snapshot);
+    rt.block_on(storage.release());
+    group.finish();
+}
+
+criterion_group!(benches, hot_version_proofs, batched_proofs);
+criterion_main!(benches);
@@ -1,12 +1,23 @@
-use std::{any::Any, collections::BTreeMap, sync::Arc};
+use std::{
+    any::Any,
+    borrow::Cow,
+    collections::{btree_map, BTreeMap},
+    ops::Bound,
+    sync::Arc,
+};
 
+use anyhow::Result;
 use tendermint::abci;
 
 use crate::{
     store::{multistore::MultistoreConfig, substore::SubstoreConfig},
-    StateWrite,
+    OverlayOp, ScanOrder, StateWrite,
 };
 
+mod spill;
+pub use spill::SpillOptions;
+use spill::{Spill, SpillWriter};
+
 /// A cache of changes to the state of the blockchain.
 ///
 /// A [`StateDelta`](crate::StateDelta) is `Cache` above a `StateRead`.
@@ -20,12 +31,52 @@ pub struct Cache {
     pub(crate) ephemeral_objects: BTreeMap<&'static str, Option<Box<dyn Any + Send + Sync>>>,
     /// A list of ABCI events that occurred while building this set of state changes.
     pub(crate) events: Vec<abci::Event>,
+    /// Unwritten changes to the consensus-critical state that were moved to
+    /// disk, from oldest to newest. Changes in `unwritten_changes` take
+    /// precedence over these, and newer stores over older ones.
+    ///
+    /// The stores are shared with copies of the changes, and are only
+    /// written to while this cache holds the sole reference to them.
+    pub(crate) spills: Vec<Arc<Spill>>,
+    /// Prefixes whose verifiable keys were all deleted, see
+    /// [`StateWrite::prefix_delete`], each mapped to the number of `spills`,
+    /// oldest first, that it hides.
+    ///
+    /// A prefix deletion hides the keys under its prefix in those stores, in
+    /// the caches below this one, and in the underlying state. Changes in
+    /// `unwritten_changes` are never hidden.
+    pub(crate) prefix_deletions: BTreeMap<String, usize>,
+    /// The approximate size, in bytes, of `unwritten_changes`.
+    pub(crate) unwritten_bytes: usize,
 }
 
 impl Cache {
-    /// Inspect the cache of unwritten changes to the verifiable state.
-    pub fn unwritten_changes(&self) -> &BTreeMap<String, Option<Vec<u8>>> {
-        &self.unwritten_changes
+    /// Returns the unwritten changes to the verifiable state, in key order,
+    /// including those that were spilled to disk, which are read back into
+    /// memory.
+    ///
+    /// Prefix deletions are not included, see [`Cache::prefix_deletions`],
+    /// but spilled changes they hide are reported as deletions.
+    pub fn unwritten_changes(&self) -> Result<BTreeMap<String, Option<Vec<u8>>>> {
+        let mut changes = self.unwritten_changes.clone();
+        for entry in spilled_changes(&self.spills) {
+            let (key, mut value) = entry?;
+            if let Some(hidden) = self.hidden_spills(&key) {
+                value = get_spilled(&self.spills[hidden..], &key)?.flatten();
+            }
+            changes.entry(key).or_insert(value);
+        }
+        Ok(changes)
+    }
+
+    /// Returns the prefixes whose verifiable keys were all deleted by
+    /// [`StateWrite::prefix_delete`], in key order.
+    ///
+    /// Keys written under a prefix after it was deleted are reported by
+    /// [`Cache::unwritten_changes`] as usual. Committing the changes turns
+    /// each prefix deletion into a deletion of every key under it.
+    pub fn prefix_deletions(&self) -> impl Iterator<Item = &str> {
+        self.prefix_deletions.keys().map(String::as_str)
     }
 
     /// Inspect the cache of unwritten changes to the nonverifiable state.
@@ -34,21 +85,91 @@ impl Cache {
     }
 
     /// Merge the given cache with this one, taking its writes in place of ours.
-    pub fn merge(&mut self, other: Cache) {
+    ///
+    /// Changes that `other` spilled to disk stay there: its spill stores are
+    /// stacked on top of ours, and our in-memory changes that they overwrite
+    /// are dropped. So are our in-memory changes under the prefixes that
+    /// `other` deleted, whose deletions also hide all of our spill stores.
+    ///
+    /// # Errors
+    /// If the changes that `other` spilled to disk cannot be read, in which
+    /// case this cache is left unchanged.
+    pub fn merge(&mut self, other: Cache) -> Result<()> {
+        // Find the changes that `other` spilled over ours before changing
+        // anything, since reading them back can fail.
+        let mut overwritten = Vec::new();
+        if !other.spills.is_empty() {
+            for key in self.unwritten_changes.keys() {
+                if get_spilled(&other.spills, key)?.is_some() {
+                    overwritten.push(key.clone());
+                }
+            }
+        }
+
+        let spill_count = self.spills.len();
+        for (prefix, hidden) in other.prefix_deletions {
+            self.remove_unwritten_prefix(&prefix);
+            self.prefix_deletions
+                .retain(|deleted, _| !deleted.starts_with(prefix.as_str()));
+            self.prefix_deletions.insert(prefix, spill_count + hidden);
+        }
+        if !other.spills.is_empty() {
+            for key in overwritten {
+                let value = self.unwritten_changes.remove(&key);
+                self.unwritten_bytes = self
+                    .unwritten_bytes
+                    .saturating_sub(key.len() + value.flatten().as_ref().map_or(0, Vec::len));
+            }
+            self.spills.extend(other.spills);
+        }
         // One might ask, why does this exist separately from `apply_to`?  The
         // answer is that `apply_to` takes a `StateWrite`, so we'd have to have
         // `Cache: StateWrite`, and that implies `Cache: StateRead`, but the
         // `StateRead` trait assumes asynchronous access, and in any case, we
         // probably don't want to be reading directly from a `Cache` (?)
+        self.unwritten_bytes += other.unwritten_bytes;
         self.unwritten_changes.extend(other.unwritten_changes);
         self.nonverifiable_changes
             .extend(other.nonverifiable_changes);
         self.ephemeral_objects.extend(other.ephemeral_objects);
         self.events.extend(other.events);
+
+        // Merged caches that already spilled keep their memory bounded too.
+        if let Some(options) = self.spills.last().map(|spill| spill.options().clone()) {
+            if self.unwritten_bytes > options.threshold_bytes {
+                self.spill_unwritten(&options);
+            }
+        }
+        Ok(())
     }
 
     /// Consume this cache, applying its writes to the given state.
-    pub fn apply_to<S: StateWrite>(self, mut state: S) {
+    ///
+    /// Prefix deletions are applied first, with [`StateWrite::prefix_delete`].
+    /// Changes that were spilled to disk are then streamed to the state,
+    /// oldest first, so that newer writes take precedence, skipping those
+    /// that a prefix deletion hides.
+    ///
+    /// # Errors
+    /// If the changes that were spilled to disk cannot be read, in which case
+    /// `state` may hold part of the changes, and should be discarded.
+    pub fn apply_to<S: StateWrite>(self, mut state: S) -> Result<()> {
+        for prefix in self.prefix_deletions.keys() {
+            state.prefix_delete(prefix.clone());
+        }
+        for (i, spill) in self.spills.iter().enumerate() {
+            for entry in spill.iter_prefix("") {
+                let (key, value) = entry?;
+                if self.hidden_spills(&key).is_some_and(|hidden| i < hidden) {
+                    continue;
+                }
+                if let Some(value) = value {
+                    state.put_raw(key, value);
+                } else {
+                    state.delete(key);
+                }
+            }
+        }
         for (key, value) in self.unwritten_changes {
             if let Some(value) = value {
                 state.put_raw(key, value);
@@ -72,13 +193,16 @@ impl Cache {
         for event in self.events {
             state.record(event);
         }
+        Ok(())
     }
 
     /// Returns `true` if there are cached writes on top of the snapshot, and `false` otherwise.
     pub fn is_dirty(&self) -> bool {
         !(self.unwritten_changes.is_empty()
             && self.nonverifiable_changes.is_empty()
-            && self.ephemeral_objects.is_empty())
+            && self.ephemeral_objects.is_empty()
+            && self.spills.is_empty()
+            && self.prefix_deletions.is_empty())
     }
 
     /// Extracts and returns the ABCI events contained in this cache.
@@ -89,11 +213,45 @@ impl Cache {
     /// Consumes a `Cache` and returns a map of `SubstoreConfig` to `Cache` that
     /// corresponds to changes belonging to each substore. The keys in each `Cache`
     /// are truncated to remove the substore prefix.
+    ///
+    /// Changes that were spilled to disk are copied to a new spill store for
+    /// each substore, rather than read into memory, so that each `Cache` holds
+    /// at most one store, whose keys are disjoint from its in-memory changes.
+    ///
+    /// Prefix deletions must first be replaced with deletions of each key
+    /// under them, as commits do, since they may span substores.
     pub fn shard_by_prefix(
         self,
         prefixes: &MultistoreConfig,
-    ) -> BTreeMap<Arc<SubstoreConfig>, Self> {
+    ) -> Result<BTreeMap<Arc<SubstoreConfig>, Self>> {
         let mut changes_by_substore = BTreeMap::new();
+
+        let mut writers: BTreeMap<Arc<SubstoreConfig>, SpillWriter> = BTreeMap::new();
+        // Older stores are copied first, so that newer changes replace them.
+        for spill in &self.spills {
+            for entry in spill.iter_prefix("") {
+                let (key, value) = entry?;
+                if self.unwritten_changes.contains_key(&key) {
+                    continue;
+                }
+                let (truncated_key, substore_config) = prefixes.route_key_str(&key);
+                let writer = match writers.entry(substore_config) {
+                    btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                    btree_map::Entry::Vacant(entry) => {
+                        entry.insert(SpillWriter::create(spill.options())?)
+                    }
+                };
+                writer.put(truncated_key, &value)?;
+            }
+        }
+        for (substore_config, writer) in writers {
+            changes_by_substore
+                .entry(substore_config)
+                .or_insert_with(Cache::default)
+                .spills
+                .push(Arc::new(writer.finish()?));
+        }
+
         for (key, some_value) in self.unwritten_changes.into_iter() {
             let (truncated_key, substore_config) = prefixes.route_key_str(&key);
             changes_by_substore
@@ -111,15 +269,328 @@ impl Cache {
                 .nonverifiable_changes
                 .insert(truncated_key.to_vec(), some_value);
         }
-        changes_by_substore
+        Ok(changes_by_substore)
     }
 
+    /// Copies the verifiable and nonverifiable changes, sharing the changes
+    /// that were spilled to disk.
     pub(crate) fn clone_changes(&self) -> Self {
         Self {
             unwritten_changes: self.unwritten_changes.clone(),
             nonverifiable_changes: self.nonverifiable_changes.clone(),
             ephemeral_objects: Default::default(),
             events: Default::default(),
+            spills: self.spills.clone(),
+            prefix_deletions: self.prefix_deletions.clone(),
+            unwritten_bytes: self.unwritten_bytes,
+        }
+    }
+
+    /// Converts the verifiable and nonverifiable changes into [`OverlayOp`]s,
+    /// in key order, prefix deletions first, then verifiable changes.
+    ///
+    /// Changes that were spilled to disk are read back into memory.
+    pub(crate) fn into_overlay_ops(self) -> Result<Vec<OverlayOp>> {
+        let prefix_deletions = self
+            .prefix_deletions
+            .keys()
+            .map(|prefix| OverlayOp::PrefixDelete {
+                prefix: prefix.clone(),
+            })
+            .collect::<Vec<_>>();
+        let verifiable = self
+            .unwritten_changes()?
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => OverlayOp::Put { key, value },
+                None => OverlayOp::Delete { key },
+            });
+        let nonverifiable =
+            self.nonverifiable_changes
+                .into_iter()
+                .map(|(key, value)| match value {
+                    Some(value) => OverlayOp::NonverifiablePut { key, value },
+                    None => OverlayOp::NonverifiableDelete { key },
+                });
+        Ok(prefix_deletions
+            .into_iter()
+            .chain(verifiable)
+            .chain(nonverifiable)
+            .collect())
+    }
+
+    /// Returns the keys of the verifiable changes, including those that were
+    /// spilled to disk. A key may be repeated if it was spilled more than once.
+    pub(crate) fn unwritten_keys(&self) -> impl Iterator<Item = Result<String>> + '_ {
+        let spilled = self
+            .spills
+            .iter()
+            .flat_map(|spill| spill.iter_prefix("").map(|entry| entry.map(|(key, _)| key)));
+        self.unwritten_changes
+            .keys()
+            .cloned()
+            .map(Ok)
+            .chain(spilled)
+    }
+
+    /// Takes the verifiable changes out of the cache, for them to be streamed
+    /// with [`stream_unwritten`].
+    pub(crate) fn take_unwritten(
+        &mut self,
+    ) -> (Vec<Arc<Spill>>, BTreeMap<String, Option<Vec<u8>>>) {
+        self.unwritten_bytes = 0;
+        (
+            std::mem::take(&mut self.spills),
+            std::mem::take(&mut self.unwritten_changes),
+        )
+    }
+
+    /// Returns the change to the verifiable `key`, if any.
+    ///
+    /// A key under a deleted prefix that was not written since reads as a
+    /// deletion.
+    pub(crate) fn get_unwritten(&self, key: &str) -> Result<Option<Option<Vec<u8>>>> {
+        if let Some(entry) = self.unwritten_changes.get(key) {
+            return Ok(Some(entry.clone()));
+        }
+        match self.hidden_spills(key) {
+            Some(hidden) => Ok(Some(get_spilled(&self.spills[hidden..], key)?.flatten())),
+            None => get_spilled(&self.spills, key),
+        }
+    }
+
+    /// Returns the number of spill stores, oldest first, whose change to
+    /// `key` is hidden by a prefix deletion, or `None` if no deleted prefix
+    /// covers `key`.
+    fn hidden_spills(&self, key: &str) -> Option<usize> {
+        self.prefix_deletions
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .map(|(_, hidden)| *hidden)
+            .max()
+    }
+
+    /// Records the deletion of every verifiable key under `prefix`, dropping
+    /// the in-memory changes under it.
+    pub(crate) fn delete_prefix(&mut self, prefix: String) {
+        self.remove_unwritten_prefix(&prefix);
+        // Deletions of longer prefixes are covered by this one.
+        self.prefix_deletions
+            .retain(|deleted, _| !deleted.starts_with(prefix.as_str()));
+        self.prefix_deletions.insert(prefix, self.spills.len());
+    }
+
+    /// Replaces the deletion of `prefix` with a deletion of each of `keys`,
+    /// the keys under it in the state below this cache, and of each key
+    /// under it that was spilled to disk before it was deleted.
+    ///
+    /// Keys that were written since the prefix was deleted are left as they
+    /// are.
+    pub(crate) fn materialize_prefix_deletion(
+        &mut self,
+        prefix: &str,
+        keys: impl IntoIterator<Item = String>,
+    ) -> Result<()> {
+        let Some(hidden) = self.prefix_deletions.get(prefix).copied() else {
+            return Ok(());
+        };
+        let mut deleted: Vec<String> = keys.into_iter().collect();
+        for spill in &self.spills[..hidden] {
+            for entry in spill.iter_prefix(prefix) {
+                deleted.push(entry?.0);
+            }
+        }
+
+        let mut materialized = Vec::new();
+        for key in deleted {
+            if self.get_unwritten(&key)? == Some(None) {
+                materialized.push(key);
+            }
+        }
+        self.prefix_deletions.remove(prefix);
+        for key in materialized {
+            self.put_unwritten(key, None, None);
+        }
+        Ok(())
+    }
+
+    /// Drops the in-memory changes to the verifiable keys under `prefix`.
+    fn remove_unwritten_prefix(&mut self, prefix: &str) {
+        let removed: Vec<String> = self
+            .unwritten_changes
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in removed {
+            let value = self.unwritten_changes.remove(&key);
+            self.unwritten_bytes = self
+                .unwritten_bytes
+                .saturating_sub(key.len() + value.flatten().as_ref().map_or(0, Vec::len));
+        }
+    }
+
+    /// Returns the verifiable change nearest to the start of `range` in scan
+    /// `order`, among keys with the given `prefix`.
+    ///
+    /// Changes held in memory are borrowed, and changes read back from disk
+    /// are owned.
+    pub(crate) fn nearest_unwritten_change<'a>(
+        &'a self,
+        range: (Bound<&String>, Bound<&String>),
+        prefix: &str,
+        order: ScanOrder,
+    ) -> Result<Option<(Cow<'a, str>, Cow<'a, Option<Vec<u8>>>)>> {
+        let mut nearest = match order {
+            ScanOrder::Ascending => self
+                .unwritten_changes
+                .range::<String, _>(range)
+                .take_while(|(k, _v)| k.starts_with(prefix))
+                .next(),
+            // Keys greater than the prefix that do not match it sort after
+            // all matching keys, so we skip over them.
+            ScanOrder::Descending => self
+                .unwritten_changes
+                .range::<String, _>(range)
+                .rev()
+                .find(|(k, _v)| k.starts_with(prefix)),
+        }
+        .map(|(k, v)| (Cow::Borrowed(k.as_str()), Cow::Borrowed(v)));
+
+        // The stores are searched from newest to oldest, and a change only
+        // replaces one that is strictly further away, so that on equal keys,
+        // the in-memory change wins, followed by the newest spilled one.
+        for spill in self.spills.iter().rev() {
+            let Some((k, v)) = spill.nearest(range, prefix, order)? else {
+                continue;
+            };
+            let is_nearer = nearest
+                .as_ref()
+                .map_or(true, |(nearest_k, _)| !order.is_before_or_eq(nearest_k, &k));
+            if is_nearer {
+                nearest = Some((Cow::Owned(k), Cow::Owned(v)));
+            }
+        }
+        Ok(nearest)
+    }
+
+    /// Records a change to the verifiable `key`, spilling the in-memory
+    /// changes to disk if they exceed the threshold set by `spill_options`.
+    pub(crate) fn put_unwritten(
+        &mut self,
+        key: String,
+        value: Option<Vec<u8>>,
+        spill_options: Option<&SpillOptions>,
+    ) {
+        let key_len = key.len();
+        let added = key_len + value.as_ref().map_or(0, Vec::len);
+        let removed = match self.unwritten_changes.insert(key, value) {
+            Some(previous) => key_len + previous.as_ref().map_or(0, Vec::len),
+            None => 0,
+        };
+        self.unwritten_bytes = (self.unwritten_bytes + added).saturating_sub(removed);
+
+        if let Some(options) = spill_options {
+            if self.unwritten_bytes > options.threshold_bytes {
+                self.spill_unwritten(options);
+            }
         }
     }
+
+    /// Moves the in-memory verifiable changes to disk.
+    ///
+    /// If the changes cannot be written, they are kept in memory.
+    fn spill_unwritten(&mut self, options: &SpillOptions) {
+        // A store shared with a copy of the changes, or hidden by a prefix
+        // deletion, is left untouched, and a new one is stacked on top of it.
+        let top_hidden = self
+            .prefix_deletions
+            .values()
+            .any(|hidden| *hidden == self.spills.len());
+        if top_hidden || self.spills.last_mut().and_then(Arc::get_mut).is_none() {
+            match Spill::create(options) {
+                Ok(spill) => self.spills.push(Arc::new(spill)),
+                Err(e) => {
+                    tracing::warn!(
+                        ?e,
+                        "failed to create overlay spill store, keeping changes in memory"
+                    );
+                    return;
+                }
+            }
+        }
+
+        let spill = self.spills.last().expect("a spill store was just created");
+        match spill.write(&self.unwritten_changes) {
+            Ok(()) => {
+                tracing::debug!(
+                    count = self.unwritten_changes.len(),
+                    bytes = self.unwritten_bytes,
+                    "spilled overlay changes to disk"
+                );
+                self.unwritten_changes.clear();
+                self.unwritten_bytes = 0;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    ?e,
+                    "failed to spill overlay changes, keeping them in memory"
+                );
+            }
+        }
+    }
+}
+
+/// Returns the newest change to `key` held in `spills`, if any.
+fn get_spilled(spills: &[Arc<Spill>], key: &str) -> Result<Option<Option<Vec<u8>>>> {
+    for spill in spills.iter().rev() {
+        if let Some(entry) = spill.get(key)? {
+            return Ok(Some(entry));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the newest change to each key held in `spills`, reading them
+/// from disk as the iterator advances.
+///
+/// The stores are visited from oldest to newest, and changes overwritten by
+/// a newer store are skipped, so the changes are not in key order.
+fn spilled_changes(
+    spills: &[Arc<Spill>],
+) -> impl Iterator<Item = Result<(String, Option<Vec<u8>>)>> + '_ {
+    spills.iter().enumerate().flat_map(move |(i, spill)| {
+        spill.iter_prefix("").filter_map(move |entry| {
+            let (key, value) = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            match get_spilled(&spills[i + 1..], &key) {
+                Ok(Some(_)) => None,
+                Ok(None) => Some(Ok((key, value))),
+                Err(e) => Some(Err(e)),
+            }
+        })
+    })
+}
+
+/// Streams the verifiable changes of a [`Cache`], taken out with
+/// [`Cache::take_unwritten`], with a single change per key.
+///
+/// The spilled changes are read from disk as the iterator advances, each
+/// one replaced by the in-memory change to its key if there is one. The
+/// remaining in-memory changes follow.
+pub(crate) fn stream_unwritten(
+    spills: &[Arc<Spill>],
+    mut in_memory: BTreeMap<String, Option<Vec<u8>>>,
+) -> impl Iterator<Item = Result<(String, Option<Vec<u8>>)>> + '_ {
+    let mut spilled = spilled_changes(spills);
+    std::iter::from_fn(move || match spilled.next() {
+        Some(Ok((key, value))) => {
+            let value = in_memory.remove(&key).unwrap_or(value);
+            Some(Ok((key, value)))
+        }
+        Some(Err(e)) => Some(Err(e)),
+        None => in_memory.pop_first().map(Ok),
+    })
 }
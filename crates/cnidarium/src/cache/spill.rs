@@ -0,0 +1,259 @@
+use std::{collections::BTreeMap, ops::Bound, path::PathBuf};
+
+use anyhow::Result;
+use rocksdb::{WriteBatch, WriteOptions, DB};
+
+use crate::ScanOrder;
+
+/// Configures when and where a [`StateDelta`](crate::StateDelta) spills its
+/// pending verifiable writes to disk.
+#[derive(Clone, Debug)]
+pub struct SpillOptions {
+    /// The approximate size, in bytes, of pending verifiable writes held in
+    /// memory above which they are moved to a scratch store on disk.
+    pub threshold_bytes: usize,
+    /// The directory in which scratch stores are created. If `None`, the
+    /// system's temporary directory is used.
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for SpillOptions {
+    fn default() -> Self {
+        Self {
+            threshold_bytes: 256 * 1024 * 1024,
+            dir: None,
+        }
+    }
+}
+
+/// A scratch RocksDB instance holding verifiable changes evicted from a [`Cache`](crate::Cache).
+///
+/// Values are stored with a one-byte tag, so that deletions can be recorded
+/// as tombstones. The store is not durable, and is removed from disk on drop.
+pub(crate) struct Spill {
+    // Fields are dropped in declaration order: the database must be closed
+    // before its directory is removed.
+    db: DB,
+    options: SpillOptions,
+    dir: tempfile::TempDir,
+}
+
+impl std::fmt::Debug for Spill {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Spill")
+            .field("path", &self.dir.path())
+            .finish_non_exhaustive()
+    }
+}
+
+const TOMBSTONE: u8 = 0;
+const VALUE: u8 = 1;
+
+/// The number of changes accumulated by a [`SpillWriter`] before they are
+/// written to its store.
+const WRITE_BATCH_SIZE: usize = 10_000;
+
+impl Spill {
+    /// Creates an empty scratch store, as configured by `options`.
+    pub(crate) fn create(options: &SpillOptions) -> Result<Self> {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("cnidarium-spill-");
+        let dir = match &options.dir {
+            Some(dir) => builder.tempdir_in(dir)?,
+            None => builder.tempdir()?,
+        };
+
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, dir.path())?;
+        tracing::debug!(path = ?dir.path(), "created overlay spill store");
+
+        Ok(Self {
+            db,
+            options: options.clone(),
+            dir,
+        })
+    }
+
+    /// Returns the options this store was created with.
+    pub(crate) fn options(&self) -> &SpillOptions {
+        &self.options
+    }
+
+    /// Writes `changes` to the store, replacing any previously spilled values.
+    pub(crate) fn write<'a>(
+        &self,
+        changes: impl IntoIterator<Item = (&'a String, &'a Option<Vec<u8>>)>,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (key, value) in changes {
+            batch.put(key, encode(value));
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        // The store does not outlive the process, so it needs no durability.
+        let mut write_opts = WriteOptions::default();
+        write_opts.disable_wal(true);
+        self.db.write_opt(batch, &write_opts)?;
+        Ok(())
+    }
+
+    /// Returns the spilled change to `key`, if any.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Option<Vec<u8>>>> {
+        Ok(self.db.get(key)?.map(|bytes| decode(&bytes)))
+    }
+
+    /// Returns the spilled change nearest to the start of `range` in scan
+    /// `order`, among keys with the given `prefix`.
+    ///
+    /// The start of the range must not precede `prefix` in scan order.
+    pub(crate) fn nearest(
+        &self,
+        range: (Bound<&String>, Bound<&String>),
+        prefix: &str,
+        order: ScanOrder,
+    ) -> Result<Option<(String, Option<Vec<u8>>)>> {
+        let mut iter = self.db.raw_iterator();
+        match order {
+            ScanOrder::Ascending => match range.0 {
+                Bound::Included(start) => iter.seek(start),
+                Bound::Excluded(start) => {
+                    iter.seek(start);
+                    if iter.key() == Some(start.as_bytes()) {
+                        iter.next();
+                    }
+                }
+                Bound::Unbounded => iter.seek(prefix),
+            },
+            ScanOrder::Descending => match range.1 {
+                Bound::Included(end) => iter.seek_for_prev(end),
+                Bound::Excluded(end) => {
+                    iter.seek_for_prev(end);
+                    if iter.key() == Some(end.as_bytes()) {
+                        iter.prev();
+                    }
+                }
+                Bound::Unbounded => match prefix_successor(prefix) {
+                    Some(end) => {
+                        iter.seek_for_prev(&end);
+                        if iter.key() == Some(end.as_slice()) {
+                            iter.prev();
+                        }
+                    }
+                    None => iter.seek_to_last(),
+                },
+            },
+        }
+        iter.status()?;
+
+        let Some((key, value)) = iter.item() else {
+            return Ok(None);
+        };
+        if !key.starts_with(prefix.as_bytes()) || !in_range(key, range) {
+            return Ok(None);
+        }
+        let key = String::from_utf8(key.to_vec())?;
+        Ok(Some((key, decode(value))))
+    }
+
+    /// Returns an iterator over the spilled changes to keys with the given
+    /// `prefix`, in key order.
+    pub(crate) fn iter_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = Result<(String, Option<Vec<u8>>)>> + 'a {
+        let mode = rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward);
+        self.db
+            .iterator(mode)
+            .take_while(move |entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(prefix.as_bytes()))
+            })
+            .map(|entry| {
+                let (key, value) = entry?;
+                let key = String::from_utf8(key.to_vec())?;
+                Ok((key, decode(&value)))
+            })
+    }
+}
+
+/// Fills a new [`Spill`], writing the changes once enough have accumulated,
+/// rather than holding them all in memory.
+pub(crate) struct SpillWriter {
+    spill: Spill,
+    batch: WriteBatch,
+}
+
+impl SpillWriter {
+    /// Creates a writer filling an empty scratch store, as configured by `options`.
+    pub(crate) fn create(options: &SpillOptions) -> Result<Self> {
+        Ok(Self {
+            spill: Spill::create(options)?,
+            batch: WriteBatch::default(),
+        })
+    }
+
+    /// Records a change to `key`, replacing any previously written value.
+    pub(crate) fn put(&mut self, key: &str, value: &Option<Vec<u8>>) -> Result<()> {
+        self.batch.put(key, encode(value));
+        if self.batch.len() >= WRITE_BATCH_SIZE {
+            self.spill.write_batch(std::mem::take(&mut self.batch))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining changes, and returns the filled store.
+    pub(crate) fn finish(self) -> Result<Spill> {
+        self.spill.write_batch(self.batch)?;
+        Ok(self.spill)
+    }
+}
+
+fn encode(value: &Option<Vec<u8>>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut bytes = Vec::with_capacity(value.len() + 1);
+            bytes.push(VALUE);
+            bytes.extend_from_slice(value);
+            bytes
+        }
+        None => vec![TOMBSTONE],
+    }
+}
+
+fn decode(bytes: &[u8]) -> Option<Vec<u8>> {
+    match bytes.split_first() {
+        Some((&VALUE, value)) => Some(value.to_vec()),
+        _ => None,
+    }
+}
+
+fn in_range(key: &[u8], range: (Bound<&String>, Bound<&String>)) -> bool {
+    let above_start = match range.0 {
+        Bound::Included(start) => key >= start.as_bytes(),
+        Bound::Excluded(start) => key > start.as_bytes(),
+        Bound::Unbounded => true,
+    };
+    let below_end = match range.1 {
+        Bound::Included(end) => key <= end.as_bytes(),
+        Bound::Excluded(end) => key < end.as_bytes(),
+        Bound::Unbounded => true,
+    };
+    above_start && below_end
+}
+
+/// Returns the smallest byte string that is greater than every key with the
+/// given prefix, or `None` if there is none (e.g. for the empty prefix).
+fn prefix_successor(prefix: &str) -> Option<Vec<u8>> {
+    let mut bytes = prefix.as_bytes().to_vec();
+    while let Some(last) = bytes.pop() {
+        if last < u8::MAX {
+            bytes.push(last + 1);
+            return Some(bytes);
+        }
+    }
+    None
+}
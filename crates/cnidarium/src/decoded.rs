@@ -0,0 +1,77 @@
+use std::{any::Any, collections::BTreeMap, sync::Arc};
+
+use parking_lot::Mutex;
+
+/// A cache of values decoded from the verifiable key-value store, keyed by
+/// their raw key, as returned by [`StateRead::decoded_cache`](crate::StateRead::decoded_cache).
+///
+/// Each [`StateDelta`](crate::StateDelta) holds its own cache, which is dropped
+/// along with the delta, so decoded values live as long as the transaction
+/// that read them. Writing or deleting a key in the delta evicts its decoded
+/// value, and deleting a prefix evicts the values under it. Forks start with
+/// an empty cache.
+///
+/// The cache is filled by typed reads, which can't hold a borrow of the state
+/// while they decode, so insertions are guarded by a [generation](Self::generation):
+/// a value decoded from bytes read before a write is not inserted after it.
+///
+/// The handle is cheaply clonable; all clones share the same entries.
+#[derive(Clone, Default)]
+pub struct DecodedCache(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    values: BTreeMap<String, Box<dyn Any + Send + Sync>>,
+    /// Incremented whenever an entry is evicted.
+    generation: u64,
+}
+
+impl std::fmt::Debug for DecodedCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.0.lock();
+        f.debug_struct("DecodedCache")
+            .field("len", &inner.values.len())
+            .field("generation", &inner.generation)
+            .finish()
+    }
+}
+
+impl DecodedCache {
+    /// Returns the current generation of the cache, to pass to
+    /// [`insert`](Self::insert) along with a value decoded from bytes read
+    /// after this call.
+    pub fn generation(&self) -> u64 {
+        self.0.lock().generation
+    }
+
+    /// Returns the value decoded from `key`, if it was cached as a `T`.
+    pub fn get<T: Any + Clone>(&self, key: &str) -> Option<T> {
+        self.0.lock().values.get(key)?.downcast_ref::<T>().cloned()
+    }
+
+    /// Caches the value decoded from `key`, unless an entry was evicted since
+    /// `generation` was read, in which case `value` may be stale.
+    ///
+    /// A value of a different type cached for the same key is replaced.
+    pub fn insert<T: Any + Send + Sync>(&self, key: &str, value: T, generation: u64) {
+        let mut inner = self.0.lock();
+        if inner.generation == generation {
+            inner.values.insert(key.to_string(), Box::new(value));
+        }
+    }
+
+    /// Evicts the value decoded from `key`, which is being overwritten.
+    pub(crate) fn evict(&self, key: &str) {
+        let mut inner = self.0.lock();
+        inner.values.remove(key);
+        inner.generation += 1;
+    }
+
+    /// Evicts the values decoded from every key under `prefix`, which are
+    /// being deleted.
+    pub(crate) fn evict_prefix(&self, prefix: &str) {
+        let mut inner = self.0.lock();
+        inner.values.retain(|key, _| !key.starts_with(prefix));
+        inner.generation += 1;
+    }
+}
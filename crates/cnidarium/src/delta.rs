@@ -1,17 +1,58 @@
-use std::{any::Any, sync::Arc};
+use std::{any::Any, collections::BTreeSet, sync::Arc};
 
+use borsh::{BorshDeserialize, BorshSerialize};
 use futures::StreamExt;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use tendermint::abci;
 
+#[cfg(feature = "metrics")]
+use crate::metrics;
 use crate::{
     future::{
-        CacheFuture, StateDeltaNonconsensusPrefixRawStream, StateDeltaNonconsensusRangeRawStream,
-        StateDeltaPrefixKeysStream, StateDeltaPrefixRawStream,
+        CacheFuture, MergedPrefixRawStream, StateDeltaNonconsensusPrefixRawStream,
+        StateDeltaNonconsensusRangeRawStream, StateDeltaPrefixKeysStream,
+        StateDeltaPrefixRawStream,
     },
-    utils, Cache, EscapedByteSlice, StateRead, StateWrite,
+    utils, Cache, DecodedCache, EscapedByteSlice, ScanOrder, Snapshot, SpillOptions, StateRead,
+    StateWrite, StorageError,
 };
 
+/// A single key-value write, as recorded by [`StateDelta::overlay_ops`].
+#[derive(Clone, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub enum OverlayOp {
+    /// A write to the verifiable key-value store.
+    Put { key: String, value: Vec<u8> },
+    /// A deletion from the verifiable key-value store.
+    Delete { key: String },
+    /// A write to the non-verifiable key-value store.
+    NonverifiablePut { key: Vec<u8>, value: Vec<u8> },
+    /// A deletion from the non-verifiable key-value store.
+    NonverifiableDelete { key: Vec<u8> },
+    /// A deletion of every key under a prefix from the verifiable key-value
+    /// store, see [`StateWrite::prefix_delete`].
+    PrefixDelete { prefix: String },
+}
+
+/// Where a verifiable key resides, as seen from a [`StateDelta`], reported by
+/// [`StateDelta::key_location`].
+///
+/// The overlay is the set of pending changes of the delta and its parent
+/// layers; the store is the underlying state it was created on, which may
+/// itself be another delta.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyLocation {
+    /// The key is neither in the overlay nor in the store.
+    Absent,
+    /// The key was written in the overlay, and is absent from the store.
+    OverlayPut,
+    /// The key was deleted in the overlay.
+    OverlayDelete,
+    /// The key is only in the store.
+    StoreOnly,
+    /// The key was written in the overlay, replacing a value in the store.
+    OverlayShadowingStore,
+}
+
 /// An arbitrarily-deeply nested stack of delta updates to an underlying state.
 ///
 /// This API allows exploring a tree of possible execution paths concurrently,
@@ -60,6 +101,32 @@ pub struct StateDelta<S: StateRead> {
     /// wrapped this way is so that prefix streams can have 'static lifetimes.
     /// We option-wrap it so it can be chained with the layers; it will never be None.
     leaf_cache: Arc<RwLock<Option<Cache>>>,
+    /// If set, controls when the leaf cache's verifiable writes are spilled to disk.
+    spill_options: Option<Arc<SpillOptions>>,
+    /// The values decoded by typed reads from this delta, see [`DecodedCache`].
+    decoded: DecodedCache,
+    /// If set, records the verifiable reads made through this delta, see
+    /// [`StateDelta::with_conflict_detection`].
+    read_set: Option<Arc<Mutex<ReadSet>>>,
+}
+
+/// The verifiable keys and prefixes read through a [`StateDelta`], used to
+/// detect conflicts with commits that landed after it was forked.
+#[derive(Debug, Default)]
+pub(crate) struct ReadSet {
+    keys: BTreeSet<String>,
+    prefixes: BTreeSet<String>,
+}
+
+impl ReadSet {
+    /// Returns whether a change to `key` could have affected one of the reads.
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.keys.contains(key)
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix.as_str()))
+    }
 }
 
 impl<S: StateRead> StateDelta<S> {
@@ -69,9 +136,54 @@ impl<S: StateRead> StateDelta<S> {
             state: Arc::new(RwLock::new(Some(state))),
             layers: Vec::default(),
             leaf_cache: Arc::new(RwLock::new(Some(Cache::default()))),
+            spill_options: None,
+            decoded: DecodedCache::default(),
+            read_set: None,
         }
     }
 
+    /// Spill pending verifiable writes to a scratch store on disk once they
+    /// exceed the threshold set in `options`, rather than holding them all in
+    /// memory.
+    ///
+    /// Spilled writes remain visible to reads, and stay on disk when the
+    /// delta is flattened or committed: they are streamed into the commit's
+    /// write batch, or into the state the delta is applied to. The tree update
+    /// of a commit still holds every changed value in memory. A scratch store
+    /// is removed once the delta and every copy of its changes, such as those
+    /// retained for [`Storage::subscribe_changes`](crate::Storage::subscribe_changes),
+    /// are dropped. The setting is inherited by forks and by nested
+    /// transactions started with [`begin_transaction`](Self::begin_transaction).
+    pub fn with_spill(mut self, options: SpillOptions) -> Self {
+        self.spill_options = Some(Arc::new(options));
+        self
+    }
+
+    /// Begins a nested transaction on top of this delta, to be used as a
+    /// savepoint.
+    ///
+    /// The child reads this delta's pending writes, and its own writes stay in
+    /// the child until it is [applied](Self::apply), which merges them into
+    /// this delta. Dropping the child instead discards them, rolling this delta
+    /// back to the savepoint. The child borrows this delta mutably, so it can
+    /// only be used again once the child is applied or dropped. Children can
+    /// be nested further.
+    ///
+    /// This is equivalent to `StateDelta::new(&mut delta)`, except that the
+    /// child inherits this delta's [spill settings](Self::with_spill).
+    pub fn begin_transaction(&mut self) -> StateDelta<&mut Self> {
+        let spill_options = self.spill_options.clone();
+        let mut child = StateDelta::new(self);
+        child.spill_options = spill_options;
+        child
+    }
+
+    /// Returns a read-only view of this delta, to hand to code that must not
+    /// write to it.
+    pub fn as_read(&self) -> ReadTransaction<'_, S> {
+        ReadTransaction { delta: self }
+    }
+
     /// Fork execution, returning a new child state that includes all previous changes.
     pub fn fork(&mut self) -> Self {
         // If we have writes in the leaf cache, we'll move them to a new layer,
@@ -98,6 +210,9 @@ impl<S: StateRead> StateDelta<S> {
             state: self.state.clone(),
             layers: self.layers.clone(),
             leaf_cache: Arc::new(RwLock::new(Some(Cache::default()))),
+            spill_options: self.spill_options.clone(),
+            decoded: DecodedCache::default(),
+            read_set: self.read_set.clone(),
         }
     }
 
@@ -107,7 +222,11 @@ impl<S: StateRead> StateDelta<S> {
     ///
     /// The [`apply`](Self::apply) method is a convenience wrapper around this
     /// that applies the changes to the underlying state.
-    pub fn flatten(self) -> (S, Cache) {
+    ///
+    /// # Errors
+    /// If writes that were spilled to disk cannot be read back, see
+    /// [`Cache::merge`]. The tree is invalidated all the same.
+    pub fn flatten(self) -> anyhow::Result<(S, Cache)> {
         tracing::trace!("flattening branch");
         // Take ownership of the underlying state, immediately invalidating all
         // other delta stacks in the same family.
@@ -125,7 +244,7 @@ impl<S: StateRead> StateDelta<S> {
                 .write()
                 .take()
                 .expect("cache must not have already been applied");
-            changes.merge(cache);
+            changes.merge(cache)?;
         }
         // Last, apply the changes in the leaf cache.
         changes.merge(
@@ -133,9 +252,219 @@ impl<S: StateRead> StateDelta<S> {
                 .write()
                 .take()
                 .expect("unable to take leaf cache, was it already applied?"),
-        );
+        )?;
+
+        Ok((state, changes))
+    }
+
+    /// Records a read of the verifiable `key`, if reads are being tracked.
+    fn record_read(&self, key: &str) {
+        if let Some(read_set) = &self.read_set {
+            read_set.lock().keys.insert(key.to_string());
+        }
+    }
+
+    /// Records a scan of the verifiable `prefix`, if reads are being tracked.
+    fn record_prefix_read(&self, prefix: &str) {
+        if let Some(read_set) = &self.read_set {
+            read_set.lock().prefixes.insert(prefix.to_string());
+        }
+    }
+
+    /// Returns the newest cached change to the verifiable `key`, if any.
+    ///
+    /// A `Some(None)` value means that the key was deleted.
+    fn get_unwritten(&self, key: &str) -> anyhow::Result<Option<Option<Vec<u8>>>> {
+        // Check if we have a cache hit in the leaf cache.
+        if let Some(entry) = self
+            .leaf_cache
+            .read()
+            .as_ref()
+            .expect("delta must not have been applied")
+            .get_unwritten(key)?
+        {
+            return Ok(Some(entry));
+        }
+
+        // Iterate through the stack, top to bottom, to see if we have a cache hit.
+        self.get_layered(key)
+    }
+
+    /// Returns the newest change to the verifiable `key` cached in the layers
+    /// below the leaf cache, if any.
+    fn get_layered(&self, key: &str) -> anyhow::Result<Option<Option<Vec<u8>>>> {
+        for layer in self.layers.iter().rev() {
+            if let Some(entry) = layer
+                .read()
+                .as_ref()
+                .expect("delta must not have been applied")
+                .get_unwritten(key)?
+            {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reports whether the verifiable `key` resides in this delta's overlay, in
+    /// its underlying store, or both, for diagnostics and tests.
+    pub async fn key_location(&self, key: &str) -> anyhow::Result<KeyLocation> {
+        let overlay = self.get_unwritten(key)?;
+        if let Some(None) = overlay {
+            return Ok(KeyLocation::OverlayDelete);
+        }
 
-        (state, changes)
+        let stored = self
+            .state
+            .read()
+            .as_ref()
+            .expect("delta must not have been applied")
+            .get_raw(key);
+        let in_store = stored.await?.is_some();
+        Ok(match (overlay.is_some(), in_store) {
+            (false, false) => KeyLocation::Absent,
+            (false, true) => KeyLocation::StoreOnly,
+            (true, false) => KeyLocation::OverlayPut,
+            (true, true) => KeyLocation::OverlayShadowingStore,
+        })
+    }
+
+    /// Returns the operations that reproduce the key-value changes of this
+    /// branch of the tree, as a serializable [`OverlayOp`] list.
+    ///
+    /// The operations describe the net effect of the branch, in key order,
+    /// with at most one operation per key, preceded by the prefix deletions,
+    /// as [`OverlayOp::PrefixDelete`] operations: replaying them with
+    /// [`StateWriteExt::replay_ops`](crate::StateWriteExt::replay_ops) yields
+    /// the same write set, but not the same history of intermediate writes.
+    /// Ephemeral objects and events are not included.
+    ///
+    /// # Errors
+    /// If writes that were spilled to disk cannot be read back.
+    pub fn overlay_ops(&self) -> anyhow::Result<Vec<OverlayOp>> {
+        self.clone_changes()?.into_overlay_ops()
+    }
+
+    /// Flatten all changes in this branch of the tree into a single [`Cache`],
+    /// like [`flatten`](Self::flatten), but without invalidating the tree.
+    ///
+    /// Only the verifiable and nonverifiable changes are copied; ephemeral
+    /// objects and events are left out. Changes spilled to disk are shared
+    /// rather than copied.
+    pub(crate) fn clone_changes(&self) -> anyhow::Result<Cache> {
+        let mut changes = Cache::default();
+        for layer in self.layers.iter().chain(std::iter::once(&self.leaf_cache)) {
+            changes.merge(
+                layer
+                    .read()
+                    .as_ref()
+                    .expect("cache must not have already been applied")
+                    .clone_changes(),
+            )?;
+        }
+        Ok(changes)
+    }
+
+    /// Compacts the pending changes of this delta, to bound the memory held by
+    /// a long-lived working state, returning the number of changes dropped.
+    ///
+    /// Pending changes only keep the latest write to each key, so superseded
+    /// values are released as soon as they are overwritten. Compaction also
+    /// drops the deletions of keys that don't exist below this delta's pending
+    /// changes, i.e. in its parent layers or in the underlying state, such as
+    /// keys that were written and then deleted. Reads are unaffected.
+    ///
+    /// Only the changes made since the last [`fork`](Self::fork) are compacted:
+    /// older layers are shared with other branches of the tree. Deletions that
+    /// were spilled to disk are kept.
+    pub async fn compact_overlay(&mut self) -> anyhow::Result<usize> {
+        let (verifiable, nonverifiable) = {
+            let leaf_cache = self.leaf_cache.read();
+            let leaf_cache = leaf_cache
+                .as_ref()
+                .expect("delta must not have been applied");
+            let verifiable: Vec<String> = leaf_cache
+                .unwritten_changes
+                .iter()
+                .filter(|(_, value)| value.is_none())
+                .map(|(key, _)| key.clone())
+                .collect();
+            let nonverifiable: Vec<Vec<u8>> = leaf_cache
+                .nonverifiable_changes
+                .iter()
+                .filter(|(_, value)| value.is_none())
+                .map(|(key, _)| key.clone())
+                .collect();
+            (verifiable, nonverifiable)
+        };
+
+        let mut dropped = Vec::new();
+        for key in verifiable {
+            if !self.exists_below_leaf(&key).await? {
+                dropped.push(key);
+            }
+        }
+        let mut nonverifiable_dropped = Vec::new();
+        for key in nonverifiable {
+            if !self.nonverifiable_exists_below_leaf(&key).await? {
+                nonverifiable_dropped.push(key);
+            }
+        }
+
+        let mut leaf_cache = self.leaf_cache.write();
+        let leaf_cache = leaf_cache
+            .as_mut()
+            .expect("delta must not have been applied");
+        for key in &dropped {
+            leaf_cache.unwritten_changes.remove(key);
+            leaf_cache.unwritten_bytes = leaf_cache.unwritten_bytes.saturating_sub(key.len());
+        }
+        for key in &nonverifiable_dropped {
+            leaf_cache.nonverifiable_changes.remove(key);
+        }
+
+        Ok(dropped.len() + nonverifiable_dropped.len())
+    }
+
+    /// Returns whether the verifiable `key` exists in the layers or the state
+    /// below the leaf cache.
+    async fn exists_below_leaf(&self, key: &str) -> anyhow::Result<bool> {
+        if let Some(value) = self.get_layered(key)? {
+            return Ok(value.is_some());
+        }
+
+        let value = self
+            .state
+            .read()
+            .as_ref()
+            .expect("delta must not have been applied")
+            .get_raw(key);
+        Ok(value.await?.is_some())
+    }
+
+    /// Returns whether the nonverifiable `key` exists in the layers or the
+    /// state below the leaf cache.
+    async fn nonverifiable_exists_below_leaf(&self, key: &[u8]) -> anyhow::Result<bool> {
+        let layered = self.layers.iter().rev().find_map(|layer| {
+            layer
+                .read()
+                .as_ref()
+                .expect("delta must not have been applied")
+                .nonverifiable_changes
+                .get(key)
+                .cloned()
+        });
+        if let Some(value) = layered {
+            return Ok(value.is_some());
+        }
+
+        let value = self
+            .state
+            .read()
+            .as_ref()
+            .expect("delta must not have been applied")
+            .nonverifiable_get_raw(key);
+        Ok(value.await?.is_some())
     }
 }
 
@@ -143,12 +472,21 @@ impl<S: StateRead + StateWrite> StateDelta<S> {
     /// Apply all changes in this branch of the tree to the underlying state,
     /// releasing it back to the caller and invalidating all other branches of
     /// the tree.
+    ///
+    /// # Panics
+    /// If writes that were spilled to disk cannot be read back. Callers that
+    /// handle this should [`flatten`](Self::flatten) the delta and apply the
+    /// changes with [`Cache::apply_to`] instead.
     pub fn apply(self) -> (S, Vec<abci::Event>) {
-        let (mut state, mut changes) = self.flatten();
+        let (mut state, mut changes) = self
+            .flatten()
+            .unwrap_or_else(|e| panic!("failed to read spilled changes: {e:#}"));
         let events = changes.take_events();
 
         // Apply the flattened changes to the underlying state.
-        changes.apply_to(&mut state);
+        changes
+            .apply_to(&mut state)
+            .unwrap_or_else(|e| panic!("failed to read spilled changes: {e:#}"));
 
         // Finally, return ownership of the state back to the caller.
         (state, events)
@@ -157,12 +495,12 @@ impl<S: StateRead + StateWrite> StateDelta<S> {
 
 impl<S: StateRead + StateWrite> StateDelta<Arc<S>> {
     pub fn try_apply(self) -> anyhow::Result<(S, Vec<abci::Event>)> {
-        let (arc_state, mut changes) = self.flatten();
+        let (arc_state, mut changes) = self.flatten()?;
         let events = std::mem::take(&mut changes.events);
 
         if let Ok(mut state) = Arc::try_unwrap(arc_state) {
             // Apply the flattened changes to the underlying state.
-            changes.apply_to(&mut state);
+            changes.apply_to(&mut state)?;
 
             // Finally, return ownership of the state back to the caller.
             Ok((state, events))
@@ -172,6 +510,271 @@ impl<S: StateRead + StateWrite> StateDelta<Arc<S>> {
     }
 }
 
+impl StateDelta<Snapshot> {
+    /// Opts this delta into optimistic concurrency control.
+    ///
+    /// The delta records the verifiable keys it reads, and the prefixes it
+    /// scans, and can then be committed with [`Storage::commit`](crate::Storage::commit)
+    /// even if other commits landed after the version it was forked from. The
+    /// commit fails with a [`StorageError::Conflict`] if any of those commits
+    /// changed a key that the delta read, or one that it wrote, and otherwise
+    /// applies the delta's writes on top of the latest version.
+    ///
+    /// Without this, a delta can only be committed on top of the version it
+    /// was forked from. Reads of the nonverifiable store are not tracked, but
+    /// writes to a nonverifiable key changed since the fork conflict. The
+    /// setting is inherited by forks, which share the same record of reads.
+    ///
+    /// Commits are checked against the changes kept in memory, so the delta
+    /// can only be committed while the changes of every commit made since it
+    /// was forked are retained, see [`StorageOptions::change_log_retention`](crate::StorageOptions::change_log_retention).
+    /// Otherwise, the commit fails with a [`StorageError::ChangesNotRetained`](crate::StorageError::ChangesNotRetained)
+    /// error.
+    pub fn with_conflict_detection(mut self) -> Self {
+        self.read_set = Some(Arc::default());
+        self
+    }
+
+    /// Takes the record of reads kept by [`StateDelta::with_conflict_detection`],
+    /// if any.
+    pub(crate) fn take_read_set(&mut self) -> Option<ReadSet> {
+        self.read_set
+            .take()
+            .map(|read_set| std::mem::take(&mut *read_set.lock()))
+    }
+
+    /// Returns the version of the underlying [`Snapshot`].
+    pub fn version(&self) -> jmt::Version {
+        self.snapshot().version()
+    }
+
+    /// Checks which of `keys` are present in the verifiable key-value store.
+    ///
+    /// Cached writes count as present and cached deletions as absent. The
+    /// remaining keys are checked against the underlying [`Snapshot`] in a
+    /// single batch, as with [`Snapshot::contains_keys`]. Returns one `bool`
+    /// per key, in input order.
+    pub async fn contains_keys(&self, keys: &[String]) -> anyhow::Result<Vec<bool>> {
+        let mut present = Vec::with_capacity(keys.len());
+        let mut misses = Vec::new();
+        let mut miss_indices = Vec::new();
+        for (index, key) in keys.iter().enumerate() {
+            self.record_read(key);
+            match self.get_unwritten(key)? {
+                Some(entry) => present.push(entry.is_some()),
+                None => {
+                    present.push(false);
+                    misses.push(key.clone());
+                    miss_indices.push(index);
+                }
+            }
+        }
+
+        let found = self.snapshot().contains_keys(&misses).await?;
+        for (index, found) in miss_indices.into_iter().zip(found) {
+            present[index] = found;
+        }
+        Ok(present)
+    }
+
+    /// Gets the values of `keys` from the verifiable key-value store, as raw
+    /// bytes.
+    ///
+    /// Cached writes and deletions are resolved in memory, as with
+    /// [`StateRead::get_raw`]. The remaining keys are read from the underlying
+    /// [`Snapshot`] in a single batch, as with [`Snapshot::multi_get_raw`].
+    /// Returns one value per key, in input order.
+    pub async fn multi_get_raw(&self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        let mut misses = Vec::new();
+        let mut miss_indices = Vec::new();
+        for (index, key) in keys.iter().enumerate() {
+            self.record_read(key);
+            match self.get_unwritten(key)? {
+                Some(entry) => values.push(entry),
+                None => {
+                    values.push(None);
+                    misses.push(key.clone());
+                    miss_indices.push(index);
+                }
+            }
+        }
+
+        let found = self.snapshot().multi_get_raw(&misses).await?;
+        for (index, value) in miss_indices.into_iter().zip(found) {
+            values[index] = value;
+        }
+        Ok(values)
+    }
+
+    /// Returns the length of the value of `key` in the verifiable key-value
+    /// store, or `None` if it is absent.
+    ///
+    /// Cached writes take precedence, as with [`StateRead::get_raw`]: a pending
+    /// value reports its own length, and a pending deletion reports `None`.
+    /// Otherwise, the length is read from the underlying [`Snapshot`] without
+    /// reading the value, as with [`Snapshot::value_size`].
+    pub async fn value_size(&self, key: &str) -> anyhow::Result<Option<usize>> {
+        self.record_read(key);
+        if let Some(entry) = self.get_unwritten(key)? {
+            return Ok(entry.map(|value| value.len()));
+        }
+        self.snapshot().value_size(key).await
+    }
+
+    /// Returns whether `key` is present in the verifiable key-value store.
+    ///
+    /// A pending write counts as present and a pending deletion as absent.
+    /// Otherwise, the underlying [`Snapshot`] is checked without copying the
+    /// value, as with [`Snapshot::contains_raw`].
+    pub async fn contains_raw(&self, key: &str) -> anyhow::Result<bool> {
+        self.record_read(key);
+        if let Some(entry) = self.get_unwritten(key)? {
+            return Ok(entry.is_some());
+        }
+        self.snapshot().contains_raw(key).await
+    }
+
+    /// Replaces the contents of the substore registered with `prefix` with
+    /// `entries`, whose keys are relative to the substore, as produced by
+    /// [`Snapshot::export_substore`]. Returns the number of entries imported.
+    ///
+    /// Keys of the substore that are not in `entries` are deleted, so that once
+    /// committed, the substore has the same root as the one it was exported
+    /// from. Other substores are not affected.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError::UnknownSubstore`] if `prefix` is not a
+    /// registered substore, or an error if an entry has an empty key. Entries
+    /// imported before the error remain written to this delta, which should
+    /// then be discarded.
+    pub async fn import_substore<St>(&mut self, prefix: &str, entries: St) -> anyhow::Result<u64>
+    where
+        St: futures::Stream<Item = anyhow::Result<(String, Vec<u8>)>>,
+    {
+        let Some(config) = self.snapshot().0.multistore_cache.config.substore(prefix) else {
+            return Err(StorageError::UnknownSubstore {
+                prefix: prefix.to_string(),
+            }
+            .into());
+        };
+
+        let existing: Vec<String> = self
+            .prefix_keys(&config.prefix_with_delimiter)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<anyhow::Result<_>>()?;
+        for key in existing {
+            self.delete(key);
+        }
+
+        let mut count = 0;
+        let mut entries = std::pin::pin!(entries);
+        while let Some(entry) = entries.next().await {
+            let (key, value) = entry?;
+            anyhow::ensure!(!key.is_empty(), "substore keys must not be empty");
+            self.put_raw(format!("{}{key}", config.prefix_with_delimiter), value);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Like [`Snapshot::prefix_raw_global`], scanning every store that can
+    /// hold keys starting with `prefix` in a single ascending stream, with
+    /// the delta's pending writes and deletions merged in as with
+    /// [`StateRead::prefix_raw`].
+    pub fn prefix_raw_global(
+        &self,
+        prefix: &str,
+    ) -> StateDeltaPrefixRawStream<MergedPrefixRawStream<<Snapshot as StateRead>::PrefixRawStream>>
+    {
+        self.record_prefix_read(prefix);
+        StateDeltaPrefixRawStream {
+            underlying: self.snapshot().prefix_raw_global(prefix).peekable(),
+            layers: self.layers.clone(),
+            leaf_cache: self.leaf_cache.clone(),
+            last_key: None,
+            prefix: prefix.to_owned(),
+            order: ScanOrder::Ascending,
+        }
+    }
+
+    /// Returns the value of `key` in the verifiable key-value store, along
+    /// with the version at which it was last written, as with
+    /// [`Snapshot::get_raw_versioned`].
+    ///
+    /// Values written in the delta but not committed yet are reported with
+    /// the version `u64::MAX`.
+    pub async fn get_raw_versioned(
+        &self,
+        key: &str,
+    ) -> anyhow::Result<Option<(Vec<u8>, jmt::Version)>> {
+        self.record_read(key);
+        match self.get_unwritten(key)? {
+            Some(value) => Ok(value.map(|value| (value, u64::MAX))),
+            None => self.snapshot().get_raw_versioned(key).await,
+        }
+    }
+
+    /// Deletes every key of the verifiable key-value store that is visible
+    /// from this delta, in the main store and in every substore, returning the
+    /// number of keys deleted.
+    ///
+    /// Keys written earlier in this delta are deleted too, and keys it already
+    /// deleted are not counted. Once committed, every substore is an empty
+    /// tree. The main store is too if there are no substores: otherwise, it
+    /// still holds the root of each substore, which is maintained by commits
+    /// and is not deleted here. The nonverifiable store is not affected.
+    ///
+    /// Deletions are recorded key by key, since each of them must be applied
+    /// to the tree to compute the new roots.
+    pub async fn clear(&mut self) -> anyhow::Result<u64> {
+        let config = self.snapshot().0.multistore_cache.config.clone();
+
+        let mut scans = vec![String::new()];
+        scans.extend(config.iter().map(|c| c.prefix_with_delimiter.clone()));
+
+        let mut count = 0;
+        for prefix in scans {
+            let keys: Vec<String> = self
+                .prefix_keys(&prefix)
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<anyhow::Result<_>>()?;
+            for key in keys {
+                // The roots of the substores live in the main store.
+                if prefix.is_empty() && config.substore(&key).is_some() {
+                    continue;
+                }
+                self.delete(key);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Like [`StateRead::prefix_raw`], but only scans registered substores, as
+    /// described in [`Snapshot::prefix_raw_strict`].
+    pub fn prefix_raw_strict(
+        &self,
+        prefix: &str,
+    ) -> anyhow::Result<<Self as StateRead>::PrefixRawStream> {
+        self.snapshot().check_substore_prefix(prefix)?;
+        Ok(self.prefix_raw(prefix))
+    }
+
+    /// Returns a handle to the underlying [`Snapshot`].
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        self.state
+            .read()
+            .as_ref()
+            .expect("delta must not have been applied")
+            .clone()
+    }
+}
+
 impl<S: StateRead> StateRead for StateDelta<S> {
     type GetRawFut = CacheFuture<S::GetRawFut>;
     type PrefixRawStream = StateDeltaPrefixRawStream<S::PrefixRawStream>;
@@ -182,30 +785,18 @@ impl<S: StateRead> StateRead for StateDelta<S> {
         StateDeltaNonconsensusRangeRawStream<S::NonconsensusRangeRawStream>;
 
     fn get_raw(&self, key: &str) -> Self::GetRawFut {
-        // Check if we have a cache hit in the leaf cache.
-        if let Some(entry) = self
-            .leaf_cache
-            .read()
-            .as_ref()
-            .expect("delta must not have been applied")
-            .unwritten_changes
-            .get(key)
-        {
-            return CacheFuture::hit(entry.clone());
-        }
-
-        // Iterate through the stack, top to bottom, to see if we have a cache hit.
-        for layer in self.layers.iter().rev() {
-            if let Some(entry) = layer
-                .read()
-                .as_ref()
-                .expect("delta must not have been applied")
-                .unwritten_changes
-                .get(key)
-            {
-                return CacheFuture::hit(entry.clone());
+        self.record_read(key);
+        match self.get_unwritten(key) {
+            Ok(Some(entry)) => {
+                #[cfg(feature = "metrics")]
+                metrics::counter!(metrics::STORAGE_DELTA_CACHE_HITS).increment(1);
+                return CacheFuture::hit(entry);
             }
+            Ok(None) => {}
+            Err(e) => return CacheFuture::error(e),
         }
+        #[cfg(feature = "metrics")]
+        metrics::counter!(metrics::STORAGE_DELTA_CACHE_MISSES).increment(1);
 
         // If we got here, the key must be in the underlying state or not present at all.
         CacheFuture::miss(
@@ -293,6 +884,18 @@ impl<S: StateRead> StateRead for StateDelta<S> {
             .object_type(key)
     }
 
+    fn decoded_cache(&self) -> Option<DecodedCache> {
+        Some(self.decoded.clone())
+    }
+
+    fn substore_prefixes(&self) -> Vec<String> {
+        self.state
+            .read()
+            .as_ref()
+            .expect("delta must not have been applied")
+            .substore_prefixes()
+    }
+
     fn object_get<T: std::any::Any + Send + Sync + Clone>(&self, key: &'static str) -> Option<T> {
         // Check if we have a cache hit in the leaf cache.
         if let Some(entry) = self
@@ -337,12 +940,17 @@ impl<S: StateRead> StateRead for StateDelta<S> {
     }
 
     fn prefix_raw(&self, prefix: &str) -> Self::PrefixRawStream {
+        self.prefix_raw_ordered(prefix, ScanOrder::Ascending)
+    }
+
+    fn prefix_raw_ordered(&self, prefix: &str, order: ScanOrder) -> Self::PrefixRawStream {
+        self.record_prefix_read(prefix);
         let underlying = self
             .state
             .read()
             .as_ref()
             .expect("delta must not have been applied")
-            .prefix_raw(prefix)
+            .prefix_raw_ordered(prefix, order)
             .peekable();
         StateDeltaPrefixRawStream {
             underlying,
@@ -350,10 +958,12 @@ impl<S: StateRead> StateRead for StateDelta<S> {
             leaf_cache: self.leaf_cache.clone(),
             last_key: None,
             prefix: prefix.to_owned(),
+            order,
         }
     }
 
     fn prefix_keys(&self, prefix: &str) -> Self::PrefixKeysStream {
+        self.record_prefix_read(prefix);
         let underlying = self
             .state
             .read()
@@ -413,21 +1023,30 @@ impl<S: StateRead> StateRead for StateDelta<S> {
 
 impl<S: StateRead> StateWrite for StateDelta<S> {
     fn put_raw(&mut self, key: String, value: jmt::OwnedValue) {
+        self.decoded.evict(&key);
         self.leaf_cache
             .write()
             .as_mut()
             .expect("delta must not have been applied")
-            .unwritten_changes
-            .insert(key, Some(value));
+            .put_unwritten(key, Some(value), self.spill_options.as_deref());
     }
 
     fn delete(&mut self, key: String) {
+        self.decoded.evict(&key);
         self.leaf_cache
             .write()
             .as_mut()
             .expect("delta must not have been applied")
-            .unwritten_changes
-            .insert(key, None);
+            .put_unwritten(key, None, self.spill_options.as_deref());
+    }
+
+    fn prefix_delete(&mut self, prefix: String) {
+        self.decoded.evict_prefix(&prefix);
+        self.leaf_cache
+            .write()
+            .as_mut()
+            .expect("delta must not have been applied")
+            .delete_prefix(prefix);
     }
 
     fn nonverifiable_delete(&mut self, key: Vec<u8>) {
@@ -498,6 +1117,54 @@ impl<S: StateRead> StateWrite for StateDelta<S> {
     }
 }
 
+/// A read-only view of a [`StateDelta`], obtained with [`StateDelta::as_read`].
+///
+/// The view only exposes reads, which see the delta's pending writes merged
+/// over the underlying state, so that a function taking a `ReadTransaction`
+/// cannot write to the delta it is given:
+///
+/// ```compile_fail
+/// use cnidarium::{ReadTransaction, StateRead, StateWrite};
+///
+/// fn helper<S: StateRead>(tx: ReadTransaction<'_, S>) {
+///     tx.put_raw("key".to_string(), vec![]);
+/// }
+/// ```
+///
+/// The view borrows the delta, and is `Copy`, so it can be passed around as
+/// freely as a shared reference.
+#[derive(Debug)]
+pub struct ReadTransaction<'a, S: StateRead> {
+    delta: &'a StateDelta<S>,
+}
+
+impl<S: StateRead> Clone for ReadTransaction<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: StateRead> Copy for ReadTransaction<'_, S> {}
+
+impl<'a, S: StateRead> ReadTransaction<'a, S> {
+    /// Gets a value from the verifiable key-value store as raw bytes, as with
+    /// [`StateRead::get_raw`].
+    pub fn get_raw(&self, key: &str) -> <StateDelta<S> as StateRead>::GetRawFut {
+        self.delta.get_raw(key)
+    }
+
+    /// Retrieves all values for keys matching a prefix from the verifiable
+    /// key-value store, as with [`StateRead::prefix_raw`].
+    pub fn prefix_raw(&self, prefix: &str) -> <StateDelta<S> as StateRead>::PrefixRawStream {
+        self.delta.prefix_raw(prefix)
+    }
+
+    /// Returns whether `key` is present in the verifiable key-value store.
+    pub async fn contains_key(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.delta.get_raw(key).await?.is_some())
+    }
+}
+
 /// Extension trait providing `try_begin_transaction()` on `Arc<StateDelta<S>>`.
 pub trait ArcStateDeltaExt: Sized {
     type S: StateRead;
@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+/// Errors returned by [`Storage`](crate::Storage) that callers may want to
+/// handle specifically.
+///
+/// These are returned wrapped in an [`anyhow::Error`], and can be recovered
+/// using [`anyhow::Error::downcast_ref`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum StorageError {
+    /// The database at `path` is locked by another storage instance, either in
+    /// this process or in another one that did not release it.
+    AlreadyOpen { path: PathBuf },
+    /// The `u64` counter stored at `key` cannot be incremented without
+    /// overflowing.
+    CounterOverflow { key: String },
+    /// Version `version` was not committed before the timeout elapsed. The
+    /// latest committed version was `latest`.
+    VersionTimeout {
+        version: jmt::Version,
+        latest: jmt::Version,
+    },
+    /// No substore is registered with the prefix `prefix`.
+    UnknownSubstore { prefix: String },
+    /// RocksDB returned an error. If `retryable` is set, the error is
+    /// transient, and the operation may succeed if attempted again.
+    Backend { retryable: bool, message: String },
+    /// All `max` iterator slots are taken by open streams, and none was freed
+    /// in time. See [`StorageOptions::max_open_iterators`](crate::StorageOptions::max_open_iterators).
+    TooManyIterators { max: usize },
+    /// A commit would write `internal_nodes` internal tree nodes, more than the
+    /// `max` allowed by [`StorageOptions::max_internal_nodes_per_commit`](crate::StorageOptions::max_internal_nodes_per_commit).
+    TreeUpdateTooWide { internal_nodes: usize, max: usize },
+    /// A state opened at the past version `version` was asked for a read that
+    /// is not versioned, such as a prefix scan or a nonverifiable read. See
+    /// [`Storage::state_at_version`](crate::Storage::state_at_version).
+    UnversionedRead { version: jmt::Version },
+    /// Version `version` was removed by [`Storage::prune`](crate::Storage::prune).
+    /// The oldest version still available is `oldest`.
+    VersionPruned {
+        version: jmt::Version,
+        oldest: jmt::Version,
+    },
+    /// A [`ShadowStorage`](crate::ShadowStorage) observed different results
+    /// from its primary and shadow storages for `operation`.
+    ShadowDivergence {
+        operation: String,
+        primary: String,
+        shadow: String,
+    },
+    /// A delta committed with [conflict detection](crate::StateDelta::with_conflict_detection)
+    /// read or wrote `key`, which a commit made after the delta was forked
+    /// changed. A nonverifiable key is reported as its escaped bytes.
+    Conflict { key: String },
+    /// The changes committed at `version` are no longer kept in memory. See
+    /// [`StorageOptions::change_log_retention`](crate::StorageOptions::change_log_retention).
+    ChangesNotRetained { version: jmt::Version },
+    /// A commit wrote `key` under `prefix`, which was registered with
+    /// [`Storage::register_substore`](crate::Storage::register_substore) but is
+    /// only routed to its substore once the storage is reloaded. A
+    /// nonverifiable key is reported as its escaped bytes.
+    PendingSubstore { prefix: String, key: String },
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::AlreadyOpen { path } => write!(
+                f,
+                "the database at {} is already open (its LOCK file is held by another instance)",
+                path.display()
+            ),
+            StorageError::CounterOverflow { key } => {
+                write!(f, "the counter at {key} would overflow")
+            }
+            StorageError::VersionTimeout { version, latest } => write!(
+                f,
+                "timed out waiting for version {version} to be committed (latest version is {latest})"
+            ),
+            StorageError::UnknownSubstore { prefix } => {
+                write!(f, "no substore is registered for the prefix {prefix:?}")
+            }
+            StorageError::Backend { retryable, message } => {
+                let kind = if *retryable { "transient" } else { "permanent" };
+                write!(f, "{kind} storage backend error: {message}")
+            }
+            StorageError::TooManyIterators { max } => {
+                write!(f, "all {max} iterator slots are taken by open streams")
+            }
+            StorageError::TreeUpdateTooWide {
+                internal_nodes,
+                max,
+            } => write!(
+                f,
+                "the commit would write {internal_nodes} internal tree nodes, more than the maximum of {max}"
+            ),
+            StorageError::UnversionedRead { version } => write!(
+                f,
+                "version {version} is no longer retained in memory, and only versioned reads of it are supported"
+            ),
+            StorageError::VersionPruned { version, oldest } => write!(
+                f,
+                "version {version} has been pruned (the oldest available version is {oldest})"
+            ),
+            StorageError::ShadowDivergence {
+                operation,
+                primary,
+                shadow,
+            } => write!(
+                f,
+                "shadow storage diverged on {operation}: primary returned {primary}, shadow returned {shadow}"
+            ),
+            StorageError::Conflict { key } => {
+                write!(f, "{key} was changed by a commit made after the delta was forked")
+            }
+            StorageError::ChangesNotRetained { version } => {
+                write!(f, "the changes committed at version {version} are no longer retained")
+            }
+            StorageError::PendingSubstore { prefix, key } => write!(
+                f,
+                "{key} is under the substore prefix {prefix:?}, which is not loaded until the storage is reloaded"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
@@ -6,10 +6,11 @@ use futures::{
     stream::Peekable,
     Stream,
 };
-use parking_lot::RwLock;
+use parking_lot::{RwLock, RwLockReadGuard};
 use pin_project::pin_project;
 use smallvec::SmallVec;
 use std::{
+    borrow::Cow,
     future::Future,
     ops::Bound,
     pin::Pin,
@@ -17,7 +18,7 @@ use std::{
     task::{ready, Context, Poll},
 };
 
-use crate::Cache;
+use crate::{Cache, ScanOrder};
 
 /// Future representing a read from a state snapshot.
 #[pin_project]
@@ -50,6 +51,12 @@ impl<F> CacheFuture<F> {
         }
     }
 
+    pub(crate) fn error(error: anyhow::Error) -> Self {
+        Self {
+            inner: Either::Left(futures::future::ready(Err(error))),
+        }
+    }
+
     pub(crate) fn miss(underlying: F) -> Self {
         Self {
             inner: Either::Right(underlying),
@@ -247,6 +254,7 @@ where
     pub(crate) leaf_cache: Arc<RwLock<Option<Cache>>>,
     pub(crate) last_key: Option<String>,
     pub(crate) prefix: String,
+    pub(crate) order: ScanOrder,
 }
 
 impl<St> Stream for StateDeltaPrefixRawStream<St>
@@ -311,43 +319,82 @@ where
             // key we returned (exclusive, so we make forward progress on the
             // stream) and the peeked key (inclusive, because we need to find out
             // whether or not there was a covering deletion).
-            let search_range = (
-                this.last_key
-                    .as_ref()
-                    .map(Bound::Excluded)
-                    .unwrap_or(Bound::Included(this.prefix)),
-                peeked
-                    .map(|(k, _)| Bound::Included(k))
-                    .unwrap_or(Bound::Unbounded),
-            );
+            //
+            // When scanning in descending order, the roles of the bounds are
+            // swapped: the peeked key is the lower bound of the search range.
+            let search_range = match this.order {
+                ScanOrder::Ascending => (
+                    this.last_key
+                        .as_ref()
+                        .map(Bound::Excluded)
+                        .unwrap_or(Bound::Included(this.prefix)),
+                    peeked
+                        .map(|(k, _)| Bound::Included(k))
+                        .unwrap_or(Bound::Unbounded),
+                ),
+                ScanOrder::Descending => (
+                    peeked
+                        .map(|(k, _)| Bound::Included(k))
+                        .unwrap_or(Bound::Included(this.prefix)),
+                    this.last_key
+                        .as_ref()
+                        .map(Bound::Excluded)
+                        .unwrap_or(Bound::Unbounded),
+                ),
+            };
 
             // It'd be slightly cleaner to initialize `leftmost_pair` with the
             // peeked contents, but that would taint `leftmost_pair` with a
             // `peeked` borrow, and we may need to mutate the underlying stream
             // later.  Instead, initialize it with `None` to only search the
             // cache layers, and compare at the end.
-            let mut leftmost_pair = None;
+            //
+            // In descending order, "leftmost" means the closest pair in scan
+            // order, i.e. the rightmost pair in key order.
+            //
+            // Pairs are borrowed from the layers, unless a layer spilled them
+            // to disk.
+            let mut leftmost_pair: Option<(Cow<str>, Cow<Option<Vec<u8>>>)> = None;
             for layer in layer_guards.iter() {
                 // Find this layer's leftmost key-value pair in the search range.
-                let found_pair = layer
+                let found_pair = match layer
                     .as_ref()
                     .expect("layer must not have been applied")
-                    .unwritten_changes
-                    .range::<String, _>(search_range)
-                    .take_while(|(k, _v)| k.starts_with(this.prefix.as_str()))
-                    .next();
+                    .nearest_unwritten_change(search_range, this.prefix.as_str(), *this.order)
+                {
+                    Ok(found_pair) => found_pair,
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                };
 
                 // Check whether the new pair, if any, is the new leftmost pair.
-                match (leftmost_pair, found_pair) {
-                    // We want to replace the pair even when the key is equal,
-                    // so that we always prefer a newer value over an older value.
-                    (Some((leftmost_k, _)), Some((k, v))) if k <= leftmost_k => {
-                        leftmost_pair = Some((k, v));
-                    }
-                    (None, Some((k, v))) => {
+                // We want to replace the pair even when the key is equal,
+                // so that we always prefer a newer value over an older value.
+                if let Some((k, v)) = found_pair {
+                    let is_leftmost = leftmost_pair.as_ref().map_or(true, |(leftmost_k, _)| {
+                        this.order.is_before_or_eq(&*k, &**leftmost_k)
+                    });
+                    if is_leftmost {
                         leftmost_pair = Some((k, v));
                     }
-                    _ => {}
+                }
+            }
+
+            // A prefix deletion hides the keys under its prefix in older
+            // layers and in the underlying stream without being listed itself,
+            // so the next key is looked up from the newest layer down.
+            let next_key = has_prefix_deletions(&layer_guards)
+                .then(|| {
+                    leftmost_pair
+                        .as_ref()
+                        .map(|(k, _)| k.to_string())
+                        .or_else(|| peeked.map(|(k, _)| k.clone()))
+                })
+                .flatten();
+            if let Some(key) = next_key {
+                match newest_change(&layer_guards, &key) {
+                    Ok(Some(v)) => leftmost_pair = Some((Cow::Owned(key), Cow::Owned(v))),
+                    Ok(None) => {}
+                    Err(e) => return Poll::Ready(Some(Err(e))),
                 }
             }
 
@@ -368,13 +415,13 @@ where
                     // priority over the peeked pair.
                     //
                     // If the keys are exactly equal, we advance the underlying stream.
-                    if peeked.map(|(kp, _)| kp) == Some(k) {
+                    if peeked.map(|(kp, _)| kp.as_str()) == Some(&*k) {
                         let _ = this.underlying.as_mut().poll_next(cx);
                     }
-                    overwrite_in_place(this.last_key, k);
-                    if let Some(v) = v {
+                    overwrite_in_place(this.last_key, &k);
+                    if let Some(v) = v.into_owned() {
                         // If the value is Some, we have a key-value pair to yield.
-                        return Poll::Ready(Some(Ok((k.clone(), v.clone()))));
+                        return Poll::Ready(Some(Ok((k.into_owned(), v))));
                     } else {
                         // If the value is None, this pair represents a deletion,
                         // so continue looping until we find a non-deleted pair.
@@ -492,28 +539,53 @@ where
             // `peeked` borrow, and we may need to mutate the underlying stream
             // later.  Instead, initialize it with `None` to only search the
             // cache layers, and compare at the end.
-            let mut leftmost_pair = None;
+            //
+            // Pairs are borrowed from the layers, unless a layer spilled them
+            // to disk.
+            let mut leftmost_pair: Option<(Cow<str>, Cow<Option<Vec<u8>>>)> = None;
             for layer in layer_guards.iter() {
                 // Find this layer's leftmost key-value pair in the search range.
-                let found_pair = layer
+                let found_pair = match layer
                     .as_ref()
                     .expect("layer must not have been applied")
-                    .unwritten_changes
-                    .range::<String, _>(search_range)
-                    .take_while(|(k, _v)| k.starts_with(this.prefix.as_str()))
-                    .next();
+                    .nearest_unwritten_change(
+                        search_range,
+                        this.prefix.as_str(),
+                        ScanOrder::Ascending,
+                    ) {
+                    Ok(found_pair) => found_pair,
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                };
 
                 // Check whether the new pair, if any, is the new leftmost pair.
-                match (leftmost_pair, found_pair) {
-                    // We want to replace the pair even when the key is equal,
-                    // so that we always prefer a newer value over an older value.
-                    (Some((leftmost_k, _)), Some((k, v))) if k <= leftmost_k => {
-                        leftmost_pair = Some((k, v));
-                    }
-                    (None, Some((k, v))) => {
+                // We want to replace the pair even when the key is equal,
+                // so that we always prefer a newer value over an older value.
+                if let Some((k, v)) = found_pair {
+                    if leftmost_pair
+                        .as_ref()
+                        .map_or(true, |(leftmost_k, _)| k <= *leftmost_k)
+                    {
                         leftmost_pair = Some((k, v));
                     }
-                    _ => {}
+                }
+            }
+
+            // A prefix deletion hides the keys under its prefix in older
+            // layers and in the underlying stream without being listed itself,
+            // so the next key is looked up from the newest layer down.
+            let next_key = has_prefix_deletions(&layer_guards)
+                .then(|| {
+                    leftmost_pair
+                        .as_ref()
+                        .map(|(k, _)| k.to_string())
+                        .or_else(|| peeked.cloned())
+                })
+                .flatten();
+            if let Some(key) = next_key {
+                match newest_change(&layer_guards, &key) {
+                    Ok(Some(v)) => leftmost_pair = Some((Cow::Owned(key), Cow::Owned(v))),
+                    Ok(None) => {}
+                    Err(e) => return Poll::Ready(Some(Err(e))),
                 }
             }
 
@@ -534,13 +606,13 @@ where
                     // priority over the peeked pair.
                     //
                     // If the keys are exactly equal, we advance the underlying stream.
-                    if peeked == Some(k) {
+                    if peeked.map(String::as_str) == Some(&*k) {
                         let _ = this.underlying.as_mut().poll_next(cx);
                     }
-                    overwrite_in_place(this.last_key, k);
+                    overwrite_in_place(this.last_key, &k);
                     if v.is_some() {
                         // If the value is Some, we have a key-value pair to yield.
-                        return Poll::Ready(Some(Ok(k.clone())));
+                        return Poll::Ready(Some(Ok(k.into_owned())));
                     } else {
                         // If the value is None, this pair represents a deletion,
                         // so continue looping until we find a non-deleted pair.
@@ -761,3 +833,89 @@ where
         }
     }
 }
+
+/// Returns whether any of the cache `layers` deleted a prefix.
+fn has_prefix_deletions(layers: &[RwLockReadGuard<'_, Option<Cache>>]) -> bool {
+    layers.iter().any(|layer| {
+        !layer
+            .as_ref()
+            .expect("layer must not have been applied")
+            .prefix_deletions
+            .is_empty()
+    })
+}
+
+/// Returns the newest change to the verifiable `key` in the cache `layers`,
+/// ordered oldest first, taking prefix deletions into account.
+fn newest_change(
+    layers: &[RwLockReadGuard<'_, Option<Cache>>],
+    key: &str,
+) -> Result<Option<Option<Vec<u8>>>> {
+    for layer in layers.iter().rev() {
+        let change = layer
+            .as_ref()
+            .expect("layer must not have been applied")
+            .get_unwritten(key)?;
+        if change.is_some() {
+            return Ok(change);
+        }
+    }
+    Ok(None)
+}
+
+/// Merges several streams of key-value pairs, each in ascending key order,
+/// into a single stream in ascending key order.
+///
+/// Used by [`Snapshot::prefix_raw_global`](crate::Snapshot::prefix_raw_global)
+/// to merge the scans of several substores. The keys of the merged streams
+/// must be disjoint.
+pub struct MergedPrefixRawStream<St>
+where
+    St: Stream<Item = Result<(String, Vec<u8>)>> + Unpin,
+{
+    pub(crate) streams: Vec<Peekable<St>>,
+}
+
+impl<St> Stream for MergedPrefixRawStream<St>
+where
+    St: Stream<Item = Result<(String, Vec<u8>)>> + Unpin,
+{
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // Every stream must have an item ready (or be exhausted) before we can
+        // tell which one holds the smallest key. Poll all of them, so that each
+        // pending stream registers the waker, before returning.
+        let mut pending = false;
+        let mut errored = None;
+        let mut smallest: Option<(usize, &String)> = None;
+        for (index, stream) in self.streams.iter_mut().enumerate() {
+            match Pin::new(stream).poll_peek(cx) {
+                Poll::Pending => pending = true,
+                Poll::Ready(Some(Err(_))) => {
+                    errored = Some(index);
+                    break;
+                }
+                Poll::Ready(Some(Ok((key, _)))) => {
+                    if smallest.map_or(true, |(_, smallest_key)| key < smallest_key) {
+                        smallest = Some((index, key));
+                    }
+                }
+                Poll::Ready(None) => {}
+            }
+        }
+        let next = smallest.map(|(index, _)| index);
+
+        // If we get an underlying error, bubble it up immediately.
+        if let Some(index) = errored {
+            return Pin::new(&mut self.streams[index]).poll_next(cx);
+        }
+        if pending {
+            return Poll::Pending;
+        }
+        match next {
+            Some(index) => Pin::new(&mut self.streams[index]).poll_next(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
@@ -26,9 +26,24 @@
 //! range queries on keys rather than key hashes. This index, however, is not
 //! part of the verifiable consensus state.
 //!
+//! In particular, proofs cover individual keys: [`Snapshot::get_with_proof`]
+//! proves the presence or absence of one key. Keys sharing a prefix are
+//! scattered across the tree by hashing, so the absence of every key under a
+//! prefix cannot be proven with a range proof. Applications that need to prove
+//! that a prefix is empty should maintain a verifiable summary of it, such as
+//! a count of its entries under a dedicated key, and prove that key instead.
+//!
 //! * A secondary, non-verifiable key-value store with byte keys and byte
 //! values, backed directly by RocksDB.  This is intended for use building
-//! application-specific indexes of the verifiable consensus state.
+//! application-specific indexes of the verifiable consensus state, and other
+//! data that must be durable but need not be proven, like caches.  Its
+//! writes ([`StateWrite::nonverifiable_put_raw`]) are committed in the same
+//! RocksDB write batch as the verifiable ones, but are not hashed into any
+//! tree. A commit that touches a substore still advances its version and
+//! records its root in the main store, even if it only writes nonverifiable
+//! data to it. Nonverifiable data is read back with
+//! [`StateRead::nonverifiable_get_raw`] and
+//! [`StateRead::nonverifiable_prefix_raw`].
 //!
 //! * A tertiary, in-memory object store. This is intended for use implementing
 //! accumulators, like lists of data to be batch-processed at the end of the
@@ -44,12 +59,76 @@
 //! store is also stored in a separate RocksDB column family, allowing storage
 //! optimizations.
 //!
+//! # Root hash
+//!
+//! The root hash of a version of the chain state, as returned by
+//! [`Storage::commit`], [`Snapshot::root_hash`] or
+//! [`Storage::root_hash_at_version`], is the root of the main store's tree. It
+//! is computed as follows, so that verifiers can reproduce it:
+//!
+//! * Each substore is a JMT over SHA-256, whose leaves are keyed by the hash of
+//!   the key with the substore prefix and its delimiter stripped, e.g. `key`
+//!   for `ibc/key`.
+//! * After the substores changed at a version are committed, the main store
+//!   holds the 32-byte root hash of each substore, as a value under the
+//!   substore's prefix, e.g. `ibc`. A substore that did not change keeps the
+//!   root recorded for it at an earlier version.
+//! * The main store is a JMT over SHA-256 holding these substore roots along
+//!   with the keys that do not belong to any substore, and its root is the
+//!   root hash of the chain state.
+//!
+//! Since a JMT commits to its key-value pairs regardless of the order in which
+//! they were written, the root hash does not depend on the order in which the
+//! substores are registered or committed.
+//!
 //! Remember that the chain state is a public API.  Mapping from raw byte values
 //! to typed data should be accomplished by means of extension traits.  For
 //! instance, the `penumbra_proto` crate provides an extension trait to
 //! automatically (de)serialize into proto or domain types, allowing its use as
 //! an object store.
 //!
+//! # Extension traits
+//!
+//! Components define their own extension traits, with typed, domain-specific
+//! accessors implemented on top of the raw methods of [`StateRead`] and
+//! [`StateWrite`]. Read and write accessors are split into two traits, with
+//! blanket implementations, so that they are available on every state type,
+//! and so that read-only code only requires read access:
+//!
+//! ```
+//! use anyhow::Result;
+//! use async_trait::async_trait;
+//! use cnidarium::{StateRead, StateWrite};
+//!
+//! #[async_trait]
+//! pub trait CounterReadExt: StateRead {
+//!     /// Returns the value of the counter, or zero if it was never set.
+//!     async fn get_counter(&self) -> Result<u64> {
+//!         let Some(bytes) = self.get_raw("counter").await? else {
+//!             return Ok(0);
+//!         };
+//!         let bytes = bytes
+//!             .try_into()
+//!             .map_err(|_| anyhow::anyhow!("counter is not a u64"))?;
+//!         Ok(u64::from_be_bytes(bytes))
+//!     }
+//! }
+//!
+//! impl<T: StateRead + ?Sized> CounterReadExt for T {}
+//!
+//! pub trait CounterWriteExt: StateWrite {
+//!     /// Sets the value of the counter.
+//!     fn set_counter(&mut self, value: u64) {
+//!         self.put_raw("counter".to_string(), value.to_be_bytes().to_vec());
+//!     }
+//! }
+//!
+//! impl<T: StateWrite + ?Sized> CounterWriteExt for T {}
+//! ```
+//!
+//! This crate follows the same pattern with [`StateReadExt`] and
+//! [`StateWriteExt`].
+//!
 //! With the `rpc` feature enabled, this crate also provides a GRPC interface to
 //! the key-value store using Tonic.
 #![deny(clippy::unwrap_used)]
@@ -59,7 +138,9 @@
 #![allow(clippy::disallowed_types)]
 
 mod cache;
+mod decoded;
 mod delta;
+mod error;
 mod escaped_byte_slice;
 mod metrics;
 mod read;
@@ -75,14 +156,21 @@ mod write_batch;
 
 #[cfg(feature = "metrics")]
 pub use crate::metrics::register_metrics;
-pub use cache::Cache;
-pub use delta::{ArcStateDeltaExt, StateDelta};
+pub use cache::{Cache, SpillOptions};
+pub use decoded::DecodedCache;
+pub use delta::{ArcStateDeltaExt, KeyLocation, OverlayOp, ReadTransaction, StateDelta};
+pub use error::StorageError;
 pub use escaped_byte_slice::EscapedByteSlice;
 pub use jmt::{ics23_spec, RootHash};
-pub use read::StateRead;
-pub use snapshot::Snapshot;
-pub use storage::{Storage, TempStorage};
-pub use write::StateWrite;
+pub use read::{ListStream, PrefixValuesStream, ScanOrder, StateRead, StateReadExt};
+pub use snapshot::{ArchiveState, JmtProof, Snapshot, SubstoreRootProof};
+pub use storage::{
+    ColumnFamilyInfo, ColumnFamilyRole, CommitTrace, Compression, DanglingReference,
+    IntegrityReport, NamespacedSnapshot, NamespacedStorage, ReadOnlyStorage, RetryPolicy,
+    RocksDbConfig, SecondaryStorage, ShadowStorage, Storage, StorageOptions, StorageStatus,
+    SubstoreStatus, SubstoreTrace, TempStorage,
+};
+pub use write::{StateWrite, StateWriteExt};
 pub use write_batch::StagedWriteBatch;
 
 pub mod future;
@@ -26,8 +26,56 @@ pub fn register_metrics() {
         Unit::Seconds,
         "The duration of a nonverifiable_get_raw request"
     );
+    describe_histogram!(
+        STORAGE_PREFIX_RAW_DURATION,
+        Unit::Seconds,
+        "The duration of a prefix_raw scan, until it completes or is dropped"
+    );
+    describe_counter!(
+        STORAGE_DELTA_CACHE_HITS,
+        Unit::Count,
+        "The number of get_raw requests served from the pending writes of a state delta"
+    );
+    describe_counter!(
+        STORAGE_DELTA_CACHE_MISSES,
+        Unit::Count,
+        "The number of get_raw requests a state delta forwarded to the underlying state"
+    );
+    describe_counter!(
+        STORAGE_VALUE_CACHE_HITS,
+        Unit::Count,
+        "The number of get_raw requests served from the value cache"
+    );
+    describe_counter!(
+        STORAGE_VALUE_CACHE_MISSES,
+        Unit::Count,
+        "The number of get_raw requests that missed the value cache"
+    );
+    describe_histogram!(
+        STORAGE_COMMIT_DURATION,
+        Unit::Seconds,
+        "The duration of a commit, from preparing its write batch to writing it"
+    );
+    describe_counter!(
+        STORAGE_COMMIT_BYTES_WRITTEN,
+        Unit::Bytes,
+        "The number of bytes written to the database by commits"
+    );
+    describe_counter!(
+        STORAGE_COMMIT_KEYS_WRITTEN,
+        Unit::Count,
+        "The number of verifiable and nonverifiable keys written or deleted by commits"
+    );
 }
 
 pub const STORAGE_GET_RAW_DURATION: &str = "cnidarium_get_raw_duration_seconds";
 pub const STORAGE_NONCONSENSUS_GET_RAW_DURATION: &str =
     "cnidarium_nonverifiable_get_raw_duration_seconds";
+pub const STORAGE_PREFIX_RAW_DURATION: &str = "cnidarium_prefix_raw_duration_seconds";
+pub const STORAGE_DELTA_CACHE_HITS: &str = "cnidarium_delta_cache_hits_total";
+pub const STORAGE_DELTA_CACHE_MISSES: &str = "cnidarium_delta_cache_misses_total";
+pub const STORAGE_VALUE_CACHE_HITS: &str = "cnidarium_value_cache_hits_total";
+pub const STORAGE_VALUE_CACHE_MISSES: &str = "cnidarium_value_cache_misses_total";
+pub const STORAGE_COMMIT_DURATION: &str = "cnidarium_commit_duration_seconds";
+pub const STORAGE_COMMIT_BYTES_WRITTEN: &str = "cnidarium_commit_bytes_written_total";
+pub const STORAGE_COMMIT_KEYS_WRITTEN: &str = "cnidarium_commit_keys_written_total";
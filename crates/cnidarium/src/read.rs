@@ -1,9 +1,43 @@
 use std::{any::Any, future::Future, ops::RangeBounds, sync::Arc};
 
 use anyhow::Result;
-use futures::Stream;
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+
+/// The order in which keys are returned by a scan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScanOrder {
+    /// Lexicographically ascending key order.
+    #[default]
+    Ascending,
+    /// Lexicographically descending key order.
+    Descending,
+}
+
+impl ScanOrder {
+    /// Returns `true` if `a` is returned before `b` (or is equal to it) when
+    /// scanning in this order.
+    pub(crate) fn is_before_or_eq<K: Ord + ?Sized>(&self, a: &K, b: &K) -> bool {
+        match self {
+            ScanOrder::Ascending => a <= b,
+            ScanOrder::Descending => a >= b,
+        }
+    }
+}
 
 /// Read access to chain state.
+///
+/// # Cancellation safety
+///
+/// All read methods are cancellation safe: the futures and streams they return
+/// can be dropped at any point, e.g. when a `tokio::select!` branch loses a
+/// race against a timeout, without affecting the state they were created from.
+///
+/// Reads never mutate the state. Lookups in a [`StateDelta`](crate::StateDelta)'s
+/// cache happen eagerly, when the method is called, and reads that miss the
+/// cache are served by blocking tasks that own all the RocksDB resources they
+/// use. Dropping the future or stream detaches the task, which then releases
+/// these resources as soon as it completes.
 pub trait StateRead: Send + Sync {
     type GetRawFut: Future<Output = Result<Option<Vec<u8>>>> + Send + 'static;
     type PrefixRawStream: Stream<Item = Result<(String, Vec<u8>)>> + Send + 'static;
@@ -44,12 +78,66 @@ pub trait StateRead: Send + Sync {
     /// is present.
     fn object_type(&self, key: &'static str) -> Option<std::any::TypeId>;
 
+    /// Returns the cache in which typed reads keep the values they decode from
+    /// the verifiable key-value store, if this state has one.
+    ///
+    /// Only a [`StateDelta`](crate::StateDelta) has a cache, which lasts as long
+    /// as the delta. See [`DecodedCache`](crate::DecodedCache).
+    fn decoded_cache(&self) -> Option<crate::DecodedCache> {
+        None
+    }
+
+    /// Returns the prefixes of the substores that verifiable keys are routed
+    /// to, apart from the main store.
+    ///
+    /// [`StateReadExt::range_raw`] uses them to scan every store that a range
+    /// spans. Only states backed by a [`Storage`](crate::Storage) route keys
+    /// to substores; other states report none.
+    fn substore_prefixes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
     /// Retrieve all values for keys matching a prefix from the verifiable key-value store, as raw bytes.
     ///
+    /// Keys are returned in ascending order, and each key is returned at most
+    /// once. When a [`StateDelta`](crate::StateDelta) is layered over the
+    /// underlying storage, the most recent write to a key takes precedence:
+    /// a key written in the delta is returned with the delta's value, even if
+    /// it is also present in storage, and a key deleted in the delta is not
+    /// returned at all.
+    ///
     /// Users should generally prefer to use `prefix` or `prefix_proto` from an extension trait.
     fn prefix_raw(&self, prefix: &str) -> Self::PrefixRawStream;
 
+    /// Retrieve all values for keys matching a prefix from the verifiable key-value store, as raw bytes,
+    /// in the supplied key order.
+    ///
+    /// This is useful when the scan direction is chosen dynamically; [`StateRead::prefix_raw`] is
+    /// equivalent to scanning in [`ScanOrder::Ascending`] order.
+    ///
+    /// The default implementation only supports [`ScanOrder::Ascending`], which it serves with
+    /// [`StateRead::prefix_raw`], so that implementors written before this method was added keep
+    /// compiling. Implementors that can scan in descending order should override it.
+    ///
+    /// # Panics
+    ///
+    /// With the default implementation, if `order` is [`ScanOrder::Descending`].
+    fn prefix_raw_ordered(&self, prefix: &str, order: ScanOrder) -> Self::PrefixRawStream {
+        match order {
+            ScanOrder::Ascending => self.prefix_raw(prefix),
+            ScanOrder::Descending => {
+                unimplemented!("this state does not support descending prefix scans")
+            }
+        }
+    }
+
     /// Retrieve all keys (but not values) matching a prefix from the verifiable key-value store.
+    ///
+    /// Keys are returned in ascending order, and cached writes and deletions
+    /// are merged as with [`StateRead::prefix_raw`]. Values are never read: a
+    /// [`Snapshot`](crate::Snapshot) only iterates over the index of key
+    /// preimages, which maps each key to its 32-byte hash, so the cost of the
+    /// scan does not depend on the size of the values.
     fn prefix_keys(&self, prefix: &str) -> Self::PrefixKeysStream;
 
     /// Retrieve all values for keys matching a prefix from the non-verifiable key-value store, as raw bytes.
@@ -83,6 +171,10 @@ impl<'a, S: StateRead + Send + Sync> StateRead for &'a S {
         (**self).prefix_raw(prefix)
     }
 
+    fn prefix_raw_ordered(&self, prefix: &str, order: ScanOrder) -> S::PrefixRawStream {
+        (**self).prefix_raw_ordered(prefix, order)
+    }
+
     fn prefix_keys(&self, prefix: &str) -> S::PrefixKeysStream {
         (**self).prefix_keys(prefix)
     }
@@ -110,6 +202,14 @@ impl<'a, S: StateRead + Send + Sync> StateRead for &'a S {
     fn object_type(&self, key: &'static str) -> Option<std::any::TypeId> {
         (**self).object_type(key)
     }
+
+    fn decoded_cache(&self) -> Option<crate::DecodedCache> {
+        (**self).decoded_cache()
+    }
+
+    fn substore_prefixes(&self) -> Vec<String> {
+        (**self).substore_prefixes()
+    }
 }
 
 impl<'a, S: StateRead + Send + Sync> StateRead for &'a mut S {
@@ -127,6 +227,10 @@ impl<'a, S: StateRead + Send + Sync> StateRead for &'a mut S {
         (**self).prefix_raw(prefix)
     }
 
+    fn prefix_raw_ordered(&self, prefix: &str, order: ScanOrder) -> S::PrefixRawStream {
+        (**self).prefix_raw_ordered(prefix, order)
+    }
+
     fn prefix_keys(&self, prefix: &str) -> S::PrefixKeysStream {
         (**self).prefix_keys(prefix)
     }
@@ -154,6 +258,14 @@ impl<'a, S: StateRead + Send + Sync> StateRead for &'a mut S {
     fn object_type(&self, key: &'static str) -> Option<std::any::TypeId> {
         (**self).object_type(key)
     }
+
+    fn decoded_cache(&self) -> Option<crate::DecodedCache> {
+        (**self).decoded_cache()
+    }
+
+    fn substore_prefixes(&self) -> Vec<String> {
+        (**self).substore_prefixes()
+    }
 }
 
 impl<S: StateRead + Send + Sync> StateRead for Arc<S> {
@@ -171,6 +283,10 @@ impl<S: StateRead + Send + Sync> StateRead for Arc<S> {
         (**self).prefix_raw(prefix)
     }
 
+    fn prefix_raw_ordered(&self, prefix: &str, order: ScanOrder) -> S::PrefixRawStream {
+        (**self).prefix_raw_ordered(prefix, order)
+    }
+
     fn prefix_keys(&self, prefix: &str) -> S::PrefixKeysStream {
         (**self).prefix_keys(prefix)
     }
@@ -198,6 +314,14 @@ impl<S: StateRead + Send + Sync> StateRead for Arc<S> {
     fn object_type(&self, key: &'static str) -> Option<std::any::TypeId> {
         (**self).object_type(key)
     }
+
+    fn decoded_cache(&self) -> Option<crate::DecodedCache> {
+        (**self).decoded_cache()
+    }
+
+    fn substore_prefixes(&self) -> Vec<String> {
+        (**self).substore_prefixes()
+    }
 }
 
 impl StateRead for () {
@@ -229,6 +353,10 @@ impl StateRead for () {
         futures::stream::iter(std::iter::empty())
     }
 
+    fn prefix_raw_ordered(&self, _prefix: &str, _order: ScanOrder) -> Self::PrefixRawStream {
+        futures::stream::iter(std::iter::empty())
+    }
+
     fn prefix_keys(&self, _prefix: &str) -> Self::PrefixKeysStream {
         futures::stream::iter(std::iter::empty())
     }
@@ -245,3 +373,404 @@ impl StateRead for () {
         Ok(futures::stream::iter(std::iter::empty()))
     }
 }
+
+/// Extension trait providing higher-level read helpers on top of [`StateRead`].
+#[async_trait]
+pub trait StateReadExt: StateRead {
+    /// Retrieve all values for keys matching a prefix from the verifiable
+    /// key-value store, as raw bytes, in descending key order.
+    ///
+    /// This is [`StateRead::prefix_raw_ordered`] with [`ScanOrder::Descending`]:
+    /// cached writes and deletions are merged into the scan as they are for
+    /// [`StateRead::prefix_raw`], which returns the same entries in reverse.
+    /// It panics if the state keeps the default `prefix_raw_ordered`, which
+    /// only scans in ascending order.
+    fn prefix_raw_rev(&self, prefix: &str) -> Self::PrefixRawStream {
+        self.prefix_raw_ordered(prefix, ScanOrder::Descending)
+    }
+
+    /// Retrieve the values of all keys matching a prefix from the verifiable
+    /// key-value store, as raw bytes, in key order.
+    ///
+    /// This yields the same entries as [`StateRead::prefix_raw`], cached writes
+    /// and deletions included, without their keys.
+    fn prefix_values(&self, prefix: &str) -> PrefixValuesStream<Self::PrefixRawStream> {
+        self.prefix_raw(prefix).map(entry_value as fn(_) -> _)
+    }
+
+    /// Scans the verifiable key-value store for keys matching `prefix`, in key
+    /// order, and returns the first entry for which `pred` returns `true`.
+    ///
+    /// Cached writes are merged into the scan, so a value written in a
+    /// [`StateDelta`](crate::StateDelta) can be the first match, and a cached
+    /// deletion hides the underlying entry. The scan stops at the first match:
+    /// the underlying stream is dropped, which halts the storage iterator.
+    async fn find_prefix<P>(&self, prefix: &str, pred: P) -> Result<Option<(String, Vec<u8>)>>
+    where
+        P: Fn(&str, &[u8]) -> bool + Send + Sync,
+    {
+        let mut stream = std::pin::pin!(self.prefix_raw(prefix));
+        while let Some(entry) = stream.next().await {
+            let (key, value) = entry?;
+            if pred(&key, &value) {
+                return Ok(Some((key, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns whether no key of the verifiable key-value store matches
+    /// `prefix`.
+    ///
+    /// Cached writes and deletions are taken into account, as with
+    /// [`StateRead::prefix_keys`]. The scan stops at the first key found.
+    async fn prefix_is_empty(&self, prefix: &str) -> Result<bool> {
+        let mut keys = std::pin::pin!(self.prefix_keys(prefix));
+        Ok(keys.next().await.transpose()?.is_none())
+    }
+
+    /// Returns the number of keys of the verifiable key-value store that match
+    /// `prefix`.
+    ///
+    /// The keys are scanned with [`StateRead::prefix_keys`], so no value is
+    /// read. Cached writes of new keys add to the count, and cached deletions
+    /// of existing keys subtract from it.
+    async fn count_prefix(&self, prefix: &str) -> Result<u64> {
+        let mut keys = std::pin::pin!(self.prefix_keys(prefix));
+        let mut count = 0;
+        while let Some(key) = keys.next().await {
+            key?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Gets the values of several keys from the verifiable key-value store, as
+    /// of a single, consistent view of the state.
+    ///
+    /// All reads are issued before any of them is awaited: cached writes are
+    /// looked up at once, and the remaining reads are served by the underlying
+    /// [`Snapshot`](crate::Snapshot), which is pinned to a single version. Since
+    /// writing to a [`StateDelta`](crate::StateDelta) requires exclusive access,
+    /// no write can be interleaved with the reads, so the values returned are
+    /// always consistent with each other.
+    async fn snapshot_get(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        let reads: Vec<_> = keys.iter().map(|key| self.get_raw(key)).collect();
+        futures::future::try_join_all(reads).await
+    }
+
+    /// Gets the first entries of several prefixes of the verifiable key-value
+    /// store in one call, returning up to `n` entries, in key order, for each
+    /// requested `(prefix, n)`.
+    ///
+    /// The scans run concurrently, and each is dropped as soon as it has
+    /// yielded `n` entries. Cached writes are merged into every scan, as with
+    /// [`StateRead::prefix_raw`]. If a prefix is requested more than once, the
+    /// largest `n` applies.
+    async fn multi_prefix_head(
+        &self,
+        requests: &[(String, usize)],
+    ) -> Result<std::collections::BTreeMap<String, Vec<(String, Vec<u8>)>>> {
+        use futures::TryStreamExt;
+
+        let mut limits = std::collections::BTreeMap::new();
+        for (prefix, n) in requests {
+            let limit = limits.entry(prefix.as_str()).or_insert(0);
+            *limit = (*limit).max(*n);
+        }
+
+        let scans = limits.into_iter().map(|(prefix, n)| async move {
+            let head: Vec<_> = self.prefix_raw(prefix).take(n).try_collect().await?;
+            anyhow::Ok((prefix.to_string(), head))
+        });
+        Ok(futures::future::try_join_all(scans)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    /// Gets an object written by
+    /// [`StateWriteExt::object_put_persistent`](crate::StateWriteExt::object_put_persistent).
+    ///
+    /// The in-memory copy in the ephemeral object store is returned if there
+    /// is one. Otherwise, e.g. once the object has been committed, it is
+    /// decoded from the non-verifiable store.
+    async fn object_get_persistent<T>(&self, key: &'static str) -> Result<Option<T>>
+    where
+        T: borsh::BorshDeserialize + Clone + Any + Send + Sync,
+    {
+        if let Some(value) = self.object_get(key) {
+            return Ok(Some(value));
+        }
+        self.nonverifiable_get_raw(key.as_bytes())
+            .await?
+            .map(|bytes| T::try_from_slice(&bytes).map_err(Into::into))
+            .transpose()
+    }
+
+    /// Returns the number of elements in the list stored under `list_key`, as
+    /// written by [`StateWriteExt::list_append`](crate::StateWriteExt::list_append).
+    async fn list_len(&self, list_key: &str) -> Result<u64> {
+        let Some(bytes) = self.get_raw(list_key).await? else {
+            return Ok(0);
+        };
+        let bytes: [u8; 8] = bytes.as_slice().try_into().map_err(|_| {
+            anyhow::anyhow!(
+                "length of list {list_key} is not a u64 (found {} bytes)",
+                bytes.len()
+            )
+        })?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Streams the `(index, value)` pairs of the list stored under `list_key`,
+    /// in index order, as written by
+    /// [`StateWriteExt::list_append`](crate::StateWriteExt::list_append).
+    fn list_iter(&self, list_key: &str) -> ListStream<Self::PrefixRawStream> {
+        self.prefix_raw(&format!("{list_key}/"))
+            .map(parse_list_entry as fn(_) -> _)
+    }
+
+    /// Streams the `(index, value)` pairs stored in the nonverifiable store
+    /// under `prefix` whose index lies in `range`, in index order, as written
+    /// by [`StateWriteExt::nonverifiable_put_by_index`](crate::StateWriteExt::nonverifiable_put_by_index).
+    ///
+    /// Each entry is stored under `prefix` followed by its index as a
+    /// big-endian `u64`, so that key order matches numeric order. Indexed
+    /// entries live in the nonverifiable store, since verifiable keys must be
+    /// UTF-8 strings.
+    ///
+    /// # Errors
+    /// This has the restrictions of [`StateRead::nonverifiable_range_raw`]: it
+    /// only supports the main store, and the start of `range` must not be
+    /// greater than its end. Entries under `prefix` that are not followed by
+    /// exactly eight bytes yield an error.
+    fn nonverifiable_range_by_index(
+        &self,
+        prefix: &str,
+        range: impl RangeBounds<u64>,
+    ) -> Result<futures::stream::BoxStream<'static, Result<(u64, Vec<u8>)>>> {
+        use std::ops::Bound;
+
+        // Excluding `u64::MAX` at the start leaves an empty range.
+        let (start, empty) = match range.start_bound() {
+            Bound::Included(&index) => (index, false),
+            Bound::Excluded(&index) => index
+                .checked_add(1)
+                .map_or((u64::MAX, true), |i| (i, false)),
+            Bound::Unbounded => (0, false),
+        };
+        let end = match range.end_bound() {
+            _ if empty => Bound::Excluded(u64::MAX),
+            Bound::Included(&index) => index
+                .checked_add(1)
+                .map_or(Bound::Unbounded, Bound::Excluded),
+            Bound::Excluded(&index) => Bound::Excluded(index),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let encode = |index: u64| index.to_be_bytes().to_vec();
+        let raw_range = (Bound::Included(encode(start)), end.map(encode));
+
+        let prefix_len = prefix.len();
+        Ok(self
+            .nonverifiable_range_raw(Some(prefix.as_bytes()), raw_range)?
+            .map(move |entry| {
+                let (key, value) = entry?;
+                let index: [u8; 8] = key
+                    .get(prefix_len..)
+                    .and_then(|index| index.try_into().ok())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("{:?} is not an indexed key", crate::EscapedByteSlice(&key))
+                    })?;
+                Ok((u64::from_be_bytes(index), value))
+            })
+            .boxed())
+    }
+
+    /// Streams the key-value pairs of the verifiable key-value store whose key
+    /// lies between `start` and `end`, in ascending key order, as raw bytes.
+    ///
+    /// Keys are compared as bytes, and each bound can be inclusive, exclusive
+    /// or unbounded. The range is served by [`StateRead::prefix_raw`] scans of
+    /// the longest prefix shared by both bounds, so cached writes and deletions
+    /// are merged into it in the same way. An empty range, e.g. one whose
+    /// start is greater than its end, yields an empty stream.
+    ///
+    /// A range can span several stores, e.g. with an unbounded end, or bounds
+    /// like `ib` and `id`. Each store that holds keys in the range, among the
+    /// main store and those reported by [`StateRead::substore_prefixes`], is
+    /// scanned separately, and the scans are merged. Every scan reads from the
+    /// start of the shared prefix in its store, skipping the keys before
+    /// `start`.
+    fn range_raw(
+        &self,
+        start: std::ops::Bound<Vec<u8>>,
+        end: std::ops::Bound<Vec<u8>>,
+    ) -> futures::stream::BoxStream<'static, Result<(Vec<u8>, Vec<u8>)>> {
+        use futures::TryStreamExt;
+        use std::ops::Bound;
+
+        let empty = match (&start, &end) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start >= end,
+            _ => false,
+        };
+        if empty {
+            return futures::stream::empty().boxed();
+        }
+
+        let prefix = match (&start, &end) {
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => {
+                let len = start.iter().zip(end).take_while(|(a, b)| a == b).count();
+                &start[..len]
+            }
+            _ => &[][..],
+        };
+        // Verifiable keys are UTF-8 strings, so the prefix can be cut back to
+        // the last character boundary.
+        let prefix = match std::str::from_utf8(prefix) {
+            Ok(prefix) => prefix,
+            Err(e) => std::str::from_utf8(&prefix[..e.valid_up_to()])
+                .expect("prefix is valid up to this point"),
+        };
+
+        // Keys are routed to the substore with the longest prefix that they
+        // start with, followed by the delimiter, and to the main store if none
+        // matches. A prefix equal to a substore's prefix is routed to it too.
+        let substores = self.substore_prefixes();
+        let route = |prefix: &str| {
+            substores
+                .iter()
+                .filter(|substore| {
+                    prefix == substore.as_str()
+                        || prefix
+                            .strip_prefix(substore.as_str())
+                            .is_some_and(|rest| rest.starts_with('/'))
+                })
+                .max_by_key(|substore| substore.len())
+        };
+        // The store holding the keys under `prefix` that no substore nested in
+        // it claims, and a prefix routed to it that covers them.
+        let home = substores
+            .iter()
+            .filter(|substore| {
+                prefix
+                    .strip_prefix(substore.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .max_by_key(|substore| substore.len());
+        let mut home_prefix = prefix;
+        while route(home_prefix) != home {
+            let mut chars = home_prefix.chars();
+            chars.next_back();
+            home_prefix = chars.as_str();
+        }
+        let mut scans = vec![self.prefix_raw(home_prefix).boxed().peekable()];
+
+        // Then, the substores nested under `prefix` whose keys intersect the range.
+        for substore in &substores {
+            let key_prefix = format!("{substore}/");
+            if Some(substore) == home || !key_prefix.starts_with(prefix) {
+                continue;
+            }
+            // The main store holds the substore's root hash under its prefix,
+            // which the scan of `home_prefix` only covers if that is routed to
+            // the main store too.
+            if home.is_some() {
+                let root_key = substore.clone();
+                let root =
+                    futures::stream::once(self.get_raw(substore)).try_filter_map(move |value| {
+                        futures::future::ready(Ok(value.map(|value| (root_key.clone(), value))))
+                    });
+                scans.push(root.boxed().peekable());
+            }
+            let key_prefix = key_prefix.into_bytes();
+            let after_start = match &start {
+                Bound::Included(start) | Bound::Excluded(start) => {
+                    start.starts_with(&key_prefix) || *start < key_prefix
+                }
+                Bound::Unbounded => true,
+            };
+            let before_end = match &end {
+                Bound::Included(end) => *end >= key_prefix,
+                Bound::Excluded(end) => *end > key_prefix,
+                Bound::Unbounded => true,
+            };
+            if after_start && before_end {
+                let key_prefix = std::str::from_utf8(&key_prefix).expect("prefixes are UTF-8");
+                scans.push(self.prefix_raw(key_prefix).boxed().peekable());
+            }
+        }
+
+        // The scans of a delta all include its cached writes under their
+        // prefix, so a key can be yielded by several of them.
+        let mut last_key: Option<String> = None;
+        crate::future::MergedPrefixRawStream { streams: scans }
+            .try_filter(move |(key, _)| {
+                let repeated = last_key.as_ref() == Some(key);
+                if !repeated {
+                    last_key = Some(key.clone());
+                }
+                futures::future::ready(!repeated)
+            })
+            .map_ok(|(key, value)| (key.into_bytes(), value))
+            .try_skip_while(move |(key, _)| {
+                futures::future::ready(Ok(match &start {
+                    Bound::Included(start) => key < start,
+                    Bound::Excluded(start) => key <= start,
+                    Bound::Unbounded => false,
+                }))
+            })
+            .try_take_while(move |(key, _)| {
+                futures::future::ready(Ok(match &end {
+                    Bound::Included(end) => key <= end,
+                    Bound::Excluded(end) => key < end,
+                    Bound::Unbounded => true,
+                }))
+            })
+            .boxed()
+    }
+}
+
+impl<T: StateRead + ?Sized> StateReadExt for T {}
+
+/// The stream returned by [`StateReadExt::list_iter`].
+pub type ListStream<S> =
+    futures::stream::Map<S, fn(Result<(String, Vec<u8>)>) -> Result<(u64, Vec<u8>)>>;
+
+/// The stream returned by [`StateReadExt::prefix_values`].
+pub type PrefixValuesStream<S> =
+    futures::stream::Map<S, fn(Result<(String, Vec<u8>)>) -> Result<Vec<u8>>>;
+
+/// Returns the key of the element at `index` in the list stored under `list_key`.
+pub(crate) fn list_element_key(list_key: &str, index: u64) -> String {
+    format!("{list_key}/{index:020}")
+}
+
+/// Returns the nonverifiable key of the entry at `index` under `prefix`.
+pub(crate) fn index_key(prefix: &str, index: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(prefix.len() + 8);
+    key.extend_from_slice(prefix.as_bytes());
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+fn entry_value(entry: Result<(String, Vec<u8>)>) -> Result<Vec<u8>> {
+    entry.map(|(_, value)| value)
+}
+
+fn parse_list_entry(entry: Result<(String, Vec<u8>)>) -> Result<(u64, Vec<u8>)> {
+    let (key, value) = entry?;
+    let index = key
+        .rsplit('/')
+        .next()
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("{key} is not a list element key"))?;
+    Ok((index, value))
+}
@@ -213,7 +213,7 @@ async fn watch_changes(
         let (version, changes) = changes_rx.borrow_and_update().clone();
 
         if key_regex.is_some() || nv_key_regex.is_none() {
-            for (key, value) in changes.unwritten_changes().iter() {
+            for (key, value) in changes.unwritten_changes()?.iter() {
                 if key_regex
                     .as_ref()
                     .unwrap_or(&Regex::new(r"").expect("empty regex ok"))
@@ -1,8 +1,9 @@
 use std::iter;
-use std::{any::Any, sync::Arc};
+use std::{any::Any, collections::BTreeMap, sync::Arc};
 
 use anyhow::Result;
 use async_trait::async_trait;
+use futures::{Stream, StreamExt};
 use ibc_types::core::commitment::MerkleProof;
 use tokio::sync::mpsc;
 use tracing::Span;
@@ -10,10 +11,18 @@ use tracing::Span;
 #[cfg(feature = "metrics")]
 use crate::metrics;
 use crate::store::multistore::{self, MultistoreCache};
-use crate::{store, StateRead};
+use crate::store::node_cache::NodeCache;
+use crate::{store, ScanOrder, StateRead, StorageError};
 
+mod archive;
+mod iterator_limit;
+mod jmt_proof;
 mod rocks_wrapper;
 
+pub use archive::ArchiveState;
+pub(crate) use archive::VersionPins;
+pub(crate) use iterator_limit::IteratorLimit;
+pub use jmt_proof::{JmtProof, SubstoreRootProof};
 pub(crate) use rocks_wrapper::RocksDbSnapshot;
 
 /// A snapshot of the underlying storage at a specific state version, suitable
@@ -22,6 +31,20 @@ pub(crate) use rocks_wrapper::RocksDbSnapshot;
 /// Snapshots are cheap to create and clone.  Internally, they're implemented as
 /// a wrapper around a [RocksDB snapshot](https://github.com/facebook/rocksdb/wiki/Snapshot)
 /// with a pinned JMT version number for the snapshot.
+///
+/// # Concurrency with commits
+///
+/// A snapshot is unaffected by commits, including those in progress while it
+/// is being read: every read returns the state as of [`Snapshot::version`],
+/// and the version itself never changes. This holds because:
+///
+/// - the RocksDB snapshot is taken when the `Snapshot` is created, and all
+///   reads, including iterators, go through it, so writes made afterwards,
+///   such as overwritten key preimages, are invisible to it;
+/// - a commit is written to RocksDB as a single atomic write batch, so a
+///   RocksDB snapshot can never observe part of a commit;
+/// - the snapshot of a new version is only published once its write batch
+///   has been applied.
 #[derive(Clone)]
 pub struct Snapshot(pub(crate) Arc<Inner>);
 
@@ -36,6 +59,9 @@ pub(crate) struct Inner {
     pub(crate) version: jmt::Version,
     // Used to retrieve column family handles.
     pub(crate) db: Arc<rocksdb::DB>,
+    /// Whether the RocksDB snapshot was taken after `version` was committed,
+    /// in which case only the versioned values of the trees can be read.
+    pub(crate) historical: bool,
 }
 
 impl Snapshot {
@@ -50,6 +76,46 @@ impl Snapshot {
             version,
             db,
             multistore_cache,
+            historical: false,
+        }))
+    }
+
+    /// Creates a `Snapshot` like [`Snapshot::new`], that reads the current state
+    /// of the database rather than pinning it with a RocksDB snapshot.
+    ///
+    /// This is used by secondary instances, whose state only changes when they
+    /// catch up with their primary, and which do not support RocksDB snapshots.
+    pub(crate) fn unpinned(
+        db: Arc<rocksdb::DB>,
+        version: jmt::Version,
+        multistore_cache: multistore::MultistoreCache,
+    ) -> Self {
+        Self(Arc::new(Inner {
+            snapshot: Arc::new(RocksDbSnapshot::unpinned(db.clone())),
+            version,
+            db,
+            multistore_cache,
+            historical: false,
+        }))
+    }
+
+    /// Creates a `Snapshot` of a past `version` on top of the current state of
+    /// the database.
+    ///
+    /// Reads that are not versioned, namely those going through the key
+    /// preimage index or the nonverifiable store, would observe later writes,
+    /// so they fail with [`StorageError::UnversionedRead`].
+    pub(crate) fn historical(
+        db: Arc<rocksdb::DB>,
+        version: jmt::Version,
+        multistore_cache: multistore::MultistoreCache,
+    ) -> Self {
+        Self(Arc::new(Inner {
+            snapshot: Arc::new(RocksDbSnapshot::new(db.clone())),
+            version,
+            db,
+            multistore_cache,
+            historical: true,
         }))
     }
 
@@ -60,6 +126,11 @@ impl Snapshot {
     /// Returns some value corresponding to the key, along with an ICS23 existence proof
     /// up to the current JMT root hash. If the key is not present, returns `None` and a
     /// non-existence proof.
+    ///
+    /// For a substore configured with
+    /// [`StorageOptions::hash_values`](crate::StorageOptions::hash_values), the
+    /// value returned is the SHA-256 hash of the value, which is what the tree
+    /// holds and the proof covers.
     pub async fn get_with_proof(&self, key: Vec<u8>) -> Result<(Option<Vec<u8>>, MerkleProof)> {
         if key.is_empty() {
             anyhow::bail!("empty keys are not allowed")
@@ -74,6 +145,7 @@ impl Snapshot {
         let substore_key_bytes = substore_key.to_vec();
         let substore_version = self.substore_version(&substore_config).unwrap_or(u64::MAX);
         let key_to_substore_root = substore_config.prefix.clone();
+        let node_cache = self.0.multistore_cache.config.node_cache.clone();
 
         let substore = store::substore::SubstoreSnapshot {
             config: substore_config,
@@ -84,7 +156,10 @@ impl Snapshot {
 
         let (substore_value, substore_commitment_proof) = tokio::task::spawn_blocking({
             let span = span.clone();
-            move || span.in_scope(|| substore.get_with_proof(substore_key_bytes))
+            let node_cache = node_cache.clone();
+            move || {
+                span.in_scope(|| substore.get_with_proof(substore_key_bytes, node_cache.as_deref()))
+            }
         })
         .await??;
 
@@ -105,7 +180,11 @@ impl Snapshot {
 
             let (_, main_commitment_proof) = tokio::task::spawn_blocking({
                 let span = span.clone();
-                move || span.in_scope(|| mainstore.get_with_proof(key_to_substore_root.into()))
+                move || {
+                    span.in_scope(|| {
+                        mainstore.get_with_proof(key_to_substore_root.into(), node_cache.as_deref())
+                    })
+                }
             })
             .await??;
 
@@ -120,6 +199,86 @@ impl Snapshot {
         ))
     }
 
+    /// Returns the values of several keys, each along with an ICS23 proof as
+    /// described in [`Snapshot::get_with_proof`], in input order.
+    ///
+    /// All proofs are generated by a single blocking task, which reads tree
+    /// nodes through a shared cache, so the ancestors shared by the keys'
+    /// paths are read and decoded only once. The proof from the main root to
+    /// a substore's root is generated once per substore.
+    pub async fn multi_get_with_proof(
+        &self,
+        keys: &[Vec<u8>],
+    ) -> Result<Vec<(Option<Vec<u8>>, MerkleProof)>> {
+        if keys.iter().any(|key| key.is_empty()) {
+            anyhow::bail!("empty keys are not allowed")
+        }
+
+        let span = tracing::Span::current();
+        let config = &self.0.multistore_cache.config;
+        let substore_snapshot = |substore_config: Arc<store::substore::SubstoreConfig>| {
+            let version = self.substore_version(&substore_config).unwrap_or(u64::MAX);
+            store::substore::SubstoreSnapshot {
+                config: substore_config,
+                rocksdb_snapshot: self.0.snapshot.clone(),
+                version,
+                db: self.0.db.clone(),
+            }
+        };
+
+        let mut substores = BTreeMap::new();
+        let mut routed_keys = Vec::with_capacity(keys.len());
+        for key in keys {
+            let (substore_key, substore_config) = config.route_key_bytes(key);
+            let prefix = substore_config.prefix.clone();
+            substores
+                .entry(prefix.clone())
+                .or_insert_with(|| substore_snapshot(substore_config));
+            routed_keys.push((prefix, substore_key.to_vec()));
+        }
+        let mainstore = substore_snapshot(config.main_store.clone());
+
+        // Without a storage-wide cache, a cache local to this batch is used.
+        let node_cache = config
+            .node_cache
+            .clone()
+            .unwrap_or_else(|| Arc::new(NodeCache::new(usize::MAX)));
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let mut root_proofs: BTreeMap<String, ics23::CommitmentProof> = BTreeMap::new();
+                routed_keys
+                    .into_iter()
+                    .map(|(prefix, substore_key)| {
+                        let substore = substores
+                            .get(&prefix)
+                            .expect("every routed key has a substore snapshot");
+                        let (value, proof) =
+                            substore.get_with_proof(substore_key, Some(&node_cache))?;
+                        let mut proofs = vec![proof];
+
+                        // For keys in a substore, also prove the substore root.
+                        if !prefix.is_empty() {
+                            let root_proof = match root_proofs.get(&prefix) {
+                                Some(root_proof) => root_proof.clone(),
+                                None => {
+                                    let (_, root_proof) = mainstore
+                                        .get_with_proof(prefix.clone().into(), Some(&node_cache))?;
+                                    root_proofs.insert(prefix, root_proof.clone());
+                                    root_proof
+                                }
+                            };
+                            proofs.push(root_proof);
+                        }
+
+                        Ok((value, MerkleProof { proofs }))
+                    })
+                    .collect()
+            })
+        })
+        .await?
+    }
+
     pub fn prefix_version(&self, prefix: &str) -> Result<Option<jmt::Version>> {
         let Some(config) = self
             .0
@@ -172,16 +331,529 @@ impl Snapshot {
         tokio::task::spawn_blocking(move || span.in_scope(|| substore.root_hash())).await?
     }
 
+    /// Returns the root hash of the chain state at this snapshot's version.
+    ///
+    /// This is the root of the main tree, which commits to every substore
+    /// through the root hash it holds under the substore's prefix. See the
+    /// crate documentation for the details.
     pub async fn root_hash(&self) -> Result<crate::RootHash> {
         self.prefix_root_hash("").await
     }
 
+    /// Returns the root hash of every registered substore at this snapshot's
+    /// version, keyed by prefix. The main store is not included.
+    pub async fn substore_roots(&self) -> Result<BTreeMap<String, crate::RootHash>> {
+        let mut roots = BTreeMap::new();
+        for config in self.0.multistore_cache.config.iter() {
+            let root_hash = self.prefix_root_hash(&config.prefix).await?;
+            roots.insert(config.prefix.clone(), root_hash);
+        }
+        Ok(roots)
+    }
+
+    /// Checks that the root of every substore, recomputed at the substore's
+    /// version, matches the root recorded for it in the main store.
+    pub(crate) async fn verify_substore_roots(&self) -> Result<()> {
+        for config in self.0.multistore_cache.config.iter() {
+            let recorded = self.get_raw(&config.prefix).await?;
+            // A substore that was never written to has no recorded root.
+            let recomputed = if self.substore_version(config) == Some(u64::MAX) {
+                None
+            } else {
+                Some(self.prefix_root_hash(&config.prefix).await?.0.to_vec())
+            };
+            anyhow::ensure!(
+                recorded == recomputed,
+                "root of substore {} does not match the root recorded in the main store (recorded: {:?}, recomputed: {:?})",
+                config.prefix,
+                recorded.as_deref().map(hex::encode),
+                recomputed.as_deref().map(hex::encode),
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the prefix of the substore that `key` is routed to, along with
+    /// the root hash of that substore at this snapshot's version.
+    ///
+    /// This is the root that the substore proof for `key` (the first proof
+    /// returned by [`Snapshot::get_with_proof`]) verifies against. Keys that
+    /// are routed to the main store return an empty prefix and the main root.
+    pub async fn substore_root_for_key(&self, key: &str) -> Result<(String, crate::RootHash)> {
+        let (_, config) = self.0.multistore_cache.config.route_key_str(key);
+        let root_hash = self.prefix_root_hash(&config.prefix).await?;
+        Ok((config.prefix.clone(), root_hash))
+    }
+
+    /// Like [`StateRead::prefix_raw`], but only scans registered substores.
+    ///
+    /// `prefix` must either be the prefix of a registered substore, or start
+    /// with it and its delimiter, e.g. `ibc/clients/` for the `ibc` substore.
+    /// This guards against a mistyped prefix silently scanning the main store.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError::UnknownSubstore`] if `prefix` doesn't designate
+    /// a registered substore.
+    pub fn prefix_raw_strict(&self, prefix: &str) -> Result<<Self as StateRead>::PrefixRawStream> {
+        self.check_substore_prefix(prefix)?;
+        Ok(self.prefix_raw(prefix))
+    }
+
+    /// Checks that `prefix` designates a registered substore, as described in
+    /// [`Snapshot::prefix_raw_strict`].
+    pub(crate) fn check_substore_prefix(&self, prefix: &str) -> Result<()> {
+        match self.0.multistore_cache.config.find_substore_strict(prefix) {
+            Some(_) => Ok(()),
+            None => Err(StorageError::UnknownSubstore {
+                prefix: prefix.to_string(),
+            }
+            .into()),
+        }
+    }
+
+    /// Streams the entries of the substore registered with `substore_prefix`
+    /// whose keys, relative to the substore, start with `inner_prefix`. Keys
+    /// are yielded relative to the substore, in key order.
+    ///
+    /// Unlike [`StateRead::prefix_raw`], the substore is named explicitly
+    /// rather than routed from the prefix, and only its column families are
+    /// iterated.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError::UnknownSubstore`] if `substore_prefix` is not
+    /// a registered substore.
+    pub fn substore_prefix_raw(
+        &self,
+        substore_prefix: &str,
+        inner_prefix: &str,
+    ) -> Result<<Self as StateRead>::PrefixRawStream> {
+        let Some(config) = self.0.multistore_cache.config.substore(substore_prefix) else {
+            return Err(StorageError::UnknownSubstore {
+                prefix: substore_prefix.to_string(),
+            }
+            .into());
+        };
+
+        Ok(self.scan_substore(config, inner_prefix, ScanOrder::Ascending, String::new()))
+    }
+
+    /// Like [`StateRead::prefix_raw`], but scans every store that can hold keys
+    /// starting with `prefix`, and merges them into a single stream in
+    /// ascending key order.
+    ///
+    /// [`StateRead::prefix_raw`] routes the prefix to a single store: a scan
+    /// of `""` only covers the main store, and skips the keys of every
+    /// substore. Here, the main store is scanned along with each substore
+    /// whose keys fall under `prefix`, e.g. a scan of `"ib"` covers both the
+    /// main store and the `ibc` substore, so that `prefix_raw_global("")`
+    /// yields a total ordering of all verifiable keys.
+    ///
+    /// The main store holds the root hash of each substore that has been
+    /// written to, under the substore's prefix, so those entries are yielded
+    /// too. Each store scanned holds its own iterator open, and counts
+    /// against [`StorageOptions::max_open_iterators`](crate::StorageOptions::max_open_iterators).
+    pub fn prefix_raw_global(
+        &self,
+        prefix: &str,
+    ) -> crate::future::MergedPrefixRawStream<<Self as StateRead>::PrefixRawStream> {
+        let config = &self.0.multistore_cache.config;
+        let mut streams = vec![self
+            .scan_substore(
+                config.main_store.clone(),
+                prefix,
+                ScanOrder::Ascending,
+                String::new(),
+            )
+            .peekable()];
+
+        for substore in config.iter() {
+            let inner_prefix = if substore.prefix_with_delimiter.starts_with(prefix) {
+                ""
+            } else if let Some(inner_prefix) = prefix.strip_prefix(&substore.prefix_with_delimiter)
+            {
+                inner_prefix
+            } else {
+                continue;
+            };
+            let key_prefix = substore.prefix_with_delimiter.clone();
+            streams.push(
+                self.scan_substore(
+                    substore.clone(),
+                    inner_prefix,
+                    ScanOrder::Ascending,
+                    key_prefix,
+                )
+                .peekable(),
+            );
+        }
+
+        crate::future::MergedPrefixRawStream { streams }
+    }
+
+    /// Streams every entry of the substore registered with `prefix`, in key
+    /// order, with keys relative to the substore (i.e. without the prefix and
+    /// its delimiter).
+    ///
+    /// The entries can be loaded into another storage instance with
+    /// [`StateDelta::import_substore`](crate::StateDelta::import_substore).
+    ///
+    /// # Errors
+    /// Returns a [`StorageError::UnknownSubstore`] if `prefix` is not a
+    /// registered substore. The main store cannot be exported, since it also
+    /// holds the roots of the substores.
+    pub fn export_substore(
+        &self,
+        prefix: &str,
+    ) -> Result<impl Stream<Item = Result<(String, Vec<u8>)>> + Send + 'static> {
+        self.substore_prefix_raw(prefix, "")
+    }
+
+    /// Scans the keys of a single substore that start with `inner_prefix`,
+    /// relative to the substore, yielding them prefixed with `key_prefix`.
+    fn scan_substore(
+        &self,
+        config: Arc<store::substore::SubstoreConfig>,
+        inner_prefix: &str,
+        order: ScanOrder,
+        key_prefix: String,
+    ) -> <Self as StateRead>::PrefixRawStream {
+        if let Err(e) = self.ensure_unversioned_reads() {
+            return error_stream(e);
+        }
+        let span = tracing::debug_span!(
+            "prefix_raw",
+            substore = config.prefix,
+            prefix = inner_prefix
+        );
+
+        let version = self
+            .substore_version(&config)
+            .expect("the substore exists and has been initialized");
+
+        let substore = store::substore::SubstoreSnapshot {
+            config,
+            rocksdb_snapshot: self.0.snapshot.clone(),
+            version,
+            db: self.0.db.clone(),
+        };
+
+        let inner_prefix = inner_prefix.as_bytes().to_vec();
+        let retry = self.0.multistore_cache.config.read_retry;
+        let slot = self.0.multistore_cache.config.iterator_limit.reserve();
+        let (tx_prefix_item, rx_prefix_query) = mpsc::channel(10);
+
+        // Since the JMT keys are hashed, we can't use a prefix iterator directly.
+        // We need to first prefix range the key preimages column family, then use the hashed matches to fetch the values
+        // from the JMT column family.
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let _permit = match slot.wait() {
+                    Ok(permit) => permit,
+                    Err(e) => return tx_prefix_item.blocking_send(Err(e)).map_err(Into::into),
+                };
+                let cf_jmt_keys = substore.config.cf_jmt_keys(&substore.db);
+                // The last key sent, from which the iteration resumes if it is retried.
+                let mut last_key: Option<Vec<u8>> = None;
+
+                let _start = std::time::Instant::now();
+                let rsp = retry.run(|| {
+                    let mut options = rocksdb::ReadOptions::default();
+                    options.set_iterate_range(rocksdb::PrefixRange(inner_prefix.as_slice()));
+                    let resume_from = last_key.clone();
+                    let mode = match (order, resume_from.as_deref()) {
+                        (ScanOrder::Ascending, None) => rocksdb::IteratorMode::Start,
+                        (ScanOrder::Descending, None) => rocksdb::IteratorMode::End,
+                        (ScanOrder::Ascending, Some(key)) => {
+                            rocksdb::IteratorMode::From(key, rocksdb::Direction::Forward)
+                        }
+                        (ScanOrder::Descending, Some(key)) => {
+                            rocksdb::IteratorMode::From(key, rocksdb::Direction::Reverse)
+                        }
+                    };
+                    let jmt_keys_iterator =
+                        substore
+                            .rocksdb_snapshot
+                            .iterator_cf_opt(cf_jmt_keys, options, mode);
+
+                    for tuple in jmt_keys_iterator {
+                        // For each key that matches the prefix, fetch the value from the JMT column family.
+                        let (key_preimage, _) = tuple?;
+                        if resume_from.as_deref() == Some(key_preimage.as_ref()) {
+                            continue;
+                        }
+                        let substore_key = std::str::from_utf8(key_preimage.as_ref())
+                            .expect("saved jmt keys are utf-8 strings");
+                        let key_hash = jmt::KeyHash::with::<sha2::Sha256>(substore_key.as_bytes());
+
+                        let full_key = format!("{key_prefix}{substore_key}");
+
+                        let v = substore
+                            .get_jmt(key_hash)?
+                            .expect("keys in jmt_keys should have a corresponding value in jmt");
+
+                        tx_prefix_item.blocking_send(Ok((full_key, v)))?;
+                        last_key = Some(key_preimage.to_vec());
+                    }
+                    anyhow::Ok(())
+                });
+                #[cfg(feature = "metrics")]
+                metrics::histogram!(metrics::STORAGE_PREFIX_RAW_DURATION).record(_start.elapsed());
+                rsp
+            })
+        });
+
+        tokio_stream::wrappers::ReceiverStream::new(rx_prefix_query)
+    }
+
+    /// Returns the number of live keys in each substore at this snapshot's
+    /// version, keyed by substore prefix.
+    ///
+    /// The main store is listed under the empty prefix. Its count includes the
+    /// keys under which it commits to the root of each substore that has been
+    /// written to.
+    pub async fn substore_key_counts(&self) -> Result<BTreeMap<String, u64>> {
+        let span = tracing::Span::current();
+        let config = &self.0.multistore_cache.config;
+        let substores: Vec<_> = iter::once(&config.main_store)
+            .chain(config.iter())
+            .map(|substore_config| store::substore::SubstoreSnapshot {
+                config: substore_config.clone(),
+                rocksdb_snapshot: self.0.snapshot.clone(),
+                version: self.substore_version(substore_config).unwrap_or(u64::MAX),
+                db: self.0.db.clone(),
+            })
+            .collect();
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                substores
+                    .into_iter()
+                    .map(|substore| {
+                        anyhow::Ok((substore.config.prefix.clone(), substore.leaf_count()?))
+                    })
+                    .collect()
+            })
+        })
+        .await?
+    }
+
+    /// Checks which of `keys` are present in the verifiable key-value store.
+    ///
+    /// Keys are grouped by the substore they are routed to, and all lookups
+    /// are performed by a single blocking task, rather than one per key as
+    /// with [`StateRead::get_raw`]. Returns one `bool` per key, in input order.
+    pub async fn contains_keys(&self, keys: &[String]) -> Result<Vec<bool>> {
+        let span = Span::current();
+        let groups = self.group_by_substore(keys);
+        let len = keys.len();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let mut present = vec![false; len];
+                for (substore, lookups) in groups.into_values() {
+                    for (index, key_hash) in lookups {
+                        present[index] = substore.get_jmt(key_hash)?.is_some();
+                    }
+                }
+                Ok(present)
+            })
+        })
+        .await?
+    }
+
+    /// Gets the values of `keys` from the verifiable key-value store, as raw
+    /// bytes.
+    ///
+    /// As with [`Snapshot::contains_keys`], keys are grouped by the substore
+    /// they are routed to, and all lookups are performed by a single blocking
+    /// task, rather than one per key as with [`StateRead::get_raw`]. Values are
+    /// versioned and resolved through the tree, so each lookup is still a
+    /// separate read rather than part of a RocksDB `multi_get`. Returns one
+    /// value per key, in input order.
+    pub async fn multi_get_raw(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>> {
+        let span = Span::current();
+        let groups = self.group_by_substore(keys);
+        let len = keys.len();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let mut values = vec![None; len];
+                for (substore, lookups) in groups.into_values() {
+                    for (index, key_hash) in lookups {
+                        values[index] = substore.get_jmt(key_hash)?;
+                    }
+                }
+                Ok(values)
+            })
+        })
+        .await?
+    }
+
+    /// Groups the hashes of `keys` by the substore they are routed to, along
+    /// with the index of each key in `keys`.
+    fn group_by_substore(
+        &self,
+        keys: &[String],
+    ) -> BTreeMap<
+        String,
+        (
+            store::substore::SubstoreSnapshot,
+            Vec<(usize, jmt::KeyHash)>,
+        ),
+    > {
+        let mut groups: BTreeMap<String, (store::substore::SubstoreSnapshot, Vec<_>)> =
+            BTreeMap::new();
+        for (index, key) in keys.iter().enumerate() {
+            let (key, config) = self.0.multistore_cache.config.route_key_str(key);
+            let key_hash = jmt::KeyHash::with::<sha2::Sha256>(key);
+            let (_, lookups) = groups.entry(config.prefix.clone()).or_insert_with(|| {
+                let version = self
+                    .substore_version(&config)
+                    .expect("the substore exists and has been initialized");
+                let substore = store::substore::SubstoreSnapshot {
+                    config,
+                    rocksdb_snapshot: self.0.snapshot.clone(),
+                    version,
+                    db: self.0.db.clone(),
+                };
+                (substore, Vec::new())
+            });
+            lookups.push((index, key_hash));
+        }
+        groups
+    }
+
+    /// Returns the length of the value of `key` in the verifiable key-value
+    /// store, or `None` if it is absent.
+    ///
+    /// This matches `get_raw(key).await?.map(|v| v.len())`, but the value is
+    /// not read out of the database: its length is decoded from the stored
+    /// encoding in place.
+    pub async fn value_size(&self, key: &str) -> Result<Option<usize>> {
+        let span = Span::current();
+        let (key, config) = self.0.multistore_cache.config.route_key_str(key);
+        let version = self
+            .substore_version(&config)
+            .expect("the substore exists and has been initialized");
+        let substore = store::substore::SubstoreSnapshot {
+            config,
+            rocksdb_snapshot: self.0.snapshot.clone(),
+            version,
+            db: self.0.db.clone(),
+        };
+        let key_hash = jmt::KeyHash::with::<sha2::Sha256>(key);
+        let retry = self.0.multistore_cache.config.read_retry;
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| retry.run(|| substore.get_value_size(key_hash)))
+        })
+        .await?
+    }
+
+    /// Returns whether `key` is present in the verifiable key-value store.
+    ///
+    /// This matches `get_raw(key).await?.is_some()`, but like
+    /// [`Snapshot::value_size`], the value is not copied out of the database.
+    pub async fn contains_raw(&self, key: &str) -> Result<bool> {
+        Ok(self.value_size(key).await?.is_some())
+    }
+
+    /// Returns the value of `key` in the verifiable key-value store, along
+    /// with the version at which it was last written, or `None` if the key is
+    /// absent.
+    ///
+    /// The version is that of the chain state, as returned by
+    /// [`Snapshot::version`], so it only changes when the value is
+    /// overwritten, even with the same bytes.
+    ///
+    /// Substores are versioned independently of the main store, so for a key
+    /// in a substore, the version of the write is mapped back as in
+    /// [`Storage::key_history`](crate::Storage::key_history). This reads one
+    /// entry of the history of the substore root per commit that changed the
+    /// substore since the key was written.
+    ///
+    /// # Errors
+    /// Returns an error if the root of the substore at the version the key
+    /// was written is no longer retained, e.g. after [`Storage::prune`](crate::Storage::prune).
+    pub async fn get_raw_versioned(&self, key: &str) -> Result<Option<(Vec<u8>, jmt::Version)>> {
+        let span = Span::current();
+        let config = &self.0.multistore_cache.config;
+        let (key, substore_config) = config.route_key_str(key);
+        let key_hash = jmt::KeyHash::with::<sha2::Sha256>(key);
+        let root_key_hash = jmt::KeyHash::with::<sha2::Sha256>(substore_config.prefix.as_bytes());
+
+        let main_store =
+            (substore_config != config.main_store).then(|| store::substore::SubstoreSnapshot {
+                config: config.main_store.clone(),
+                rocksdb_snapshot: self.0.snapshot.clone(),
+                version: self.version(),
+                db: self.0.db.clone(),
+            });
+        let substore = store::substore::SubstoreSnapshot {
+            version: self
+                .substore_version(&substore_config)
+                .expect("the substore exists and has been initialized"),
+            config: substore_config,
+            rocksdb_snapshot: self.0.snapshot.clone(),
+            db: self.0.db.clone(),
+        };
+        let retry = config.read_retry;
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let Some((value, substore_version)) =
+                    retry.run(|| substore.get_jmt_versioned(key_hash))?
+                else {
+                    return Ok(None);
+                };
+                let Some(main_store) = main_store else {
+                    return Ok(Some((value, substore_version)));
+                };
+
+                // Every commit that changes the substore records its new root
+                // in the main store and increments the substore version, so
+                // the write's version is that of the root recorded as many
+                // changes ago.
+                let changes_since = (substore.version() - substore_version) as usize;
+                let version = retry
+                    .run(|| main_store.nth_latest_write_version(root_key_hash, changes_since))?
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "the root of substore {} at version {substore_version} was pruned",
+                            substore.config.prefix
+                        )
+                    })?;
+                Ok(Some((value, version)))
+            })
+        })
+        .await?
+    }
+
     pub(crate) fn substore_version(
         &self,
         prefix: &Arc<store::substore::SubstoreConfig>,
     ) -> Option<jmt::Version> {
         self.0.multistore_cache.get_version(prefix)
     }
+
+    /// Returns an error if this snapshot cannot serve reads that are not
+    /// versioned, see [`Snapshot::historical`].
+    fn ensure_unversioned_reads(&self) -> Result<()> {
+        if self.0.historical {
+            return Err(StorageError::UnversionedRead {
+                version: self.version(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Returns a stream that yields `error` and ends.
+fn error_stream<T: Send + 'static>(
+    error: anyhow::Error,
+) -> tokio_stream::wrappers::ReceiverStream<Result<T>> {
+    let (tx, rx) = mpsc::channel(1);
+    let _ = tx.try_send(Err(error));
+    tokio_stream::wrappers::ReceiverStream::new(rx)
 }
 
 #[async_trait]
@@ -197,7 +869,7 @@ impl StateRead for Snapshot {
 
     /// Fetch a key from the JMT.
     fn get_raw(&self, key: &str) -> Self::GetRawFut {
-        let span = Span::current();
+        let span = tracing::debug_span!("get_raw", key);
         let (key, config) = self.0.multistore_cache.config.route_key_str(key);
 
         let rocksdb_snapshot = self.0.snapshot.clone();
@@ -214,13 +886,25 @@ impl StateRead for Snapshot {
             db,
         };
         let key_hash = jmt::KeyHash::with::<sha2::Sha256>(key);
+        let retry = self.0.multistore_cache.config.read_retry;
+
+        let value_cache = self.0.multistore_cache.config.value_cache.clone();
+        if let Some(value) = value_cache
+            .as_ref()
+            .and_then(|cache| cache.get(&substore.config.prefix, key_hash, version))
+        {
+            return crate::future::SnapshotFuture(tokio::task::spawn(async move { Ok(value) }));
+        }
 
         crate::future::SnapshotFuture(tokio::task::spawn_blocking(move || {
             span.in_scope(|| {
                 let _start = std::time::Instant::now();
-                let rsp = substore.get_jmt(key_hash);
+                let rsp = retry.run(|| substore.get_jmt(key_hash));
                 #[cfg(feature = "metrics")]
                 metrics::histogram!(metrics::STORAGE_GET_RAW_DURATION).record(_start.elapsed());
+                if let (Some(cache), Ok(value)) = (&value_cache, &rsp) {
+                    cache.insert(&substore.config.prefix, key_hash, version, value.clone());
+                }
                 rsp
             })
         }))
@@ -228,6 +912,9 @@ impl StateRead for Snapshot {
 
     /// Fetch a key from nonverifiable storage.
     fn nonverifiable_get_raw(&self, key: &[u8]) -> Self::GetRawFut {
+        if let Err(e) = self.ensure_unversioned_reads() {
+            return crate::future::SnapshotFuture(tokio::task::spawn(async move { Err(e) }));
+        }
         let span = Span::current();
         let (key, config) = self.0.multistore_cache.config.route_key_bytes(key);
 
@@ -245,16 +932,19 @@ impl StateRead for Snapshot {
             db,
         };
         let key: Vec<u8> = key.to_vec();
+        let retry = self.0.multistore_cache.config.read_retry;
 
         crate::future::SnapshotFuture(tokio::task::spawn_blocking(move || {
             span.in_scope(|| {
                 let _start = std::time::Instant::now();
 
                 let cf_nonverifiable = substore.config.cf_nonverifiable(&substore.db);
-                let rsp = substore
-                    .rocksdb_snapshot
-                    .get_cf(cf_nonverifiable, key)
-                    .map_err(Into::into);
+                let rsp = retry.run(|| {
+                    substore
+                        .rocksdb_snapshot
+                        .get_cf(cf_nonverifiable, &key)
+                        .map_err(Into::into)
+                });
                 #[cfg(feature = "metrics")]
                 metrics::histogram!(metrics::STORAGE_NONCONSENSUS_GET_RAW_DURATION)
                     .record(_start.elapsed());
@@ -263,74 +953,41 @@ impl StateRead for Snapshot {
         }))
     }
 
+    fn substore_prefixes(&self) -> Vec<String> {
+        self.0
+            .multistore_cache
+            .config
+            .iter()
+            .map(|config| config.prefix.clone())
+            .collect()
+    }
+
     /// Returns a stream of all key-value pairs with the given prefix.
     fn prefix_raw(&self, prefix: &str) -> Self::PrefixRawStream {
-        let span = Span::current();
-
-        let rocksdb_snapshot = self.0.snapshot.clone();
-        let db = self.0.db.clone();
+        self.prefix_raw_ordered(prefix, ScanOrder::Ascending)
+    }
 
+    /// Returns a stream of all key-value pairs with the given prefix, in the supplied key order.
+    fn prefix_raw_ordered(&self, prefix: &str, order: ScanOrder) -> Self::PrefixRawStream {
         let (prefix_truncated, config) = self.0.multistore_cache.config.match_prefix_str(prefix);
         tracing::trace!(substore_key = prefix_truncated,  substore_prefix = config.prefix, prefix_supplied = ?prefix, "matched prefix, fetching substore");
-        let substore_prefix = config.prefix.clone();
-
-        let version = self
-            .substore_version(&config)
-            .expect("the substore exists and has been initialized");
-
-        let substore = store::substore::SubstoreSnapshot {
-            config,
-            rocksdb_snapshot,
-            version,
-            db,
+        let key_prefix = if config.prefix.is_empty() {
+            String::new()
+        } else {
+            config.prefix_with_delimiter.clone()
         };
 
-        let mut options = rocksdb::ReadOptions::default();
-        options.set_iterate_range(rocksdb::PrefixRange(prefix_truncated.as_bytes()));
-        let mode = rocksdb::IteratorMode::Start;
-        let (tx_prefix_item, rx_prefix_query) = mpsc::channel(10);
-
-        // Since the JMT keys are hashed, we can't use a prefix iterator directly.
-        // We need to first prefix range the key preimages column family, then use the hashed matches to fetch the values
-        // from the JMT column family.
-        tokio::task::spawn_blocking(move || {
-            span.in_scope(|| {
-                let cf_jmt_keys = substore.config.cf_jmt_keys(&substore.db);
-                let jmt_keys_iterator =
-                    substore
-                        .rocksdb_snapshot
-                        .iterator_cf_opt(cf_jmt_keys, options, mode);
-
-                for tuple in jmt_keys_iterator {
-                    // For each key that matches the prefix, fetch the value from the JMT column family.
-                    let (key_preimage, _) = tuple?;
-                    let substore_key = std::str::from_utf8(key_preimage.as_ref())
-                        .expect("saved jmt keys are utf-8 strings");
-                    let key_hash = jmt::KeyHash::with::<sha2::Sha256>(substore_key.as_bytes());
-
-                    let full_key = if substore_prefix.is_empty() {
-                        substore_key.to_string()
-                    } else {
-                        format!("{substore_prefix}/{substore_key}").to_string()
-                    };
-
-                    let v = substore
-                        .get_jmt(key_hash)?
-                        .expect("keys in jmt_keys should have a corresponding value in jmt");
-
-                    tx_prefix_item.blocking_send(Ok((full_key, v)))?;
-                }
-                anyhow::Ok(())
-            })
-        });
-
-        tokio_stream::wrappers::ReceiverStream::new(rx_prefix_query)
+        self.scan_substore(config, prefix_truncated, order, key_prefix)
     }
 
     // NOTE: this implementation is almost the same as the above, but without
     // fetching the values. not totally clear if this could be combined, or if that would
-    // be better overall.
+    // be better overall. Only the key preimage index is iterated, so values are
+    // never loaded from RocksDB.
     fn prefix_keys(&self, prefix: &str) -> Self::PrefixKeysStream {
+        if let Err(e) = self.ensure_unversioned_reads() {
+            return error_stream(e);
+        }
         let span = Span::current();
 
         let rocksdb_snapshot = self.0.snapshot.clone();
@@ -352,10 +1009,15 @@ impl StateRead for Snapshot {
         let mut options = rocksdb::ReadOptions::default();
         options.set_iterate_range(rocksdb::PrefixRange(prefix_truncated.as_bytes()));
         let mode = rocksdb::IteratorMode::Start;
+        let slot = self.0.multistore_cache.config.iterator_limit.reserve();
         let (tx_prefix_keys, rx_prefix_keys) = mpsc::channel(10);
 
         tokio::task::spawn_blocking(move || {
             span.in_scope(|| {
+                let _permit = match slot.wait() {
+                    Ok(permit) => permit,
+                    Err(e) => return tx_prefix_keys.blocking_send(Err(e)).map_err(Into::into),
+                };
                 let cf_jmt_keys = substore.config.cf_jmt_keys(&substore.db);
                 let iter = substore
                     .rocksdb_snapshot
@@ -385,6 +1047,9 @@ impl StateRead for Snapshot {
 
     /// Returns a stream of all key-value pairs with the given prefix, from nonverifiable storage.
     fn nonverifiable_prefix_raw(&self, prefix: &[u8]) -> Self::NonconsensusPrefixRawStream {
+        if let Err(e) = self.ensure_unversioned_reads() {
+            return error_stream(e);
+        }
         let span = Span::current();
         let rocksdb_snapshot = self.0.snapshot.clone();
         let db = self.0.db.clone();
@@ -405,11 +1070,16 @@ impl StateRead for Snapshot {
         let mut options = rocksdb::ReadOptions::default();
         options.set_iterate_range(rocksdb::PrefixRange(truncated_prefix));
         let mode = rocksdb::IteratorMode::Start;
+        let slot = self.0.multistore_cache.config.iterator_limit.reserve();
 
         let (tx_prefix_query, rx_prefix_query) = mpsc::channel(10);
 
         tokio::task::spawn_blocking(move || {
             span.in_scope(|| {
+                let _permit = match slot.wait() {
+                    Ok(permit) => permit,
+                    Err(e) => return tx_prefix_query.blocking_send(Err(e)).map_err(Into::into),
+                };
                 let cf_nonverifiable = substore.config.cf_nonverifiable(&substore.db);
                 let iter =
                     substore
@@ -449,6 +1119,7 @@ impl StateRead for Snapshot {
         prefix: Option<&[u8]>,
         range: impl std::ops::RangeBounds<Vec<u8>>,
     ) -> anyhow::Result<Self::NonconsensusRangeRawStream> {
+        self.ensure_unversioned_reads()?;
         let span = Span::current();
         let rocksdb_snapshot = self.0.snapshot.clone();
         let db = self.0.db.clone();
@@ -510,9 +1181,14 @@ impl StateRead for Snapshot {
         let mode = rocksdb::IteratorMode::Start;
         let prefix = prefix.to_vec();
 
+        let slot = self.0.multistore_cache.config.iterator_limit.reserve();
         let (tx, rx) = mpsc::channel::<Result<(Vec<u8>, Vec<u8>)>>(10);
         tokio::task::spawn_blocking(move || {
             span.in_scope(|| {
+                let _permit = match slot.wait() {
+                    Ok(permit) => permit,
+                    Err(e) => return tx.blocking_send(Err(e)).map_err(Into::into),
+                };
                 let cf_nonverifiable = substore.config.cf_nonverifiable(&substore.db);
                 let iter =
                     substore
@@ -0,0 +1,148 @@
+use std::{any::Any, collections::BTreeMap, sync::Arc};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use crate::{ScanOrder, Snapshot, StateRead};
+
+/// Tracks the versions that are pinned by live [`ArchiveState`]s, so that
+/// pruning can keep them readable.
+///
+/// The registry is shared by all the snapshots of a storage instance.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct VersionPins(Arc<Mutex<BTreeMap<jmt::Version, usize>>>);
+
+impl VersionPins {
+    /// Pins `version` until the returned guard is dropped.
+    pub(crate) fn pin(&self, version: jmt::Version) -> VersionPin {
+        *self.0.lock().entry(version).or_default() += 1;
+        VersionPin {
+            pins: self.clone(),
+            version,
+        }
+    }
+
+    /// Returns the oldest pinned version, if any.
+    pub(crate) fn oldest(&self) -> Option<jmt::Version> {
+        self.0.lock().keys().next().copied()
+    }
+}
+
+/// A guard that keeps a version pinned in a [`VersionPins`] registry.
+#[derive(Debug)]
+pub(crate) struct VersionPin {
+    pins: VersionPins,
+    version: jmt::Version,
+}
+
+impl Drop for VersionPin {
+    fn drop(&mut self) {
+        let mut pins = self.pins.0.lock();
+        if let Some(count) = pins.get_mut(&self.version) {
+            *count -= 1;
+            if *count == 0 {
+                pins.remove(&self.version);
+            }
+        }
+    }
+}
+
+/// An immutable, shareable view of the state at a single version.
+///
+/// An `ArchiveState` is obtained with [`Snapshot::into_archive`]. It only
+/// offers read access: unlike a [`Snapshot`], it cannot be used to build a
+/// [`StateDelta`](crate::StateDelta) that gets committed. It is cheap to clone,
+/// and can be sent to and shared between tasks.
+///
+/// For as long as any clone of it is alive, its version is pinned: it is
+/// reported by [`Storage::oldest_pinned_version`](crate::Storage::oldest_pinned_version),
+/// and pruning must keep it readable.
+#[derive(Clone, Debug)]
+pub struct ArchiveState(Arc<ArchiveInner>);
+
+#[derive(Debug)]
+struct ArchiveInner {
+    snapshot: Snapshot,
+    _pin: VersionPin,
+}
+
+impl Snapshot {
+    /// Freezes this snapshot into an [`ArchiveState`], pinning its version for
+    /// the lifetime of the archive and all of its clones.
+    pub fn into_archive(self) -> ArchiveState {
+        let pin = self.0.multistore_cache.config.pins.pin(self.version());
+        ArchiveState(Arc::new(ArchiveInner {
+            snapshot: self,
+            _pin: pin,
+        }))
+    }
+}
+
+impl ArchiveState {
+    /// Returns the version of the state.
+    pub fn version(&self) -> jmt::Version {
+        self.0.snapshot.version()
+    }
+
+    /// Returns the root hash of the main tree at this version.
+    pub async fn root_hash(&self) -> Result<crate::RootHash> {
+        self.0.snapshot.root_hash().await
+    }
+
+    /// Returns the root hash of the substore with the given prefix at this version.
+    pub async fn prefix_root_hash(&self, prefix: &str) -> Result<crate::RootHash> {
+        self.0.snapshot.prefix_root_hash(prefix).await
+    }
+}
+
+impl StateRead for ArchiveState {
+    type GetRawFut = <Snapshot as StateRead>::GetRawFut;
+    type PrefixRawStream = <Snapshot as StateRead>::PrefixRawStream;
+    type PrefixKeysStream = <Snapshot as StateRead>::PrefixKeysStream;
+    type NonconsensusPrefixRawStream = <Snapshot as StateRead>::NonconsensusPrefixRawStream;
+    type NonconsensusRangeRawStream = <Snapshot as StateRead>::NonconsensusRangeRawStream;
+
+    fn get_raw(&self, key: &str) -> Self::GetRawFut {
+        self.0.snapshot.get_raw(key)
+    }
+
+    fn nonverifiable_get_raw(&self, key: &[u8]) -> Self::GetRawFut {
+        self.0.snapshot.nonverifiable_get_raw(key)
+    }
+
+    fn object_get<T: Any + Send + Sync + Clone>(&self, key: &'static str) -> Option<T> {
+        self.0.snapshot.object_get(key)
+    }
+
+    fn object_type(&self, key: &'static str) -> Option<std::any::TypeId> {
+        self.0.snapshot.object_type(key)
+    }
+
+    fn substore_prefixes(&self) -> Vec<String> {
+        self.0.snapshot.substore_prefixes()
+    }
+
+    fn prefix_raw(&self, prefix: &str) -> Self::PrefixRawStream {
+        self.0.snapshot.prefix_raw(prefix)
+    }
+
+    fn prefix_raw_ordered(&self, prefix: &str, order: ScanOrder) -> Self::PrefixRawStream {
+        self.0.snapshot.prefix_raw_ordered(prefix, order)
+    }
+
+    fn prefix_keys(&self, prefix: &str) -> Self::PrefixKeysStream {
+        self.0.snapshot.prefix_keys(prefix)
+    }
+
+    fn nonverifiable_prefix_raw(&self, prefix: &[u8]) -> Self::NonconsensusPrefixRawStream {
+        self.0.snapshot.nonverifiable_prefix_raw(prefix)
+    }
+
+    fn nonverifiable_range_raw(
+        &self,
+        prefix: Option<&[u8]>,
+        range: impl std::ops::RangeBounds<Vec<u8>>,
+    ) -> Result<Self::NonconsensusRangeRawStream> {
+        self.0.snapshot.nonverifiable_range_raw(prefix, range)
+    }
+}
@@ -0,0 +1,90 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::StorageError;
+
+/// Caps the number of RocksDB iterators that the snapshots of a storage
+/// instance hold open at the same time.
+///
+/// Each stream returned by a snapshot's prefix and range queries takes a slot
+/// for as long as its iterator is open, i.e. until the stream is exhausted or
+/// dropped.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IteratorLimit {
+    /// The available slots, if the number of iterators is capped.
+    slots: Option<Arc<Semaphore>>,
+    max: usize,
+    /// How long to wait for a slot before failing, if at all.
+    timeout: Option<Duration>,
+}
+
+impl IteratorLimit {
+    pub(crate) fn new(max: Option<usize>, timeout: Option<Duration>) -> Self {
+        Self {
+            slots: max.map(|max| Arc::new(Semaphore::new(max))),
+            max: max.unwrap_or_default(),
+            timeout,
+        }
+    }
+
+    /// Takes a slot if one is available, or prepares to wait for one.
+    ///
+    /// This never blocks, so that it can be called when a stream is created.
+    pub(crate) fn reserve(&self) -> IteratorSlot {
+        let Some(slots) = &self.slots else {
+            return IteratorSlot::Unlimited;
+        };
+        match (slots.clone().try_acquire_owned(), self.timeout) {
+            (Ok(permit), _) => IteratorSlot::Held(permit),
+            (Err(_), Some(timeout)) => IteratorSlot::Waiting {
+                slots: slots.clone(),
+                timeout,
+                max: self.max,
+            },
+            (Err(_), None) => IteratorSlot::Exhausted { max: self.max },
+        }
+    }
+}
+
+/// A slot reserved with [`IteratorLimit::reserve`].
+pub(crate) enum IteratorSlot {
+    Unlimited,
+    Held(OwnedSemaphorePermit),
+    Waiting {
+        slots: Arc<Semaphore>,
+        timeout: Duration,
+        max: usize,
+    },
+    Exhausted {
+        max: usize,
+    },
+}
+
+impl IteratorSlot {
+    /// Waits for the slot to be available, returning the permit to hold while
+    /// the iterator is open.
+    ///
+    /// This blocks the current thread, so it must only be called from blocking
+    /// tasks.
+    pub(crate) fn wait(self) -> Result<Option<OwnedSemaphorePermit>> {
+        match self {
+            IteratorSlot::Unlimited => Ok(None),
+            IteratorSlot::Held(permit) => Ok(Some(permit)),
+            IteratorSlot::Waiting {
+                slots,
+                timeout,
+                max,
+            } => {
+                let acquire = tokio::time::timeout(timeout, slots.acquire_owned());
+                match tokio::runtime::Handle::current().block_on(acquire) {
+                    Ok(Ok(permit)) => Ok(Some(permit)),
+                    // The semaphore is never closed, so only the timeout can fail.
+                    Ok(Err(_)) | Err(_) => Err(StorageError::TooManyIterators { max }.into()),
+                }
+            }
+            IteratorSlot::Exhausted { max } => Err(StorageError::TooManyIterators { max }.into()),
+        }
+    }
+}
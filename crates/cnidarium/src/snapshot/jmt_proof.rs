@@ -0,0 +1,119 @@
+use anyhow::Result;
+use jmt::{proof::SparseMerkleProof, KeyHash, RootHash};
+use sha2::Sha256;
+use tracing::Span;
+
+use crate::{store::substore::SubstoreSnapshot, Snapshot};
+
+/// A proof of the value of a key against the root hash of a version of the
+/// state, made of native JMT sparse Merkle proofs.
+///
+/// Obtained with [`Snapshot::get_with_jmt_proof`].
+#[derive(Clone, Debug)]
+pub struct JmtProof {
+    /// The proof of the key's value against the root of the tree holding it.
+    pub key_proof: SparseMerkleProof<Sha256>,
+    /// For a key held in a substore, the proof that the main store holds the
+    /// root of that substore.
+    pub substore: Option<SubstoreRootProof>,
+}
+
+/// A proof that the main store holds `root_hash` as the root of the substore
+/// with the given `prefix`.
+#[derive(Clone, Debug)]
+pub struct SubstoreRootProof {
+    pub prefix: String,
+    pub root_hash: RootHash,
+    pub proof: SparseMerkleProof<Sha256>,
+}
+
+impl JmtProof {
+    /// Verifies that `key` has the given `value`, or is absent if `value` is
+    /// `None`, in the state whose root hash is `root_hash`.
+    pub fn verify(&self, root_hash: RootHash, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        let (key_root_hash, key) = match &self.substore {
+            None => (root_hash, key),
+            Some(substore) => {
+                let substore_key = key
+                    .strip_prefix(substore.prefix.as_bytes())
+                    .and_then(|key| key.strip_prefix(b"/"))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("the key is not in substore {}", substore.prefix)
+                    })?;
+                substore.proof.verify(
+                    root_hash,
+                    KeyHash::with::<Sha256>(substore.prefix.as_bytes()),
+                    Some(substore.root_hash.0),
+                )?;
+                (substore.root_hash, substore_key)
+            }
+        };
+        self.key_proof
+            .verify(key_root_hash, KeyHash::with::<Sha256>(key), value)
+    }
+}
+
+impl Snapshot {
+    /// Returns the value of `key`, along with a [`JmtProof`] of its inclusion,
+    /// or of its exclusion if it is absent, against the root hash of this
+    /// snapshot's version.
+    ///
+    /// Unlike [`Snapshot::get_with_proof`], which produces ICS23 proofs, this
+    /// produces the JMT's own sparse Merkle proofs. As with it, for a substore
+    /// configured with [`StorageOptions::hash_values`](crate::StorageOptions::hash_values),
+    /// the value returned is the SHA-256 hash of the value.
+    ///
+    /// Proofs can only be generated against committed versions: the changes
+    /// held by a [`StateDelta`](crate::StateDelta) are not in the tree yet.
+    pub async fn get_with_jmt_proof(&self, key: Vec<u8>) -> Result<(Option<Vec<u8>>, JmtProof)> {
+        if key.is_empty() {
+            anyhow::bail!("empty keys are not allowed")
+        }
+
+        let config = &self.0.multistore_cache.config;
+        let substore_snapshot = |config| SubstoreSnapshot {
+            version: self.substore_version(&config).unwrap_or(u64::MAX),
+            config,
+            rocksdb_snapshot: self.0.snapshot.clone(),
+            db: self.0.db.clone(),
+        };
+
+        let (substore_key, substore_config) = config.route_key_bytes(&key);
+        let key_hash = KeyHash::with::<Sha256>(substore_key);
+        let prefix = substore_config.prefix.clone();
+        let substore = substore_snapshot(substore_config);
+        let mainstore = (!prefix.is_empty()).then(|| substore_snapshot(config.main_store.clone()));
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let (value, key_proof) = substore.get_with_jmt_proof(key_hash)?;
+                let substore = match mainstore {
+                    None => None,
+                    Some(mainstore) => {
+                        let (root_hash, proof) =
+                            mainstore
+                                .get_with_jmt_proof(KeyHash::with::<Sha256>(prefix.as_bytes()))?;
+                        let root_hash: [u8; 32] = root_hash
+                            .ok_or_else(|| anyhow::anyhow!("substore {prefix} has no root"))?
+                            .try_into()
+                            .map_err(|_| anyhow::anyhow!("invalid root for substore {prefix}"))?;
+                        Some(SubstoreRootProof {
+                            prefix,
+                            root_hash: RootHash(root_hash),
+                            proof,
+                        })
+                    }
+                };
+                Ok((
+                    value,
+                    JmtProof {
+                        key_proof,
+                        substore,
+                    },
+                ))
+            })
+        })
+        .await?
+    }
+}
@@ -1,7 +1,11 @@
 use std::fmt::{Debug, Formatter};
-use std::ops::Deref;
 use std::sync::Arc;
 
+use rocksdb::{
+    AsColumnFamilyRef, DBIteratorWithThreadMode, DBPinnableSlice, DBRawIteratorWithThreadMode,
+    IteratorMode, ReadOptions, DB,
+};
+
 /// A wrapper type that acts as a `rocksdb::Snapshot` of an `Arc`'d database
 /// handle.
 ///
@@ -11,10 +15,14 @@ use std::sync::Arc;
 /// handle.  Instead, this wrapper type bundles an `Arc`'d handle together with
 /// the `rocksdb::Snapshot`, so that the database is guaranteed to live at least
 /// as long as any snapshot of it.
+///
+/// A wrapper created with [`RocksDbSnapshot::unpinned`] holds no snapshot, and
+/// reads the current state of the database instead. This is used by secondary
+/// instances, which do not support snapshots.
 pub struct RocksDbSnapshot {
     /// The snapshot itself.  It's not really `'static`, so it's on us to ensure
     /// that the database stays live as long as the snapshot does.
-    inner: rocksdb::Snapshot<'static>,
+    inner: Option<rocksdb::Snapshot<'static>>,
     /// The raw pointer form of the Arc<DB> we use to guarantee the database
     /// lives at least as long as the snapshot.  We create this from the Arc<DB>
     /// in the constructor, pass it to the snapshot on creation, and then
@@ -35,22 +43,14 @@ impl Debug for RocksDbSnapshot {
 }
 
 // Safety requires that the inner snapshot instance must never live longer than
-// the wrapper.  We're assured that this is the case, because we only return a
-// borrow of the inner snapshot, and because `rocksdb::Snapshot` is neither
-// `Copy` nor `Clone`.
+// the wrapper.  We're assured that this is the case, because we only read
+// through a borrow of the inner snapshot, and because `rocksdb::Snapshot` is
+// neither `Copy` nor `Clone`.
 //
 // We're also reasonably certain that the upstream crate will not add such an
 // implementation in the future, because its drop impl is used to make the FFI
 // call that discards the in-memory snapshot, so it would not be safe to add
 // such an implementation.
-impl Deref for RocksDbSnapshot {
-    type Target = rocksdb::Snapshot<'static>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.inner
-    }
-}
-
 impl RocksDbSnapshot {
     /// Creates a new snapshot of the given `db`.
     pub fn new(db: Arc<rocksdb::DB>) -> Self {
@@ -62,12 +62,100 @@ impl RocksDbSnapshot {
         let static_db: &'static rocksdb::DB = unsafe { &*raw_db };
         let inner = rocksdb::Snapshot::new(static_db);
 
-        Self { inner, raw_db }
+        Self {
+            inner: Some(inner),
+            raw_db,
+        }
+    }
+
+    /// Creates a handle that reads the current state of the given `db`,
+    /// without pinning it with a snapshot.
+    pub fn unpinned(db: Arc<rocksdb::DB>) -> Self {
+        Self {
+            inner: None,
+            raw_db: Arc::into_raw(db),
+        }
+    }
+
+    fn db(&self) -> &DB {
+        // The pointer stays valid until the wrapper is dropped.
+        unsafe { &*self.raw_db }
+    }
+
+    // The reads below mirror those of `rocksdb::Snapshot`, and read from the
+    // database itself if the handle is unpinned.
+
+    pub fn get_cf(
+        &self,
+        cf: &impl AsColumnFamilyRef,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+        match &self.inner {
+            Some(snapshot) => snapshot.get_cf(cf, key),
+            None => self.db().get_cf(cf, key),
+        }
+    }
+
+    pub fn get_pinned_cf(
+        &self,
+        cf: &impl AsColumnFamilyRef,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<DBPinnableSlice<'_>>, rocksdb::Error> {
+        match &self.inner {
+            Some(snapshot) => snapshot.get_pinned_cf(cf, key),
+            None => self.db().get_pinned_cf(cf, key),
+        }
+    }
+
+    pub fn iterator_cf(
+        &self,
+        cf: &impl AsColumnFamilyRef,
+        mode: IteratorMode,
+    ) -> DBIteratorWithThreadMode<'_, DB> {
+        match &self.inner {
+            Some(snapshot) => snapshot.iterator_cf(cf, mode),
+            None => self.db().iterator_cf(cf, mode),
+        }
+    }
+
+    pub fn iterator_cf_opt(
+        &self,
+        cf: &impl AsColumnFamilyRef,
+        readopts: ReadOptions,
+        mode: IteratorMode,
+    ) -> DBIteratorWithThreadMode<'_, DB> {
+        match &self.inner {
+            Some(snapshot) => snapshot.iterator_cf_opt(cf, readopts, mode),
+            None => self.db().iterator_cf_opt(cf, readopts, mode),
+        }
+    }
+
+    pub fn raw_iterator_cf(
+        &self,
+        cf: &impl AsColumnFamilyRef,
+    ) -> DBRawIteratorWithThreadMode<'_, DB> {
+        match &self.inner {
+            Some(snapshot) => snapshot.raw_iterator_cf(cf),
+            None => self.db().raw_iterator_cf(cf),
+        }
+    }
+
+    pub fn raw_iterator_cf_opt(
+        &self,
+        cf: &impl AsColumnFamilyRef,
+        readopts: ReadOptions,
+    ) -> DBRawIteratorWithThreadMode<'_, DB> {
+        match &self.inner {
+            Some(snapshot) => snapshot.raw_iterator_cf_opt(cf, readopts),
+            None => self.db().raw_iterator_cf_opt(cf, readopts),
+        }
     }
 }
 
 impl Drop for RocksDbSnapshot {
     fn drop(&mut self) {
+        // Release the snapshot before the database handle it borrows.
+        drop(self.inner.take());
         // Now that we know we're finished with the `Snapshot`, we can
         // reconstruct the `Arc` and drop it, to decrement the DB refcount.
         unsafe {
@@ -79,6 +79,12 @@ impl SnapshotCache {
             .filter(|s| s.version() == version)
     }
 
+    /// Evicts every entry but the `len` most recent ones. The latest `Snapshot`
+    /// is always kept, even if `len` is zero.
+    pub fn retain_latest(&mut self, len: usize) {
+        self.cache.truncate(cmp::max(len, 1));
+    }
+
     /// Empties the cache.
     pub fn clear(&mut self) {
         self.cache.clear();
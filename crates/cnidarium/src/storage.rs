@@ -1,24 +1,54 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::{bail, ensure, Result};
-use parking_lot::RwLock;
-use rocksdb::{Options, DB};
-use std::collections::HashMap;
+use futures::future::BoxFuture;
+use parking_lot::{Mutex, RwLock};
+use rocksdb::{ColumnFamilyDescriptor, Options, DB};
+use std::collections::BTreeMap;
 use tokio::sync::watch;
-use tracing::Span;
+use tracing::{Instrument, Span};
 
+#[cfg(feature = "metrics")]
+use crate::metrics;
 use crate::{
     cache::Cache,
-    snapshot::Snapshot,
+    snapshot::{IteratorLimit, Snapshot},
     store::{
         multistore::{self, MultistoreConfig},
+        node_cache::NodeCache,
         substore::{SubstoreConfig, SubstoreSnapshot, SubstoreStorage},
+        value_cache::ValueCache,
     },
 };
-use crate::{snapshot_cache::SnapshotCache, StagedWriteBatch, StateDelta};
-
+use crate::{snapshot_cache::SnapshotCache, StagedWriteBatch, StateDelta, StorageError};
+
+mod changes;
+mod checkpoint;
+mod columns;
+mod history;
+mod integrity;
+mod namespace;
+mod options;
+mod prune;
+mod read_only;
+mod register;
+mod retry;
+mod secondary;
+mod shadow;
+mod status;
 mod temp;
+mod trace;
+pub use columns::{ColumnFamilyInfo, ColumnFamilyRole};
+pub use integrity::{DanglingReference, IntegrityReport};
+pub use namespace::{NamespacedSnapshot, NamespacedStorage};
+pub use options::{Compression, RocksDbConfig, StorageOptions};
+pub use read_only::ReadOnlyStorage;
+pub use retry::RetryPolicy;
+pub use secondary::SecondaryStorage;
+pub use shadow::ShadowStorage;
+pub use status::{StorageStatus, SubstoreStatus};
 pub use temp::TempStorage;
+pub use trace::{CommitTrace, SubstoreTrace};
 
 /// A handle for a storage instance, backed by RocksDB.
 ///
@@ -39,7 +69,14 @@ struct Inner {
     snapshot_rx: watch::Receiver<Snapshot>,
     changes_rx: watch::Receiver<(jmt::Version, Arc<Cache>)>,
     snapshots: RwLock<SnapshotCache>,
+    change_log: RwLock<changes::ChangeLog>,
+    /// Serializes commits with the registration of new substores, which
+    /// must not race with writes under their prefix.
+    commit_lock: Mutex<()>,
     multistore_config: MultistoreConfig,
+    /// The prefixes registered with [`Storage::register_substore`] since the
+    /// storage was loaded, whose column families are not created yet.
+    pending_substores: RwLock<Vec<String>>,
     /// A handle to the dispatcher task.
     /// This is used by `Storage::release` to wait for the task to terminate.
     jh_dispatcher: Option<tokio::task::JoinHandle<()>>,
@@ -48,9 +85,25 @@ struct Inner {
 
 impl Storage {
     /// Loads a storage instance from the given path, initializing it if necessary.
+    ///
+    /// # Errors
+    /// If the database is held open by another instance (e.g. a previous
+    /// process that did not release its lock), this returns a
+    /// [`StorageError::AlreadyOpen`] error immediately, rather than blocking.
     pub async fn load(path: PathBuf, default_prefixes: Vec<String>) -> Result<Self> {
+        Storage::load_with_options(path, default_prefixes, StorageOptions::default()).await
+    }
+
+    /// Loads a storage instance from the given path like [`Storage::load`],
+    /// configuring it with the supplied [`StorageOptions`].
+    pub async fn load_with_options(
+        path: PathBuf,
+        default_prefixes: Vec<String>,
+        options: StorageOptions,
+    ) -> Result<Self> {
         let span = Span::current();
         let db_path = path.clone();
+        let hash_values = options.hash_values.clone();
         // initializing main storage instance.
         let prefixes = tokio::task::spawn_blocking(move || {
             span.in_scope(|| {
@@ -70,33 +123,123 @@ impl Storage {
                     columns.push("config".to_string());
                 }
 
-                let db = DB::open_cf(&opts, path, columns).expect("can open database");
+                let db =
+                    DB::open_cf(&opts, &path, columns).map_err(|e| open_error(path.clone(), e))?;
                 let cf_config = db
                     .cf_handle("config")
                     .expect("config column family is created if missing");
+
+                // The settings that change the root hash of a substore are
+                // recorded when its column families are first created, which
+                // is when it is first loaded, and must not change afterwards.
+                // Substores registered since the last load, and databases
+                // created before the settings were recorded, have none yet.
+                let recorded = db.get(options::MAIN_STORE_SETTINGS_KEY)?;
+                let recorded =
+                    options::decode_substore_settings(recorded.as_deref().unwrap_or_default())?;
+                options::check_hash_values("", recorded, hash_values.contains(""))?;
+                if recorded.is_none() {
+                    db.put(
+                        options::MAIN_STORE_SETTINGS_KEY,
+                        options::encode_substore_settings(hash_values.contains("")),
+                    )?;
+                }
+
                 let config_iter = db.iterator_cf(cf_config, rocksdb::IteratorMode::Start);
                 let mut prefixes = Vec::new();
                 tracing::info!("reading prefixes from config column family");
                 for i in config_iter {
-                    let (key, _) = i.expect("can read from iterator");
-                    prefixes.push(String::from_utf8(key.to_vec()).expect("prefix is utf8"));
+                    let (key, value) = i.expect("can read from iterator");
+                    let prefix = String::from_utf8(key.to_vec()).expect("prefix is utf8");
+                    let recorded = options::decode_substore_settings(&value)?;
+                    options::check_hash_values(&prefix, recorded, hash_values.contains(&prefix))?;
+                    if recorded.is_none() {
+                        db.put_cf(
+                            cf_config,
+                            prefix.as_bytes(),
+                            options::encode_substore_settings(hash_values.contains(&prefix)),
+                        )?;
+                    }
+                    prefixes.push(prefix);
                 }
 
                 for prefix in default_prefixes {
                     if !prefixes.contains(&prefix) {
-                        db.put_cf(cf_config, prefix.as_bytes(), b"")
-                            .expect("can write to db");
+                        db.put_cf(
+                            cf_config,
+                            prefix.as_bytes(),
+                            options::encode_substore_settings(hash_values.contains(&prefix)),
+                        )
+                        .expect("can write to db");
                         prefixes.push(prefix);
                     }
                 }
 
                 std::mem::drop(db);
-                prefixes
+                anyhow::Ok(prefixes)
             })
         })
-        .await?;
+        .await??;
+
+        Storage::init_with_options(db_path, prefixes, options).await
+    }
 
-        Storage::init(db_path, prefixes).await
+    /// Loads a storage instance like [`Storage::load`], but waits for up to
+    /// `timeout` for the database to become available.
+    ///
+    /// While the database is held open by another instance, loading is retried
+    /// until the lock is released or the timeout elapses. This is useful when a
+    /// previous instance is still shutting down. Any other error is returned
+    /// without retrying.
+    ///
+    /// # Errors
+    /// Returns an error describing the timeout if the storage could not be
+    /// loaded in time. If the database was still locked, the error can be
+    /// downcast to [`StorageError::AlreadyOpen`].
+    pub async fn load_with_timeout(
+        path: PathBuf,
+        default_prefixes: Vec<String>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut backoff = Duration::from_millis(10);
+
+        loop {
+            let attempt = tokio::time::timeout_at(
+                deadline,
+                Storage::load(path.clone(), default_prefixes.clone()),
+            )
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "timed out after {timeout:?} while loading storage at {}",
+                    path.display()
+                )
+            })?;
+
+            // Only a held lock is worth waiting for; any other error is
+            // returned right away.
+            let locked = |e: &anyhow::Error| {
+                matches!(
+                    e.downcast_ref::<StorageError>(),
+                    Some(StorageError::AlreadyOpen { .. })
+                )
+            };
+            match attempt {
+                Err(e) if locked(&e) && tokio::time::Instant::now() + backoff < deadline => {
+                    tracing::debug!(?path, ?backoff, "database is locked, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, Duration::from_millis(500));
+                }
+                Err(e) if locked(&e) => {
+                    return Err(e.context(format!(
+                        "timed out after {timeout:?} waiting for the lock on {}",
+                        path.display()
+                    )))
+                }
+                attempt => return attempt,
+            }
+        }
     }
 
     /// Initializes a new storage instance at the given path. Takes a list of default prefixes
@@ -108,75 +251,92 @@ impl Storage {
     /// 4. Initialize the substore cache with the latest version of each substore.
     /// 5. Spawn a dispatcher task that forwards new snapshots to subscribers.
     pub async fn init(path: PathBuf, prefixes: Vec<String>) -> Result<Self> {
+        Storage::init_with_options(path, prefixes, StorageOptions::default()).await
+    }
+
+    /// Initializes a new storage instance at the given path like [`Storage::init`],
+    /// configuring it with the supplied [`StorageOptions`].
+    pub async fn init_with_options(
+        path: PathBuf,
+        prefixes: Vec<String>,
+        options: StorageOptions,
+    ) -> Result<Self> {
         let span = Span::current();
+        let verify_roots = options.verify_roots_on_load;
+        let change_log_retention = options
+            .change_log_retention
+            .unwrap_or(changes::DEFAULT_CHANGE_LOG_RETENTION);
 
-        tokio::task
+        let storage: Self = tokio::task
             ::spawn_blocking(move || {
                 span.in_scope(|| {
                     let mut substore_configs = Vec::new();
                     tracing::info!("initializing global store config");
-                    let main_store = Arc::new(SubstoreConfig::new(""));
+                    let main_store = Arc::new(
+                        SubstoreConfig::new("")
+                            .with_compression(options.compression_for(""))
+                            .with_nonverifiable_ttl(options.nonverifiable_ttl_for(""))
+                            .with_hash_values(options.hash_values_for("")),
+                    );
                     for substore_prefix in prefixes {
                         tracing::info!(prefix = ?substore_prefix, "creating substore config for prefix");
-                        if substore_prefix.is_empty() {
-                            bail!("the empty prefix is reserved")
-                        }
-                        substore_configs.push(Arc::new(SubstoreConfig::new(substore_prefix)));
+                        let compression = options.compression_for(&substore_prefix);
+                        let ttl = options.nonverifiable_ttl_for(&substore_prefix);
+                        let hash_values = options.hash_values_for(&substore_prefix);
+                        substore_configs.push(Arc::new(
+                            SubstoreConfig::new(substore_prefix)
+                                .with_compression(compression)
+                                .with_nonverifiable_ttl(ttl)
+                                .with_hash_values(hash_values),
+                        ));
                     }
-
                     let multistore_config = MultistoreConfig {
-                        main_store: main_store.clone(),
-                        substores: substore_configs.clone(),
+                        node_cache: (options.node_cache_capacity > 0)
+                            .then(|| Arc::new(NodeCache::new(options.node_cache_capacity))),
+                        value_cache: (options.value_cache_capacity > 0)
+                            .then(|| Arc::new(ValueCache::new(options.value_cache_capacity))),
+                        pins: Default::default(),
+                        read_retry: options.read_retry,
+                        iterator_limit: IteratorLimit::new(
+                            options.max_open_iterators,
+                            options.open_iterator_timeout,
+                        ),
+                        max_internal_nodes: options.max_internal_nodes_per_commit,
+                        ..MultistoreConfig::try_new(
+                            main_store.clone(),
+                            substore_configs.clone(),
+                            options.allow_nested_prefixes,
+                        )?
                     };
 
-                    let mut substore_columns: Vec<&String> = substore_configs
+                    // The column families share the block cache, if one is configured.
+                    let column_options = options.rocksdb.column_options();
+                    let mut substore_columns: Vec<ColumnFamilyDescriptor> = substore_configs
                         .iter()
-                        .flat_map(|config| config.columns())
+                        .flat_map(|config| config.column_descriptors(&column_options))
                         .collect();
-                    let mut columns: Vec<&String> = main_store.columns().collect();
+                    let mut columns: Vec<ColumnFamilyDescriptor> =
+                        main_store.column_descriptors(&column_options).collect();
                     columns.append(&mut substore_columns);
 
                     tracing::info!(?path, "opening rocksdb");
-                    let cf_config_string = "config".to_string();
                     // RocksDB setup: define options, collect all the columns, and open the database.
-                    // Each substore defines a prefix and its own set of columns.
+                    // Each substore defines a prefix and its own set of columns, configured
+                    // with the substore's options.
                     // See [`crate::store::SubstoreConfig`] for more details.
                     let mut opts = Options::default();
                     opts.create_if_missing(true);
                     opts.create_missing_column_families(true);
-                    columns.push(&cf_config_string);
+                    options.rocksdb.apply_to_db(&mut opts);
+                    columns.push(ColumnFamilyDescriptor::new("config", Options::default()));
 
-                    let db = DB::open_cf(&opts, path, columns)?;
+                    let db = DB::open_cf_descriptors(&opts, &path, columns)
+                        .map_err(|e| open_error(path.clone(), e))?;
                     let shared_db = Arc::new(db);
 
                     // Initialize the substore cache with the latest version of each substore.
-                    // Note: for compatibility reasons with Tendermint/CometBFT, we set the "pre-genesis"
-                    // jmt version to be u64::MAX, corresponding to -1 mod 2^64.
-                    let jmt_version = main_store
-                        .latest_version_from_db(&shared_db)?
-                        .unwrap_or(u64::MAX);
-
-                    let mut multistore_cache =
-                        multistore::MultistoreCache::from_config(multistore_config.clone());
-
-                    for substore_config in substore_configs {
-                        let substore_version = substore_config
-                            .latest_version_from_db(&shared_db)?
-                            .unwrap_or(u64::MAX);
-
-                        multistore_cache.set_version(substore_config.clone(), substore_version);
-                        tracing::debug!(
-                            substore_prefix = ?substore_config.prefix,
-                            ?substore_version,
-                            "initializing substore"
-                        );
-                    }
-
-                    multistore_cache.set_version(main_store, jmt_version);
-                    tracing::debug!(?jmt_version, "initializing main store");
-
                     let latest_snapshot =
-                        Snapshot::new(shared_db.clone(), jmt_version, multistore_cache);
+                        latest_snapshot_from_db(shared_db.clone(), &multistore_config)?;
 
                     // A concurrent-safe ring buffer of the latest 10 snapshots.
                     let snapshots = RwLock::new(SnapshotCache::new(latest_snapshot.clone(), 10));
@@ -224,11 +384,19 @@ impl Storage {
                         changes_rx,
                         multistore_config,
                         snapshots,
+                        change_log: RwLock::new(changes::ChangeLog::new(change_log_retention)),
+                        commit_lock: Mutex::new(()),
+                        pending_substores: RwLock::new(Vec::new()),
                         db: shared_db,
                     })))
                 })
             })
-            .await?
+            .await??;
+
+        if verify_roots {
+            storage.latest_snapshot().verify_substore_roots().await?;
+        }
+        Ok(storage)
     }
 
     /// Returns the latest version (block height) of the tree recorded by the
@@ -239,6 +407,16 @@ impl Storage {
         self.latest_snapshot().version()
     }
 
+    /// Returns the latest version of each substore, keyed by prefix, with the
+    /// main store under the empty prefix.
+    ///
+    /// Substore versions only advance when the substore changes, so they can
+    /// trail the version of the main store. A substore that was never written
+    /// to is at version `u64::MAX`.
+    pub fn substore_versions(&self) -> BTreeMap<String, jmt::Version> {
+        self.latest_snapshot().0.multistore_cache.versions()
+    }
+
     /// Returns a [`watch::Receiver`] that can be used to subscribe to new state versions.
     pub fn subscribe(&self) -> watch::Receiver<Snapshot> {
         let mut rx = self.0.snapshot_rx.clone();
@@ -257,6 +435,42 @@ impl Storage {
         rx
     }
 
+    /// Waits until version `version` has been committed, returning a
+    /// [`Snapshot`] of the latest version at that point, which may be newer.
+    ///
+    /// If the version is not committed within `timeout`, returns a
+    /// [`StorageError::VersionTimeout`].
+    pub async fn wait_for_version(
+        &self,
+        version: jmt::Version,
+        timeout: Duration,
+    ) -> Result<Snapshot> {
+        // Unlike `subscribe`, the current value is not marked as seen, so that
+        // an already committed version resolves immediately.
+        let mut rx = self.0.snapshot_rx.clone();
+        // A pre-genesis snapshot has version `u64::MAX`, and must not match.
+        let is_committed =
+            |snapshot: &Snapshot| snapshot.version() != u64::MAX && snapshot.version() >= version;
+
+        match tokio::time::timeout(timeout, rx.wait_for(is_committed)).await {
+            Ok(Ok(snapshot)) => Ok(snapshot.clone()),
+            Ok(Err(_)) => bail!("storage was released while waiting for version {version}"),
+            Err(_) => Err(StorageError::VersionTimeout {
+                version,
+                latest: self.latest_version(),
+            }
+            .into()),
+        }
+    }
+
+    /// Returns the oldest version pinned by a live [`ArchiveState`](crate::ArchiveState),
+    /// if any.
+    ///
+    /// Pruning must keep every version from this one onwards readable.
+    pub fn oldest_pinned_version(&self) -> Option<jmt::Version> {
+        self.0.multistore_config.pins.oldest()
+    }
+
     /// Returns a new [`Snapshot`] on top of the latest version of the tree.
     pub fn latest_snapshot(&self) -> Snapshot {
         self.0.snapshots.read().latest()
@@ -268,11 +482,78 @@ impl Storage {
         self.0.snapshots.read().get(version)
     }
 
+    /// Returns the root hash of the chain state at `version`, or `None` if
+    /// that version was not committed.
+    ///
+    /// Unlike [`Storage::snapshot`], this works for versions that are no
+    /// longer held in memory, since only the root node of the main tree at
+    /// `version` is read. See the crate documentation for how the root hash
+    /// commits to the substores.
+    pub async fn root_hash_at_version(
+        &self,
+        version: jmt::Version,
+    ) -> Result<Option<crate::RootHash>> {
+        let snapshot = self.latest_snapshot();
+        // The pre-genesis version wraps around to `u64::MAX`, and has no root.
+        if snapshot.version() == u64::MAX || version > snapshot.version() {
+            return Ok(None);
+        }
+
+        let main_store = SubstoreSnapshot {
+            config: self.0.multistore_config.main_store.clone(),
+            rocksdb_snapshot: snapshot.0.snapshot.clone(),
+            version,
+            db: self.0.db.clone(),
+        };
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let tree = jmt::Sha256Jmt::new(&main_store);
+                tree.get_root_hash_option(version)
+            })
+        })
+        .await?
+    }
+
     /// Prepares a commit for the provided [`StateDelta`], returning a [`StagedWriteBatch`].
     /// The batch can be committed to the database using the [`Storage::commit_batch`] method.
-    pub async fn prepare_commit(&self, delta: StateDelta<Snapshot>) -> Result<StagedWriteBatch> {
+    ///
+    /// A delta created [with conflict detection](StateDelta::with_conflict_detection)
+    /// that was forked from an older version is checked against the commits
+    /// made since, and its changes are then prepared on top of the latest
+    /// version.
+    pub async fn prepare_commit(
+        &self,
+        mut delta: StateDelta<Snapshot>,
+    ) -> Result<StagedWriteBatch> {
+        let read_set = delta.take_read_set();
         // Extract the snapshot and the changes from the state delta
-        let (snapshot, changes) = delta.flatten();
+        let (snapshot, changes) = delta.flatten()?;
+        let snapshot = match read_set {
+            Some(read_set) => self.rebase_checked(snapshot, &read_set, &changes)?,
+            None => snapshot,
+        };
+        self.prepare_commit_changes(snapshot, changes).await
+    }
+
+    /// Computes the root hash that committing the provided [`StateDelta`] would
+    /// produce, without committing it.
+    ///
+    /// This performs the same tree updates as [`Storage::prepare_commit`], and
+    /// discards the resulting write batch. Neither the delta nor the storage
+    /// are modified, so the delta can still be committed afterwards.
+    pub async fn compute_root(&self, delta: &StateDelta<Snapshot>) -> Result<crate::RootHash> {
+        let batch = self
+            .prepare_commit_changes(delta.snapshot(), delta.clone_changes()?)
+            .await?;
+        Ok(batch.root_hash)
+    }
+
+    async fn prepare_commit_changes(
+        &self,
+        snapshot: Snapshot,
+        changes: Cache,
+    ) -> Result<StagedWriteBatch> {
         let prev_snapshot_version = snapshot.version();
 
         // We use wrapping_add here so that we can write `new_version = 0` by
@@ -292,20 +573,63 @@ impl Storage {
             .await
     }
 
+    /// Replaces each prefix deletion in `cache` with a deletion of every key
+    /// under its prefix in `snapshot`, so that the deletions can be applied to
+    /// the trees.
+    ///
+    /// The keys are those listed by [`StateRead::prefix_keys`](crate::StateRead::prefix_keys),
+    /// along with the keys of each substore nested under the prefix. The roots
+    /// of the substores, which live in the main store, are not deleted.
+    async fn materialize_prefix_deletions(
+        &self,
+        snapshot: &Snapshot,
+        cache: &mut Cache,
+    ) -> Result<()> {
+        use crate::StateRead as _;
+        use futures::TryStreamExt;
+
+        let config = &self.0.multistore_config;
+        let prefixes: Vec<String> = cache.prefix_deletions().map(str::to_string).collect();
+        for prefix in prefixes {
+            let mut scans = vec![prefix.clone()];
+            scans.extend(
+                config
+                    .iter()
+                    .filter(|c| c.prefix_with_delimiter.starts_with(prefix.as_str()))
+                    .map(|c| c.prefix_with_delimiter.clone()),
+            );
+
+            let mut keys = std::collections::BTreeSet::new();
+            for scan in scans {
+                let found: Vec<String> = snapshot.prefix_keys(&scan).try_collect().await?;
+                keys.extend(
+                    found
+                        .into_iter()
+                        .filter(|key| config.substore(key).is_none()),
+                );
+            }
+            tracing::debug!(?prefix, count = keys.len(), "materializing prefix deletion");
+            cache.materialize_prefix_deletion(&prefix, keys)?;
+        }
+        Ok(())
+    }
+
     async fn prepare_commit_inner(
         &self,
         snapshot: Snapshot,
-        cache: Cache,
+        mut cache: Cache,
         version: jmt::Version,
         perform_migration: bool,
     ) -> Result<StagedWriteBatch> {
+        let start = std::time::Instant::now();
         tracing::debug!(new_jmt_version = ?version, "preparing to commit state delta");
+        self.materialize_prefix_deletions(&snapshot, &mut cache)
+            .await?;
         // Save a copy of the changes to send to subscribers later.
         let changes = Arc::new(cache.clone_changes());
 
-        let mut changes_by_substore = cache.shard_by_prefix(&self.0.multistore_config);
-        #[allow(clippy::disallowed_types)]
-        let mut substore_roots = HashMap::new();
+        let mut changes_by_substore = cache.shard_by_prefix(&self.0.multistore_config)?;
+        let mut substore_roots = BTreeMap::new();
         let mut multistore_versions =
             multistore::MultistoreCache::from_config(self.0.multistore_config.clone());
 
@@ -313,6 +637,7 @@ impl Storage {
         let rocksdb_snapshot = snapshot.0.snapshot.clone();
 
         let mut new_versions = vec![];
+        let mut internal_nodes = 0;
 
         // We use a single write batch to commit all the substores at once. Each task will append
         // its own changes to the batch, and we will commit it at the end.
@@ -339,7 +664,13 @@ impl Storage {
         //  The current implementation leans on the fact that the number of
         //  substores is small, and that the synchronization overhead of a joinset
         //  would exceed its benefits. This works well for now.
-        for config in self.0.multistore_config.iter() {
+        //
+        //  The substores are committed in ascending prefix order, regardless of
+        //  the order in which they were registered, so that every node builds
+        //  the same write batch and the same trace for the same changes.
+        let mut configs: Vec<_> = self.0.multistore_config.iter().collect();
+        configs.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        for config in configs {
             tracing::debug!(substore_prefix = ?config.prefix, "processing substore");
             // If the substore is empty, we need to fetch its initialized version from the cache.
             let old_substore_version = config
@@ -373,10 +704,11 @@ impl Storage {
             let substore_storage = SubstoreStorage { substore_snapshot };
 
             // Commit the substore and collect its root hash
-            let (root_hash, substore_batch) = substore_storage
+            let (root_hash, substore_batch, substore_internal_nodes) = substore_storage
                 .commit(changeset, write_batch, new_version, perform_migration)
                 .await?;
             write_batch = substore_batch;
+            internal_nodes += substore_internal_nodes;
 
             tracing::debug!(
                 ?root_hash,
@@ -404,6 +736,9 @@ impl Storage {
                 Cache::default()
             });
 
+        // The substore roots are inserted in ascending prefix order. The root of
+        // the main store does not depend on the order of its writes, so the
+        // global root hash only depends on the substore roots and their prefixes.
         for (config, (root_hash, _)) in substore_roots.iter() {
             main_store_changes
                 .unwritten_changes
@@ -422,9 +757,21 @@ impl Storage {
             substore_snapshot: main_store_snapshot,
         };
 
-        let (global_root_hash, write_batch) = main_store_storage
+        let (global_root_hash, write_batch, main_store_internal_nodes) = main_store_storage
             .commit(main_store_changes, write_batch, version, perform_migration)
             .await?;
+        internal_nodes += main_store_internal_nodes;
+
+        // Migrations are trusted, so they are not subject to the cap.
+        if let Some(max) = self.0.multistore_config.max_internal_nodes {
+            if internal_nodes > max && !perform_migration {
+                return Err(StorageError::TreeUpdateTooWide {
+                    internal_nodes,
+                    max,
+                }
+                .into());
+            }
+        }
         tracing::debug!(
             ?global_root_hash,
             ?version,
@@ -443,24 +790,148 @@ impl Storage {
             substore_roots,
             perform_migration,
             changes,
+            internal_nodes,
+            prepare_duration: start.elapsed(),
         })
     }
 
     /// Commits the provided [`StateDelta`] to persistent storage as the latest
     /// version of the chain state.
-    pub async fn commit(&self, delta: StateDelta<Snapshot>) -> Result<crate::RootHash> {
+    ///
+    /// Existing [`Snapshot`]s are not affected by the commit, even while it is
+    /// in progress: see the [`Snapshot`] documentation for the details of the
+    /// contract between reads and commits.
+    ///
+    /// Returns the newly committed version along with its root hash. Unlike
+    /// reading [`Storage::latest_version`] after the commit, this reports the
+    /// version created by this commit even if another one lands concurrently.
+    ///
+    /// # Determinism
+    /// The returned root hash only depends on the state being committed, not
+    /// on the order in which the substores were registered: each substore root
+    /// is written to the main store under the substore's prefix, in ascending
+    /// prefix order, and the root of the main store commits to its key-value
+    /// pairs regardless of the order in which they were written.
+    pub async fn commit(
+        &self,
+        delta: StateDelta<Snapshot>,
+    ) -> Result<(jmt::Version, crate::RootHash)> {
+        let batch = self
+            .prepare_commit(delta)
+            .instrument(tracing::debug_span!("prepare_commit"))
+            .await?;
+        let version = batch.version;
+        let (version, root_hash) =
+            tracing::debug_span!("commit_batch", version).in_scope(|| self.commit_batch(batch))?;
+        Ok((version, root_hash))
+    }
+
+    /// Commits the provided [`StateDelta`] like [`Storage::commit`], and returns
+    /// a fresh [`StateDelta`] on top of the newly committed version.
+    ///
+    /// The returned delta is equivalent to `StateDelta::new(storage.latest_snapshot())`
+    /// immediately after the commit, but it is built from the snapshot produced
+    /// by the commit itself, so it stays pinned to that version even if another
+    /// commit lands concurrently. The newly committed version and its root
+    /// hash are returned along with it.
+    pub async fn commit_and_continue(
+        &self,
+        delta: StateDelta<Snapshot>,
+    ) -> Result<(jmt::Version, crate::RootHash, StateDelta<Snapshot>)> {
         let batch = self.prepare_commit(delta).await?;
-        self.commit_batch(batch)
+        let (version, root_hash, snapshot) = self.commit_batch_inner(batch)?;
+        let snapshot = snapshot.expect("commits that advance the version publish a snapshot");
+        Ok((version, root_hash, StateDelta::new(snapshot)))
+    }
+
+    /// Commits the provided [`StateDelta`] like [`Storage::commit`], then
+    /// prunes every version older than the `keep_versions` most recent ones
+    /// with [`Storage::prune`], which describes the versions that are kept.
+    ///
+    /// The result of the commit is returned once it succeeds. If pruning then
+    /// fails, the failure is logged rather than returned, since the version is
+    /// committed all the same: the old versions are pruned by a later call, or
+    /// by calling [`Storage::prune`] directly.
+    pub async fn commit_and_prune(
+        &self,
+        delta: StateDelta<Snapshot>,
+        keep_versions: u64,
+    ) -> Result<(jmt::Version, crate::RootHash)> {
+        let (version, root_hash) = self.commit(delta).await?;
+        if let Err(error) = self.prune(keep_versions).await {
+            tracing::error!(
+                ?error,
+                ?version,
+                "failed to prune old versions after commit"
+            );
+        }
+        Ok((version, root_hash))
+    }
+
+    /// Commits the provided [`StateDelta`] like [`Storage::commit`], but only
+    /// if `invariant` holds over it.
+    ///
+    /// The invariant is evaluated against the delta itself, which reads as the
+    /// state that is about to be committed. If it returns an error, nothing is
+    /// committed, and its error is returned.
+    pub async fn commit_if<F>(
+        &self,
+        delta: StateDelta<Snapshot>,
+        invariant: F,
+    ) -> Result<(jmt::Version, crate::RootHash)>
+    where
+        F: for<'a> FnOnce(&'a StateDelta<Snapshot>) -> BoxFuture<'a, Result<()>>,
+    {
+        invariant(&delta).await?;
+        self.commit(delta).await
     }
 
-    /// Commits the supplied [`StagedWriteBatch`] to persistent storage.
+    /// Commits the provided [`StateDelta`] like [`Storage::commit`], recording
+    /// the commit under a `commit` span that is a child of `parent`.
+    ///
+    /// The commit work runs on blocking threads, where the caller's span would
+    /// not otherwise be current. This propagates the caller's trace context
+    /// across that boundary, even when the commit is driven from a task that
+    /// is not instrumented with `parent`.
+    pub async fn commit_with_span(
+        &self,
+        delta: StateDelta<Snapshot>,
+        parent: Span,
+    ) -> Result<(jmt::Version, crate::RootHash)> {
+        let span = tracing::debug_span!(parent: &parent, "commit");
+        self.commit(delta).instrument(span).await
+    }
+
+    /// Commits the supplied [`StagedWriteBatch`] to persistent storage, and
+    /// returns the committed version along with its root hash.
+    ///
+    /// # Atomicity
+    /// The tree nodes, values and nonverifiable writes of every substore and
+    /// of the main store are staged in a single RocksDB write batch, which is
+    /// written at once: either every substore advances to its new version, or
+    /// none does. Substore versions are read back from their trees when the
+    /// storage is loaded, and the in-memory versions are only updated once
+    /// the batch is written, so a commit that fails, panics, or is interrupted
+    /// by a crash before then leaves all the substores at their previous
+    /// versions.
     ///
     /// # Migrations
     /// In the case of chain state migrations we need to commit the new state
     /// without incrementing the version. If `perform_migration` is `true` the
     /// snapshot will _not_ be written to the snapshot cache, and no subscribers
     /// will be notified. Substore versions will not be updated.
-    pub fn commit_batch(&self, batch: StagedWriteBatch) -> Result<crate::RootHash> {
+    pub fn commit_batch(&self, batch: StagedWriteBatch) -> Result<(jmt::Version, crate::RootHash)> {
+        let (version, root_hash, _) = self.commit_batch_inner(batch)?;
+        Ok((version, root_hash))
+    }
+
+    /// Commits `batch` like [`Storage::commit_batch`], and also returns the
+    /// snapshot of the new version that the commit published, or `None` if
+    /// the batch performs a migration.
+    fn commit_batch_inner(
+        &self,
+        batch: StagedWriteBatch,
+    ) -> Result<(jmt::Version, crate::RootHash, Option<Snapshot>)> {
         let StagedWriteBatch {
             write_batch,
             version,
@@ -469,9 +940,14 @@ impl Storage {
             substore_roots,
             perform_migration,
             changes,
+            prepare_duration: _prepare_duration,
+            ..
         } = batch;
 
+        let _start = std::time::Instant::now();
         let db = self.0.db.clone();
+        // The lock is held until the new snapshot is published.
+        let _commit_guard = self.0.commit_lock.lock();
 
         // check that the version of the batch being committed is the correct next version
         let old_version = self.latest_version();
@@ -487,6 +963,7 @@ impl Storage {
             expected_new_version,
             version
         );
+        self.check_pending_substores(&changes)?;
 
         // also check that each of the substore versions are the correct next version
         let snapshot = self.latest_snapshot();
@@ -535,7 +1012,27 @@ impl Storage {
 
         tracing::debug!(new_jmt_version = ?batch.version, "committing batch to db");
 
-        db.write(write_batch).expect("can write to db");
+        #[cfg(feature = "metrics")]
+        let bytes_written = write_batch.size_in_bytes() as u64;
+        // Spilled changes are counted too, and a key spilled more than once
+        // counts once.
+        #[cfg(feature = "metrics")]
+        let keys_written = changes
+            .unwritten_keys()
+            .collect::<Result<std::collections::BTreeSet<_>>>()?
+            .len()
+            + changes.nonverifiable_changes().len();
+        // A failed write is surfaced rather than retried here, since the
+        // caller must decide whether to prepare and commit the delta again.
+        db.write(write_batch)
+            .map_err(|e| retry::classify(e.into()))?;
+        #[cfg(feature = "metrics")]
+        {
+            metrics::counter!(metrics::STORAGE_COMMIT_BYTES_WRITTEN).increment(bytes_written);
+            metrics::counter!(metrics::STORAGE_COMMIT_KEYS_WRITTEN).increment(keys_written as u64);
+            metrics::histogram!(metrics::STORAGE_COMMIT_DURATION)
+                .record(_prepare_duration + _start.elapsed());
+        }
         tracing::debug!(
             ?global_root_hash,
             ?version,
@@ -543,10 +1040,13 @@ impl Storage {
         );
 
         // If we're not performing a migration, we should update the snapshot cache
-        if !perform_migration {
+        let published = if !perform_migration {
             tracing::debug!("updating snapshot cache");
 
             let latest_snapshot = Snapshot::new(db.clone(), version, multistore_versions);
+            // The changes are logged before the snapshot is published, so that
+            // they are available to anyone who observes the new version.
+            self.0.change_log.write().push(version, changes.clone());
             // Obtain a write lock to the snapshot cache, and push the latest snapshot
             // available. The lock guard is implicitly dropped immediately.
             self.0
@@ -562,24 +1062,34 @@ impl Storage {
             let _ = self
                 .0
                 .dispatcher_tx
-                .send((latest_snapshot, (version, changes)));
+                .send((latest_snapshot.clone(), (version, changes)));
+            Some(latest_snapshot)
         } else {
             tracing::debug!("skipping snapshot cache update");
-        }
+            None
+        };
 
-        Ok(global_root_hash)
+        Ok((version, global_root_hash, published))
     }
 
     #[cfg(feature = "migration")]
     /// Commit the provided [`StateDelta`] to persistent storage without increasing the version
     /// of the chain state, and skips the snapshot cache update.
     pub async fn commit_in_place(&self, delta: StateDelta<Snapshot>) -> Result<crate::RootHash> {
-        let (snapshot, changes) = delta.flatten();
+        let (snapshot, changes) = delta.flatten()?;
         let old_version = self.latest_version();
         let batch = self
             .prepare_commit_inner(snapshot, changes, old_version, true)
             .await?;
-        self.commit_batch(batch)
+        let (_, root_hash) = self.commit_batch(batch)?;
+        // Committing in place rewrites the nodes and values of the current version.
+        if let Some(node_cache) = &self.0.multistore_config.node_cache {
+            node_cache.clear();
+        }
+        if let Some(value_cache) = &self.0.multistore_config.value_cache {
+            value_cache.clear();
+        }
+        Ok(root_hash)
     }
 
     /// Returns the internal handle to RocksDB, this is useful to test adjacent storage crates.
@@ -588,6 +1098,13 @@ impl Storage {
         self.0.db.clone()
     }
 
+    /// Evicts every in-memory snapshot but the `keep` latest ones, so that
+    /// older versions are read from the database.
+    #[cfg(test)]
+    pub(crate) fn evict_snapshots(&self, keep: usize) {
+        self.0.snapshots.write().retain_latest(keep);
+    }
+
     /// Shuts down the database and the dispatcher task, and waits for all resources to be reclaimed.
     /// Panics if there are still outstanding references to the `Inner` storage.
     pub async fn release(mut self) {
@@ -609,3 +1126,61 @@ impl Inner {
         }
     }
 }
+
+/// Reads the latest version of each substore from the database, and returns a
+/// [`Snapshot`] of the latest version of the multistore.
+pub(crate) fn latest_snapshot_from_db(
+    db: Arc<DB>,
+    multistore_config: &MultistoreConfig,
+) -> Result<Snapshot> {
+    let (jmt_version, multistore_cache) = latest_versions_from_db(&db, multistore_config)?;
+    Ok(Snapshot::new(db, jmt_version, multistore_cache))
+}
+
+/// Reads the latest version of the main store and of each substore from `db`.
+pub(crate) fn latest_versions_from_db(
+    db: &Arc<DB>,
+    multistore_config: &MultistoreConfig,
+) -> Result<(jmt::Version, multistore::MultistoreCache)> {
+    // Note: for compatibility reasons with Tendermint/CometBFT, we set the "pre-genesis"
+    // jmt version to be u64::MAX, corresponding to -1 mod 2^64.
+    let main_store = multistore_config.main_store.clone();
+    let jmt_version = main_store.latest_version_from_db(db)?.unwrap_or(u64::MAX);
+
+    let mut multistore_cache = multistore::MultistoreCache::from_config(multistore_config.clone());
+
+    for substore_config in multistore_config.iter() {
+        let substore_version = substore_config
+            .latest_version_from_db(db)?
+            .unwrap_or(u64::MAX);
+
+        multistore_cache.set_version(substore_config.clone(), substore_version);
+        tracing::debug!(
+            substore_prefix = ?substore_config.prefix,
+            ?substore_version,
+            "initializing substore"
+        );
+    }
+
+    multistore_cache.set_version(main_store, jmt_version);
+    tracing::debug!(?jmt_version, "initializing main store");
+
+    Ok((jmt_version, multistore_cache))
+}
+
+/// Converts an error from opening the database into an [`anyhow::Error`],
+/// reporting a held lock as [`StorageError::AlreadyOpen`].
+fn open_error(path: PathBuf, e: rocksdb::Error) -> anyhow::Error {
+    // RocksDB reports a held lock as an I/O error on the `LOCK` file, whether
+    // it is held by this process or by another one.
+    let message = e.to_string();
+    let lock_file = path.join("LOCK");
+    if matches!(e.kind(), rocksdb::ErrorKind::IOError)
+        && message.contains(lock_file.to_string_lossy().as_ref())
+    {
+        tracing::error!(?path, %message, "database is locked by another instance");
+        StorageError::AlreadyOpen { path }.into()
+    } else {
+        anyhow::Error::from(e).context(format!("failed to open database at {}", path.display()))
+    }
+}
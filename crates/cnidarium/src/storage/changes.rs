@@ -0,0 +1,286 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use futures::Stream;
+use tracing::Span;
+
+use crate::{delta::ReadSet, Cache, OverlayOp, Snapshot, StateRead, Storage, StorageError};
+
+/// The number of commits whose changes are kept in memory by default, see
+/// [`StorageOptions::change_log_retention`](crate::StorageOptions::change_log_retention).
+pub(crate) const DEFAULT_CHANGE_LOG_RETENTION: usize = 10;
+
+/// The verifiable changes of the most recent commits, kept in memory to serve
+/// [`Storage::changed_keys_since`].
+#[derive(Debug)]
+pub(crate) struct ChangeLog {
+    /// A sequence of increasingly recent `(version, changes)` pairs.
+    entries: VecDeque<(jmt::Version, Arc<Cache>)>,
+    capacity: usize,
+}
+
+impl ChangeLog {
+    /// Creates an empty log retaining the changes of the last `capacity` commits.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records the changes committed at `version`, evicting the oldest entry
+    /// if the log is full.
+    pub(crate) fn push(&mut self, version: jmt::Version, changes: Arc<Cache>) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((version, changes));
+    }
+
+    /// Returns the changes committed at `version`.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError::ChangesNotRetained`] error if they are no
+    /// longer retained.
+    pub(super) fn at(&self, version: jmt::Version) -> Result<Arc<Cache>> {
+        self.entries
+            .iter()
+            .find(|(v, _)| *v == version)
+            .map(|(_, changes)| changes.clone())
+            .ok_or_else(|| StorageError::ChangesNotRetained { version }.into())
+    }
+
+    /// Returns the changes committed after `version`, from oldest to newest.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError::ChangesNotRetained`] error if some of them
+    /// are no longer retained.
+    pub(super) fn since(
+        &self,
+        version: jmt::Version,
+        latest: jmt::Version,
+    ) -> Result<Vec<Arc<Cache>>> {
+        // Versions are compared by their distance to the latest one, so that the
+        // pre-genesis version, `u64::MAX`, sorts before version 0.
+        let depth = latest.wrapping_sub(version);
+        let changes: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(v, _)| latest.wrapping_sub(*v) < depth)
+            .map(|(_, changes)| changes.clone())
+            .collect();
+        if changes.len() as u64 != depth {
+            return Err(StorageError::ChangesNotRetained {
+                version: version.wrapping_add(1),
+            }
+            .into());
+        }
+        Ok(changes)
+    }
+}
+
+impl Storage {
+    /// Checks the reads and writes of a delta forked from `snapshot` against
+    /// the commits made since, returning the latest snapshot to prepare its
+    /// `changes` on top of.
+    ///
+    /// Only the changes of the most recent commits are kept in memory, as set
+    /// by [`StorageOptions::change_log_retention`](crate::StorageOptions::change_log_retention),
+    /// which bounds how far behind the latest version the delta can be.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError::Conflict`] for the first key that one of
+    /// those commits changed and that the delta read or wrote, including the
+    /// nonverifiable keys that it wrote, or a [`StorageError::ChangesNotRetained`]
+    /// error if the changes of those commits are no longer retained.
+    pub(super) fn rebase_checked(
+        &self,
+        snapshot: Snapshot,
+        read_set: &ReadSet,
+        changes: &Cache,
+    ) -> Result<Snapshot> {
+        let latest = self.latest_snapshot();
+        let version = snapshot.version();
+        if version == latest.version() {
+            return Ok(snapshot);
+        }
+
+        let log = self.0.change_log.read().since(version, latest.version())?;
+        for committed in log {
+            for key in committed.unwritten_keys() {
+                let key = key?;
+                if read_set.contains(&key) || changes.get_unwritten(&key)?.is_some() {
+                    return Err(StorageError::Conflict { key }.into());
+                }
+            }
+            // Nonverifiable reads are not tracked, but blind writes still
+            // conflict, as they do for verifiable keys.
+            for key in committed.nonverifiable_changes().keys() {
+                if changes.nonverifiable_changes().contains_key(key) {
+                    let key = format!("{:?}", crate::EscapedByteSlice(key));
+                    return Err(StorageError::Conflict { key }.into());
+                }
+            }
+        }
+        Ok(latest)
+    }
+
+    /// Streams the verifiable writes that produced `version`, in key order.
+    ///
+    /// Each key written by the commit is reported once, with its raw bytes and
+    /// the value it was set to, or `None` if it was deleted. Unlike
+    /// [`Storage::changed_keys_since`], the writes are reported as they were
+    /// committed, so a deletion is reported even if the key was absent before.
+    /// Nonverifiable writes are not included.
+    ///
+    /// The writes are taken from the changes kept in memory when the version
+    /// was committed, so neither the trees nor the previous version are read.
+    /// Only the changes of the most recent commits made by this instance are
+    /// kept, as configured by [`StorageOptions::change_log_retention`](crate::StorageOptions::change_log_retention):
+    /// an indexer that falls further behind, or that reads the changes of
+    /// versions committed before a restart, has to read the state instead.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError::ChangesNotRetained`] error if `version` was
+    /// not committed by this instance, or if its changes are no longer
+    /// retained.
+    pub async fn changeset(
+        &self,
+        version: jmt::Version,
+    ) -> Result<impl Stream<Item = Result<(Vec<u8>, Option<Vec<u8>>)>>> {
+        let changes = self.0.change_log.read().at(version)?;
+        let writes: Vec<_> = changes
+            .unwritten_changes()?
+            .into_iter()
+            .map(|(key, value)| Ok((key.into_bytes(), value)))
+            .collect();
+        Ok(futures::stream::iter(writes))
+    }
+
+    /// Returns the net changes to the verifiable store between `version` and
+    /// the latest version, in key order.
+    ///
+    /// Each changed key is reported once, with its latest value, or with
+    /// `None` if it was deleted. A deletion is only reported for a key that
+    /// was present at `version`: a key that was never present, or that was
+    /// written and deleted again after `version`, is omitted. As with the
+    /// changes sent to [`Storage::subscribe_changes`] subscribers, incremental
+    /// consumers can thus tell deletions apart from untouched keys.
+    ///
+    /// # Errors
+    /// The changes of the most recent commits are only kept in memory, so this
+    /// returns an error if `version` is newer than the latest version, or if it
+    /// is too old for its snapshot or some of its changes to still be retained.
+    pub async fn changed_keys_since(
+        &self,
+        version: jmt::Version,
+    ) -> Result<Vec<(String, Option<Vec<u8>>)>> {
+        let latest = self.latest_version();
+        let base = self
+            .snapshot(version)
+            .ok_or_else(|| anyhow::anyhow!("version {version} is not retained in memory"))?;
+        let log = self.0.change_log.read().since(version, latest)?;
+
+        let mut net = BTreeMap::new();
+        for changes in log {
+            net.extend(changes.unwritten_changes()?);
+        }
+
+        let mut diff = Vec::with_capacity(net.len());
+        for (key, value) in net {
+            if value.is_none() && base.get_raw(&key).await?.is_none() {
+                continue;
+            }
+            diff.push((key, value));
+        }
+        Ok(diff)
+    }
+
+    /// Returns the writes that roll the state back from the latest version to
+    /// `to`, in key order, verifiable writes first.
+    ///
+    /// This is the inverse of replaying the commits made after `to`: applying
+    /// the writes to the latest state, e.g. with
+    /// [`StateWriteExt::replay_ops`](crate::StateWriteExt::replay_ops), yields
+    /// the state at `to`. Each key changed since `to` is restored to the value
+    /// it had at `to`, or deleted if it was added afterwards. Keys that were
+    /// changed and then restored to their value at `to` are omitted.
+    ///
+    /// The keys changed since `to` are found in the versioned value index of
+    /// every substore, and their values at `to` are read with
+    /// [`Storage::state_at_version`], so `to` does not need to be held in
+    /// memory. The index is read in full, so this is meant for rare operations
+    /// like reorgs. The preimage of a key deleted after `to` is no longer
+    /// indexed, and is recovered from the changes of the most recent commits,
+    /// see [`StorageOptions::change_log_retention`](crate::StorageOptions::change_log_retention).
+    ///
+    /// # Nonverifiable writes
+    /// The nonverifiable store is not versioned, so its writes are only rolled
+    /// back while the snapshot of `to` and the changes of every commit made
+    /// since are held in memory. Otherwise, they are left out of the plan, and
+    /// a warning is logged.
+    ///
+    /// # Errors
+    /// Returns an error if `to` is newer than the latest version, if it was
+    /// pruned, or if a key deleted after `to` is no longer known.
+    pub async fn rollback_plan(&self, to: jmt::Version) -> Result<Vec<OverlayOp>> {
+        let latest = self.latest_snapshot();
+        let base = self.state_at_version(to).await?;
+        let log = self.0.change_log.read().since(to, latest.version()).ok();
+
+        let mut known_keys = BTreeSet::new();
+        let mut nonverifiable_keys = BTreeSet::new();
+        for changes in log.iter().flatten() {
+            for key in changes.unwritten_keys() {
+                known_keys.insert(key?);
+            }
+            nonverifiable_keys.extend(changes.nonverifiable_changes().keys().cloned());
+        }
+        let nonverifiable_base = match (&log, self.snapshot(to)) {
+            (Some(_), Some(snapshot)) => Some(snapshot),
+            _ => {
+                tracing::warn!(
+                    to,
+                    "the nonverifiable writes made since are not retained, and are not rolled back"
+                );
+                None
+            }
+        };
+
+        let span = Span::current();
+        let keys = tokio::task::spawn_blocking({
+            let latest = latest.clone();
+            move || span.in_scope(|| super::history::keys_written_since(&latest, to, &known_keys))
+        })
+        .await??;
+
+        let mut plan = Vec::with_capacity(keys.len() + nonverifiable_keys.len());
+        for key in keys {
+            let old = base.get_raw(&key).await?;
+            if old == latest.get_raw(&key).await? {
+                continue;
+            }
+            plan.push(match old {
+                Some(value) => OverlayOp::Put { key, value },
+                None => OverlayOp::Delete { key },
+            });
+        }
+        let Some(nonverifiable_base) = nonverifiable_base else {
+            return Ok(plan);
+        };
+        for key in nonverifiable_keys {
+            let old = nonverifiable_base.nonverifiable_get_raw(&key).await?;
+            if old == latest.nonverifiable_get_raw(&key).await? {
+                continue;
+            }
+            plan.push(match old {
+                Some(value) => OverlayOp::NonverifiablePut { key, value },
+                None => OverlayOp::NonverifiableDelete { key },
+            });
+        }
+        Ok(plan)
+    }
+}
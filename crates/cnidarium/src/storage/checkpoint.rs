@@ -0,0 +1,330 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
+use tracing::Span;
+
+use crate::{OverlayOp, RootHash, StateDelta, StateWriteExt, Storage};
+
+/// The contents of an incremental checkpoint file, as written by
+/// [`Storage::incremental_checkpoint`].
+#[derive(BorshSerialize, BorshDeserialize)]
+struct IncrementalCheckpoint {
+    /// The version the checkpoint applies on top of.
+    since_version: jmt::Version,
+    /// The version reached once the checkpoint is applied.
+    version: jmt::Version,
+    /// The root hash of the main store at `version`.
+    root_hash: [u8; 32],
+    /// The changes committed at each version after `since_version`, in order.
+    commits: Vec<(jmt::Version, Vec<OverlayOp>)>,
+}
+
+/// The magic bytes opening a snapshot export file.
+const EXPORT_MAGIC: &[u8; 8] = b"CNIDSNAP";
+
+/// The header of a snapshot export file, as written by
+/// [`Storage::export_snapshot`]. It is followed by the contents of each file
+/// of the checkpoint, in order.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct ExportHeader {
+    /// The version the export was taken at.
+    version: jmt::Version,
+    /// The root hash of the main store at `version`.
+    root_hash: [u8; 32],
+    /// The version of each substore at `version`, keyed by prefix, with the
+    /// main store under the empty prefix.
+    substore_versions: BTreeMap<String, jmt::Version>,
+    /// The name and size of each file of the checkpoint.
+    files: Vec<(String, u64)>,
+}
+
+impl Storage {
+    /// Writes a full checkpoint of the database to the `target` directory,
+    /// which must not exist yet.
+    ///
+    /// The checkpoint is a consistent copy of the database, that can be opened
+    /// with [`Storage::load`]. Its files are hard-linked to the live ones when
+    /// the target is on the same filesystem, so it is cheap to create.
+    pub async fn checkpoint(&self, target: PathBuf) -> Result<()> {
+        let span = Span::current();
+        let db = self.0.db.clone();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let checkpoint = rocksdb::checkpoint::Checkpoint::new(&db)?;
+                checkpoint.create_checkpoint(&target)?;
+                tracing::info!(?target, "created checkpoint");
+                anyhow::Ok(())
+            })
+        })
+        .await?
+    }
+
+    /// Writes an incremental checkpoint to the `target` file, holding the
+    /// changes committed after `since_version`, up to the latest version.
+    ///
+    /// The file holds the verifiable and nonverifiable writes of each of these
+    /// versions, rather than a copy of the database files, so its size is
+    /// proportional to the volume of changes. It is applied with
+    /// [`Storage::apply_incremental_checkpoint`] on top of a checkpoint at
+    /// `since_version`.
+    ///
+    /// # Errors
+    /// The changes of the most recent commits are only kept in memory, so this
+    /// refuses to write a checkpoint if `since_version` has been pruned, or if
+    /// some of the changes since then are no longer retained.
+    pub async fn incremental_checkpoint(
+        &self,
+        since_version: jmt::Version,
+        target: PathBuf,
+    ) -> Result<()> {
+        let snapshot = self.latest_snapshot();
+        let version = snapshot.version();
+        ensure!(
+            self.snapshot(since_version).is_some(),
+            "version {since_version} has been pruned"
+        );
+        let changes = self.0.change_log.read().since(since_version, version)?;
+
+        let checkpoint = IncrementalCheckpoint {
+            since_version,
+            version,
+            root_hash: snapshot.root_hash().await?.0,
+            commits: (1..)
+                .map(|offset| since_version.wrapping_add(offset))
+                .zip(changes)
+                .map(|(version, changes)| {
+                    Ok((version, changes.clone_changes().into_overlay_ops()?))
+                })
+                .collect::<Result<_>>()?,
+        };
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                std::fs::write(&target, borsh::to_vec(&checkpoint)?)?;
+                tracing::info!(
+                    ?target,
+                    since_version,
+                    version,
+                    "created incremental checkpoint"
+                );
+                anyhow::Ok(())
+            })
+        })
+        .await?
+    }
+
+    /// Applies the incremental checkpoint in the `source` file, as written by
+    /// [`Storage::incremental_checkpoint`], returning the new root hash.
+    ///
+    /// Each version recorded in the checkpoint is committed in turn, so that
+    /// the storage reaches the same version, with the same root hash, as the
+    /// storage the checkpoint was taken from.
+    ///
+    /// # Errors
+    /// Returns an error if the latest version of this storage is not the one
+    /// the checkpoint applies on top of, or if the resulting root hash does not
+    /// match the recorded one. Versions committed before a mismatch is detected
+    /// are not rolled back.
+    pub async fn apply_incremental_checkpoint(&self, source: PathBuf) -> Result<RootHash> {
+        let bytes = tokio::fs::read(&source).await?;
+        let checkpoint = IncrementalCheckpoint::try_from_slice(&bytes)?;
+        ensure!(
+            self.latest_version() == checkpoint.since_version,
+            "incremental checkpoint applies on top of version {}, but the latest version is {}",
+            checkpoint.since_version,
+            self.latest_version()
+        );
+
+        let mut root_hash = self.latest_snapshot().root_hash().await?;
+        for (version, ops) in &checkpoint.commits {
+            let mut delta = StateDelta::new(self.latest_snapshot());
+            delta.replay_ops(ops);
+            let (committed, committed_root) = self.commit(delta).await?;
+            root_hash = committed_root;
+            ensure!(
+                committed == *version,
+                "expected to reach version {version}, but reached version {committed}"
+            );
+        }
+
+        ensure!(
+            root_hash.0 == checkpoint.root_hash,
+            "root hash after applying the incremental checkpoint does not match the recorded one"
+        );
+        Ok(root_hash)
+    }
+
+    /// Exports the database at `version` to the `out` file, which must not
+    /// exist yet.
+    ///
+    /// The export is a [checkpoint](Storage::checkpoint) of every column family,
+    /// packed into a single file along with the root hash and the version of
+    /// each substore, so that it can be moved to another machine and restored
+    /// with [`Storage::import_snapshot`]. Taking the checkpoint only holds up
+    /// commits for as long as RocksDB takes to flush its memtables and link
+    /// the files, and the file is then written from the checkpoint while
+    /// commits go on. The checkpoint is kept in a temporary directory next to
+    /// `out`, so that its files can be hard-linked.
+    ///
+    /// # Errors
+    /// A checkpoint holds the latest state of the database, so `version` must
+    /// still be the latest version when the checkpoint is taken. If a commit
+    /// lands in the meantime, this returns an error, and the export can be
+    /// retried at the new latest version.
+    pub async fn export_snapshot(&self, version: jmt::Version, out: &Path) -> Result<()> {
+        ensure!(
+            self.latest_version() == version,
+            "version {version} is not the latest version, {}",
+            self.latest_version()
+        );
+        ensure!(!out.exists(), "{} already exists", out.display());
+
+        let parent = match out.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let scratch = tempfile::Builder::new()
+            .prefix(".cnidarium-export")
+            .tempdir_in(parent)?;
+        let checkpoint = scratch.path().join("checkpoint");
+        self.checkpoint(checkpoint.clone()).await?;
+
+        // Read the versions back from the checkpoint itself, in case a commit
+        // landed before it was taken.
+        let exported = Storage::load_read_only(checkpoint.clone()).await?;
+        let snapshot = exported.latest_snapshot();
+        ensure!(
+            snapshot.version() == version,
+            "version {version} was superseded by version {} before the checkpoint was taken",
+            snapshot.version()
+        );
+        let root_hash = snapshot.root_hash().await?.0;
+        let substore_versions = snapshot.0.multistore_cache.versions();
+        std::mem::drop(snapshot);
+        std::mem::drop(exported);
+
+        let out = out.to_path_buf();
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let mut files = Vec::new();
+                for entry in std::fs::read_dir(&checkpoint)? {
+                    let entry = entry?;
+                    let metadata = entry.metadata()?;
+                    ensure!(metadata.is_file(), "checkpoint holds a directory");
+                    let name = entry
+                        .file_name()
+                        .into_string()
+                        .map_err(|name| anyhow::anyhow!("invalid file name {name:?}"))?;
+                    files.push((name, metadata.len()));
+                }
+                files.sort();
+
+                let header = ExportHeader {
+                    version,
+                    root_hash,
+                    substore_versions,
+                    files,
+                };
+                let mut writer = BufWriter::new(File::create_new(&out)?);
+                writer.write_all(EXPORT_MAGIC)?;
+                borsh::to_writer(&mut writer, &header)?;
+                for (name, size) in &header.files {
+                    let copied =
+                        std::io::copy(&mut File::open(checkpoint.join(name))?, &mut writer)?;
+                    ensure!(copied == *size, "{name} changed while it was exported");
+                }
+                writer
+                    .into_inner()
+                    .map_err(|e| e.into_error())?
+                    .sync_all()?;
+
+                tracing::info!(?out, version, "exported snapshot");
+                anyhow::Ok(())
+            })
+        })
+        .await?
+    }
+
+    /// Restores a database exported by [`Storage::export_snapshot`] from the
+    /// `input` file into the `dest` directory, which must not exist yet.
+    ///
+    /// The restored database can be opened with [`Storage::load`], and is at
+    /// the exported version. Before returning, it is opened read-only to check
+    /// that its root hash and substore versions match the ones recorded in the
+    /// export. If they don't, or if the file is truncated, `dest` is removed.
+    pub async fn import_snapshot(input: &Path, dest: &Path) -> Result<()> {
+        ensure!(!dest.exists(), "{} already exists", dest.display());
+
+        let input = input.to_path_buf();
+        let dest_path = dest.to_path_buf();
+        let span = Span::current();
+        let header = tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let mut reader = BufReader::new(File::open(&input)?);
+                let mut magic = [0u8; 8];
+                reader.read_exact(&mut magic)?;
+                ensure!(
+                    &magic == EXPORT_MAGIC,
+                    "{} is not a snapshot export",
+                    input.display()
+                );
+                let header = ExportHeader::deserialize_reader(&mut reader)?;
+
+                std::fs::create_dir_all(&dest_path)?;
+                for (name, size) in &header.files {
+                    // Files are restored into `dest` only.
+                    if Path::new(name).file_name() != Some(name.as_ref()) {
+                        bail!("invalid file name {name:?} in snapshot export");
+                    }
+                    let mut file = File::create_new(dest_path.join(name))?;
+                    let copied = std::io::copy(&mut (&mut reader).take(*size), &mut file)?;
+                    ensure!(copied == *size, "snapshot export is truncated at {name}");
+                    file.sync_all()?;
+                }
+                anyhow::Ok(header)
+            })
+        })
+        .await?;
+
+        let verified = match header {
+            Ok(header) => Self::verify_import(dest, &header).await,
+            Err(e) => Err(e),
+        };
+        if verified.is_err() && dest.exists() {
+            let _ = std::fs::remove_dir_all(dest);
+        }
+        verified
+    }
+
+    /// Checks that the database restored in `dest` matches `header`.
+    async fn verify_import(dest: &Path, header: &ExportHeader) -> Result<()> {
+        let restored = Storage::load_read_only(dest.to_path_buf())
+            .await
+            .context("failed to open the restored database")?;
+        let snapshot = restored.latest_snapshot();
+        ensure!(
+            snapshot.version() == header.version,
+            "restored database is at version {}, but the export is at version {}",
+            snapshot.version(),
+            header.version
+        );
+        ensure!(
+            snapshot.root_hash().await?.0 == header.root_hash,
+            "root hash of the restored database does not match the exported one"
+        );
+        ensure!(
+            snapshot.0.multistore_cache.versions() == header.substore_versions,
+            "substore versions of the restored database do not match the exported ones"
+        );
+        tracing::info!(?dest, version = header.version, "imported snapshot");
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use rocksdb::{Options, DB};
+
+use crate::Storage;
+
+/// The role of a column family, as reported by [`Storage::column_families`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ColumnFamilyRole {
+    /// The JMT key and value indexes of the main store.
+    MainStore,
+    /// One of the column families of the substore with the given prefix.
+    Substore(String),
+    /// The nonverifiable data of the main store.
+    NonVerifiable,
+    /// The storage configuration, i.e. the list of substore prefixes.
+    Metadata,
+    /// The JMT nodes of the main store.
+    JmtNodes,
+    /// The default column family that RocksDB always creates, which only
+    /// records the oldest version left by [`Storage::prune`](crate::Storage::prune).
+    Default,
+    /// A column family that is present on disk but not part of the current
+    /// configuration, e.g. one left over from a substore that was dropped.
+    Unknown,
+}
+
+/// A column family of the database, as reported by [`Storage::column_families`].
+#[derive(Clone, Debug)]
+pub struct ColumnFamilyInfo {
+    /// The name of the column family.
+    pub name: String,
+    /// The role of the column family.
+    pub role: ColumnFamilyRole,
+    /// The estimated size of the live data in the column family, in bytes, or
+    /// `None` if the column family is not open.
+    pub approximate_size: Option<u64>,
+}
+
+impl Storage {
+    /// Lists every column family of the database, with its role and
+    /// approximate size.
+    ///
+    /// Column families are listed from the database on disk, so those that
+    /// are not part of the current configuration, and therefore not open, are
+    /// reported with the [`ColumnFamilyRole::Unknown`] role.
+    pub fn column_families(&self) -> Result<Vec<ColumnFamilyInfo>> {
+        let db = &self.0.db;
+        let config = &self.0.multistore_config;
+        let main_store = &config.main_store;
+
+        let names = DB::list_cf(&Options::default(), db.path())?;
+        let mut families = Vec::with_capacity(names.len());
+        for name in names {
+            let role = if name == "config" {
+                ColumnFamilyRole::Metadata
+            } else if name == rocksdb::DEFAULT_COLUMN_FAMILY_NAME {
+                ColumnFamilyRole::Default
+            } else if name == main_store.cf_jmt_name() {
+                ColumnFamilyRole::JmtNodes
+            } else if name == main_store.cf_nonverifiable_name() {
+                ColumnFamilyRole::NonVerifiable
+            } else if main_store.columns().any(|column| *column == name) {
+                ColumnFamilyRole::MainStore
+            } else if let Some(substore) = config
+                .iter()
+                .find(|substore| substore.columns().any(|column| *column == name))
+            {
+                ColumnFamilyRole::Substore(substore.prefix.clone())
+            } else {
+                ColumnFamilyRole::Unknown
+            };
+
+            let approximate_size = match db.cf_handle(&name) {
+                Some(cf) => db.property_int_value_cf(cf, "rocksdb.estimate-live-data-size")?,
+                None => None,
+            };
+
+            families.push(ColumnFamilyInfo {
+                name,
+                role,
+                approximate_size,
+            });
+        }
+        Ok(families)
+    }
+}
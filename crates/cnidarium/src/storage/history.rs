@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{ensure, Result};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt, TryStreamExt,
+};
+use jmt::storage::HasPreimage;
+use tracing::Span;
+
+use crate::{
+    store::{multistore::MultistoreCache, substore::SubstoreSnapshot},
+    ArchiveState, Snapshot, Storage, StorageError,
+};
+
+impl Storage {
+    /// Returns the history of the verifiable `key`: every version at which it
+    /// was written, in increasing order, along with the value written, or
+    /// `None` if it was deleted.
+    ///
+    /// The writes are found by looking up the key in the versioned value index
+    /// of its substore, so only the versions at which this key changed are
+    /// read, rather than every version.
+    ///
+    /// Substores are versioned independently of the main store. Their versions
+    /// are mapped back to versions of the chain state using the history of the
+    /// substore root, which the main store records under the substore prefix
+    /// at every version where the substore changed.
+    ///
+    /// Only the retained history is reported: if the values written at early
+    /// versions are no longer in the database, the stream starts with the
+    /// earliest one still present.
+    pub fn key_history(
+        &self,
+        key: &str,
+    ) -> BoxStream<'static, Result<(jmt::Version, Option<Vec<u8>>)>> {
+        let storage = self.clone();
+        let key = key.to_string();
+        stream::once(async move { storage.key_history_inner(&key).await })
+            .map_ok(|history| stream::iter(history.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn key_history_inner(&self, key: &str) -> Result<Vec<(jmt::Version, Option<Vec<u8>>)>> {
+        let snapshot = self.latest_snapshot();
+        let multistore_cache = &snapshot.0.multistore_cache;
+        let main_store_config = multistore_cache.config.main_store.clone();
+        let (substore_key, config) = multistore_cache.config.route_key_str(key);
+        let key_hash = jmt::KeyHash::with::<sha2::Sha256>(substore_key);
+        let root_key_hash = jmt::KeyHash::with::<sha2::Sha256>(config.prefix.as_bytes());
+
+        let main_store = SubstoreSnapshot {
+            config: main_store_config.clone(),
+            rocksdb_snapshot: snapshot.0.snapshot.clone(),
+            version: snapshot.version(),
+            db: snapshot.0.db.clone(),
+        };
+        let substore = (config != main_store_config).then(|| SubstoreSnapshot {
+            version: snapshot
+                .substore_version(&config)
+                .expect("the substore exists and has been initialized"),
+            config,
+            rocksdb_snapshot: snapshot.0.snapshot.clone(),
+            db: snapshot.0.db.clone(),
+        });
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let Some(substore) = substore else {
+                    return main_store.get_value_history(key_hash);
+                };
+
+                // The substore version is incremented by every commit that
+                // records its root, so the roots line up with the most recent
+                // substore versions.
+                let roots = main_store.get_value_history(root_key_hash)?;
+                let history = substore.get_value_history(key_hash)?;
+                if history.is_empty() {
+                    return Ok(history);
+                }
+                let first_substore_version = substore
+                    .version()
+                    .checked_add(1)
+                    .and_then(|next| next.checked_sub(roots.len() as u64))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "substore {} has more recorded roots than versions",
+                            substore.config.prefix
+                        )
+                    })?;
+
+                Ok(history
+                    .into_iter()
+                    .filter_map(|(substore_version, value)| {
+                        let index = substore_version.checked_sub(first_substore_version)?;
+                        let (version, _) = roots[index as usize];
+                        Some((version, value))
+                    })
+                    .collect())
+            })
+        })
+        .await?
+    }
+
+    /// Returns a read-only view of the chain state as committed at `version`.
+    ///
+    /// The version of each substore at `version` is recovered from the history
+    /// of its root in the main store, so that verifiable reads return exactly
+    /// what was committed at `version`, across all substores, even once that
+    /// version is no longer held in memory.
+    ///
+    /// Historical states are read-only: unlike a [`Snapshot`], an
+    /// [`ArchiveState`] cannot be used to build a
+    /// [`StateDelta`](crate::StateDelta) that gets committed.
+    ///
+    /// If `version` is no longer retained in memory, only the versioned values
+    /// of the trees can be read. Prefix scans of the verifiable store, which go
+    /// through the key preimage index, and reads of the nonverifiable store
+    /// fail with [`StorageError::UnversionedRead`](crate::StorageError::UnversionedRead).
+    ///
+    /// # Errors
+    /// Returns an error if `version` has not been committed yet, and a
+    /// [`StorageError::VersionPruned`] error if it was removed by
+    /// [`Storage::prune`].
+    pub async fn state_at_version(&self, version: jmt::Version) -> Result<ArchiveState> {
+        if let Some(snapshot) = self.snapshot(version) {
+            return Ok(snapshot.into_archive());
+        }
+
+        let latest = self.latest_snapshot();
+        // The pre-genesis version wraps around to `u64::MAX`.
+        ensure!(
+            latest.version() != u64::MAX && version <= latest.version(),
+            "version {version} has not been committed (latest version is {})",
+            latest.version()
+        );
+
+        if let Some(oldest) = self.oldest_available_version()? {
+            if version < oldest {
+                return Err(StorageError::VersionPruned { version, oldest }.into());
+            }
+        }
+
+        let db = self.0.db.clone();
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let multistore_cache = multistore_cache_at(&latest, version)?;
+                Ok(Snapshot::historical(db, version, multistore_cache).into_archive())
+            })
+        })
+        .await?
+    }
+}
+
+/// Returns the version of every substore at the past `version`, which must
+/// not be newer than the `latest` snapshot.
+///
+/// Every commit that changed a substore recorded its new root in the main
+/// store, and incremented its version, so the substore version at `version`
+/// is found by counting the roots recorded after it.
+pub(super) fn multistore_cache_at(
+    latest: &Snapshot,
+    version: jmt::Version,
+) -> Result<MultistoreCache> {
+    let config = latest.0.multistore_cache.config.clone();
+    let main_store = SubstoreSnapshot {
+        config: config.main_store.clone(),
+        rocksdb_snapshot: latest.0.snapshot.clone(),
+        version: latest.version(),
+        db: latest.0.db.clone(),
+    };
+
+    let mut multistore_cache = MultistoreCache::from_config(config.clone());
+    for substore_config in config.iter() {
+        let latest_version = latest.substore_version(substore_config).unwrap_or(u64::MAX);
+        let root_key_hash = jmt::KeyHash::with::<sha2::Sha256>(substore_config.prefix.as_bytes());
+        let roots = main_store.get_value_history(root_key_hash)?;
+        let later = roots.iter().filter(|(v, _)| *v > version).count();
+        let substore_version = if later == roots.len() {
+            u64::MAX
+        } else {
+            latest_version.checked_sub(later as u64).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "substore {} has more recorded roots than versions",
+                    substore_config.prefix
+                )
+            })?
+        };
+        multistore_cache.set_version(substore_config.clone(), substore_version);
+    }
+    multistore_cache.set_version(config.main_store.clone(), version);
+    Ok(multistore_cache)
+}
+
+/// Returns the verifiable keys written after the past `version`, up to the
+/// `latest` snapshot, found in the versioned value index of every substore.
+///
+/// The keys are resolved with the preimage index, which no longer holds the
+/// keys deleted since `version`: those must be among the `known` keys. The
+/// roots of the substores, recorded in the main store, are not reported.
+pub(super) fn keys_written_since(
+    latest: &Snapshot,
+    version: jmt::Version,
+    known: &BTreeSet<String>,
+) -> Result<BTreeSet<String>> {
+    let config = &latest.0.multistore_cache.config;
+    let past = multistore_cache_at(latest, version)?;
+
+    let mut keys = BTreeSet::new();
+    for substore_config in config.iter().chain(std::iter::once(&config.main_store)) {
+        let substore = SubstoreSnapshot {
+            config: substore_config.clone(),
+            rocksdb_snapshot: latest.0.snapshot.clone(),
+            version: latest.substore_version(substore_config).unwrap_or(u64::MAX),
+            db: latest.0.db.clone(),
+        };
+        let since = past.get_version(substore_config).unwrap_or(u64::MAX);
+        let prefix = substore_config.prefix.as_str();
+        let deleted: BTreeMap<[u8; 32], &String> = known
+            .iter()
+            .filter_map(|key| {
+                let (substore_key, routed) = config.route_key_str(key);
+                (routed == *substore_config)
+                    .then(|| (jmt::KeyHash::with::<sha2::Sha256>(substore_key).0, key))
+            })
+            .collect();
+
+        for key_hash in substore.key_hashes_written_since(since)? {
+            let key = match substore.preimage(key_hash)? {
+                Some(preimage) if prefix.is_empty() => String::from_utf8(preimage)?,
+                Some(preimage) => format!("{prefix}/{}", String::from_utf8(preimage)?),
+                None => deleted
+                    .get(&key_hash.0)
+                    .map(|key| key.to_string())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "the key with hash {} in substore {prefix:?} was deleted after version {version}, and is no longer known",
+                            hex::encode(key_hash.0)
+                        )
+                    })?,
+            };
+            if prefix.is_empty() && config.substore(&key).is_some() {
+                continue;
+            }
+            keys.insert(key);
+        }
+    }
+    Ok(keys)
+}
@@ -0,0 +1,145 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use anyhow::Result;
+use borsh::BorshDeserialize;
+use jmt::storage::{Node, NodeKey};
+use rocksdb::IteratorMode;
+use tracing::Span;
+
+use crate::{
+    snapshot::RocksDbSnapshot,
+    store::substore::{DbNodeKey, SubstoreConfig},
+    Storage,
+};
+
+/// The outcome of a [`Storage::check_integrity`] audit.
+#[derive(Clone, Debug, Default)]
+pub struct IntegrityReport {
+    /// The number of retained tree roots that were walked, across all substores.
+    pub roots_checked: usize,
+    /// The number of distinct tree nodes reachable from a retained root.
+    pub reachable_nodes: usize,
+    /// The number of tree nodes stored in the database that are not reachable
+    /// from any retained root. These only waste space.
+    pub orphaned_nodes: usize,
+    /// Nodes referenced by a reachable internal node, but missing from the
+    /// database. These indicate corruption.
+    pub dangling_references: Vec<DanglingReference>,
+}
+
+impl IntegrityReport {
+    /// Returns `true` if no dangling references were found.
+    ///
+    /// Orphaned nodes do not make a database unhealthy.
+    pub fn is_healthy(&self) -> bool {
+        self.dangling_references.is_empty()
+    }
+}
+
+/// A tree node that is referenced by its parent, but missing from the database.
+#[derive(Clone, Debug)]
+pub struct DanglingReference {
+    /// The prefix of the substore containing the reference.
+    pub prefix: String,
+    /// The key of the missing node.
+    pub node_key: NodeKey,
+}
+
+impl Storage {
+    /// Audits the tree nodes of every substore against the latest snapshot.
+    ///
+    /// Every root whose version is retained by the latest snapshot is walked,
+    /// checking that all the nodes it references exist. Nodes that are stored
+    /// but unreachable from every retained root are counted as orphans.
+    ///
+    /// This reads every node key of the database into memory, and is intended
+    /// for offline diagnostics rather than for use on a hot path.
+    pub async fn check_integrity(&self) -> Result<IntegrityReport> {
+        let span = Span::current();
+        let snapshot = self.latest_snapshot();
+        let db = self.0.db.clone();
+        let configs: Vec<_> = self
+            .0
+            .multistore_config
+            .iter()
+            .chain(std::iter::once(&self.0.multistore_config.main_store))
+            .map(|config| (config.clone(), snapshot.substore_version(config)))
+            .collect();
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let mut report = IntegrityReport::default();
+                for (config, version) in configs {
+                    check_substore(&db, &snapshot.0.snapshot, &config, version, &mut report)?;
+                }
+                tracing::debug!(
+                    roots_checked = report.roots_checked,
+                    reachable_nodes = report.reachable_nodes,
+                    orphaned_nodes = report.orphaned_nodes,
+                    dangling_references = report.dangling_references.len(),
+                    "checked storage integrity"
+                );
+                Ok(report)
+            })
+        })
+        .await?
+    }
+}
+
+/// Walks the retained trees of a single substore, accumulating into `report`.
+///
+/// Roots newer than `latest_version` are not retained, and are treated as
+/// orphans along with the nodes only they reference.
+fn check_substore(
+    db: &Arc<rocksdb::DB>,
+    rocksdb_snapshot: &RocksDbSnapshot,
+    config: &Arc<SubstoreConfig>,
+    latest_version: Option<jmt::Version>,
+    report: &mut IntegrityReport,
+) -> Result<()> {
+    let cf_jmt = config.cf_jmt(db);
+
+    // Collect the encoded keys of every stored node, and the retained roots.
+    let mut stored = BTreeSet::new();
+    let mut roots = Vec::new();
+    for entry in rocksdb_snapshot.iterator_cf(cf_jmt, IteratorMode::Start) {
+        let (raw_key, _) = entry?;
+        let node_key = DbNodeKey::decode(&raw_key)?.into_inner();
+        let is_retained = latest_version.is_some_and(|v| node_key.version() <= v);
+        if is_retained && node_key == NodeKey::new_empty_path(node_key.version()) {
+            roots.push(node_key);
+        }
+        stored.insert(raw_key.to_vec());
+    }
+    report.roots_checked += roots.len();
+
+    // Walk the trees, sharing the visited set since versions share subtrees.
+    let mut reachable = BTreeSet::new();
+    let mut pending = roots;
+    while let Some(node_key) = pending.pop() {
+        let encoded = DbNodeKey::encode_from_node_key(&node_key)?;
+        if !reachable.insert(encoded.clone()) {
+            continue;
+        }
+
+        let Some(raw_node) = rocksdb_snapshot.get_cf(cf_jmt, &encoded)? else {
+            tracing::warn!(prefix = ?config.prefix, ?node_key, "found dangling node reference");
+            report.dangling_references.push(DanglingReference {
+                prefix: config.prefix.clone(),
+                node_key,
+            });
+            continue;
+        };
+
+        if let Node::Internal(internal) = Node::try_from_slice(&raw_node)? {
+            for (nibble, child) in internal.children_sorted() {
+                pending.push(node_key.gen_child_node_key(child.version, *nibble));
+            }
+        }
+    }
+
+    let reachable_stored = reachable.intersection(&stored).count();
+    report.reachable_nodes += reachable_stored;
+    report.orphaned_nodes += stored.len() - reachable_stored;
+    Ok(())
+}
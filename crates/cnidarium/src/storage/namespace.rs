@@ -0,0 +1,235 @@
+use std::{any::Any, sync::Arc};
+
+use anyhow::Result;
+use futures::{stream::BoxStream, StreamExt};
+
+use crate::{Cache, RootHash, ScanOrder, Snapshot, StateDelta, StateRead, Storage};
+
+/// A view of a [`Storage`] that transparently namespaces every key under a
+/// fixed prefix, so that several tenants can share one database without
+/// their keys colliding.
+///
+/// Reads through a [`NamespacedSnapshot`] prepend the namespace to the keys
+/// they look up, and strip it from the keys they return. Commits through
+/// [`NamespacedStorage::commit`] prepend it to every key written, in both the
+/// verifiable and nonverifiable stores.
+///
+/// # Root hashes
+/// Namespacing only rewrites keys: every namespace is stored in the same
+/// trees, so a write in one namespace changes the root hash seen by all the
+/// others. Keys are routed to substores after the namespace is prepended, so
+/// they land in the main store unless a substore is registered under the
+/// namespace itself, in which case that namespace gets its own substore root.
+/// Versions are shared as well: every commit, in any namespace, advances the
+/// version of the underlying storage.
+#[derive(Clone, Debug)]
+pub struct NamespacedStorage {
+    storage: Storage,
+    namespace: Arc<str>,
+}
+
+impl Storage {
+    /// Returns a view of this storage that namespaces every key under
+    /// `namespace`. See [`NamespacedStorage`].
+    pub fn with_namespace(&self, namespace: String) -> NamespacedStorage {
+        NamespacedStorage {
+            storage: self.clone(),
+            namespace: namespace.into(),
+        }
+    }
+}
+
+impl NamespacedStorage {
+    /// Returns the namespace prepended to every key.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Returns the underlying storage, which is not namespaced.
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    /// Returns the latest version of the underlying storage.
+    pub fn latest_version(&self) -> jmt::Version {
+        self.storage.latest_version()
+    }
+
+    /// Returns a namespaced view of the latest snapshot, on top of which
+    /// deltas passed to [`NamespacedStorage::commit`] should be built.
+    pub fn latest_snapshot(&self) -> NamespacedSnapshot {
+        self.wrap(self.storage.latest_snapshot())
+    }
+
+    /// Returns a namespaced view of the snapshot at `version`, if it is still
+    /// in the snapshot cache.
+    pub fn snapshot(&self, version: jmt::Version) -> Option<NamespacedSnapshot> {
+        self.storage.snapshot(version).map(|s| self.wrap(s))
+    }
+
+    /// Commits `delta` like [`Storage::commit`], prepending the namespace to
+    /// every key it writes or deletes.
+    pub async fn commit(
+        &self,
+        delta: StateDelta<NamespacedSnapshot>,
+    ) -> Result<(jmt::Version, RootHash)> {
+        let (base, changes) = delta.flatten()?;
+        // Changes spilled to disk are read back, to be stored under the namespace.
+        let namespaced = Cache {
+            unwritten_changes: changes
+                .unwritten_changes()?
+                .into_iter()
+                .map(|(key, value)| (format!("{}{key}", self.namespace), value))
+                .collect(),
+            spills: Vec::new(),
+            // The spilled changes the deletions hide were read back as
+            // deletions, so they no longer hide any store.
+            prefix_deletions: changes
+                .prefix_deletions()
+                .map(|prefix| (format!("{}{prefix}", self.namespace), 0))
+                .collect(),
+            nonverifiable_changes: changes
+                .nonverifiable_changes
+                .into_iter()
+                .map(|(key, value)| (base.namespaced_bytes(&key), value))
+                .collect(),
+            ..changes
+        };
+        let batch = self
+            .storage
+            .prepare_commit_changes(base.snapshot, namespaced)
+            .await?;
+        self.storage.commit_batch(batch)
+    }
+
+    fn wrap(&self, snapshot: Snapshot) -> NamespacedSnapshot {
+        NamespacedSnapshot {
+            snapshot,
+            namespace: self.namespace.clone(),
+        }
+    }
+}
+
+/// A [`Snapshot`] read through the namespace of a [`NamespacedStorage`].
+#[derive(Clone, Debug)]
+pub struct NamespacedSnapshot {
+    snapshot: Snapshot,
+    namespace: Arc<str>,
+}
+
+impl NamespacedSnapshot {
+    /// Returns the version of the underlying snapshot.
+    pub fn version(&self) -> jmt::Version {
+        self.snapshot.version()
+    }
+
+    /// Returns the underlying snapshot, which is not namespaced.
+    pub fn inner(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    fn namespaced(&self, key: &str) -> String {
+        format!("{}{key}", self.namespace)
+    }
+
+    fn namespaced_bytes(&self, key: &[u8]) -> Vec<u8> {
+        [self.namespace.as_bytes(), key].concat()
+    }
+
+    /// Strips the namespace from the keys of a stream of entries.
+    fn strip<K, V, S>(&self, stream: S) -> BoxStream<'static, Result<(K, V)>>
+    where
+        K: Strip + Send + 'static,
+        V: Send + 'static,
+        S: futures::Stream<Item = Result<(K, V)>> + Send + 'static,
+    {
+        let len = self.namespace.len();
+        stream
+            .map(move |entry| entry.map(|(key, value)| (key.strip(len), value)))
+            .boxed()
+    }
+}
+
+/// A key whose leading namespace can be stripped.
+trait Strip {
+    fn strip(self, len: usize) -> Self;
+}
+
+impl Strip for String {
+    fn strip(mut self, len: usize) -> Self {
+        self.drain(..len);
+        self
+    }
+}
+
+impl Strip for Vec<u8> {
+    fn strip(mut self, len: usize) -> Self {
+        self.drain(..len);
+        self
+    }
+}
+
+impl StateRead for NamespacedSnapshot {
+    type GetRawFut = <Snapshot as StateRead>::GetRawFut;
+    type PrefixRawStream = BoxStream<'static, Result<(String, Vec<u8>)>>;
+    type PrefixKeysStream = BoxStream<'static, Result<String>>;
+    type NonconsensusPrefixRawStream = BoxStream<'static, Result<(Vec<u8>, Vec<u8>)>>;
+    type NonconsensusRangeRawStream = BoxStream<'static, Result<(Vec<u8>, Vec<u8>)>>;
+
+    fn get_raw(&self, key: &str) -> Self::GetRawFut {
+        self.snapshot.get_raw(&self.namespaced(key))
+    }
+
+    fn nonverifiable_get_raw(&self, key: &[u8]) -> Self::GetRawFut {
+        self.snapshot
+            .nonverifiable_get_raw(&self.namespaced_bytes(key))
+    }
+
+    fn object_get<T: Any + Send + Sync + Clone>(&self, _key: &'static str) -> Option<T> {
+        // Like `Snapshot`, this holds no ephemeral objects.
+        None
+    }
+
+    fn object_type(&self, _key: &'static str) -> Option<std::any::TypeId> {
+        None
+    }
+
+    fn prefix_raw(&self, prefix: &str) -> Self::PrefixRawStream {
+        self.strip(self.snapshot.prefix_raw(&self.namespaced(prefix)))
+    }
+
+    fn prefix_raw_ordered(&self, prefix: &str, order: ScanOrder) -> Self::PrefixRawStream {
+        self.strip(
+            self.snapshot
+                .prefix_raw_ordered(&self.namespaced(prefix), order),
+        )
+    }
+
+    fn prefix_keys(&self, prefix: &str) -> Self::PrefixKeysStream {
+        let len = self.namespace.len();
+        self.snapshot
+            .prefix_keys(&self.namespaced(prefix))
+            .map(move |key| key.map(|key| key.strip(len)))
+            .boxed()
+    }
+
+    fn nonverifiable_prefix_raw(&self, prefix: &[u8]) -> Self::NonconsensusPrefixRawStream {
+        self.strip(
+            self.snapshot
+                .nonverifiable_prefix_raw(&self.namespaced_bytes(prefix)),
+        )
+    }
+
+    fn nonverifiable_range_raw(
+        &self,
+        prefix: Option<&[u8]>,
+        range: impl std::ops::RangeBounds<Vec<u8>>,
+    ) -> Result<Self::NonconsensusRangeRawStream> {
+        // The range is relative to the prefix, so only the prefix is namespaced.
+        let prefix = self.namespaced_bytes(prefix.unwrap_or_default());
+        Ok(self.strip(
+            self.snapshot
+                .nonverifiable_range_raw(Some(&prefix), range)?,
+        ))
+    }
+}
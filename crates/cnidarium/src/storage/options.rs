@@ -0,0 +1,262 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Result};
+use rocksdb::{BlockBasedOptions, Cache, Options};
+
+use super::RetryPolicy;
+
+/// The key, in the default column family, of the settings recorded for the
+/// main store. The settings of the other substores are the values of their
+/// prefixes in the `config` column family.
+pub(crate) const MAIN_STORE_SETTINGS_KEY: &[u8] = b"main_store_settings";
+
+/// A compression codec applied to the data of a substore on disk.
+///
+/// Since each substore is backed by its own set of RocksDB column families,
+/// the codec is configured per column family. Changing the codec of an
+/// existing substore only affects newly-written data: existing data is
+/// rewritten with the new codec as it gets compacted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Compression {
+    /// Store values uncompressed, e.g. for incompressible data like hashes.
+    None,
+    /// Snappy compression, the RocksDB default.
+    #[default]
+    Snappy,
+    /// LZ4 compression.
+    Lz4,
+    /// Zstandard compression, slower but with a better compression ratio.
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn to_rocksdb(self) -> rocksdb::DBCompressionType {
+        match self {
+            Compression::None => rocksdb::DBCompressionType::None,
+            Compression::Snappy => rocksdb::DBCompressionType::Snappy,
+            Compression::Lz4 => rocksdb::DBCompressionType::Lz4,
+            Compression::Zstd => rocksdb::DBCompressionType::Zstd,
+        }
+    }
+}
+
+/// Options used to open a [`Storage`](crate::Storage) instance.
+///
+/// Options are not persisted: they must be supplied every time the storage is
+/// loaded.
+#[derive(Clone, Debug, Default)]
+pub struct StorageOptions {
+    /// The compression codec used by substores that don't specify one.
+    pub compression: Compression,
+    /// Per-substore compression codecs, keyed by substore prefix. The empty
+    /// prefix refers to the main store.
+    pub substore_compression: BTreeMap<String, Compression>,
+    /// Per-substore time-to-live of nonverifiable entries, keyed by substore
+    /// prefix. The empty prefix refers to the main store.
+    ///
+    /// Entries of a substore's nonverifiable column family that are older than
+    /// its TTL are dropped by RocksDB during compaction, without explicit
+    /// deletes. Entries expire at the granularity of on-disk files, so they
+    /// may remain readable for some time past their TTL, and only whole
+    /// seconds are taken into account.
+    ///
+    /// Expiry depends on wall-clock time, which differs between nodes, so it
+    /// is only offered for nonverifiable data: it must never be used for
+    /// anything that affects consensus. The TTL should be set when the
+    /// substore is created, since RocksDB may refuse to switch the compaction
+    /// style of a column family that already holds data.
+    pub nonverifiable_ttl: BTreeMap<String, Duration>,
+    /// The prefixes of the substores whose tree holds the SHA-256 hash of each
+    /// value rather than the value itself. The empty prefix refers to the main
+    /// store.
+    ///
+    /// With large values, this keeps tree updates cheap: the tree only hashes
+    /// and stores the 32-byte hash of each value, while the value itself is
+    /// stored in a companion column family, indexed by its hash. Reads resolve
+    /// values transparently, but proofs are over the hash of the value: for
+    /// such a substore, [`Snapshot::get_with_proof`](crate::Snapshot::get_with_proof)
+    /// returns the hash, and verifiers must check that the SHA-256 hash of the
+    /// value they were given matches it.
+    ///
+    /// Since it changes the root hash of the substore, this must be set when
+    /// the substore is created, and never changed afterwards: the setting is
+    /// recorded in the database when the substore's column families are
+    /// created, and [`Storage::load`](crate::Storage::load) refuses to load a
+    /// substore with a different setting. Values are kept in the companion
+    /// column family for as long as the substore exists, even once no version
+    /// refers to them, so its size only grows.
+    pub hash_values: BTreeSet<String>,
+    /// The maximum number of decoded tree nodes kept in memory to speed up
+    /// proof generation, shared by all snapshots of the storage. Recently used
+    /// nodes are kept, so repeated proofs against a recent version mostly hit
+    /// the cache. Zero, the default, disables the cache.
+    pub node_cache_capacity: usize,
+    /// The maximum number of values read from the verifiable store kept in
+    /// memory, shared by all snapshots of the storage. Values are cached along
+    /// with the version they were read at, so that hot keys, like parameters
+    /// read by every transaction, are mostly served from memory. Zero, the
+    /// default, disables the cache.
+    pub value_cache_capacity: usize,
+    /// How snapshot reads are retried after transient errors. By default,
+    /// reads are not retried.
+    pub read_retry: RetryPolicy,
+    /// If set, the maximum number of RocksDB iterators held open at once by
+    /// the streams of prefix and range queries over snapshots.
+    ///
+    /// A stream holds its iterator until it is exhausted or dropped, so this
+    /// guards against callers that leak streams. Once the cap is reached, new
+    /// streams wait for a slot for up to `open_iterator_timeout`, then yield a
+    /// single [`StorageError::TooManyIterators`](crate::StorageError::TooManyIterators).
+    pub max_open_iterators: Option<usize>,
+    /// How long a new stream waits for an iterator slot when
+    /// `max_open_iterators` is reached. If unset, it fails immediately.
+    pub open_iterator_timeout: Option<Duration>,
+    /// Whether to check, once the storage is loaded, that the root of every
+    /// substore recomputed from its latest version matches the root recorded
+    /// for it in the main store.
+    ///
+    /// A mismatch means that the substore versions were not reconstructed
+    /// correctly, which would silently change the app hash, so loading fails.
+    /// The check reads one root per substore.
+    pub verify_roots_on_load: bool,
+    /// If set, the maximum number of internal tree nodes a single commit may
+    /// write, across all substores.
+    ///
+    /// Writes scattered across the keyspace update many internal nodes, which
+    /// makes the commit expensive. This is a guard against denial of service
+    /// for settings where the writes of a block are derived from untrusted
+    /// input that is not otherwise validated: a commit that exceeds the cap
+    /// fails with [`StorageError::TreeUpdateTooWide`](crate::StorageError::TreeUpdateTooWide)
+    /// before anything is written. Since it makes commits fail, it must not be
+    /// used where every node has to commit the same blocks, unless all of them
+    /// apply the same cap. Migrations are not subject to the cap.
+    pub max_internal_nodes_per_commit: Option<usize>,
+    /// Whether a substore prefix may itself be a prefix of another one, like
+    /// `dex/` and `dex/lp/`.
+    ///
+    /// Keys are routed to the substore with the longest matching prefix, but
+    /// nested prefixes are usually a configuration mistake, so loading fails
+    /// unless this is set.
+    pub allow_nested_prefixes: bool,
+    /// The number of most recent commits whose changes are kept in memory, to
+    /// serve [`Storage::changeset`](crate::Storage::changeset),
+    /// [`Storage::changed_keys_since`](crate::Storage::changed_keys_since) and
+    /// the [conflict detection](crate::StateDelta::with_conflict_detection)
+    /// of deltas forked from older versions. If unset, the changes of the
+    /// last 10 commits are kept.
+    ///
+    /// The changes are not persisted: after a restart, only those of the
+    /// commits made since are available.
+    pub change_log_retention: Option<usize>,
+    /// Tuning of the underlying RocksDB database.
+    pub rocksdb: RocksDbConfig,
+}
+
+/// Tuning knobs of the RocksDB database backing a [`Storage`](crate::Storage),
+/// set in [`StorageOptions::rocksdb`]. Compression is configured per substore,
+/// see [`StorageOptions::compression`].
+///
+/// A zero value keeps RocksDB's default for that setting, so the default
+/// configuration matches an untuned database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RocksDbConfig {
+    /// The capacity, in bytes, of a block cache shared by every column family.
+    /// By default, each column family has its own 8 MiB cache.
+    pub block_cache_size: usize,
+    /// The size, in bytes, of the memtable of each column family, which is
+    /// flushed to disk once full. RocksDB defaults to 64 MiB.
+    pub write_buffer_size: usize,
+    /// The maximum number of files RocksDB keeps open. By default, and for
+    /// values that RocksDB can't represent, there is no limit.
+    pub max_open_files: u32,
+}
+
+impl RocksDbConfig {
+    /// Applies the database-wide settings to `opts`.
+    pub(crate) fn apply_to_db(&self, opts: &mut Options) {
+        if let Some(max_open_files) = i32::try_from(self.max_open_files)
+            .ok()
+            .filter(|max| *max > 0)
+        {
+            opts.set_max_open_files(max_open_files);
+        }
+    }
+
+    /// Returns the options every column family starts from, before the
+    /// settings of its substore are applied.
+    pub(crate) fn column_options(&self) -> Options {
+        let mut opts = Options::default();
+        if self.write_buffer_size > 0 {
+            opts.set_write_buffer_size(self.write_buffer_size);
+        }
+        if self.block_cache_size > 0 {
+            let cache = Cache::new_lru_cache(self.block_cache_size);
+            let mut table_opts = BlockBasedOptions::default();
+            table_opts.set_block_cache(&cache);
+            opts.set_block_based_table_factory(&table_opts);
+        }
+        opts
+    }
+}
+
+impl StorageOptions {
+    /// Returns the compression codec to use for the substore with the given prefix.
+    pub(crate) fn compression_for(&self, prefix: &str) -> Compression {
+        self.substore_compression
+            .get(prefix)
+            .copied()
+            .unwrap_or(self.compression)
+    }
+
+    /// Returns whether the substore with the given prefix hashes its values
+    /// into the tree.
+    pub(crate) fn hash_values_for(&self, prefix: &str) -> bool {
+        self.hash_values.contains(prefix)
+    }
+
+    /// Returns the nonverifiable TTL of the substore with the given prefix, if any.
+    pub(crate) fn nonverifiable_ttl_for(&self, prefix: &str) -> Option<Duration> {
+        self.nonverifiable_ttl.get(prefix).copied()
+    }
+}
+
+/// Encodes the settings of a substore that must not change once it holds
+/// data, as recorded in the database.
+pub(crate) fn encode_substore_settings(hash_values: bool) -> [u8; 1] {
+    [u8::from(hash_values)]
+}
+
+/// Decodes the settings recorded by [`encode_substore_settings`], returning
+/// whether the substore hashes its values, or `None` if no setting was
+/// recorded yet.
+pub(crate) fn decode_substore_settings(value: &[u8]) -> Result<Option<bool>> {
+    match value {
+        [] => Ok(None),
+        [0] => Ok(Some(false)),
+        [1] => Ok(Some(true)),
+        _ => bail!(
+            "invalid substore settings {:?}",
+            crate::EscapedByteSlice(value)
+        ),
+    }
+}
+
+/// Checks that a substore is loaded with the `hash_values` setting it was
+/// created with, if one was recorded.
+pub(crate) fn check_hash_values(
+    prefix: &str,
+    recorded: Option<bool>,
+    hash_values: bool,
+) -> Result<()> {
+    if let Some(recorded) = recorded {
+        ensure!(
+            recorded == hash_values,
+            "substore {prefix:?} was created with hash_values set to {recorded}, \
+             but is loaded with it set to {hash_values}"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use anyhow::Result;
+use borsh::BorshDeserialize;
+use jmt::storage::{Node, NodeKey};
+use rocksdb::{IteratorMode, ReadOptions, WriteBatch};
+use tracing::Span;
+
+use crate::{
+    snapshot::RocksDbSnapshot,
+    store::substore::{DbNodeKey, SubstoreConfig, VersionedKeyHash},
+    Storage,
+};
+
+/// The key, in the default column family, of the oldest version that has not
+/// been pruned, encoded in big-endian.
+const OLDEST_VERSION_KEY: &[u8] = b"oldest_version";
+
+/// The number of deletions accumulated before they are written to the database.
+const DELETE_BATCH_SIZE: usize = 10_000;
+
+impl Storage {
+    /// Returns the oldest version that is still available, or `None` if no
+    /// version was ever removed by [`Storage::prune`].
+    pub fn oldest_available_version(&self) -> Result<Option<jmt::Version>> {
+        let Some(bytes) = self.0.db.get(OLDEST_VERSION_KEY)? else {
+            return Ok(None);
+        };
+        let bytes: [u8; 8] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid oldest version marker"))?;
+        Ok(Some(u64::from_be_bytes(bytes)))
+    }
+
+    /// Deletes the tree nodes and versioned values that are only needed to
+    /// read versions older than the `keep_versions` most recent ones, in every
+    /// substore.
+    ///
+    /// The latest version is always kept, so a `keep_versions` of 0 keeps it
+    /// alone, like 1. Every version from `latest + 1 - keep_versions` onwards
+    /// remains readable, along with the proofs against its root, and so does
+    /// any version pinned
+    /// by a live [`ArchiveState`](crate::ArchiveState). The oldest available
+    /// version is recorded before anything is deleted, so that
+    /// [`Storage::state_at_version`] rejects older versions with
+    /// [`StorageError::VersionPruned`](crate::StorageError::VersionPruned)
+    /// rather than reading partially deleted trees. In-memory snapshots of
+    /// pruned versions are evicted, and the caches of tree nodes and values
+    /// are cleared once the deletions are written.
+    ///
+    /// Stale tree nodes are found by walking the retained trees, since the
+    /// index of stale nodes is not persisted. The keys of the old nodes that
+    /// the retained trees still share are held in memory during the walk.
+    /// Values stored by their hash, in substores configured with
+    /// [`StorageOptions::hash_values`](crate::StorageOptions::hash_values), are
+    /// kept, since other keys and versions may share them and their references
+    /// are not counted: the column family holding them grows without bound.
+    pub async fn prune(&self, keep_versions: u64) -> Result<()> {
+        let latest = self.latest_snapshot();
+        // The pre-genesis version wraps around to `u64::MAX`.
+        if latest.version() == u64::MAX {
+            return Ok(());
+        }
+        let mut cutoff = latest
+            .version()
+            .saturating_sub(keep_versions.saturating_sub(1));
+        if let Some(pinned) = self.oldest_pinned_version() {
+            cutoff = cutoff.min(pinned);
+        }
+        if self
+            .oldest_available_version()?
+            .is_some_and(|oldest| oldest >= cutoff)
+        {
+            return Ok(());
+        }
+
+        self.0.db.put(OLDEST_VERSION_KEY, cutoff.to_be_bytes())?;
+        self.0
+            .snapshots
+            .write()
+            .retain_latest(usize::try_from(latest.version() - cutoff + 1).unwrap_or(usize::MAX));
+        tracing::info!(
+            latest_version = latest.version(),
+            cutoff,
+            "pruning old versions"
+        );
+
+        let db = self.0.db.clone();
+        let span = Span::current();
+        let pruned = tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let cutoff_versions = super::history::multistore_cache_at(&latest, cutoff)?;
+                let config = &latest.0.multistore_cache.config;
+                for substore in std::iter::once(&config.main_store).chain(config.iter()) {
+                    let (Some(substore_cutoff), Some(substore_latest)) = (
+                        cutoff_versions.get_version(substore),
+                        latest.substore_version(substore),
+                    ) else {
+                        continue;
+                    };
+                    // The substore had not been written to yet at the cutoff.
+                    if substore_cutoff == u64::MAX {
+                        continue;
+                    }
+                    prune_substore(
+                        &db,
+                        &latest.0.snapshot,
+                        substore,
+                        substore_cutoff,
+                        substore_latest,
+                    )?;
+                }
+                anyhow::Ok(())
+            })
+        })
+        .await?;
+
+        // Pruned nodes and values must no longer be served from memory.
+        if let Some(node_cache) = &self.0.multistore_config.node_cache {
+            node_cache.clear();
+        }
+        if let Some(value_cache) = &self.0.multistore_config.value_cache {
+            value_cache.clear();
+        }
+        pruned
+    }
+}
+
+/// Deletes the nodes and values of a single substore that are not needed to
+/// read its versions from `cutoff` to `latest`.
+fn prune_substore(
+    db: &Arc<rocksdb::DB>,
+    rocksdb_snapshot: &RocksDbSnapshot,
+    config: &Arc<SubstoreConfig>,
+    cutoff: jmt::Version,
+    latest: jmt::Version,
+) -> Result<()> {
+    let cf_jmt = config.cf_jmt(db);
+    let cf_jmt_values = config.cf_jmt_values(db);
+
+    // A commit writes the path from each node it creates up to a new root, so
+    // the nodes written at a retained version are reachable from its root
+    // through nodes of that same version. Only the older nodes they reference
+    // need to be collected.
+    let mut live = BTreeSet::new();
+    let mut pending: Vec<_> = (cutoff..=latest).map(NodeKey::new_empty_path).collect();
+    while let Some(node_key) = pending.pop() {
+        let encoded = DbNodeKey::encode_from_node_key(&node_key)?;
+        if node_key.version() < cutoff && !live.insert(encoded.clone()) {
+            continue;
+        }
+        let Some(raw_node) = rocksdb_snapshot.get_cf(cf_jmt, &encoded)? else {
+            continue;
+        };
+        if let Node::Internal(internal) = Node::try_from_slice(&raw_node)? {
+            for (nibble, child) in internal.children_sorted() {
+                if child.version < cutoff || child.version == node_key.version() {
+                    pending.push(node_key.gen_child_node_key(child.version, *nibble));
+                }
+            }
+        }
+    }
+
+    let mut batch = WriteBatch::default();
+    let flush = |batch: &mut WriteBatch| -> Result<()> {
+        if batch.len() >= DELETE_BATCH_SIZE {
+            db.write(std::mem::take(batch))?;
+        }
+        Ok(())
+    };
+
+    // Node keys start with their version in big-endian, so the nodes older
+    // than the cutoff come first.
+    let mut deleted_nodes = 0;
+    let mut options = ReadOptions::default();
+    options.set_iterate_upper_bound(cutoff.to_be_bytes().to_vec());
+    for entry in rocksdb_snapshot.iterator_cf_opt(cf_jmt, options, IteratorMode::Start) {
+        let (raw_key, _) = entry?;
+        if !live.contains(raw_key.as_ref()) {
+            batch.delete_cf(cf_jmt, &raw_key);
+            deleted_nodes += 1;
+            flush(&mut batch)?;
+        }
+    }
+
+    // Reads at the cutoff need the newest value written up to it, so every
+    // older value of the same key is stale.
+    let mut deleted_values = 0;
+    let mut previous: Option<([u8; 32], Box<[u8]>)> = None;
+    for entry in rocksdb_snapshot.iterator_cf(cf_jmt_values, IteratorMode::Start) {
+        let (raw_key, _) = entry?;
+        let VersionedKeyHash { key_hash, version } = VersionedKeyHash::decode(raw_key.to_vec())?;
+        if version > cutoff {
+            continue;
+        }
+        if let Some((previous_hash, previous_key)) = previous.take() {
+            if previous_hash == key_hash.0 {
+                batch.delete_cf(cf_jmt_values, previous_key);
+                deleted_values += 1;
+                flush(&mut batch)?;
+            }
+        }
+        previous = Some((key_hash.0, raw_key));
+    }
+
+    db.write(batch)?;
+    tracing::debug!(
+        prefix = ?config.prefix,
+        cutoff,
+        deleted_nodes,
+        deleted_values,
+        "pruned substore"
+    );
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result};
+use rocksdb::{Options, DB};
+use tracing::Span;
+
+use crate::{Snapshot, Storage};
+
+/// A read-only handle on a database, opened in RocksDB's
+/// [read-only mode](https://github.com/facebook/rocksdb/wiki/Read-only-and-Secondary-instances).
+///
+/// Unlike a [`Storage`], it has no commit methods, so the snapshots it serves
+/// can be read but never committed. Opening it does not take the database
+/// lock, so any number of read-only handles, in any number of processes, can
+/// share a data directory with the storage instance that writes to it.
+///
+/// The handle sees the database as it was when it was opened: versions
+/// committed afterwards by a writer are not visible. Use
+/// [`Storage::open_secondary`] to follow a live writer instead.
+///
+/// The handle is cheaply clonable; all clones share the same backing data store.
+#[derive(Clone)]
+pub struct ReadOnlyStorage(Arc<Inner>);
+
+impl std::fmt::Debug for ReadOnlyStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReadOnlyStorage")
+            .field("version", &self.latest_version())
+            .finish_non_exhaustive()
+    }
+}
+
+struct Inner {
+    latest_snapshot: Snapshot,
+}
+
+impl Storage {
+    /// Opens the storage located at `path` for reading only.
+    ///
+    /// The substores are read from the database's configuration, so the
+    /// storage must have been initialized by [`Storage::load`] beforehand.
+    pub async fn load_read_only(path: PathBuf) -> Result<ReadOnlyStorage> {
+        let span = Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let opts = Options::default();
+                let columns = DB::list_cf(&opts, &path).with_context(|| {
+                    format!(
+                        "failed to list the column families of the database at {}",
+                        path.display()
+                    )
+                })?;
+
+                tracing::info!(?path, "opening rocksdb read-only");
+                let db = DB::open_cf_for_read_only(&opts, &path, &columns, false)?;
+                let multistore_config = super::secondary::read_multistore_config(&db, &columns)?;
+
+                let db = Arc::new(db);
+                let latest_snapshot = super::latest_snapshot_from_db(db, &multistore_config)?;
+
+                Ok(ReadOnlyStorage(Arc::new(Inner { latest_snapshot })))
+            })
+        })
+        .await?
+    }
+}
+
+impl ReadOnlyStorage {
+    /// Returns the latest version committed when the database was opened.
+    ///
+    /// If no version had been committed, returns `u64::MAX`.
+    pub fn latest_version(&self) -> jmt::Version {
+        self.0.latest_snapshot.version()
+    }
+
+    /// Returns a [`Snapshot`] of the latest version committed when the
+    /// database was opened.
+    pub fn latest_snapshot(&self) -> Snapshot {
+        self.0.latest_snapshot.clone()
+    }
+}
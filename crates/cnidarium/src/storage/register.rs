@@ -0,0 +1,150 @@
+use anyhow::{bail, Result};
+use tracing::Span;
+
+use crate::{cache::Cache, EscapedByteSlice, Storage, StorageError};
+
+impl Storage {
+    /// Registers a new substore with `prefix`, e.g. for a component introduced
+    /// by a migration.
+    ///
+    /// The prefix is persisted to the database's configuration, alongside the
+    /// prefixes passed to [`Storage::load`], and the substore's column families
+    /// are created the next time the storage is loaded. Until then, commits
+    /// that write under `prefix` are rejected with
+    /// [`StorageError::PendingSubstore`], rather than routed to the main store,
+    /// so this should be called at a migration boundary, before the storage is
+    /// reloaded and the new component writes to it. Settings that must not change once the substore
+    /// holds data, like [`StorageOptions::hash_values`](crate::StorageOptions::hash_values),
+    /// are recorded from the options it is loaded with then. Registration is
+    /// serialized with commits, so that no commit can write under `prefix`
+    /// while it is checked. Read-only and secondary instances ignore the
+    /// substore until its column families are created.
+    ///
+    /// The new substore starts out empty: reads under its prefix return `None`,
+    /// and it reports version `u64::MAX` until its first commit, which creates
+    /// its tree at version 0. Like other substores that were never written to,
+    /// it does not contribute to the root hash until then.
+    ///
+    /// # Errors
+    /// Returns an error if `prefix` is empty, if it is nested in a registered
+    /// substore's prefix or the other way around, including if it is already
+    /// registered, or if the main store holds keys under `prefix`, since they
+    /// would no longer be reachable.
+    pub async fn register_substore(&self, prefix: &str) -> Result<()> {
+        if prefix.is_empty() {
+            bail!("substore prefixes must not be empty");
+        }
+        if let Some(config) = self
+            .0
+            .multistore_config
+            .iter()
+            .find(|c| !c.prefix.is_empty() && overlaps(prefix, &c.prefix))
+        {
+            bail!(
+                "substore prefix {prefix:?} overlaps the registered prefix {:?}",
+                config.prefix
+            );
+        }
+
+        let storage = self.clone();
+        let prefix = prefix.to_string();
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let db = &storage.0.db;
+                // Holding the commit lock keeps commits from writing under
+                // `prefix` between the checks below and the registration.
+                let _commit_guard = storage.0.commit_lock.lock();
+
+                // Keys are routed to a substore if they are its prefix, or if
+                // they start with the prefix and a delimiter.
+                let main_store = &storage.0.multistore_config.main_store;
+                for (cf, kind) in [
+                    (main_store.cf_jmt_keys(db), "verifiable"),
+                    (main_store.cf_nonverifiable(db), "nonverifiable"),
+                ] {
+                    if db.get_pinned_cf(cf, prefix.as_bytes())?.is_some() {
+                        bail!("the main store holds the {kind} key {prefix:?}");
+                    }
+                    let mut options = rocksdb::ReadOptions::default();
+                    options.set_iterate_range(rocksdb::PrefixRange(format!("{prefix}/")));
+                    if let Some(entry) = db
+                        .iterator_cf_opt(cf, options, rocksdb::IteratorMode::Start)
+                        .next()
+                    {
+                        let (key, _) = entry?;
+                        bail!(
+                            "the main store holds the {kind} key {:?} under {prefix:?}",
+                            EscapedByteSlice(&key)
+                        );
+                    }
+                }
+
+                let cf_config = db
+                    .cf_handle("config")
+                    .expect("config column family is created on load");
+                // Prefixes registered since the storage was loaded are only
+                // found in the configuration.
+                for entry in db.iterator_cf(cf_config, rocksdb::IteratorMode::Start) {
+                    let (key, _) = entry?;
+                    let other = String::from_utf8_lossy(&key);
+                    if overlaps(&prefix, &other) {
+                        bail!(
+                            "substore prefix {prefix:?} overlaps the registered prefix {other:?}"
+                        );
+                    }
+                }
+
+                tracing::info!(?prefix, "registering substore");
+                db.put_cf(cf_config, prefix.as_bytes(), b"")?;
+                storage.0.pending_substores.write().push(prefix);
+                Ok(())
+            })
+        })
+        .await?
+    }
+
+    /// Checks that `changes` do not write under the prefix of a substore that
+    /// was registered since the storage was loaded. Must be called with the
+    /// commit lock held.
+    pub(super) fn check_pending_substores(&self, changes: &Cache) -> Result<()> {
+        let pending = self.0.pending_substores.read();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let under = |key: &[u8]| {
+            pending.iter().find(|prefix| {
+                key.strip_prefix(prefix.as_bytes())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(b"/"))
+            })
+        };
+        for key in changes.unwritten_keys() {
+            let key = key?;
+            if let Some(prefix) = under(key.as_bytes()) {
+                return Err(StorageError::PendingSubstore {
+                    prefix: prefix.clone(),
+                    key,
+                }
+                .into());
+            }
+        }
+        for key in changes.nonverifiable_changes().keys() {
+            if let Some(prefix) = under(key) {
+                return Err(StorageError::PendingSubstore {
+                    prefix: prefix.clone(),
+                    key: format!("{:?}", EscapedByteSlice(key)),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether keys can be routed to both `prefix` and `other`, i.e. if
+/// either prefix is nested in the other one, at a delimiter.
+fn overlaps(prefix: &str, other: &str) -> bool {
+    let prefix = format!("{prefix}/");
+    let other = format!("{other}/");
+    prefix.starts_with(&other) || other.starts_with(&prefix)
+}
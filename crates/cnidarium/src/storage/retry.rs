@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::StorageError;
+
+/// How reads are retried after transient errors from RocksDB, such as the
+/// I/O errors occasionally returned by network-attached block storage.
+///
+/// The policy applies to the reads made by [`Snapshot`](crate::Snapshot)s:
+/// `get_raw`, `nonverifiable_get_raw`, and the iteration behind `prefix_raw`
+/// and `prefix_raw_ordered`, which resumes after the last key it yielded.
+/// Other operations are not retried. In particular, commits are never retried,
+/// since a retried commit could bump the version twice: a failed write is
+/// instead reported as a [`StorageError::Backend`], whose `retryable` flag
+/// tells the caller whether preparing and committing the delta again may
+/// succeed.
+///
+/// Only errors whose kind is transient are retried. Corruption is never
+/// retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts made for each read, including the first
+    /// one. A value of 1, the default, disables retries.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled before each subsequent one.
+    pub initial_backoff: Duration,
+    /// The maximum delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before retry number `retry`, starting from 1.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Runs `op` until it succeeds, fails with a non-transient error, or the
+    /// attempts are exhausted. RocksDB errors are converted to
+    /// [`StorageError::Backend`].
+    ///
+    /// This sleeps between attempts, so it must only be called from blocking
+    /// tasks.
+    pub(crate) fn run<T>(&self, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            match op().map_err(classify) {
+                Ok(value) => return Ok(value),
+                Err(error) if attempt < self.max_attempts && is_transient(&error) => {
+                    let backoff = self.backoff(attempt);
+                    tracing::warn!(
+                        attempt,
+                        ?backoff,
+                        %error,
+                        "retrying read after a transient storage error"
+                    );
+                    std::thread::sleep(backoff);
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Converts a RocksDB error into a [`StorageError::Backend`], leaving other
+/// errors unchanged.
+pub(crate) fn classify(error: anyhow::Error) -> anyhow::Error {
+    match error.downcast::<rocksdb::Error>() {
+        Ok(error) => StorageError::Backend {
+            retryable: is_retryable(error.kind()),
+            message: error.to_string(),
+        }
+        .into(),
+        Err(error) => error,
+    }
+}
+
+fn is_retryable(kind: rocksdb::ErrorKind) -> bool {
+    use rocksdb::ErrorKind;
+    matches!(
+        kind,
+        ErrorKind::IOError
+            | ErrorKind::Busy
+            | ErrorKind::TimedOut
+            | ErrorKind::TryAgain
+            | ErrorKind::Incomplete
+    )
+}
+
+fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<StorageError>(),
+        Some(StorageError::Backend {
+            retryable: true,
+            ..
+        })
+    )
+}
@@ -0,0 +1,174 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result};
+use parking_lot::RwLock;
+use rocksdb::{IteratorMode, Options, DB};
+use tracing::Span;
+
+use super::options;
+use crate::{
+    store::{multistore::MultistoreConfig, substore::SubstoreConfig},
+    Snapshot, Storage,
+};
+
+/// A read-only replica of a [`Storage`] instance, backed by a RocksDB
+/// [secondary instance](https://github.com/facebook/rocksdb/wiki/Read-only-and-Secondary-instances).
+///
+/// The replica opens the primary's database directory, and tails its
+/// write-ahead log when [`SecondaryStorage::catch_up`] is called. Reads are
+/// served from [`Snapshot`]s of the version the replica had caught up to.
+///
+/// RocksDB does not support snapshots in secondary mode, so the snapshots of
+/// a replica read its current state instead of pinning it. That state only
+/// changes when the replica catches up: reads of the trees, which are
+/// versioned, stay at the snapshot's version, while prefix scans and
+/// nonverifiable reads of a snapshot obtained before a catch up observe the
+/// primary's later writes.
+///
+/// The handle is cheaply clonable; all clones share the same backing data store.
+#[derive(Clone)]
+pub struct SecondaryStorage(Arc<Inner>);
+
+impl std::fmt::Debug for SecondaryStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecondaryStorage")
+            .field("version", &self.latest_version())
+            .finish_non_exhaustive()
+    }
+}
+
+struct Inner {
+    latest_snapshot: RwLock<Snapshot>,
+    multistore_config: MultistoreConfig,
+    db: Arc<DB>,
+}
+
+impl Storage {
+    /// Opens a read-only replica of the storage located at `primary_path`.
+    ///
+    /// The replica keeps its own metadata (e.g. logs) in `secondary_path`, which
+    /// must be distinct from the primary's path. The primary must have been
+    /// initialized before the replica can be opened.
+    pub async fn open_secondary(
+        primary_path: PathBuf,
+        secondary_path: PathBuf,
+    ) -> Result<SecondaryStorage> {
+        let span = Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let mut opts = Options::default();
+                // Secondary instances need to keep all the primary's files open,
+                // since they can be deleted by the primary at any time.
+                opts.set_max_open_files(-1);
+
+                let columns = DB::list_cf(&opts, &primary_path).with_context(|| {
+                    format!(
+                        "failed to list the column families of the primary at {}",
+                        primary_path.display()
+                    )
+                })?;
+
+                tracing::info!(?primary_path, ?secondary_path, "opening rocksdb secondary");
+                let db = DB::open_cf_as_secondary(&opts, &primary_path, &secondary_path, &columns)?;
+                let multistore_config = read_multistore_config(&db, &columns)?;
+
+                let db = Arc::new(db);
+                let latest_snapshot = latest_snapshot_from_db(db.clone(), &multistore_config)?;
+
+                Ok(SecondaryStorage(Arc::new(Inner {
+                    latest_snapshot: RwLock::new(latest_snapshot),
+                    multistore_config,
+                    db,
+                })))
+            })
+        })
+        .await?
+    }
+}
+
+/// Reads the substores configured in a database opened with the given
+/// `columns`, for an instance that does not configure them itself.
+pub(super) fn read_multistore_config(db: &DB, columns: &[String]) -> Result<MultistoreConfig> {
+    // Substores whose settings were not recorded by their primary yet hash
+    // their values if they have a column family holding the values.
+    let configure = |prefix: String, recorded: Option<bool>| {
+        let config = SubstoreConfig::new(prefix);
+        let hash_values = recorded.unwrap_or_else(|| {
+            columns
+                .iter()
+                .any(|column| column == config.cf_jmt_blobs_name())
+        });
+        Arc::new(config.with_hash_values(hash_values))
+    };
+
+    let cf_config = db
+        .cf_handle("config")
+        .context("the database has no config column family")?;
+    let mut substores = Vec::new();
+    for entry in db.iterator_cf(cf_config, IteratorMode::Start) {
+        let (prefix, settings) = entry?;
+        let prefix = String::from_utf8(prefix.to_vec())?;
+        let config = configure(prefix, options::decode_substore_settings(&settings)?);
+        // A substore registered since the database was last loaded by its
+        // primary has no column families yet, and holds no data.
+        if !columns.iter().any(|column| column == config.cf_jmt_name()) {
+            tracing::debug!(prefix = ?config.prefix, "skipping substore without column families");
+            continue;
+        }
+        substores.push(config);
+    }
+
+    let settings = db.get(options::MAIN_STORE_SETTINGS_KEY)?;
+    let main_store = configure(
+        String::new(),
+        options::decode_substore_settings(settings.as_deref().unwrap_or_default())?,
+    );
+    // The primary checked the prefixes when it registered them.
+    MultistoreConfig::try_new(main_store, substores, true)
+}
+
+/// Returns a snapshot of the latest version in `db`, which is opened as a
+/// secondary instance and so cannot be pinned.
+fn latest_snapshot_from_db(db: Arc<DB>, multistore_config: &MultistoreConfig) -> Result<Snapshot> {
+    let (version, multistore_cache) = super::latest_versions_from_db(&db, multistore_config)?;
+    Ok(Snapshot::unpinned(db, version, multistore_cache))
+}
+
+impl SecondaryStorage {
+    /// Returns the latest version the replica has caught up to.
+    ///
+    /// If the primary had not committed any version, returns `u64::MAX`.
+    pub fn latest_version(&self) -> jmt::Version {
+        self.0.latest_snapshot.read().version()
+    }
+
+    /// Returns a [`Snapshot`] of the latest version the replica has caught up to.
+    pub fn latest_snapshot(&self) -> Snapshot {
+        self.0.latest_snapshot.read().clone()
+    }
+
+    /// Catches up with the primary, making its latest committed version
+    /// visible to new snapshots, and returns that version.
+    ///
+    /// Snapshots obtained before calling this method keep their version, but
+    /// their unversioned reads observe the new state; see [`SecondaryStorage`].
+    pub async fn catch_up(&self) -> Result<jmt::Version> {
+        let span = Span::current();
+        let inner = self.0.clone();
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                inner.db.try_catch_up_with_primary()?;
+
+                let snapshot = latest_snapshot_from_db(inner.db.clone(), &inner.multistore_config)?;
+                let version = snapshot.version();
+                tracing::debug!(?version, "caught up with primary");
+
+                *inner.latest_snapshot.write() = snapshot;
+                Ok(version)
+            })
+        })
+        .await?
+    }
+}
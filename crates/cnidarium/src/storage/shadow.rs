@@ -0,0 +1,160 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+
+use crate::{
+    OverlayOp, RootHash, Snapshot, StateDelta, StateRead, StateWriteExt, Storage, StorageError,
+};
+
+/// A pair of [`Storage`] instances that are written in lockstep, to validate
+/// a new storage configuration against an existing one.
+///
+/// Every commit is applied to both the primary and the shadow storage, and
+/// every read is made against both and compared. The first divergence is
+/// logged and returned as a [`StorageError::ShadowDivergence`].
+///
+/// This is a migration-validation tool: it doubles the cost of every operation,
+/// and is not meant to be used in production.
+pub struct ShadowStorage {
+    primary: Storage,
+    shadow: Storage,
+    compare_roots: bool,
+}
+
+impl ShadowStorage {
+    /// Pairs `primary` with `shadow`. Both are expected to hold the same
+    /// state, at the same version.
+    pub fn new(primary: Storage, shadow: Storage) -> Self {
+        Self {
+            primary,
+            shadow,
+            compare_roots: true,
+        }
+    }
+
+    /// Stops comparing root hashes after each commit, for storages whose
+    /// roots are not expected to match, e.g. because they use different
+    /// substores. Reads are still compared.
+    pub fn without_root_checks(mut self) -> Self {
+        self.compare_roots = false;
+        self
+    }
+
+    /// Returns the primary storage.
+    pub fn primary(&self) -> &Storage {
+        &self.primary
+    }
+
+    /// Returns the shadow storage.
+    pub fn shadow(&self) -> &Storage {
+        &self.shadow
+    }
+
+    /// Returns the latest snapshot of the primary storage, on top of which
+    /// deltas passed to [`ShadowStorage::commit`] should be built.
+    pub fn latest_snapshot(&self) -> Snapshot {
+        self.primary.latest_snapshot()
+    }
+
+    /// Commits `delta` to the primary storage, and replays its writes on top of
+    /// the latest snapshot of the shadow storage, returning the version and
+    /// root hash committed to the primary storage.
+    ///
+    /// Only the verifiable and nonverifiable writes are replayed: ephemeral
+    /// objects are not persisted, and events are not compared.
+    pub async fn commit(&self, delta: StateDelta<Snapshot>) -> Result<(jmt::Version, RootHash)> {
+        check(
+            "version before commit",
+            self.primary.latest_version(),
+            self.shadow.latest_version(),
+        )?;
+
+        let ops: Vec<OverlayOp> = delta.overlay_ops()?;
+        let (version, primary_root) = self.primary.commit(delta).await?;
+
+        let mut shadow_delta = StateDelta::new(self.shadow.latest_snapshot());
+        shadow_delta.replay_ops(&ops);
+        let (_, shadow_root) = self.shadow.commit(shadow_delta).await?;
+
+        check(
+            "version after commit",
+            self.primary.latest_version(),
+            self.shadow.latest_version(),
+        )?;
+        if self.compare_roots {
+            check("root hash", primary_root, shadow_root)?;
+        }
+
+        Ok((version, primary_root))
+    }
+
+    /// Reads `key` from the verifiable store of both storages.
+    pub async fn get_raw(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let primary = self.primary.latest_snapshot().get_raw(key).await?;
+        let shadow = self.shadow.latest_snapshot().get_raw(key).await?;
+        check(&format!("get_raw({key})"), primary, shadow)
+    }
+
+    /// Reads `key` from the nonverifiable store of both storages.
+    pub async fn nonverifiable_get_raw(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let primary = self
+            .primary
+            .latest_snapshot()
+            .nonverifiable_get_raw(key)
+            .await?;
+        let shadow = self
+            .shadow
+            .latest_snapshot()
+            .nonverifiable_get_raw(key)
+            .await?;
+        check(
+            &format!("nonverifiable_get_raw({:?})", crate::EscapedByteSlice(key)),
+            primary,
+            shadow,
+        )
+    }
+
+    /// Collects the entries under `prefix` in the verifiable store of both
+    /// storages.
+    pub async fn prefix_raw(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let primary = self
+            .primary
+            .latest_snapshot()
+            .prefix_raw(prefix)
+            .try_collect::<Vec<_>>()
+            .await?;
+        let shadow = self
+            .shadow
+            .latest_snapshot()
+            .prefix_raw(prefix)
+            .try_collect::<Vec<_>>()
+            .await?;
+        check(&format!("prefix_raw({prefix})"), primary, shadow)
+    }
+
+    /// Releases both storages.
+    pub async fn release(self) {
+        self.primary.release().await;
+        self.shadow.release().await;
+    }
+}
+
+/// Returns the primary result if it matches the shadow one, and an error
+/// describing the divergence otherwise.
+fn check<T: PartialEq + std::fmt::Debug>(operation: &str, primary: T, shadow: T) -> Result<T> {
+    if primary == shadow {
+        return Ok(primary);
+    }
+
+    tracing::error!(
+        operation,
+        ?primary,
+        ?shadow,
+        "shadow storage diverged from primary"
+    );
+    Err(StorageError::ShadowDivergence {
+        operation: operation.to_string(),
+        primary: format!("{primary:?}"),
+        shadow: format!("{shadow:?}"),
+    }
+    .into())
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+
+use crate::{ColumnFamilyRole, RootHash, Storage};
+
+/// A summary of the state of a [`Storage`] instance, as returned by
+/// [`Storage::status`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "rpc", derive(serde::Serialize))]
+pub struct StorageStatus {
+    /// The latest committed version, or `u64::MAX` if nothing was committed.
+    pub latest_version: jmt::Version,
+    /// The root hash of the chain state at the latest version, or `None` if
+    /// nothing was committed.
+    pub root_hash: Option<RootHash>,
+    /// The oldest version that can still be read, see [`Storage::prune`].
+    pub oldest_available_version: jmt::Version,
+    /// The status of every registered substore, in prefix order. The main
+    /// store is not included.
+    pub substores: Vec<SubstoreStatus>,
+    /// The estimated size of the live data of the whole database, in bytes.
+    pub approximate_size: u64,
+}
+
+/// The status of a single substore, as reported in a [`StorageStatus`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "rpc", derive(serde::Serialize))]
+pub struct SubstoreStatus {
+    /// The prefix of the substore.
+    pub prefix: String,
+    /// The latest version of the substore, or `u64::MAX` if it was never
+    /// written to. Substore versions only advance when the substore changes,
+    /// so they differ from the version of the multistore.
+    pub version: jmt::Version,
+    /// The root hash of the substore at its latest version, or `None` if it
+    /// was never written to.
+    pub root_hash: Option<RootHash>,
+    /// The estimated size of the live data of the substore, in bytes.
+    pub approximate_size: u64,
+}
+
+impl Storage {
+    /// Returns a summary of the layout and versions of the storage.
+    ///
+    /// Versions are read from memory, and roots from the root node of each
+    /// tree, so this only does one read per substore, along with reading the
+    /// size estimates that RocksDB keeps for each column family.
+    pub async fn status(&self) -> Result<StorageStatus> {
+        let snapshot = self.latest_snapshot();
+        let latest_version = snapshot.version();
+        let root_hash = if latest_version == u64::MAX {
+            None
+        } else {
+            Some(snapshot.root_hash().await?)
+        };
+
+        let families = self.column_families()?;
+        let size_of = |role: &ColumnFamilyRole| -> u64 {
+            families
+                .iter()
+                .filter(|family| &family.role == role)
+                .filter_map(|family| family.approximate_size)
+                .sum()
+        };
+
+        let mut substores = Vec::new();
+        for config in self.0.multistore_config.iter() {
+            let version = snapshot
+                .substore_version(config)
+                .expect("registered substores have a version");
+            let root_hash = if version == u64::MAX {
+                None
+            } else {
+                Some(snapshot.prefix_root_hash(&config.prefix).await?)
+            };
+            substores.push(SubstoreStatus {
+                prefix: config.prefix.clone(),
+                version,
+                root_hash,
+                approximate_size: size_of(&ColumnFamilyRole::Substore(config.prefix.clone())),
+            });
+        }
+        substores.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+
+        Ok(StorageStatus {
+            latest_version,
+            root_hash,
+            oldest_available_version: self.oldest_available_version()?.unwrap_or(0),
+            substores,
+            approximate_size: families
+                .iter()
+                .filter_map(|family| family.approximate_size)
+                .sum(),
+        })
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use crate::{
+    store::multistore::MultistoreConfig, RootHash, Snapshot, StagedWriteBatch, StateDelta, Storage,
+};
+
+/// A record of the verifiable writes made by a commit, and of the roots they
+/// produced, for comparing the commits of two nodes offline.
+///
+/// Values are identified by their BLAKE3 hash, which keeps traces compact.
+/// Two nodes that committed the same changes on top of the same state produce
+/// identical traces, so the first difference between two traces pinpoints
+/// where the nodes diverged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitTrace {
+    /// The version that was committed.
+    pub version: jmt::Version,
+    /// The root hash of the main store after the commit.
+    pub root_hash: RootHash,
+    /// The changes made to each substore that was updated, ordered by prefix.
+    /// The main store, with the empty prefix, is always included.
+    pub substores: Vec<SubstoreTrace>,
+    /// The number of internal tree nodes written, across all substores.
+    pub internal_nodes: usize,
+}
+
+/// The verifiable changes made to a single substore by a commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubstoreTrace {
+    /// The prefix of the substore, empty for the main store.
+    pub prefix: String,
+    /// The root hash of the substore after the commit.
+    pub root_hash: RootHash,
+    /// The full keys written, in key order, with the BLAKE3 hash of their new
+    /// value. For the main store, this includes the new roots of the updated
+    /// substores, which are stored under their prefix.
+    pub writes: Vec<(String, [u8; 32])>,
+    /// The full keys deleted, in key order.
+    pub deletions: Vec<String>,
+}
+
+impl CommitTrace {
+    fn from_batch(config: &MultistoreConfig, batch: &StagedWriteBatch) -> Result<Self> {
+        let new_trace = |prefix: &str, root_hash| SubstoreTrace {
+            prefix: prefix.to_string(),
+            root_hash,
+            writes: Vec::new(),
+            deletions: Vec::new(),
+        };
+
+        let mut substores = BTreeMap::new();
+        let mut main_store = new_trace("", batch.root_hash);
+        for (substore, (root_hash, _)) in &batch.substore_roots {
+            main_store.writes.push((
+                substore.prefix.clone(),
+                *blake3::hash(&root_hash.0).as_bytes(),
+            ));
+            substores.insert(
+                substore.prefix.clone(),
+                new_trace(&substore.prefix, *root_hash),
+            );
+        }
+        substores.insert(String::new(), main_store);
+
+        for (key, value) in &batch.changes.unwritten_changes()? {
+            let (_, substore) = config.route_key_str(key);
+            let trace = substores
+                .get_mut(&substore.prefix)
+                .expect("every substore with changes has a new root");
+            match value {
+                Some(value) => trace
+                    .writes
+                    .push((key.clone(), *blake3::hash(value).as_bytes())),
+                None => trace.deletions.push(key.clone()),
+            }
+        }
+        for trace in substores.values_mut() {
+            trace.writes.sort();
+        }
+
+        Ok(Self {
+            version: batch.version,
+            root_hash: batch.root_hash,
+            substores: substores.into_values().collect(),
+            internal_nodes: batch.internal_nodes,
+        })
+    }
+}
+
+impl Storage {
+    /// Commits the provided [`StateDelta`] like [`Storage::commit`], also
+    /// returning a [`CommitTrace`] of the verifiable changes it made along
+    /// with the committed version and its root hash.
+    ///
+    /// Nonverifiable changes are not traced.
+    pub async fn commit_with_trace(
+        &self,
+        delta: StateDelta<Snapshot>,
+    ) -> Result<(jmt::Version, RootHash, CommitTrace)> {
+        let batch = self.prepare_commit(delta).await?;
+        let trace = CommitTrace::from_batch(&self.0.multistore_config, &batch)?;
+        let (version, root_hash) = self.commit_batch(batch)?;
+        Ok((version, root_hash, trace))
+    }
+}
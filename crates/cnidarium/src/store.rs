@@ -1,2 +1,5 @@
+mod lru;
 pub(crate) mod multistore;
+pub(crate) mod node_cache;
 pub(crate) mod substore;
+pub(crate) mod value_cache;
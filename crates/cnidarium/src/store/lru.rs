@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use parking_lot::Mutex;
+
+/// A bounded, least-recently-used map, safe to share between threads.
+///
+/// This backs the caches shared by all the snapshots of a
+/// [`Storage`](crate::Storage), see [`NodeCache`](super::node_cache::NodeCache)
+/// and [`ValueCache`](super::value_cache::ValueCache).
+#[derive(Debug)]
+pub(crate) struct Lru<K, V> {
+    capacity: usize,
+    inner: Mutex<Entries<K, V>>,
+}
+
+#[derive(Debug)]
+struct Entries<K, V> {
+    /// Incremented on every access, to order entries by recency.
+    tick: u64,
+    entries: BTreeMap<K, (u64, V)>,
+    recency: BTreeMap<u64, K>,
+}
+
+impl<K: Ord + Clone, V: Clone> Lru<K, V> {
+    /// Creates an empty map holding at most `capacity` entries.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Entries {
+                tick: 0,
+                entries: BTreeMap::new(),
+                recency: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Returns the number of entries.
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().entries.len()
+    }
+
+    /// Removes every entry.
+    pub(crate) fn clear(&self) {
+        let mut lru = self.inner.lock();
+        lru.entries.clear();
+        lru.recency.clear();
+    }
+
+    /// Returns the value of `key`, marking it as the most recently used.
+    pub(crate) fn get(&self, key: &K) -> Option<V> {
+        let mut lru = self.inner.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+        let (last_used, value) = lru.entries.get_mut(key)?;
+        let previous = std::mem::replace(last_used, tick);
+        let value = value.clone();
+        lru.recency.remove(&previous);
+        lru.recency.insert(tick, key.clone());
+        Some(value)
+    }
+
+    /// Inserts `value` under `key`, evicting the least recently used entries
+    /// beyond the capacity.
+    pub(crate) fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut lru = self.inner.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some((previous, _)) = lru.entries.insert(key.clone(), (tick, value)) {
+            lru.recency.remove(&previous);
+        }
+        lru.recency.insert(tick, key);
+
+        while lru.entries.len() > self.capacity {
+            let Some((_, evicted)) = lru.recency.pop_first() else {
+                break;
+            };
+            lru.entries.remove(&evicted);
+        }
+    }
+}
@@ -1,30 +1,120 @@
-use std::{fmt::Display, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    sync::Arc,
+};
 
-use super::substore::SubstoreConfig;
+use super::{node_cache::NodeCache, substore::SubstoreConfig, value_cache::ValueCache};
+use crate::{
+    snapshot::{IteratorLimit, VersionPins},
+    RetryPolicy,
+};
 
 /// A collection of substore, each with a unique prefix.
 #[derive(Debug, Clone)]
 pub struct MultistoreConfig {
     pub main_store: Arc<SubstoreConfig>,
-    pub substores: Vec<Arc<SubstoreConfig>>,
+    /// The registered substores, see [`MultistoreConfig::iter`]. They are kept
+    /// private so that they never get out of sync with `substore_trie`.
+    pub(crate) substores: Vec<Arc<SubstoreConfig>>,
+    /// The prefixes of `substores`, used to route keys.
+    pub(crate) substore_trie: PrefixTrie,
+    /// If set, caches the tree nodes read during proof generation.
+    pub(crate) node_cache: Option<Arc<NodeCache>>,
+    /// If set, caches the values read from the verifiable store.
+    pub(crate) value_cache: Option<Arc<ValueCache>>,
+    /// The versions pinned by live archives, shared by all snapshots.
+    pub(crate) pins: VersionPins,
+    /// How snapshot reads are retried after transient errors.
+    pub(crate) read_retry: RetryPolicy,
+    /// Caps the number of iterators held open by snapshot streams.
+    pub(crate) iterator_limit: IteratorLimit,
+    /// If set, the maximum number of internal tree nodes a commit may write.
+    pub(crate) max_internal_nodes: Option<usize>,
 }
 
 impl MultistoreConfig {
+    /// Creates a config that routes keys to the given substores, and every
+    /// other key to the main store. The other settings take their default
+    /// values.
+    ///
+    /// Unless `allow_nested` is set, substore prefixes may not be nested, like
+    /// `dex` and `dex/lp`; see [`StorageOptions::allow_nested_prefixes`](crate::StorageOptions::allow_nested_prefixes).
+    ///
+    /// # Errors
+    /// Returns an error if the main store does not have the empty prefix, if
+    /// a substore prefix is empty, is registered more than once, or ends with
+    /// the `/` delimiter, since no key would ever be routed to it, or if
+    /// prefixes are nested and `allow_nested` is not set.
+    pub fn try_new(
+        main_store: Arc<SubstoreConfig>,
+        substores: Vec<Arc<SubstoreConfig>>,
+        allow_nested: bool,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            main_store.prefix.is_empty(),
+            "the main store must have the empty prefix, not {:?}",
+            main_store.prefix
+        );
+        let mut prefixes = BTreeSet::new();
+        for substore in &substores {
+            let prefix = &substore.prefix;
+            anyhow::ensure!(!prefix.is_empty(), "the empty prefix is reserved");
+            anyhow::ensure!(
+                !prefix.ends_with('/'),
+                "substore prefix {prefix:?} ends with the delimiter, so no key would be routed to it"
+            );
+            anyhow::ensure!(
+                prefixes.insert(prefix),
+                "substore prefix {prefix:?} is registered more than once"
+            );
+        }
+        if !allow_nested {
+            check_not_nested(&substores)?;
+        }
+
+        Ok(Self {
+            main_store,
+            substore_trie: PrefixTrie::new(&substores),
+            substores,
+            ..Default::default()
+        })
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &Arc<SubstoreConfig>> {
         self.substores.iter()
     }
 
-    /// Returns the substore matching the key's prefix, return `None` otherwise.
+    /// Returns the substore registered with exactly the given prefix, if any.
+    ///
+    /// Unlike [`MultistoreConfig::find_substore`], this never returns the main store.
+    pub fn substore(&self, prefix: &str) -> Option<Arc<SubstoreConfig>> {
+        self.substores.iter().find(|s| s.prefix == prefix).cloned()
+    }
+
+    /// Returns the substore designated by `prefix`, which must either be the
+    /// prefix of a registered substore, or start with it and its delimiter.
+    ///
+    /// Unlike [`MultistoreConfig::find_substore`], this never falls back to the
+    /// main store, and does not match keys like `prefix_akey` to `prefix_a`.
+    pub fn find_substore_strict(&self, prefix: &str) -> Option<Arc<SubstoreConfig>> {
+        self.substores
+            .iter()
+            .find(|s| prefix == s.prefix || prefix.starts_with(&s.prefix_with_delimiter))
+            .cloned()
+    }
+
+    /// Returns the substore with the longest prefix matching the key's prefix,
+    /// followed by the `/` delimiter or the end of the key, or `None` otherwise.
+    ///
+    /// The lookup walks a trie of the substore prefixes, so it runs in time
+    /// proportional to the length of the key, not to the number of substores.
     pub fn find_substore(&self, key: &[u8]) -> Option<Arc<SubstoreConfig>> {
         if key.is_empty() {
             return Some(self.main_store.clone());
         }
 
-        // Note: This is a linear search, but the number of substores is small.
-        self.substores
-            .iter()
-            .find(|s| key.starts_with(s.prefix.as_bytes()))
-            .cloned()
+        self.substore_trie.longest_match(key).cloned()
     }
 
     /// Route a key to a substore, and return the truncated key and the corresponding `SubstoreConfig`.
@@ -194,10 +284,92 @@ impl Default for MultistoreConfig {
         Self {
             main_store: Arc::new(SubstoreConfig::new("")),
             substores: vec![],
+            substore_trie: PrefixTrie::default(),
+            node_cache: None,
+            value_cache: None,
+            pins: VersionPins::default(),
+            read_retry: RetryPolicy::default(),
+            iterator_limit: IteratorLimit::default(),
+            max_internal_nodes: None,
         }
     }
 }
 
+/// Checks that no substore prefix is nested in another one, i.e. starts with
+/// it and its delimiter. Prefixes like `ibc` and `ibcx` are not nested.
+fn check_not_nested(substores: &[Arc<SubstoreConfig>]) -> anyhow::Result<()> {
+    for outer in substores {
+        for inner in substores {
+            if inner.prefix.starts_with(&outer.prefix_with_delimiter) {
+                anyhow::bail!(
+                    "substore prefix {:?} is nested in substore prefix {:?}",
+                    inner.prefix,
+                    outer.prefix
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A byte-wise trie of substore prefixes.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PrefixTrie {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    /// The index of the child node reached by each byte.
+    children: BTreeMap<u8, usize>,
+    /// The substore whose prefix ends at this node, if any.
+    substore: Option<Arc<SubstoreConfig>>,
+}
+
+impl PrefixTrie {
+    /// Builds a trie of the prefixes of `substores`.
+    pub(crate) fn new(substores: &[Arc<SubstoreConfig>]) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+        for substore in substores {
+            let mut index = 0;
+            for byte in substore.prefix.bytes() {
+                index = match nodes[index].children.get(&byte) {
+                    Some(&child) => child,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let child = nodes.len() - 1;
+                        nodes[index].children.insert(byte, child);
+                        child
+                    }
+                };
+            }
+            nodes[index].substore = Some(substore.clone());
+        }
+        Self { nodes }
+    }
+
+    /// Returns the substore with the longest prefix of `key`, if any.
+    ///
+    /// A prefix only matches if it is followed by the `/` delimiter or ends
+    /// the key, so that `dex/lpx` matches `dex` rather than `dex/lp`.
+    fn longest_match(&self, key: &[u8]) -> Option<&Arc<SubstoreConfig>> {
+        let mut index = 0;
+        let mut matched = None;
+        for (i, byte) in key.iter().enumerate() {
+            match self.nodes[index].children.get(byte) {
+                Some(&child) => index = child,
+                None => break,
+            }
+            if let Some(substore) = &self.nodes[index].substore {
+                if matches!(key.get(i + 1), None | Some(b'/')) {
+                    matched = Some(substore);
+                }
+            }
+        }
+        matched
+    }
+}
+
 /// Tracks the latest version of each substore, and wraps a `MultistoreConfig`.
 #[derive(Default, Debug)]
 pub struct MultistoreCache {
@@ -230,4 +402,13 @@ impl MultistoreCache {
     pub fn get_version(&self, substore: &Arc<SubstoreConfig>) -> Option<jmt::Version> {
         self.substores.get(substore).cloned()
     }
+
+    /// Returns the version of each substore, keyed by prefix. The main store,
+    /// if present, is keyed by the empty prefix.
+    pub fn versions(&self) -> std::collections::BTreeMap<String, jmt::Version> {
+        self.substores
+            .iter()
+            .map(|(substore, version)| (substore.prefix.clone(), *version))
+            .collect()
+    }
 }
@@ -0,0 +1,134 @@
+use anyhow::Result;
+use jmt::{
+    storage::{LeafNode, Node, NodeKey, TreeReader},
+    KeyHash,
+};
+
+use super::{
+    lru::Lru,
+    substore::{DbNodeKey, SubstoreSnapshot},
+};
+
+/// A bounded, least-recently-used cache of decoded tree nodes, shared by all
+/// the snapshots of a [`Storage`](crate::Storage).
+///
+/// Entries are keyed by substore prefix and encoded node key. Since a node key
+/// includes the version at which the node was written, and nodes are never
+/// rewritten once committed, cached entries never go stale. The exceptions are
+/// `Storage::commit_in_place`, and `Storage::prune`, which deletes old nodes:
+/// both clear the cache.
+#[derive(Debug)]
+pub(crate) struct NodeCache {
+    lru: Lru<CacheKey, Node>,
+}
+
+type CacheKey = (String, Vec<u8>);
+
+impl NodeCache {
+    /// Creates an empty cache holding at most `capacity` nodes.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            lru: Lru::new(capacity),
+        }
+    }
+
+    /// Returns the number of cached nodes.
+    pub(crate) fn len(&self) -> usize {
+        self.lru.len()
+    }
+
+    /// Removes every cached node.
+    pub(crate) fn clear(&self) {
+        self.lru.clear()
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Node> {
+        self.lru.get(key)
+    }
+
+    fn insert(&self, key: CacheKey, node: Node) {
+        self.lru.insert(key, node)
+    }
+}
+
+/// A [`TreeReader`] that serves nodes from a [`NodeCache`], falling back to
+/// the underlying [`SubstoreSnapshot`] and caching what it reads.
+pub(crate) struct CachedTreeReader<'a> {
+    pub(crate) substore: &'a SubstoreSnapshot,
+    pub(crate) cache: &'a NodeCache,
+}
+
+impl TreeReader for CachedTreeReader<'_> {
+    fn get_value_option(
+        &self,
+        max_version: jmt::Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<jmt::OwnedValue>> {
+        self.substore.get_value_option(max_version, key_hash)
+    }
+
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        let key = (
+            self.substore.config.prefix.clone(),
+            DbNodeKey::encode_from_node_key(node_key)?,
+        );
+        if let Some(node) = self.cache.get(&key) {
+            return Ok(Some(node));
+        }
+
+        let node = self.substore.get_node_option(node_key)?;
+        // Nodes written at the pre-genesis version are not final, so they are
+        // never cached.
+        if let Some(node) = &node {
+            if node_key.version() != u64::MAX {
+                self.cache.insert(key, node.clone());
+            }
+        }
+        Ok(node)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.substore.get_rightmost_leaf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: u8) -> CacheKey {
+        ("".to_string(), vec![i])
+    }
+
+    #[test]
+    /// The least recently used entry is evicted once the capacity is exceeded.
+    fn evicts_least_recently_used() {
+        let cache = NodeCache::new(2);
+        cache.insert(key(0), Node::Null);
+        cache.insert(key(1), Node::Null);
+        // Touch the first entry, so that the second one is the oldest.
+        assert!(cache.get(&key(0)).is_some());
+        cache.insert(key(2), Node::Null);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&key(0)).is_some());
+        assert!(cache.get(&key(1)).is_none());
+        assert!(cache.get(&key(2)).is_some());
+
+        // Re-inserting an entry does not grow the cache.
+        cache.insert(key(2), Node::Null);
+        assert_eq!(cache.len(), 2);
+
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    /// A cache with zero capacity holds nothing.
+    fn zero_capacity_disables_cache() {
+        let cache = NodeCache::new(0);
+        cache.insert(key(0), Node::Null);
+        assert_eq!(cache.len(), 0);
+        assert!(cache.get(&key(0)).is_none());
+    }
+}
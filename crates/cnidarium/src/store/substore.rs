@@ -1,24 +1,36 @@
 use std::{
+    collections::BTreeMap,
     fmt::{Display, Formatter},
     sync::Arc,
+    time::Duration,
 };
 
 use anyhow::Result;
 use borsh::BorshDeserialize;
 use jmt::{
+    proof::SparseMerkleProof,
     storage::{HasPreimage, LeafNode, Node, NodeKey, TreeReader},
     KeyHash, RootHash,
 };
-use rocksdb::{ColumnFamily, IteratorMode, ReadOptions};
+use rocksdb::{ColumnFamily, ColumnFamilyDescriptor, IteratorMode, Options, ReadOptions};
+use sha2::Digest as _;
 use tracing::Span;
 
-use crate::{snapshot::RocksDbSnapshot, Cache};
+use crate::{snapshot::RocksDbSnapshot, Cache, Compression};
+
+use super::node_cache::{CachedTreeReader, NodeCache};
 
 use jmt::storage::TreeWriter;
 
 /// Specifies the configuration of a substore, which is a prefixed subset of
 /// the main store with its own merkle tree, nonverifiable data, preimage index, etc.
-#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Hash)]
+///
+/// A substore is identified by its prefix: configs are compared, ordered and
+/// hashed by prefix alone, ignoring their options. Collections keyed by
+/// substore, such as the versions tracked by a
+/// [`MultistoreCache`](super::multistore::MultistoreCache), therefore iterate
+/// in lexicographic prefix order, with the main store first.
+#[derive(Debug)]
 pub struct SubstoreConfig {
     /// The prefix of the substore. If empty, it is the root-level store config.
     pub prefix: String,
@@ -47,6 +59,44 @@ pub struct SubstoreConfig {
     /// part of consensus.
     /// maps: arbitrary keys to arbitrary values.
     cf_nonverifiable: String,
+    /// name: "substore-{prefix}-jmt-blobs"
+    /// role: stores the values of a substore with `hash_values` set.
+    /// maps: the SHA-256 hash of a value to the value.
+    cf_jmt_blobs: String,
+    /// The compression codec applied to the substore's column families.
+    pub compression: Compression,
+    /// If set, the age after which entries of the nonverifiable column family
+    /// are dropped by compaction.
+    pub nonverifiable_ttl: Option<Duration>,
+    /// If set, the tree holds the SHA-256 hash of each value rather than the
+    /// value itself, and values are stored in a companion column family.
+    pub hash_values: bool,
+}
+
+impl PartialEq for SubstoreConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.prefix == other.prefix
+    }
+}
+
+impl Eq for SubstoreConfig {}
+
+impl PartialOrd for SubstoreConfig {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SubstoreConfig {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.prefix.cmp(&other.prefix)
+    }
+}
+
+impl std::hash::Hash for SubstoreConfig {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.prefix.hash(state)
+    }
 }
 
 impl SubstoreConfig {
@@ -58,11 +108,36 @@ impl SubstoreConfig {
             cf_jmt_values: format!("substore-{}-jmt-values", prefix),
             cf_jmt_keys_by_keyhash: format!("substore-{}-jmt-keys-by-keyhash", prefix),
             cf_nonverifiable: format!("substore-{}-nonverifiable", prefix),
+            cf_jmt_blobs: format!("substore-{}-jmt-blobs", prefix),
             prefix_with_delimiter: format!("{}/", prefix),
             prefix,
+            compression: Compression::default(),
+            nonverifiable_ttl: None,
+            hash_values: false,
         }
     }
 
+    /// Sets the compression codec applied to the substore's column families.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the age after which entries of the substore's nonverifiable column
+    /// family are dropped by compaction.
+    pub fn with_nonverifiable_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.nonverifiable_ttl = ttl;
+        self
+    }
+
+    /// Sets whether the tree holds the hashes of the values rather than the
+    /// values themselves, as described in
+    /// [`StorageOptions::hash_values`](crate::StorageOptions::hash_values).
+    pub fn with_hash_values(mut self, hash_values: bool) -> Self {
+        self.hash_values = hash_values;
+        self
+    }
+
     /// Returns an iterator over all column families in this substore.
     /// Note(erwan): This is verbose, but very lightweight.
     pub fn columns(&self) -> impl Iterator<Item = &String> {
@@ -71,6 +146,71 @@ impl SubstoreConfig {
             .chain(std::iter::once(&self.cf_jmt_values))
             .chain(std::iter::once(&self.cf_jmt_keys_by_keyhash))
             .chain(std::iter::once(&self.cf_nonverifiable))
+            .chain(self.hash_values.then_some(&self.cf_jmt_blobs))
+    }
+
+    /// Returns descriptors for all column families in this substore, starting
+    /// from `base` and configured with the substore's options.
+    pub fn column_descriptors<'a>(
+        &'a self,
+        base: &'a Options,
+    ) -> impl Iterator<Item = ColumnFamilyDescriptor> + 'a {
+        self.columns().map(|column| {
+            let mut cf_opts = base.clone();
+            cf_opts.set_compression_type(self.compression.to_rocksdb());
+            let nonverifiable_ttl = self
+                .nonverifiable_ttl
+                .filter(|_| *column == self.cf_nonverifiable);
+            if let Some(ttl) = nonverifiable_ttl {
+                // FIFO compaction drops whole files once all of their entries
+                // are older than the TTL. Its size limit is lifted, so that no
+                // data is dropped before it expires.
+                let mut fifo_opts = rocksdb::FifoCompactOptions::default();
+                fifo_opts.set_max_table_files_size(u64::MAX);
+                cf_opts.set_compaction_style(rocksdb::DBCompactionStyle::Fifo);
+                cf_opts.set_fifo_compaction_options(&fifo_opts);
+                cf_opts.set_ttl(ttl.as_secs());
+            }
+            ColumnFamilyDescriptor::new(column, cf_opts)
+        })
+    }
+
+    /// Returns the name of the column family persisting the JMT nodes.
+    pub(crate) fn cf_jmt_name(&self) -> &str {
+        &self.cf_jmt
+    }
+
+    /// Returns the name of the column family holding the nonverifiable data.
+    pub(crate) fn cf_nonverifiable_name(&self) -> &str {
+        &self.cf_nonverifiable
+    }
+
+    /// Adds the nonverifiable `changes` of this substore, with their keys
+    /// relative to it, to `write_batch`.
+    pub(crate) fn write_nonverifiable(
+        &self,
+        db: &Arc<rocksdb::DB>,
+        changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        write_batch: &mut rocksdb::WriteBatch,
+    ) {
+        let cf_nonverifiable = self.cf_nonverifiable(db);
+        for (k, v) in changes {
+            match v {
+                Some(v) => {
+                    tracing::trace!(key = ?crate::EscapedByteSlice(&k), value = ?crate::EscapedByteSlice(&v), "put nonverifiable key");
+                    write_batch.put_cf(cf_nonverifiable, k, &v);
+                }
+                None => {
+                    write_batch.delete_cf(cf_nonverifiable, k);
+                }
+            };
+        }
+    }
+
+    /// Returns the name of the column family holding the values, if the
+    /// substore has `hash_values` set.
+    pub(crate) fn cf_jmt_blobs_name(&self) -> &str {
+        &self.cf_jmt_blobs
     }
 
     pub fn cf_jmt<'s>(&self, db_handle: &'s Arc<rocksdb::DB>) -> &'s ColumnFamily {
@@ -113,6 +253,16 @@ impl SubstoreConfig {
         })
     }
 
+    pub fn cf_jmt_blobs<'s>(&self, db_handle: &'s Arc<rocksdb::DB>) -> &'s ColumnFamily {
+        let column = self.cf_jmt_blobs.as_str();
+        db_handle.cf_handle(column).unwrap_or_else(|| {
+            panic!(
+                "jmt-blobs column family not found for prefix: {}, substore: {}",
+                column, self.prefix
+            )
+        })
+    }
+
     pub fn cf_nonverifiable<'s>(&self, db_handle: &'s Arc<rocksdb::DB>) -> &'s ColumnFamily {
         let column = self.cf_nonverifiable.as_str();
         db_handle.cf_handle(column).unwrap_or_else(|| {
@@ -220,16 +370,61 @@ impl SubstoreSnapshot {
         self.version
     }
 
+    /// Returns the number of keys in the substore's tree at the current version.
+    ///
+    /// The count is read from the root node, which tracks the number of leaves
+    /// below it, so this does not walk the tree.
+    pub fn leaf_count(&self) -> Result<u64> {
+        // The pre-genesis version has no root, and no keys.
+        if self.version() == u64::MAX {
+            return Ok(0);
+        }
+
+        let root_key = NodeKey::new_empty_path(self.version());
+        let count = match self.get_node_option(&root_key)? {
+            Some(Node::Internal(internal)) => internal.leaf_count(),
+            Some(Node::Leaf(_)) => 1,
+            Some(Node::Null) => 0,
+            None => anyhow::bail!(
+                "missing root node for substore {} at version {}",
+                self.config.prefix,
+                self.version()
+            ),
+        };
+        Ok(count as u64)
+    }
+
     /// Returns some value corresponding to the key, along with an ICS23 existence proof
     /// up to the current JMT root hash. If the key is not present, returns `None` and a
     /// non-existence proof.
+    ///
+    /// If a `node_cache` is supplied, tree nodes are read through it.
     pub(crate) fn get_with_proof(
         &self,
         key: Vec<u8>,
+        node_cache: Option<&NodeCache>,
     ) -> Result<(Option<Vec<u8>>, ics23::CommitmentProof)> {
         let version = self.version();
-        let tree = jmt::Sha256Jmt::new(self);
-        tree.get_with_ics23_proof(key, version)
+        match node_cache {
+            Some(cache) => {
+                let reader = CachedTreeReader {
+                    substore: self,
+                    cache,
+                };
+                jmt::Sha256Jmt::new(&reader).get_with_ics23_proof(key, version)
+            }
+            None => jmt::Sha256Jmt::new(self).get_with_ics23_proof(key, version),
+        }
+    }
+
+    /// Returns the value of `key_hash`, along with a JMT proof of its
+    /// inclusion, or of its exclusion if it is absent, against the root of the
+    /// tree at this version.
+    pub(crate) fn get_with_jmt_proof(
+        &self,
+        key_hash: KeyHash,
+    ) -> Result<(Option<Vec<u8>>, SparseMerkleProof<sha2::Sha256>)> {
+        jmt::Sha256Jmt::new(self).get_with_proof(key_hash, self.version())
     }
 
     /// Helper function used by `get_raw` and `prefix_raw`.
@@ -238,10 +433,20 @@ impl SubstoreSnapshot {
     /// special-cases the empty tree case so that reads on an empty tree just
     /// return None.
     pub fn get_jmt(&self, key: jmt::KeyHash) -> Result<Option<Vec<u8>>> {
+        // Nothing is committed at the pre-genesis version. The value index
+        // can't tell, since it treats `u64::MAX` as the latest version, which
+        // matters when reading a substore at a version preceding its first
+        // commit.
+        if self.version() == u64::MAX {
+            return Ok(None);
+        }
         let tree = jmt::Sha256Jmt::new(self);
         match tree.get(key, self.version()) {
             Ok(Some(value)) => {
                 tracing::trace!(substore = ?self.config.prefix, version = ?self.version(), ?key, value = ?hex::encode(&value), "read from tree");
+                if self.config.hash_values {
+                    return self.get_blob(&value).map(Some);
+                }
                 Ok(Some(value))
             }
             Ok(None) => {
@@ -262,6 +467,221 @@ impl SubstoreSnapshot {
             Err(e) => Err(e),
         }
     }
+
+    /// Returns the length of the value of `key` at this snapshot's version, as
+    /// [`SubstoreSnapshot::get_jmt`] would return it, without copying the value.
+    ///
+    /// Values are looked up in the versioned value index like
+    /// [`TreeReader::get_value_option`], and their length is decoded from the
+    /// header of their encoding, which is read in place.
+    pub fn get_value_size(&self, key_hash: KeyHash) -> Result<Option<usize>> {
+        // The tree only holds hashes, so the size is that of the stored value.
+        if self.config.hash_values {
+            let Some(value_hash) = self.get_value_option(self.version(), key_hash)? else {
+                return Ok(None);
+            };
+            let cf_jmt_blobs = self.config.cf_jmt_blobs(&self.db);
+            return match self
+                .rocksdb_snapshot
+                .get_pinned_cf(cf_jmt_blobs, &value_hash)?
+            {
+                Some(value) => Ok(Some(value.len())),
+                None => Err(missing_blob(&self.config, &value_hash)),
+            };
+        }
+
+        let cf_jmt_values = self.config.cf_jmt_values(&self.db);
+        let max_version = self.version();
+
+        if max_version == u64::MAX {
+            let k = VersionedKeyHash {
+                version: u64::MAX,
+                key_hash,
+            };
+            if let Some(v) = self
+                .rocksdb_snapshot
+                .get_pinned_cf(cf_jmt_values, k.encode())?
+            {
+                return decode_value_size(v.as_ref());
+            }
+        }
+
+        let mut lower_bound = key_hash.0.to_vec();
+        lower_bound.extend_from_slice(&0u64.to_be_bytes());
+        let mut upper_bound = key_hash.0.to_vec();
+        upper_bound.extend_from_slice(&(max_version.saturating_add(1)).to_be_bytes());
+
+        let mut readopts = ReadOptions::default();
+        readopts.set_iterate_lower_bound(lower_bound);
+        readopts.set_iterate_upper_bound(upper_bound);
+        let mut iterator = self
+            .rocksdb_snapshot
+            .raw_iterator_cf_opt(cf_jmt_values, readopts);
+        iterator.seek_to_last();
+
+        match iterator.value() {
+            Some(v) => decode_value_size(v),
+            None => {
+                iterator.status()?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns every value written for `key_hash` up to this snapshot's
+    /// version, in increasing version order, with `None` for deletions.
+    ///
+    /// The versions are those of this substore's tree, read from the versioned
+    /// value index without traversing the tree.
+    pub fn get_value_history(
+        &self,
+        key_hash: KeyHash,
+    ) -> Result<Vec<(jmt::Version, Option<Vec<u8>>)>> {
+        let cf_jmt_values = self.config.cf_jmt_values(&self.db);
+        let iterator = self.rocksdb_snapshot.iterator_cf_opt(
+            cf_jmt_values,
+            value_index_range(key_hash, self.version()),
+            IteratorMode::Start,
+        );
+
+        let mut history = Vec::new();
+        for entry in iterator {
+            let (key, value) = entry?;
+            let VersionedKeyHash { version, .. } = VersionedKeyHash::decode(key.to_vec())?;
+            let value: Option<Vec<u8>> = BorshDeserialize::try_from_slice(value.as_ref())?;
+            let value = match value {
+                Some(value_hash) if self.config.hash_values => Some(self.get_blob(&value_hash)?),
+                value => value,
+            };
+            history.push((version, value));
+        }
+        Ok(history)
+    }
+
+    /// Returns the value of `key_hash` at this snapshot's version, along with
+    /// the version of this substore's tree at which it was written.
+    ///
+    /// Like [`SubstoreSnapshot::get_value_history`], only the latest entry of
+    /// the versioned value index is read, without traversing the tree.
+    pub fn get_jmt_versioned(&self, key_hash: KeyHash) -> Result<Option<(Vec<u8>, jmt::Version)>> {
+        // Nothing is committed at the pre-genesis version, see `get_jmt`.
+        if self.version() == u64::MAX {
+            return Ok(None);
+        }
+        let cf_jmt_values = self.config.cf_jmt_values(&self.db);
+        let mut iterator = self.rocksdb_snapshot.iterator_cf_opt(
+            cf_jmt_values,
+            value_index_range(key_hash, self.version()),
+            IteratorMode::End,
+        );
+
+        let Some(entry) = iterator.next() else {
+            return Ok(None);
+        };
+        let (key, value) = entry?;
+        let VersionedKeyHash { version, .. } = VersionedKeyHash::decode(key.to_vec())?;
+        let value: Option<Vec<u8>> = BorshDeserialize::try_from_slice(value.as_ref())?;
+        let value = match value {
+            None => return Ok(None),
+            Some(value_hash) if self.config.hash_values => self.get_blob(&value_hash)?,
+            Some(value) => value,
+        };
+        Ok(Some((value, version)))
+    }
+
+    /// Returns the version of the `n`th most recent write of `key_hash` up to
+    /// this snapshot's version, counting from zero, or `None` if the versioned
+    /// value index no longer holds that many writes.
+    pub fn nth_latest_write_version(
+        &self,
+        key_hash: KeyHash,
+        n: usize,
+    ) -> Result<Option<jmt::Version>> {
+        let cf_jmt_values = self.config.cf_jmt_values(&self.db);
+        let mut iterator = self.rocksdb_snapshot.iterator_cf_opt(
+            cf_jmt_values,
+            value_index_range(key_hash, self.version()),
+            IteratorMode::End,
+        );
+
+        match iterator.nth(n) {
+            Some(entry) => {
+                let (key, _) = entry?;
+                Ok(Some(VersionedKeyHash::decode(key.to_vec())?.version))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the hashes of the keys written after the tree version `since`,
+    /// up to this snapshot's version, in key hash order. The pre-genesis
+    /// version, `u64::MAX`, stands for the version before the first write.
+    ///
+    /// The versioned value index is ordered by key hash rather than by version,
+    /// so it is read in full.
+    pub fn key_hashes_written_since(&self, since: jmt::Version) -> Result<Vec<KeyHash>> {
+        if self.version() == u64::MAX {
+            return Ok(Vec::new());
+        }
+        let cf_jmt_values = self.config.cf_jmt_values(&self.db);
+        let iterator = self.rocksdb_snapshot.iterator_cf_opt(
+            cf_jmt_values,
+            ReadOptions::default(),
+            IteratorMode::Start,
+        );
+
+        let mut key_hashes = Vec::new();
+        for entry in iterator {
+            let (key, _) = entry?;
+            let VersionedKeyHash { key_hash, version } = VersionedKeyHash::decode(key.to_vec())?;
+            let written_since = since == u64::MAX || version > since;
+            if written_since && version <= self.version() && key_hashes.last() != Some(&key_hash) {
+                key_hashes.push(key_hash);
+            }
+        }
+        Ok(key_hashes)
+    }
+
+    /// Reads the value with the given hash from the companion column family
+    /// of a substore with `hash_values` set.
+    fn get_blob(&self, value_hash: &[u8]) -> Result<Vec<u8>> {
+        let cf_jmt_blobs = self.config.cf_jmt_blobs(&self.db);
+        self.rocksdb_snapshot
+            .get_cf(cf_jmt_blobs, value_hash)?
+            .ok_or_else(|| missing_blob(&self.config, value_hash))
+    }
+}
+
+fn missing_blob(config: &SubstoreConfig, value_hash: &[u8]) -> anyhow::Error {
+    anyhow::anyhow!(
+        "missing value with hash {} in substore {}",
+        hex::encode(value_hash),
+        config.prefix
+    )
+}
+
+/// Returns read options bounding an iterator over the versioned value index to
+/// the entries of `key_hash` up to `max_version`, inclusive.
+fn value_index_range(key_hash: KeyHash, max_version: jmt::Version) -> ReadOptions {
+    let mut lower_bound = key_hash.0.to_vec();
+    lower_bound.extend_from_slice(&0u64.to_be_bytes());
+    let mut upper_bound = key_hash.0.to_vec();
+    upper_bound.extend_from_slice(&(max_version.saturating_add(1)).to_be_bytes());
+
+    let mut readopts = ReadOptions::default();
+    readopts.set_iterate_lower_bound(lower_bound);
+    readopts.set_iterate_upper_bound(upper_bound);
+    readopts
+}
+
+/// Decodes the length of a value from the header of its borsh encoding as an
+/// `Option<Vec<u8>>`: a tag byte, followed by a little-endian `u32` length.
+fn decode_value_size(encoded: &[u8]) -> Result<Option<usize>> {
+    match encoded {
+        [0, ..] => Ok(None),
+        [1, a, b, c, d, ..] => Ok(Some(u32::from_le_bytes([*a, *b, *c, *d]) as usize)),
+        _ => anyhow::bail!("invalid encoding of a versioned value"),
+    }
 }
 
 impl TreeReader for SubstoreSnapshot {
@@ -372,51 +792,74 @@ impl SubstoreStorage {
         mut write_batch: rocksdb::WriteBatch,
         write_version: jmt::Version,
         perform_migration: bool,
-    ) -> Result<(RootHash, rocksdb::WriteBatch)> {
+    ) -> Result<(RootHash, rocksdb::WriteBatch, usize)> {
         let span = Span::current();
 
         tokio::task
                 ::spawn_blocking(move || {
                     span.in_scope(|| {
                         let jmt = jmt::Sha256Jmt::new(&self.substore_snapshot);
-                        let unwritten_changes: Vec<_> = cache
-                            .unwritten_changes
-                            .into_iter()
-                            .map(|(key, some_value)| (KeyHash::with::<sha2::Sha256>(&key), key, some_value))
-                            .collect();
+                        let mut cache = cache;
+                        let (spills, in_memory) = cache.take_unwritten();
 
                         let cf_jmt_keys = self.substore_snapshot.config.cf_jmt_keys(&self.substore_snapshot.db);
                         let cf_jmt_keys_by_keyhash = self.substore_snapshot.config.cf_jmt_keys_by_keyhash(&self.substore_snapshot.db);
                         let cf_jmt = self.substore_snapshot.config.cf_jmt(&self.substore_snapshot.db);
                         let cf_jmt_values = self.substore_snapshot.config.cf_jmt_values(&self.substore_snapshot.db);
-
-                        /* Keyhash and pre-image indices */
-                        for (keyhash, key_preimage, value) in unwritten_changes.iter() {
-                            match value {
-                                Some(_) => { /* Key inserted, or updated, so we add it to the keyhash index */
-                                    write_batch.put_cf(cf_jmt_keys, key_preimage, keyhash.0);
-                                        write_batch
-                                        .put_cf(cf_jmt_keys_by_keyhash, keyhash.0, key_preimage)
-                                }
-                                None => { /* Key deleted, so we delete it from the preimage and keyhash index entries */
-                                    write_batch.delete_cf(cf_jmt_keys, key_preimage);
-                                    write_batch.delete_cf(cf_jmt_keys_by_keyhash, keyhash.0);
-                                }
-                            };
-                        }
-
-                        // We only track the keyhash and possible values; at the time of writing,
-                        // `rustfmt` panics on inlining the closure, so we use a helper function to skip the key.
-                        let skip_key = |(keyhash, _key, some_value)| (keyhash, some_value);
+                        let cf_jmt_blobs = self.substore_snapshot.config.hash_values
+                            .then(|| self.substore_snapshot.config.cf_jmt_blobs(&self.substore_snapshot.db));
+
+                        // Changes spilled to disk are streamed into the write batch and the tree
+                        // update, rather than read into memory first. A read error ends the stream,
+                        // and is returned once the tree update is done.
+                        let mut read_error = None;
+                        let value_set = crate::cache::stream_unwritten(&spills, in_memory)
+                            .map_while(|entry| entry.map_err(|e| read_error = Some(e)).ok())
+                            .map(|(key_preimage, value)| {
+                                let keyhash = KeyHash::with::<sha2::Sha256>(&key_preimage);
+
+                                /* Values of substores that only hash them into the tree */
+                                let value = match (value, cf_jmt_blobs) {
+                                    (Some(value), Some(cf_jmt_blobs)) => {
+                                        let value_hash = sha2::Sha256::digest(&value);
+                                        write_batch.put_cf(cf_jmt_blobs, value_hash, &value);
+                                        Some(value_hash.to_vec())
+                                    }
+                                    (value, _) => value,
+                                };
+
+                                /* Keyhash and pre-image indices */
+                                match value {
+                                    Some(_) => { /* Key inserted, or updated, so we add it to the keyhash index */
+                                        write_batch.put_cf(cf_jmt_keys, &key_preimage, keyhash.0);
+                                            write_batch
+                                            .put_cf(cf_jmt_keys_by_keyhash, keyhash.0, &key_preimage)
+                                    }
+                                    None => { /* Key deleted, so we delete it from the preimage and keyhash index entries */
+                                        write_batch.delete_cf(cf_jmt_keys, &key_preimage);
+                                        write_batch.delete_cf(cf_jmt_keys_by_keyhash, keyhash.0);
+                                    }
+                                };
+
+                                // We only track the keyhash and possible values.
+                                (keyhash, value)
+                            });
 
                         let (root_hash, batch) = if perform_migration {
-                            jmt.append_value_set(unwritten_changes.into_iter().map(skip_key), write_version)?
+                            jmt.append_value_set(value_set, write_version)?
                         } else {
-                            jmt.put_value_set(unwritten_changes.into_iter().map(skip_key), write_version)?
+                            jmt.put_value_set(value_set, write_version)?
                         };
+                        if let Some(e) = read_error {
+                            return Err(e);
+                        }
 
                         /* JMT nodes and values */
+                        let mut internal_nodes = 0;
                         for (node_key, node) in batch.node_batch.nodes() {
+                            if matches!(node, Node::Internal(_)) {
+                                internal_nodes += 1;
+                            }
                             let db_node_key_bytes= DbNodeKey::encode_from_node_key(node_key)?;
                             let value_bytes = borsh::to_vec(node)?;
                             tracing::trace!(?db_node_key_bytes, value_bytes = ?hex::encode(&value_bytes));
@@ -434,20 +877,13 @@ impl SubstoreStorage {
                         tracing::trace!(?root_hash, "accumulated node changes in the write batch");
 
 
-                        for (k, v) in cache.nonverifiable_changes.into_iter() {
-                            let cf_nonverifiable = self.substore_snapshot.config.cf_nonverifiable(&self.substore_snapshot.db);
-                            match v {
-                                Some(v) => {
-                                    tracing::trace!(key = ?crate::EscapedByteSlice(&k), value = ?crate::EscapedByteSlice(&v), "put nonverifiable key");
-                                    write_batch.put_cf(cf_nonverifiable, k, &v);
-                                }
-                                None => {
-                                    write_batch.delete_cf(cf_nonverifiable, k);
-                                }
-                            };
-                        }
+                        self.substore_snapshot.config.write_nonverifiable(
+                            &self.substore_snapshot.db,
+                            cache.nonverifiable_changes,
+                            &mut write_batch,
+                        );
 
-                        Ok((root_hash, write_batch))
+                        Ok((root_hash, write_batch, internal_nodes))
                     })
                 })
                 .await?
@@ -523,7 +959,6 @@ impl VersionedKeyHash {
         buf
     }
 
-    #[allow(dead_code)]
     pub fn decode(buf: Vec<u8>) -> Result<Self> {
         if buf.len() != 40 {
             Err(anyhow::anyhow!(
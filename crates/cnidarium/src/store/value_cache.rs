@@ -0,0 +1,92 @@
+#[cfg(feature = "metrics")]
+use crate::metrics;
+
+use super::lru::Lru;
+
+/// A bounded, least-recently-used cache of values read from the verifiable
+/// key-value store, shared by all the snapshots of a [`Storage`](crate::Storage).
+///
+/// Entries are keyed by substore prefix, key hash, and the version of the
+/// substore the value was read at, and hold the value, or its absence. Since
+/// a committed version is never rewritten, cached entries never go stale. The
+/// exceptions are `Storage::commit_in_place`, and `Storage::prune`, which
+/// deletes old values: both clear the cache.
+#[derive(Debug)]
+pub(crate) struct ValueCache {
+    lru: Lru<CacheKey, Option<Vec<u8>>>,
+}
+
+type CacheKey = (String, [u8; 32], jmt::Version);
+
+impl ValueCache {
+    /// Creates an empty cache holding at most `capacity` values.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            lru: Lru::new(capacity),
+        }
+    }
+
+    /// Removes every cached value.
+    pub(crate) fn clear(&self) {
+        self.lru.clear()
+    }
+
+    /// Returns the value of `key_hash` in the substore with `prefix` at
+    /// `version`, if it is cached. A `Some(None)` means the key was absent.
+    pub(crate) fn get(
+        &self,
+        prefix: &str,
+        key_hash: jmt::KeyHash,
+        version: jmt::Version,
+    ) -> Option<Option<Vec<u8>>> {
+        let value = self.lru.get(&(prefix.to_string(), key_hash.0, version));
+        #[cfg(feature = "metrics")]
+        if value.is_some() {
+            metrics::counter!(metrics::STORAGE_VALUE_CACHE_HITS).increment(1);
+        } else {
+            metrics::counter!(metrics::STORAGE_VALUE_CACHE_MISSES).increment(1);
+        }
+        value
+    }
+
+    /// Caches the value of `key_hash` in the substore with `prefix` at
+    /// `version`.
+    pub(crate) fn insert(
+        &self,
+        prefix: &str,
+        key_hash: jmt::KeyHash,
+        version: jmt::Version,
+        value: Option<Vec<u8>>,
+    ) {
+        // Nothing is committed at the pre-genesis version, which is not final.
+        if version == u64::MAX {
+            return;
+        }
+        self.lru
+            .insert((prefix.to_string(), key_hash.0, version), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Values are cached per version, and never at the pre-genesis version.
+    fn entries_are_keyed_by_version() {
+        let cache = ValueCache::new(8);
+        let key_hash = jmt::KeyHash([1; 32]);
+        cache.insert("ibc", key_hash, 0, Some(vec![0]));
+        cache.insert("ibc", key_hash, 1, None);
+        cache.insert("ibc", key_hash, u64::MAX, Some(vec![2]));
+
+        assert_eq!(cache.get("ibc", key_hash, 0), Some(Some(vec![0])));
+        assert_eq!(cache.get("ibc", key_hash, 1), Some(None));
+        assert_eq!(cache.get("ibc", key_hash, 2), None);
+        assert_eq!(cache.get("", key_hash, 0), None);
+        assert_eq!(cache.get("ibc", key_hash, u64::MAX), None);
+
+        cache.clear();
+        assert_eq!(cache.get("ibc", key_hash, 0), None);
+    }
+}
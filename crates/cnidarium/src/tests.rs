@@ -263,11 +263,13 @@ async fn simple_flow() -> anyhow::Result<()> {
     std::mem::drop(range_keys);
 
     // Now commit state_init to storage
-    storage.commit(state_init).await?;
+    let (version, root_hash) = storage.commit(state_init).await?;
 
     // Now we have version 0.
     let state0 = storage.latest_snapshot();
-    assert_eq!(state0.version(), 0);
+    assert_eq!(version, 0);
+    assert_eq!(state0.version(), version);
+    assert_eq!(state0.root_hash().await?, root_hash);
     let mut state0 = StateDelta::new(state0);
 
     // Check reads against state0:
@@ -527,10 +529,12 @@ async fn simple_flow() -> anyhow::Result<()> {
     assert_eq!(state0a.version(), 0);
 
     // Commit state0 as state1.
-    storage.commit(state0).await?;
+    let (version, root_hash) = storage.commit(state0).await?;
 
     let state1 = storage.latest_snapshot();
-    assert_eq!(state1.version(), 1);
+    assert_eq!(version, 1);
+    assert_eq!(state1.version(), version);
+    assert_eq!(state1.root_hash().await?, root_hash);
 
     // Check reads against state1
     assert_eq!(state1.get_raw("test").await?, None);
@@ -1466,3 +1470,3714 @@ async fn range_query_storage() -> anyhow::Result<()> {
     std::mem::drop(range);
     Ok(())
 }
+
+#[tokio::test]
+/// Checks that `find_prefix` returns the first matching entry in key order,
+/// taking cached writes and deletions into account.
+async fn find_prefix_first_match() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut state_init = StateDelta::new(storage.latest_snapshot());
+    state_init.put_raw("a/aa".to_string(), b"xa".to_vec());
+    state_init.put_raw("a/ab".to_string(), b"yb".to_vec());
+    state_init.put_raw("a/ac".to_string(), b"yc".to_vec());
+    state_init.put_raw("b/aa".to_string(), b"ya".to_vec());
+    storage.commit(state_init).await?;
+
+    let snapshot = storage.latest_snapshot();
+    let starts_with_y = |_key: &str, value: &[u8]| value.first() == Some(&b'y');
+
+    // Against the committed snapshot, the first match is `a/ab`.
+    assert_eq!(
+        snapshot.find_prefix("a/", starts_with_y).await?,
+        Some(("a/ab".to_string(), b"yb".to_vec()))
+    );
+    // No key under the prefix matches.
+    assert_eq!(
+        snapshot
+            .find_prefix("a/", |_, value| value.first() == Some(&b'z'))
+            .await?,
+        None
+    );
+
+    // A cached write can be the first match, and a cached deletion hides the
+    // underlying entry.
+    let mut delta = StateDelta::new(snapshot);
+    delta.put_raw("a/a".to_string(), b"ya".to_vec());
+    assert_eq!(
+        delta.find_prefix("a/", starts_with_y).await?,
+        Some(("a/a".to_string(), b"ya".to_vec()))
+    );
+    delta.delete("a/a".to_string());
+    delta.delete("a/ab".to_string());
+    assert_eq!(
+        delta.find_prefix("a/", starts_with_y).await?,
+        Some(("a/ac".to_string(), b"yc".to_vec()))
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that `prefix_values` yields the values `prefix_raw` yields, in the
+/// same order, including cached insertions and excluding cached deletions.
+async fn prefix_values_matches_prefix_raw() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut state_init = StateDelta::new(storage.latest_snapshot());
+    state_init.put_raw("amounts/a".to_string(), vec![1]);
+    state_init.put_raw("amounts/b".to_string(), vec![2]);
+    state_init.put_raw("amounts/c".to_string(), vec![3]);
+    state_init.put_raw("other/a".to_string(), vec![4]);
+    storage.commit(state_init).await?;
+
+    let snapshot = storage.latest_snapshot();
+    let values = snapshot
+        .prefix_values("amounts/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(values, vec![vec![1], vec![2], vec![3]]);
+
+    let mut delta = StateDelta::new(snapshot);
+    delta.put_raw("amounts/a".to_string(), vec![5]);
+    delta.put_raw("amounts/bb".to_string(), vec![6]);
+    delta.delete("amounts/c".to_string());
+    let values = delta
+        .prefix_values("amounts/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(values, vec![vec![5], vec![2], vec![6]]);
+    let entries = delta
+        .prefix_raw("amounts/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(
+        values,
+        entries
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that `count_prefix` counts the keys under a prefix, including cached
+/// insertions and excluding cached deletions.
+async fn count_prefix_merges_overlay() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut state_init = StateDelta::new(storage.latest_snapshot());
+    state_init.put_raw("positions/1".to_string(), b"1".to_vec());
+    state_init.put_raw("positions/2".to_string(), b"2".to_vec());
+    state_init.put_raw("positions/3".to_string(), b"3".to_vec());
+    state_init.put_raw("other/1".to_string(), b"1".to_vec());
+    storage.commit(state_init).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.count_prefix("positions/").await?, 3);
+    assert_eq!(snapshot.count_prefix("missing/").await?, 0);
+
+    let mut delta = StateDelta::new(snapshot);
+    // Overwriting an existing key doesn't change the count.
+    delta.put_raw("positions/1".to_string(), b"1'".to_vec());
+    delta.put_raw("positions/4".to_string(), b"4".to_vec());
+    delta.delete("positions/2".to_string());
+    // Deleting a key that was never present doesn't either.
+    delta.delete("positions/5".to_string());
+    assert_eq!(delta.count_prefix("positions/").await?, 3);
+
+    let mut tx = StateDelta::new(&mut delta);
+    tx.delete("positions/4".to_string());
+    tx.put_raw("positions/2".to_string(), b"2'".to_vec());
+    assert_eq!(tx.count_prefix("positions/").await?, 3);
+    tx.delete("positions/3".to_string());
+    assert_eq!(tx.count_prefix("positions/").await?, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that nonverifiable writes follow the same transactional semantics as
+/// verifiable writes: uncommitted writes and deletions are visible to reads on
+/// the delta, discarded when the delta is dropped, and merged on `apply`.
+async fn nonverifiable_delta_visibility() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut state_init = StateDelta::new(storage.latest_snapshot());
+    state_init.nonverifiable_put_raw(b"nv/base".to_vec(), b"base".to_vec());
+    storage.commit(state_init).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+
+    // Write-then-read within a transaction.
+    let mut tx = StateDelta::new(&mut state);
+    tx.nonverifiable_put_raw(b"nv/new".to_vec(), b"new".to_vec());
+    assert_eq!(
+        tx.nonverifiable_get_raw(b"nv/new").await?,
+        Some(b"new".to_vec())
+    );
+
+    // Delete-then-read within a transaction.
+    tx.nonverifiable_delete(b"nv/base".to_vec());
+    assert_eq!(tx.nonverifiable_get_raw(b"nv/base").await?, None);
+
+    // Discard without applying: the parent state is unchanged.
+    std::mem::drop(tx);
+    assert_eq!(state.nonverifiable_get_raw(b"nv/new").await?, None);
+    assert_eq!(
+        state.nonverifiable_get_raw(b"nv/base").await?,
+        Some(b"base".to_vec())
+    );
+
+    // Apply: the changes are merged into the parent state, and then committed.
+    let mut tx = StateDelta::new(&mut state);
+    tx.nonverifiable_put_raw(b"nv/new".to_vec(), b"new".to_vec());
+    tx.nonverifiable_delete(b"nv/base".to_vec());
+    tx.apply();
+    assert_eq!(
+        state.nonverifiable_get_raw(b"nv/new").await?,
+        Some(b"new".to_vec())
+    );
+    assert_eq!(state.nonverifiable_get_raw(b"nv/base").await?, None);
+    storage.commit(state).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(
+        snapshot.nonverifiable_get_raw(b"nv/new").await?,
+        Some(b"new".to_vec())
+    );
+    assert_eq!(snapshot.nonverifiable_get_raw(b"nv/base").await?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that nonverifiable writes are committed along with the verifiable
+/// ones, but are not hashed into the trees.
+async fn nonverifiable_writes_bypass_root() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["ibc".to_string()]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/aa".to_string(), b"aa".to_vec());
+    let (_, root_hash) = storage.commit(delta).await?;
+
+    // A commit that only writes nonverifiable data to the main store leaves
+    // the root hash unchanged.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.nonverifiable_put_raw(b"index/b".to_vec(), b"b".to_vec());
+    delta.nonverifiable_put_raw(b"index/a".to_vec(), b"a".to_vec());
+    let (version, nonverifiable_root) = storage.commit(delta).await?;
+    assert_eq!(version, 1);
+    assert_eq!(nonverifiable_root, root_hash);
+
+    // Like any commit that touches a substore, one that only writes
+    // nonverifiable data to it advances its version, and records its (empty)
+    // root in the main store.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.nonverifiable_put_raw(b"ibc/cache".to_vec(), b"cache".to_vec());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.prefix_version("ibc")?, Some(0));
+    let ibc_root = snapshot.prefix_root_hash("ibc").await?;
+    assert_eq!(snapshot.get_raw("ibc").await?, Some(ibc_root.0.to_vec()));
+    assert_eq!(
+        snapshot.nonverifiable_get_raw(b"ibc/cache").await?,
+        Some(b"cache".to_vec())
+    );
+    let index: Vec<_> = snapshot
+        .nonverifiable_prefix_raw(b"index/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(
+        index,
+        vec![
+            (b"index/a".to_vec(), b"a".to_vec()),
+            (b"index/b".to_vec(), b"b".to_vec()),
+        ]
+    );
+
+    // Nonverifiable writes are committed with the verifiable ones, which
+    // alone determine the new root.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/ab".to_string(), b"ab".to_vec());
+    let expected = storage.compute_root(&delta).await?;
+    delta.nonverifiable_delete(b"index/a".to_vec());
+    let (_, root_hash) = storage.commit(delta).await?;
+    assert_eq!(root_hash, expected);
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("a/ab").await?, Some(b"ab".to_vec()));
+    assert_eq!(snapshot.nonverifiable_get_raw(b"index/a").await?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that loading a database that is already held open fails promptly
+/// with a `StorageError::AlreadyOpen` error, rather than blocking.
+async fn load_reports_held_lock() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let start = std::time::Instant::now();
+    let err = Storage::load(tmpdir.path().to_owned(), vec![])
+        .await
+        .expect_err("the database is already open");
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::AlreadyOpen { .. })
+    ));
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+    // Waiting with a timeout gives up once the timeout has elapsed...
+    let timeout = std::time::Duration::from_millis(200);
+    let err = Storage::load_with_timeout(tmpdir.path().to_owned(), vec![], timeout)
+        .await
+        .expect_err("the database is still open");
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::AlreadyOpen { .. })
+    ));
+
+    // ... and succeeds once the lock is released.
+    storage.release().await;
+    let storage = Storage::load_with_timeout(
+        tmpdir.path().to_owned(),
+        vec![],
+        std::time::Duration::from_secs(5),
+    )
+    .await?;
+    storage.release().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that `next_id` allocates consecutive identifiers within a delta, and
+/// refuses to wrap around a counter at `u64::MAX`.
+async fn next_id_counter() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    assert_eq!(state.next_id("counter/ids").await?, 0);
+    assert_eq!(state.next_id("counter/ids").await?, 1);
+    assert_eq!(state.next_id("counter/ids").await?, 2);
+
+    // Counters are independent of each other.
+    assert_eq!(state.next_id("counter/other").await?, 0);
+
+    // The counter is consistent across a commit.
+    storage.commit(state).await?;
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    assert_eq!(state.next_id("counter/ids").await?, 3);
+
+    // A counter at `u64::MAX` errors rather than wrapping around.
+    state.put_raw("counter/max".to_string(), u64::MAX.to_be_bytes().to_vec());
+    let err = state
+        .next_id("counter/max")
+        .await
+        .expect_err("the counter is at u64::MAX");
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::CounterOverflow { .. })
+    ));
+    assert_eq!(
+        state.get_raw("counter/max").await?,
+        Some(u64::MAX.to_be_bytes().to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that a secondary storage instance sees the primary's commits after
+/// catching up, and that its reported version advances accordingly.
+async fn secondary_catch_up() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let primary_dir = tempfile::tempdir()?;
+    let secondary_dir = tempfile::tempdir()?;
+
+    let storage = Storage::load(primary_dir.path().to_owned(), vec!["ibc".to_string()]).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/aa".to_string(), b"aa".to_vec());
+    storage.commit(delta).await?;
+
+    let secondary = Storage::open_secondary(
+        primary_dir.path().to_owned(),
+        secondary_dir.path().to_owned(),
+    )
+    .await?;
+    assert_eq!(secondary.latest_version(), 0);
+    let snapshot = secondary.latest_snapshot();
+    assert_eq!(snapshot.get_raw("a/aa").await?, Some(b"aa".to_vec()));
+
+    // Commit more data on the primary: it isn't visible until we catch up.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/ab".to_string(), b"ab".to_vec());
+    delta.put_raw("ibc/client".to_string(), b"client".to_vec());
+    storage.commit(delta).await?;
+    assert_eq!(secondary.latest_snapshot().get_raw("a/ab").await?, None);
+    // Prefix scans, which cannot be pinned to a RocksDB snapshot on a
+    // secondary instance, also stay at the state the replica caught up to.
+    let entries: Vec<(String, Vec<u8>)> = secondary
+        .latest_snapshot()
+        .prefix_raw("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(entries, vec![("a/aa".to_string(), b"aa".to_vec())]);
+
+    assert_eq!(secondary.catch_up().await?, 1);
+    assert_eq!(secondary.latest_version(), 1);
+    let snapshot = secondary.latest_snapshot();
+    assert_eq!(snapshot.get_raw("a/ab").await?, Some(b"ab".to_vec()));
+    assert_eq!(
+        snapshot.get_raw("ibc/client").await?,
+        Some(b"client".to_vec())
+    );
+    let keys: Vec<String> = snapshot
+        .prefix_keys("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(keys, vec!["a/aa".to_string(), "a/ab".to_string()]);
+    assert_eq!(
+        snapshot.root_hash().await?,
+        storage.latest_snapshot().root_hash().await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that a read-only storage instance can be opened alongside a writer,
+/// and serves the data committed when it was opened.
+async fn read_only_alongside_writer() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["ibc".to_string()]).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/aa".to_string(), b"aa".to_vec());
+    delta.put_raw("ibc/client".to_string(), b"client".to_vec());
+    delta.nonverifiable_put_raw(b"nv".to_vec(), b"nv".to_vec());
+    storage.commit(delta).await?;
+
+    let read_only = Storage::load_read_only(tmpdir.path().to_owned()).await?;
+    assert_eq!(read_only.latest_version(), 0);
+    let snapshot = read_only.latest_snapshot();
+    assert_eq!(snapshot.get_raw("a/aa").await?, Some(b"aa".to_vec()));
+    assert_eq!(
+        snapshot.get_raw("ibc/client").await?,
+        Some(b"client".to_vec())
+    );
+    assert_eq!(
+        snapshot.nonverifiable_get_raw(b"nv").await?,
+        Some(b"nv".to_vec())
+    );
+    assert_eq!(
+        snapshot.root_hash().await?,
+        storage.latest_snapshot().root_hash().await?
+    );
+
+    // Later commits by the writer are not visible to the read-only instance.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/ab".to_string(), b"ab".to_vec());
+    storage.commit(delta).await?;
+    assert_eq!(read_only.latest_version(), 0);
+    assert_eq!(read_only.latest_snapshot().get_raw("a/ab").await?, None);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+/// Checks that `snapshot_get` returns values that are consistent with each
+/// other, even while new versions are being committed concurrently.
+async fn snapshot_get_is_consistent() -> anyhow::Result<()> {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    };
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let keys = ["account/balance", "account/nonce", "account/metadata"];
+    let commits = 20u64;
+    let barrier = Arc::new(tokio::sync::Barrier::new(2));
+    let reads = Arc::new(AtomicU64::new(0));
+    let done = Arc::new(AtomicBool::new(false));
+
+    // Commit versions in the background, writing the same counter to every
+    // key. Once the reader is running, each commit waits for a read to
+    // complete after the previous one, so that reads overlap every commit.
+    let writer = tokio::spawn({
+        let storage = storage.clone();
+        let barrier = barrier.clone();
+        let reads = reads.clone();
+        let done = done.clone();
+        async move {
+            barrier.wait().await;
+            for i in 0..commits {
+                let before = reads.load(Ordering::SeqCst);
+                let mut delta = StateDelta::new(storage.latest_snapshot());
+                for key in keys {
+                    delta.put_raw(key.to_string(), i.to_be_bytes().to_vec());
+                }
+                storage.commit(delta).await?;
+                while reads.load(Ordering::SeqCst) == before {
+                    tokio::task::yield_now().await;
+                }
+            }
+            done.store(true, Ordering::SeqCst);
+            anyhow::Ok(())
+        }
+    });
+
+    barrier.wait().await;
+    // A failed commit ends the writer without setting `done`.
+    while !done.load(Ordering::SeqCst) && !writer.is_finished() {
+        let state = StateDelta::new(storage.latest_snapshot());
+        let values = state.snapshot_get(&keys).await?;
+        assert_eq!(values.len(), keys.len());
+        assert!(
+            values.iter().all(|v| v == &values[0]),
+            "values should not be torn across versions: {values:?}"
+        );
+        reads.fetch_add(1, Ordering::SeqCst);
+    }
+    writer.await??;
+    assert!(reads.load(Ordering::SeqCst) >= commits);
+
+    // Cached writes are visible, and taken from a single view of the delta.
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_raw("account/nonce".to_string(), b"cached".to_vec());
+    state.delete("account/metadata".to_string());
+    assert_eq!(
+        state.snapshot_get(&keys).await?,
+        vec![
+            Some(19u64.to_be_bytes().to_vec()),
+            Some(b"cached".to_vec()),
+            None
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn prefix_raw_ordered_descending() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/a".to_string(), b"aa".to_vec());
+    delta.put_raw("a/c".to_string(), b"ac".to_vec());
+    delta.put_raw("a/e".to_string(), b"ae".to_vec());
+    delta.put_raw("b/a".to_string(), b"ba".to_vec());
+    storage.commit(delta).await?;
+
+    // Interleave overlay writes and a deletion with the committed keys.
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_raw("a/b".to_string(), b"ab".to_vec());
+    state.put_raw("a/c".to_string(), b"ac2".to_vec());
+    state.delete("a/e".to_string());
+    let mut tx = StateDelta::new(&mut state);
+    tx.put_raw("a/d".to_string(), b"ad".to_vec());
+    tx.put_raw("a/f".to_string(), b"af".to_vec());
+    tx.put_raw("b/b".to_string(), b"bb".to_vec());
+
+    let ascending = tx
+        .prefix_raw_ordered("a/", ScanOrder::Ascending)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut descending = tx
+        .prefix_raw_ordered("a/", ScanOrder::Descending)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let expected = vec![
+        ("a/a".to_string(), b"aa".to_vec()),
+        ("a/b".to_string(), b"ab".to_vec()),
+        ("a/c".to_string(), b"ac2".to_vec()),
+        ("a/d".to_string(), b"ad".to_vec()),
+        ("a/f".to_string(), b"af".to_vec()),
+    ];
+    assert_eq!(ascending, expected);
+    descending.reverse();
+    assert_eq!(descending, expected);
+
+    // The default `prefix_raw` is ascending.
+    let default = tx
+        .prefix_raw("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(default, expected);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn prefix_raw_rev_flow() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["a/aa", "a/aaa", "a/ab", "a/z", "b/a"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+
+    // The committed state is walked in descending key order.
+    let snapshot = storage.latest_snapshot();
+    let keys = snapshot
+        .prefix_raw_rev("a/")
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, ["a/z", "a/ab", "a/aaa", "a/aa"]);
+
+    // Overlay writes are interleaved, and overlay deletions are skipped.
+    let mut state = StateDelta::new(snapshot);
+    state.delete("a/aaa".to_string());
+    state.put_raw("a/ac".to_string(), b"ac".to_vec());
+    let mut tx = StateDelta::new(&mut state);
+    tx.put_raw("a/ab".to_string(), b"ab2".to_vec());
+    tx.delete("a/z".to_string());
+    tx.put_raw("a/b".to_string(), b"b".to_vec());
+
+    let entries = tx
+        .prefix_raw_rev("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        entries,
+        vec![
+            ("a/b".to_string(), b"b".to_vec()),
+            ("a/ac".to_string(), b"ac".to_vec()),
+            ("a/ab".to_string(), b"ab2".to_vec()),
+            ("a/aa".to_string(), b"a/aa".to_vec()),
+        ]
+    );
+
+    Ok(())
+}
+
+/// A pinned snapshot keeps returning the same values while many versions are
+/// committed concurrently.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn pinned_snapshot_is_stable_during_commits() -> anyhow::Result<()> {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..16u8 {
+        delta.put_raw(format!("k/{i:02}"), vec![i]);
+    }
+    storage.commit(delta).await?;
+
+    let pinned = storage.latest_snapshot();
+    let version = pinned.version();
+    let root_hash = pinned.root_hash().await?;
+    let expected: Vec<_> = (0..16u8).map(|i| (format!("k/{i:02}"), vec![i])).collect();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let reader = tokio::spawn({
+        let done = done.clone();
+        async move {
+            let mut reads = 0u64;
+            while !done.load(Ordering::Relaxed) || reads == 0 {
+                assert_eq!(pinned.version(), version);
+                assert_eq!(pinned.get_raw("k/03").await?, Some(vec![3]));
+                assert_eq!(pinned.get_raw("k/new").await?, None);
+                let entries = pinned
+                    .prefix_raw("k/")
+                    .collect::<Vec<_>>()
+                    .await
+                    .into_iter()
+                    .collect::<Result<Vec<_>, _>>()?;
+                assert_eq!(entries, expected);
+                assert_eq!(pinned.root_hash().await?, root_hash);
+                reads += 1;
+            }
+            anyhow::Ok(reads)
+        }
+    });
+
+    // Each commit overwrites, deletes and inserts keys the reader checks.
+    for round in 0..50u8 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        for i in 0..16u8 {
+            delta.put_raw(format!("k/{i:02}"), vec![i, round]);
+        }
+        delta.delete("k/03".to_string());
+        delta.put_raw("k/new".to_string(), vec![round]);
+        storage.commit(delta).await?;
+    }
+    done.store(true, Ordering::Relaxed);
+
+    let reads = reader.await??;
+    assert!(reads > 0);
+    assert_eq!(storage.latest_version(), version + 50);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn commit_and_continue_pins_new_version() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_raw("a/a".to_string(), b"aa".to_vec());
+    state.nonverifiable_put_raw(b"nv/a".to_vec(), b"nva".to_vec());
+    let (version, root_hash, mut state) = storage.commit_and_continue(state).await?;
+
+    assert_eq!(version, 0);
+    assert_eq!(state.version(), 0);
+    assert_eq!(state.version(), storage.latest_version());
+    assert_eq!(storage.latest_snapshot().root_hash().await?, root_hash);
+
+    // Keep executing on top of the returned state.
+    state.put_raw("a/b".to_string(), b"ab".to_vec());
+    let (_, _, state) = storage.commit_and_continue(state).await?;
+    assert_eq!(state.version(), 1);
+
+    let fresh = StateDelta::new(storage.latest_snapshot());
+    for key in ["a/a", "a/b", "a/c"] {
+        assert_eq!(state.get_raw(key).await?, fresh.get_raw(key).await?);
+    }
+    assert_eq!(
+        state.nonverifiable_get_raw(b"nv/a").await?,
+        fresh.nonverifiable_get_raw(b"nv/a").await?
+    );
+    assert_eq!(state.get_raw("a/b").await?, Some(b"ab".to_vec()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn check_integrity_counts_orphans() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["ibc".to_string()]).await?;
+
+    for i in 0u64..5 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw(format!("key/{i}"), i.to_be_bytes().to_vec());
+        delta.put_raw(format!("ibc/key/{i}"), i.to_be_bytes().to_vec());
+        if i > 0 {
+            delta.delete(format!("key/{}", i - 1));
+        }
+        storage.commit(delta).await?;
+    }
+
+    let report = storage.check_integrity().await?;
+    assert!(report.is_healthy(), "{report:?}");
+    assert_eq!(report.orphaned_nodes, 0);
+    assert!(report.roots_checked >= 10);
+
+    // Simulate an interrupted commit by writing a root for a version that was
+    // never committed.
+    let main_store = store::substore::SubstoreConfig::new("");
+    let db = storage.db();
+    let orphan_key = store::substore::DbNodeKey::encode_from_node_key(
+        &jmt::storage::NodeKey::new_empty_path(100),
+    )?;
+    db.put_cf(
+        main_store.cf_jmt(&db),
+        orphan_key,
+        borsh::to_vec(&jmt::storage::Node::Null)?,
+    )?;
+
+    let report = storage.check_integrity().await?;
+    assert!(report.is_healthy(), "{report:?}");
+    assert_eq!(report.orphaned_nodes, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn compute_root_matches_commit() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["ibc".to_string()]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/a".to_string(), b"aa".to_vec());
+    delta.put_raw("ibc/a".to_string(), b"ibc".to_vec());
+    storage.commit(delta).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_raw("a/b".to_string(), b"ab".to_vec());
+    state.delete("a/a".to_string());
+    let mut tx = StateDelta::new(&mut state);
+    tx.put_raw("ibc/b".to_string(), b"ibc".to_vec());
+    tx.apply();
+    // Keep a layer below the leaf cache.
+    let _ = state.fork();
+    state.put_raw("a/c".to_string(), b"ac".to_vec());
+
+    let predicted = storage.compute_root(&state).await?;
+    // Computing the root leaves the storage untouched.
+    assert_eq!(storage.latest_version(), 0);
+    assert_eq!(storage.compute_root(&state).await?, predicted);
+
+    let (_, root_hash) = storage.commit(state).await?;
+    assert_eq!(root_hash, predicted);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn spilled_overlay_reads_and_commits() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let spill_dir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().join("spilled"), vec!["sub".to_string()]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/000".to_string(), b"committed".to_vec());
+    delta.put_raw("a/001".to_string(), b"committed".to_vec());
+    storage.commit(delta).await?;
+
+    let spill_options = SpillOptions {
+        threshold_bytes: 256,
+        dir: Some(spill_dir.path().to_owned()),
+    };
+    let mut state = StateDelta::new(storage.latest_snapshot()).with_spill(spill_options);
+    for i in 0..100u32 {
+        state.put_raw(format!("a/{i:03}"), i.to_be_bytes().to_vec());
+    }
+    assert_eq!(std::fs::read_dir(spill_dir.path())?.count(), 1);
+
+    // Overwrite and delete keys that were already spilled.
+    state.put_raw("a/010".to_string(), b"overwritten".to_vec());
+    state.delete("a/001".to_string());
+    state.delete("a/020".to_string());
+
+    let mut expected: Vec<(String, Vec<u8>)> = (0..100u32)
+        .map(|i| (format!("a/{i:03}"), i.to_be_bytes().to_vec()))
+        .filter(|(k, _)| k != "a/001" && k != "a/020")
+        .collect();
+    expected[9].1 = b"overwritten".to_vec();
+    assert_eq!(expected[9].0, "a/010");
+
+    assert_eq!(
+        state.get_raw("a/000").await?,
+        Some(0u32.to_be_bytes().to_vec())
+    );
+    assert_eq!(state.get_raw("a/001").await?, None);
+    assert_eq!(state.get_raw("a/010").await?, Some(b"overwritten".to_vec()));
+
+    let ascending = state
+        .prefix_raw("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(ascending, expected);
+
+    let mut descending = state
+        .prefix_raw_ordered("a/", ScanOrder::Descending)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    descending.reverse();
+    assert_eq!(descending, expected);
+
+    let keys = state
+        .prefix_keys("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        keys,
+        expected.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>()
+    );
+
+    // Writes to a substore are spilled too, and routed to it on commit.
+    for i in 0..20u32 {
+        state.put_raw(format!("sub/{i:03}"), i.to_be_bytes().to_vec());
+    }
+
+    let predicted = storage.compute_root(&state).await?;
+    let (version, root_hash) = storage.commit(state).await?;
+    assert_eq!(root_hash, predicted);
+
+    // The same writes, committed without spilling, produce the same root.
+    let unspilled = Storage::load(tmpdir.path().join("unspilled"), vec!["sub".to_string()]).await?;
+    let mut delta = StateDelta::new(unspilled.latest_snapshot());
+    delta.put_raw("a/000".to_string(), b"committed".to_vec());
+    delta.put_raw("a/001".to_string(), b"committed".to_vec());
+    unspilled.commit(delta).await?;
+    let mut delta = StateDelta::new(unspilled.latest_snapshot());
+    for (key, value) in &expected {
+        delta.put_raw(key.clone(), value.clone());
+    }
+    delta.delete("a/001".to_string());
+    delta.delete("a/020".to_string());
+    for i in 0..20u32 {
+        delta.put_raw(format!("sub/{i:03}"), i.to_be_bytes().to_vec());
+    }
+    assert_eq!(unspilled.commit(delta).await?.1, root_hash);
+
+    let committed = storage
+        .latest_snapshot()
+        .prefix_raw("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(committed, expected);
+    assert_eq!(
+        storage.latest_snapshot().get_raw("sub/019").await?,
+        Some(19u32.to_be_bytes().to_vec())
+    );
+
+    // The spilled writes are reported with the commit's changes, whose spill
+    // store is kept until they are no longer retained.
+    let changes: Vec<_> = storage
+        .changeset(version)
+        .await?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(changes.len(), 100 + 20);
+    assert_eq!(std::fs::read_dir(spill_dir.path())?.count(), 1);
+    for _ in 0..10 {
+        let delta = StateDelta::new(storage.latest_snapshot());
+        storage.commit(delta).await?;
+    }
+    assert_eq!(std::fs::read_dir(spill_dir.path())?.count(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn spilled_overlay_stays_on_disk() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let spill_dir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let spill_options = SpillOptions {
+        threshold_bytes: 256,
+        dir: Some(spill_dir.path().to_owned()),
+    };
+    let mut state = StateDelta::new(storage.latest_snapshot()).with_spill(spill_options);
+
+    // Nested transactions spill like their parent.
+    let mut tx = state.begin_transaction();
+    for i in 0..100u32 {
+        tx.put_raw(format!("a/{i:03}"), i.to_be_bytes().to_vec());
+    }
+    assert_eq!(std::fs::read_dir(spill_dir.path())?.count(), 1);
+    // Applying the transaction streams its writes to the parent, which
+    // spills them in turn.
+    tx.apply();
+    assert_eq!(std::fs::read_dir(spill_dir.path())?.count(), 1);
+    assert_eq!(
+        state.get_raw("a/042").await?,
+        Some(42u32.to_be_bytes().to_vec())
+    );
+
+    // Forked layers are merged without reading their spilled writes back.
+    let mut fork = state.fork();
+    fork.put_raw("a/042".to_string(), b"overwritten".to_vec());
+    fork.delete("a/043".to_string());
+    let (_, changes) = fork.flatten()?;
+    assert!(changes.unwritten_changes.len() < 100);
+    let all = changes.unwritten_changes()?;
+    assert_eq!(all.len(), 100);
+    assert_eq!(all["a/042"], Some(b"overwritten".to_vec()));
+    assert_eq!(all["a/043"], None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dropping_spilled_overlay_removes_files() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let spill_dir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let spill_options = SpillOptions {
+        threshold_bytes: 0,
+        dir: Some(spill_dir.path().to_owned()),
+    };
+    let mut state = StateDelta::new(storage.latest_snapshot()).with_spill(spill_options);
+    state.put_raw("a/a".to_string(), b"aa".to_vec());
+    // Forks share the spilled layer.
+    let mut fork = state.fork();
+    fork.put_raw("a/b".to_string(), b"ab".to_vec());
+    assert_eq!(fork.get_raw("a/a").await?, Some(b"aa".to_vec()));
+    assert_eq!(std::fs::read_dir(spill_dir.path())?.count(), 2);
+
+    std::mem::drop(fork);
+    std::mem::drop(state);
+    assert_eq!(std::fs::read_dir(spill_dir.path())?.count(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_range_boundaries() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["a/aa", "a/aaa", "a/ab", "a/b", "a/z", "b/ab"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    // Overlay writes in the range are deleted too, while keys that are already
+    // deleted are not counted.
+    state.put_raw("a/abc".to_string(), b"a/abc".to_vec());
+    state.delete("a/b".to_string());
+    state.put_raw("a/za".to_string(), b"a/za".to_vec());
+
+    assert_eq!(state.delete_range("a/ab", "a/z").await?, 2);
+    assert_eq!(state.delete_range("a/ab", "a/z").await?, 0);
+    assert!(state.delete_range("a/z", "a/ab").await.is_err());
+
+    let remaining = |state: &StateDelta<Snapshot>| state.prefix_keys("a/").collect::<Vec<_>>();
+    let keys = remaining(&state)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, vec!["a/aa", "a/aaa", "a/z", "a/za"]);
+    // Keys outside the common prefix are untouched.
+    assert_eq!(state.get_raw("b/ab").await?, Some(b"b/ab".to_vec()));
+
+    storage.commit(state).await?;
+    let keys = remaining(&StateDelta::new(storage.latest_snapshot()))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, vec!["a/aa", "a/aaa", "a/z", "a/za"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Checks that a nested transaction sees its parent's writes, and that its own
+/// writes are merged into the parent on `apply`, or discarded on drop.
+async fn nested_transaction_savepoints() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("base".to_string(), b"base".to_vec());
+    storage.commit(delta).await?;
+
+    let mut tx = StateDelta::new(storage.latest_snapshot());
+    tx.put_raw("a".to_string(), b"a".to_vec());
+
+    // A savepoint that is rolled back leaves the transaction unchanged.
+    {
+        let mut savepoint = tx.begin_transaction();
+        assert_eq!(savepoint.get_raw("a").await?, Some(b"a".to_vec()));
+        assert_eq!(savepoint.get_raw("base").await?, Some(b"base".to_vec()));
+        savepoint.put_raw("a".to_string(), b"rolled back".to_vec());
+        savepoint.delete("base".to_string());
+        savepoint.nonverifiable_put_raw(b"index".to_vec(), b"rolled back".to_vec());
+
+        // Savepoints can be nested, and applying the inner one only merges
+        // it into the outer one.
+        let mut inner = savepoint.begin_transaction();
+        assert_eq!(inner.get_raw("base").await?, None);
+        inner.put_raw("b".to_string(), b"rolled back".to_vec());
+        inner.apply();
+        assert_eq!(savepoint.get_raw("b").await?, Some(b"rolled back".to_vec()));
+    }
+    assert_eq!(tx.get_raw("a").await?, Some(b"a".to_vec()));
+    assert_eq!(tx.get_raw("b").await?, None);
+    assert_eq!(tx.get_raw("base").await?, Some(b"base".to_vec()));
+    assert_eq!(tx.nonverifiable_get_raw(b"index").await?, None);
+
+    // A savepoint that is applied is merged into the transaction.
+    let mut savepoint = tx.begin_transaction();
+    savepoint.put_raw("a".to_string(), b"kept".to_vec());
+    savepoint.delete("base".to_string());
+    savepoint.apply();
+    assert_eq!(tx.get_raw("a").await?, Some(b"kept".to_vec()));
+    assert_eq!(tx.get_raw("base").await?, None);
+
+    storage.commit(tx).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("a").await?, Some(b"kept".to_vec()));
+    assert_eq!(snapshot.get_raw("b").await?, None);
+    assert_eq!(snapshot.get_raw("base").await?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn conflict_detection_rejects_stale_reads() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("balance/a".to_string(), b"10".to_vec());
+    delta.put_raw("balance/b".to_string(), b"20".to_vec());
+    storage.commit(delta).await?;
+    let base = storage.latest_snapshot();
+
+    // Three transactions fork from the same version.
+    let mut reads_a = StateDelta::new(base.clone()).with_conflict_detection();
+    let mut scans = StateDelta::new(base.clone()).with_conflict_detection();
+    let mut reads_b = StateDelta::new(base.clone()).with_conflict_detection();
+    let mut untracked = StateDelta::new(base.clone());
+
+    let value = reads_a.get_raw("balance/a").await?;
+    reads_a.put_raw("log/a".to_string(), value.unwrap());
+    let count = scans.prefix_keys("balance/").count().await;
+    scans.put_raw("count".to_string(), count.to_string().into_bytes());
+    let value = reads_b.get_raw("balance/b").await?;
+    reads_b.put_raw("log/b".to_string(), value.unwrap());
+    untracked.put_raw("other".to_string(), b"untracked".to_vec());
+
+    // Another commit changes `balance/a` after the fork.
+    let mut writer = StateDelta::new(base);
+    writer.put_raw("balance/a".to_string(), b"5".to_vec());
+    storage.commit(writer).await?;
+
+    // The transactions that read `balance/a`, directly or through a prefix
+    // scan, conflict, and nothing they wrote is committed.
+    let err = storage.commit(reads_a).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::Conflict { key }) if key == "balance/a"
+    ));
+    let err = storage.commit(scans).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::Conflict { key }) if key == "balance/a"
+    ));
+    assert_eq!(storage.latest_snapshot().get_raw("log/a").await?, None);
+    assert_eq!(storage.latest_snapshot().get_raw("count").await?, None);
+
+    // A transaction whose reads are untouched commits on top of the latest
+    // version, keeping the other commit's write.
+    storage.commit(reads_b).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("log/b").await?, Some(b"20".to_vec()));
+    assert_eq!(snapshot.get_raw("balance/a").await?, Some(b"5".to_vec()));
+
+    // Without conflict detection, a stale delta still can't be committed.
+    assert!(storage.commit(untracked).await.is_err());
+
+    // Writes to a key changed since the fork conflict even if it wasn't read.
+    let stale = storage.snapshot(snapshot.version() - 1).unwrap();
+    let mut blind = StateDelta::new(stale).with_conflict_detection();
+    blind.put_raw("log/b".to_string(), b"blind".to_vec());
+    let err = storage.commit(blind).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::Conflict { key }) if key == "log/b"
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn conflict_detection_is_bounded_by_the_retained_changes() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let options = StorageOptions {
+        change_log_retention: Some(2),
+        ..Default::default()
+    };
+    let storage = Storage::load_with_options(tmpdir.path().to_owned(), vec![], options).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"a".to_vec());
+    storage.commit(delta).await?;
+    let base = storage.latest_snapshot();
+
+    let mut within = StateDelta::new(base.clone()).with_conflict_detection();
+    within.put_raw("b".to_string(), b"b".to_vec());
+    let mut blind = StateDelta::new(base.clone()).with_conflict_detection();
+    blind.nonverifiable_put_raw(b"index".to_vec(), b"blind".to_vec());
+    let mut beyond = StateDelta::new(base.clone()).with_conflict_detection();
+    beyond.put_raw("c".to_string(), b"c".to_vec());
+
+    let mut delta = StateDelta::new(base);
+    delta.nonverifiable_put_raw(b"index".to_vec(), b"index".to_vec());
+    storage.commit(delta).await?;
+
+    // Blind writes to a nonverifiable key changed since the fork conflict.
+    let err = storage.commit(blind).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::Conflict { .. })
+    ));
+    // Within the window, the changes are still retained.
+    storage.commit(within).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("d".to_string(), b"d".to_vec());
+    storage.commit(delta).await?;
+
+    // Three commits behind, they are not, and the delta can't be checked.
+    let err = storage.commit(beyond).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::ChangesNotRetained { .. })
+    ));
+    assert_eq!(storage.latest_snapshot().get_raw("c").await?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn prefix_delete_then_put_wins() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["a/1", "a/2", "a/3", "ab", "b/1"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_raw("a/4".to_string(), b"a/4".to_vec());
+    let mut tx = StateDelta::new(&mut state);
+    tx.prefix_delete("a/".to_string());
+    assert_eq!(tx.get_raw("a/1").await?, None);
+    assert_eq!(tx.get_raw("a/4").await?, None);
+    assert_eq!(tx.get_raw("ab").await?, Some(b"ab".to_vec()));
+    assert_eq!(tx.prefix_keys("a/").count().await, 0);
+    // A later write under the prefix wins over the deletion.
+    tx.put_raw("a/2".to_string(), b"new".to_vec());
+    assert_eq!(tx.get_raw("a/2").await?, Some(b"new".to_vec()));
+    let keys = tx
+        .prefix_keys("a")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, vec!["a/2", "ab"]);
+    // The deletion is recorded once, rather than for each key.
+    assert_eq!(
+        tx.overlay_ops()?,
+        vec![
+            OverlayOp::PrefixDelete {
+                prefix: "a/".to_string()
+            },
+            OverlayOp::Put {
+                key: "a/2".to_string(),
+                value: b"new".to_vec()
+            },
+        ]
+    );
+    tx.apply();
+    assert_eq!(state.get_raw("a/4").await?, None);
+
+    storage.commit(state).await?;
+    let snapshot = storage.latest_snapshot();
+    let keys = snapshot
+        .prefix_keys("")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, vec!["a/2", "ab", "b/1"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn prefix_delete_hides_spilled_writes() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let spill_dir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/base".to_string(), b"base".to_vec());
+    storage.commit(delta).await?;
+
+    let spill_options = SpillOptions {
+        threshold_bytes: 256,
+        dir: Some(spill_dir.path().to_owned()),
+    };
+    let mut state = StateDelta::new(storage.latest_snapshot()).with_spill(spill_options);
+    for i in 0..50u32 {
+        state.put_raw(format!("a/{i:03}"), i.to_be_bytes().to_vec());
+    }
+    let mut fork = state.fork();
+    for i in 100..150u32 {
+        fork.put_raw(format!("a/{i:03}"), i.to_be_bytes().to_vec());
+    }
+    fork.prefix_delete("a/".to_string());
+    // Writes made after the deletion are spilled to a store of their own.
+    for i in 40..80u32 {
+        fork.put_raw(format!("a/{i:03}"), i.to_be_bytes().to_vec());
+    }
+
+    assert_eq!(fork.get_raw("a/base").await?, None);
+    assert_eq!(fork.get_raw("a/010").await?, None);
+    assert_eq!(fork.get_raw("a/110").await?, None);
+    assert_eq!(
+        fork.get_raw("a/045").await?,
+        Some(45u32.to_be_bytes().to_vec())
+    );
+    let expected: Vec<String> = (40..80u32).map(|i| format!("a/{i:03}")).collect();
+    let keys = fork
+        .prefix_keys("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, expected);
+    // The other branch doesn't see the deletion.
+    assert_eq!(
+        state.get_raw("a/010").await?,
+        Some(10u32.to_be_bytes().to_vec())
+    );
+
+    // Committing the changes deletes each key under the prefix.
+    storage.commit(fork).await?;
+    let snapshot = storage.latest_snapshot();
+    let keys = snapshot
+        .prefix_keys("a/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, expected);
+    let changes: std::collections::BTreeMap<Vec<u8>, Option<Vec<u8>>> = storage
+        .changeset(snapshot.version())
+        .await?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(changes.get(b"a/base".as_slice()), Some(&None));
+    assert_eq!(changes.get(b"a/010".as_slice()), Some(&None));
+    assert_eq!(changes.get(b"a/110".as_slice()), Some(&None));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn map_prefix_rewrites_each_value_once() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["a/1", "a/2", "a/3", "ab", "b/1"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+
+    // Values pending in the delta are rewritten along with committed ones.
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_raw("a/4".to_string(), b"a/4".to_vec());
+    let mut seen = Vec::new();
+    let count = state
+        .map_prefix("a/", |key, mut value| {
+            seen.push(key.to_string());
+            value.push(b'!');
+            Some(value)
+        })
+        .await?;
+    assert_eq!(count, 4);
+    assert_eq!(seen, vec!["a/1", "a/2", "a/3", "a/4"]);
+
+    // Returning `None` deletes the key.
+    let count = state
+        .map_prefix("a/", |key, value| (key != "a/3").then_some(value))
+        .await?;
+    assert_eq!(count, 4);
+
+    storage.commit(state).await?;
+    let snapshot = storage.latest_snapshot();
+    let entries = snapshot
+        .prefix_raw("")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        entries,
+        vec![
+            ("a/1".to_string(), b"a/1!".to_vec()),
+            ("a/2".to_string(), b"a/2!".to_vec()),
+            ("a/4".to_string(), b"a/4!".to_vec()),
+            ("ab".to_string(), b"ab".to_vec()),
+            ("b/1".to_string(), b"b/1".to_vec()),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn init_if_empty_only_runs_on_first_use() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("existing/key".to_string(), vec![1]);
+    delta.put_raw("cleared/key".to_string(), vec![1]);
+    storage.commit(delta).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    let init = |state: &mut StateDelta<Snapshot>| {
+        state.put_raw("fresh/params".to_string(), vec![0]);
+    };
+    assert!(state.init_if_empty("fresh/", init).await?);
+    assert_eq!(state.get_raw("fresh/params").await?, Some(vec![0]));
+    // The prefix is no longer empty once initialized.
+    assert!(!state.init_if_empty("fresh/", |_| unreachable!()).await?);
+
+    // Committed keys count, as do pending deletions.
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    assert!(!state.init_if_empty("existing/", |_| unreachable!()).await?);
+    state.delete("cleared/key".to_string());
+    assert!(
+        state
+            .init_if_empty("cleared/", |state| state
+                .put_raw("cleared/new".to_string(), vec![2]))
+            .await?
+    );
+    assert_eq!(state.get_raw("cleared/new").await?, Some(vec![2]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ephemeral_objects_follow_forks_and_are_not_committed() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.object_put("batch", vec![1u64]);
+
+    // A fork sees earlier objects, but not later ones, and vice versa.
+    let mut fork = state.fork();
+    state.object_put("batch", vec![1u64, 2]);
+    fork.object_put("other", true);
+    assert_eq!(fork.object_get::<Vec<u64>>("batch"), Some(vec![1]));
+    assert_eq!(state.object_get::<Vec<u64>>("batch"), Some(vec![1, 2]));
+    assert_eq!(state.object_get::<bool>("other"), None);
+
+    // Objects are visible across transactions until the state is committed.
+    let mut tx = StateDelta::new(&mut state);
+    tx.object_delete("batch");
+    assert_eq!(tx.object_get::<Vec<u64>>("batch"), None);
+    tx.apply();
+    state.object_put("parsed", 42u64);
+    assert_eq!(state.object_get::<Vec<u64>>("batch"), None);
+    assert_eq!(state.object_get("parsed"), Some(42u64));
+
+    storage.commit(state).await?;
+    let state = StateDelta::new(storage.latest_snapshot());
+    assert_eq!(state.object_get::<u64>("parsed"), None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn persistent_objects_survive_reload() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.object_put_persistent("persistent", vec![1u64, 2, 3])?;
+    state.object_put("ephemeral", vec![4u64, 5, 6]);
+
+    // Within the transaction, both objects are served from memory.
+    let mut tx = StateDelta::new(&mut state);
+    assert_eq!(tx.object_get::<Vec<u64>>("persistent"), Some(vec![1, 2, 3]));
+    assert_eq!(
+        tx.object_get_persistent::<Vec<u64>>("persistent").await?,
+        Some(vec![1, 2, 3])
+    );
+    tx.object_put_persistent("persistent", vec![7u64])?;
+    tx.apply();
+    assert_eq!(state.object_get::<Vec<u64>>("persistent"), Some(vec![7]));
+
+    storage.commit(state).await?;
+    storage.release().await;
+
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+    let state = StateDelta::new(storage.latest_snapshot());
+    assert_eq!(
+        state
+            .object_get_persistent::<Vec<u64>>("persistent")
+            .await?,
+        Some(vec![7])
+    );
+    assert_eq!(state.object_get::<Vec<u64>>("persistent"), None);
+    assert_eq!(state.object_get::<Vec<u64>>("ephemeral"), None);
+    assert_eq!(
+        state.object_get_persistent::<Vec<u64>>("ephemeral").await?,
+        None
+    );
+
+    // Deleting the object removes the durable copy too.
+    let mut state = state;
+    state.object_delete_persistent("persistent");
+    storage.commit(state).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(
+        snapshot
+            .object_get_persistent::<Vec<u64>>("persistent")
+            .await?,
+        None
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn wait_for_version_resolves_on_commit() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+    let timeout = std::time::Duration::from_secs(10);
+
+    let committer = storage.clone();
+    let handle = tokio::spawn(async move {
+        for i in 0..3u64 {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            let mut delta = StateDelta::new(committer.latest_snapshot());
+            delta.put_raw(format!("key/{i}"), i.to_be_bytes().to_vec());
+            committer.commit(delta).await?;
+        }
+        anyhow::Ok(())
+    });
+
+    let snapshot = storage.wait_for_version(2, timeout).await?;
+    assert!(snapshot.version() >= 2);
+    assert!(snapshot.get_raw("key/2").await?.is_some());
+    handle.await??;
+
+    // An already committed version resolves immediately.
+    let snapshot = storage.wait_for_version(1, timeout).await?;
+    assert_eq!(snapshot.version(), 2);
+
+    let err = storage
+        .wait_for_version(3, std::time::Duration::from_millis(50))
+        .await
+        .expect_err("version 3 is never committed");
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::VersionTimeout {
+            version: 3,
+            latest: 2
+        })
+    ));
+
+    Ok(())
+}
+
+/// Checks the merge contract of `prefix_raw`: every key appears once, the
+/// newest write wins, and deleted keys are hidden.
+#[tokio::test]
+async fn prefix_raw_merges_overlay_and_store() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["p/both", "p/deleted", "p/store", "p/twice"] {
+        delta.put_raw(key.to_string(), b"store".to_vec());
+    }
+    // Outside the prefix, but adjacent to it.
+    delta.put_raw("p0".to_string(), b"store".to_vec());
+    storage.commit(delta).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_raw("p/both".to_string(), b"overlay".to_vec());
+    state.delete("p/deleted".to_string());
+    state.put_raw("p/overlay".to_string(), b"overlay".to_vec());
+    state.put_raw("p/twice".to_string(), b"overlay".to_vec());
+    // A key written in one layer and deleted in the next is absent too.
+    state.put_raw("p/transient".to_string(), b"overlay".to_vec());
+    let mut tx = StateDelta::new(&mut state);
+    tx.put_raw("p/twice".to_string(), b"newest".to_vec());
+    tx.delete("p/transient".to_string());
+
+    let expected = vec![
+        ("p/both".to_string(), b"overlay".to_vec()),
+        ("p/overlay".to_string(), b"overlay".to_vec()),
+        ("p/store".to_string(), b"store".to_vec()),
+        ("p/twice".to_string(), b"newest".to_vec()),
+    ];
+
+    let entries = tx
+        .prefix_raw("p/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(entries, expected);
+
+    let mut reversed = tx
+        .prefix_raw_ordered("p/", ScanOrder::Descending)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    reversed.reverse();
+    assert_eq!(reversed, expected);
+
+    let keys = tx
+        .prefix_keys("p/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        keys,
+        expected.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>()
+    );
+
+    // The merged view is what gets committed.
+    tx.apply();
+    storage.commit(state).await?;
+    let entries = storage
+        .latest_snapshot()
+        .prefix_raw("p/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(entries, expected);
+
+    Ok(())
+}
+
+/// Checks that dropping read futures and streams before they complete leaves
+/// the state usable.
+#[tokio::test]
+async fn cancelled_reads_leave_state_usable() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..100u64 {
+        delta.put_raw(format!("key/{i:03}"), i.to_be_bytes().to_vec());
+        delta.nonverifiable_put_raw(format!("nv/{i:03}").into_bytes(), vec![1]);
+    }
+    storage.commit(delta).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_raw("key/overlay".to_string(), b"overlay".to_vec());
+
+    // Cancel a read that was spawned onto another task.
+    let handle = tokio::spawn(state.get_raw("key/050"));
+    handle.abort();
+    let _ = handle.await;
+
+    // Cancel reads mid-flight, as a losing `select!` branch would.
+    for i in 0..100u64 {
+        tokio::select! {
+            biased;
+            _ = std::future::ready(()) => {}
+            _ = state.get_raw(&format!("key/{i:03}")) => {}
+        }
+        let _ = tokio::time::timeout(
+            std::time::Duration::ZERO,
+            state.nonverifiable_get_raw(format!("nv/{i:03}").as_bytes()),
+        )
+        .await;
+    }
+
+    // Drop streams after reading only part of them.
+    {
+        let mut stream = std::pin::pin!(state.prefix_raw("key/"));
+        assert!(stream.next().await.is_some());
+    }
+    {
+        let mut stream = std::pin::pin!(state.nonverifiable_prefix_raw(b"nv/"));
+        assert!(stream.next().await.is_some());
+    }
+
+    // The state still serves reads and accepts writes.
+    assert_eq!(
+        state.get_raw("key/050").await?,
+        Some(50u64.to_be_bytes().to_vec())
+    );
+    assert_eq!(
+        state.get_raw("key/overlay").await?,
+        Some(b"overlay".to_vec())
+    );
+    assert_eq!(state.nonverifiable_get_raw(b"nv/050").await?, Some(vec![1]));
+    assert_eq!(state.prefix_keys("key/").count().await, 101);
+    state.put_raw("key/after".to_string(), b"after".to_vec());
+
+    storage.commit(state).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(
+        snapshot.get_raw("key/overlay").await?,
+        Some(b"overlay".to_vec())
+    );
+    assert_eq!(
+        snapshot.get_raw("key/after").await?,
+        Some(b"after".to_vec())
+    );
+    assert_eq!(snapshot.prefix_keys("key/").count().await, 102);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn archive_state_pins_its_version() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("key".to_string(), b"v0".to_vec());
+    storage.commit(delta).await?;
+
+    let archive = storage.latest_snapshot().into_archive();
+    assert_eq!(archive.version(), 0);
+    assert_eq!(storage.oldest_pinned_version(), Some(0));
+
+    // Commit enough versions for version 0 to be evicted from the snapshot cache.
+    for i in 1..=20u64 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw("key".to_string(), format!("v{i}").into_bytes());
+        storage.commit(delta).await?;
+    }
+    assert!(storage.snapshot(0).is_none());
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let archive = archive.clone();
+            tokio::spawn(async move {
+                assert_eq!(archive.get_raw("key").await?, Some(b"v0".to_vec()));
+                anyhow::Ok(archive.root_hash().await?)
+            })
+        })
+        .collect();
+    let root = archive.root_hash().await?;
+    let (pinned_clone, last_clone) = (archive.clone(), archive.clone());
+    drop(archive);
+    for handle in handles {
+        assert_eq!(handle.await??, root);
+    }
+
+    // The version stays pinned until the last clone is dropped.
+    assert_eq!(storage.oldest_pinned_version(), Some(0));
+    drop(pinned_clone);
+    assert_eq!(storage.oldest_pinned_version(), Some(0));
+    let newer = storage.latest_snapshot().into_archive();
+    drop(last_clone);
+    assert_eq!(storage.oldest_pinned_version(), Some(20));
+    drop(newer);
+    assert_eq!(storage.oldest_pinned_version(), None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn list_append_and_iterate() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let collect = |state: &StateDelta<Snapshot>| state.list_iter("log").collect::<Vec<_>>();
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    assert_eq!(state.list_len("log").await?, 0);
+    assert_eq!(state.list_append("log", b"first".to_vec()).await?, 0);
+    assert_eq!(state.list_append("log", b"second".to_vec()).await?, 1);
+    storage.commit(state).await?;
+
+    // Appends within an uncommitted transaction are visible to it.
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    let mut tx = StateDelta::new(&mut state);
+    assert_eq!(tx.list_append("log", b"third".to_vec()).await?, 2);
+    assert_eq!(tx.list_len("log").await?, 3);
+    let entries = tx
+        .list_iter("log")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(
+        entries,
+        vec![
+            (0, b"first".to_vec()),
+            (1, b"second".to_vec()),
+            (2, b"third".to_vec()),
+        ]
+    );
+    tx.apply();
+
+    // Other keys under the list key's prefix are not elements.
+    state.put_raw("logs/other".to_string(), b"other".to_vec());
+    storage.commit(state).await?;
+
+    let state = StateDelta::new(storage.latest_snapshot());
+    assert_eq!(state.list_len("log").await?, 3);
+    let values = collect(&state)
+        .await
+        .into_iter()
+        .map(|entry| entry.map(|(_, value)| value))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(
+        values,
+        vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn overlay_ops_replay_reproduces_writes() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("base/a".to_string(), b"a".to_vec());
+    delta.put_raw("base/b".to_string(), b"b".to_vec());
+    storage.commit(delta).await?;
+
+    let mut original = StateDelta::new(storage.latest_snapshot());
+    original.put_raw("new/x".to_string(), b"x0".to_vec());
+    original.delete("base/a".to_string());
+    original.object_put("object", 1u64);
+    let mut tx = original.fork();
+    tx.put_raw("new/x".to_string(), b"x1".to_vec());
+    tx.put_raw("new/y".to_string(), b"y".to_vec());
+    tx.nonverifiable_put_raw(b"nv/x".to_vec(), b"x".to_vec());
+    tx.nonverifiable_delete(b"nv/y".to_vec());
+
+    let ops = tx.overlay_ops()?;
+    assert_eq!(
+        ops,
+        vec![
+            OverlayOp::Delete {
+                key: "base/a".to_string()
+            },
+            OverlayOp::Put {
+                key: "new/x".to_string(),
+                value: b"x1".to_vec()
+            },
+            OverlayOp::Put {
+                key: "new/y".to_string(),
+                value: b"y".to_vec()
+            },
+            OverlayOp::NonverifiablePut {
+                key: b"nv/x".to_vec(),
+                value: b"x".to_vec()
+            },
+            OverlayOp::NonverifiableDelete {
+                key: b"nv/y".to_vec()
+            },
+        ]
+    );
+
+    // The operations survive a serialization round trip.
+    let ops: Vec<OverlayOp> = borsh::from_slice(&borsh::to_vec(&ops)?)?;
+
+    let mut replayed = StateDelta::new(storage.latest_snapshot());
+    replayed.replay_ops(&ops);
+    assert_eq!(replayed.overlay_ops()?, tx.overlay_ops()?);
+
+    let (_, original_changes) = tx.flatten()?;
+    let (_, replayed_changes) = replayed.flatten()?;
+    assert_eq!(
+        original_changes.unwritten_changes()?,
+        replayed_changes.unwritten_changes()?
+    );
+    assert_eq!(
+        original_changes.nonverifiable_changes(),
+        replayed_changes.nonverifiable_changes()
+    );
+
+    let mut replayed = StateDelta::new(storage.latest_snapshot());
+    replayed.replay_ops(&ops);
+    storage.commit(replayed).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("base/a").await?, None);
+    assert_eq!(snapshot.get_raw("base/b").await?, Some(b"b".to_vec()));
+    assert_eq!(snapshot.get_raw("new/x").await?, Some(b"x1".to_vec()));
+    assert_eq!(
+        snapshot.nonverifiable_get_raw(b"nv/x").await?,
+        Some(b"x".to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn shadow_storage_detects_divergence() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let primary_dir = tempfile::tempdir()?;
+    let shadow_dir = tempfile::tempdir()?;
+    let storage = ShadowStorage::new(
+        Storage::load(primary_dir.path().to_owned(), vec![]).await?,
+        Storage::load(shadow_dir.path().to_owned(), vec![]).await?,
+    );
+
+    // The writes of `simple_flow`, through nested deltas.
+    let mut state_init = StateDelta::new(storage.latest_snapshot());
+    let mut tx00 = StateDelta::new(&mut state_init);
+    tx00.put_raw("test".to_owned(), b"test".to_vec());
+    tx00.object_put("c/aa", 0u64);
+    tx00.nonverifiable_put_raw(b"iA".to_vec(), b"A".to_vec());
+    tx00.nonverifiable_put_raw(b"iC".to_vec(), b"C".to_vec());
+    tx00.nonverifiable_put_raw(b"iF".to_vec(), b"F".to_vec());
+    tx00.nonverifiable_put_raw(b"iD".to_vec(), b"D".to_vec());
+    tx00.apply();
+    let mut tx01 = StateDelta::new(&mut state_init);
+    tx01.put_raw("a/aa".to_owned(), b"aa".to_vec());
+    tx01.put_raw("a/aaa".to_owned(), b"aaa".to_vec());
+    tx01.put_raw("a/ab".to_owned(), b"ab".to_vec());
+    tx01.put_raw("a/z".to_owned(), b"z".to_vec());
+    tx01.apply();
+    storage.commit(state_init).await?;
+
+    let mut state0 = StateDelta::new(storage.latest_snapshot());
+    let mut tx10 = StateDelta::new(&mut state0);
+    tx10.delete("test".to_owned());
+    tx10.delete("a/aaa".to_owned());
+    tx10.put_raw("a/c".to_owned(), b"c".to_vec());
+    tx10.nonverifiable_put_raw(b"iB".to_vec(), b"B".to_vec());
+    tx10.apply();
+    let mut tx11 = StateDelta::new(&mut state0);
+    tx11.put_raw("a/ab".to_owned(), b"ab2".to_vec());
+    tx11.nonverifiable_delete(b"iD".to_vec());
+    tx11.apply();
+    storage.commit(state0).await?;
+
+    // Identical storages never diverge.
+    assert_eq!(storage.get_raw("test").await?, None);
+    assert_eq!(storage.get_raw("a/ab").await?, Some(b"ab2".to_vec()));
+    assert_eq!(storage.nonverifiable_get_raw(b"iD").await?, None);
+    assert_eq!(
+        storage.nonverifiable_get_raw(b"iB").await?,
+        Some(b"B".to_vec())
+    );
+    assert_eq!(
+        storage.prefix_raw("a/").await?,
+        vec![
+            ("a/aa".to_owned(), b"aa".to_vec()),
+            ("a/ab".to_owned(), b"ab2".to_vec()),
+            ("a/c".to_owned(), b"c".to_vec()),
+            ("a/z".to_owned(), b"z".to_vec()),
+        ]
+    );
+
+    // Write to the shadow storage behind the back of the pair.
+    let mut injected = StateDelta::new(storage.shadow().latest_snapshot());
+    injected.put_raw("a/ab".to_owned(), b"wrong".to_vec());
+    storage.shadow().commit(injected).await?;
+
+    let err = storage
+        .get_raw("a/ab")
+        .await
+        .expect_err("reads should diverge");
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::ShadowDivergence { .. })
+    ));
+    assert!(storage.prefix_raw("a/").await.is_err());
+    // The storages are no longer at the same version.
+    let delta = StateDelta::new(storage.latest_snapshot());
+    assert!(storage.commit(delta).await.is_err());
+
+    storage.release().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn reads_retry_transient_errors() -> anyhow::Result<()> {
+    use futures::TryStreamExt;
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let policy = RetryPolicy {
+        max_attempts: 4,
+        initial_backoff: std::time::Duration::from_millis(1),
+        max_backoff: std::time::Duration::from_millis(2),
+    };
+    assert_eq!(policy.backoff(1), std::time::Duration::from_millis(1));
+    assert_eq!(policy.backoff(3), std::time::Duration::from_millis(2));
+
+    // A read that fails `failures` times with the given error, then succeeds,
+    // returning the number of attempts made.
+    let flaky = |failures: u32, retryable: bool| {
+        let mut attempts = 0;
+        move || -> anyhow::Result<u32> {
+            attempts += 1;
+            if attempts <= failures {
+                Err(StorageError::Backend {
+                    retryable,
+                    message: "injected failure".to_string(),
+                }
+                .into())
+            } else {
+                Ok(attempts)
+            }
+        }
+    };
+
+    // Within the retry budget, the read eventually succeeds.
+    assert_eq!(policy.run(flaky(3, true))?, 4);
+    // Past it, the last error is returned, still marked as retryable.
+    let err = policy
+        .run(flaky(4, true))
+        .expect_err("the retry budget is exhausted");
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::Backend {
+            retryable: true,
+            ..
+        })
+    ));
+    // Permanent errors, like corruption, are never retried.
+    let mut attempts = 0;
+    let mut corrupted = flaky(1, false);
+    assert!(policy
+        .run(|| {
+            attempts += 1;
+            corrupted()
+        })
+        .is_err());
+    assert_eq!(attempts, 1);
+
+    // Reads go through the policy configured for the storage.
+    let tmpdir = tempfile::tempdir()?;
+    let options = StorageOptions {
+        read_retry: policy,
+        ..Default::default()
+    };
+    let storage = Storage::load_with_options(tmpdir.path().to_owned(), vec![], options).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..5u8 {
+        delta.put_raw(format!("k/{i}"), vec![i]);
+    }
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("k/3").await?, Some(vec![3]));
+    let keys: Vec<String> = snapshot
+        .prefix_raw_ordered("k/", ScanOrder::Descending)
+        .map_ok(|(key, _)| key)
+        .try_collect()
+        .await?;
+    assert_eq!(keys, vec!["k/4", "k/3", "k/2", "k/1", "k/0"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn commit_if_enforces_invariant() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    // The total supply must equal the sum of the balances.
+    fn supply_invariant(
+        state: &StateDelta<Snapshot>,
+    ) -> futures::future::BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let supply = state.get_raw("supply").await?.unwrap_or_default();
+            let balances = state
+                .prefix_raw("balance/")
+                .fold(0u8, |total, entry| async move {
+                    total + entry.map(|(_, value)| value[0]).unwrap_or_default()
+                })
+                .await;
+            anyhow::ensure!(
+                supply == vec![balances],
+                "supply {supply:?} does not match balances {balances}"
+            );
+            Ok(())
+        })
+    }
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("supply".to_string(), vec![3]);
+    delta.put_raw("balance/a".to_string(), vec![1]);
+    delta.put_raw("balance/b".to_string(), vec![2]);
+    storage.commit_if(delta, supply_invariant).await?;
+    assert_eq!(storage.latest_version(), 0);
+
+    // Minting without updating the supply breaks the invariant.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("balance/c".to_string(), vec![4]);
+    let err = storage
+        .commit_if(delta, supply_invariant)
+        .await
+        .expect_err("the invariant does not hold");
+    assert!(err.to_string().contains("does not match"));
+    assert_eq!(storage.latest_version(), 0);
+    assert_eq!(storage.latest_snapshot().get_raw("balance/c").await?, None);
+
+    Ok(())
+}
+
+#[test]
+fn multistore_cache_display_is_deterministic() {
+    use crate::store::{multistore::MultistoreCache, substore::SubstoreConfig};
+    use std::sync::Arc;
+
+    let build = |prefixes: &[&str]| {
+        let mut cache = MultistoreCache::default();
+        for (version, prefix) in prefixes.iter().enumerate() {
+            // Options don't take part in the ordering, only the prefix does.
+            let config = SubstoreConfig::new(prefix).with_compression(if version % 2 == 0 {
+                Compression::Zstd
+            } else {
+                Compression::None
+            });
+            cache.set_version(Arc::new(config), prefix.len() as u64);
+        }
+        cache.to_string()
+    };
+
+    let display = build(&["ibc", "", "dex", "cometbft-data"]);
+    assert_eq!(display, build(&["dex", "cometbft-data", "ibc", ""]));
+    assert_eq!(display, ": 0\ncometbft-data: 13\ndex: 3\nibc: 3\n");
+}
+
+#[tokio::test]
+async fn compact_overlay_keeps_reads_unchanged() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("base".to_string(), b"base".to_vec());
+    delta.nonverifiable_put_raw(b"nv/base".to_vec(), b"base".to_vec());
+    storage.commit(delta).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..100u32 {
+        delta.put_raw("counter".to_string(), i.to_be_bytes().to_vec());
+    }
+    // Keys that are written then deleted, and never existed in the base.
+    for i in 0..10 {
+        delta.put_raw(format!("scratch/{i}"), vec![i]);
+        delta.delete(format!("scratch/{i}"));
+        delta.nonverifiable_put_raw(format!("nv/scratch/{i}").into_bytes(), vec![i]);
+        delta.nonverifiable_delete(format!("nv/scratch/{i}").into_bytes());
+    }
+    // Deletions of keys in the base must be kept.
+    delta.delete("base".to_string());
+    delta.nonverifiable_delete(b"nv/base".to_vec());
+
+    assert_eq!(delta.compact_overlay().await?, 20);
+    assert_eq!(
+        delta.overlay_ops()?,
+        vec![
+            OverlayOp::Delete {
+                key: "base".to_string()
+            },
+            OverlayOp::Put {
+                key: "counter".to_string(),
+                value: 99u32.to_be_bytes().to_vec()
+            },
+            OverlayOp::NonverifiableDelete {
+                key: b"nv/base".to_vec()
+            },
+        ]
+    );
+
+    assert_eq!(
+        delta.get_raw("counter").await?,
+        Some(99u32.to_be_bytes().to_vec())
+    );
+    assert_eq!(delta.get_raw("base").await?, None);
+    assert_eq!(delta.get_raw("scratch/3").await?, None);
+    assert_eq!(delta.nonverifiable_get_raw(b"nv/base").await?, None);
+    assert_eq!(delta.nonverifiable_get_raw(b"nv/scratch/3").await?, None);
+    assert_eq!(delta.prefix_raw("scratch/").count().await, 0);
+
+    // Compacting again finds nothing left to drop.
+    assert_eq!(delta.compact_overlay().await?, 0);
+
+    storage.commit(delta).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("base").await?, None);
+    assert_eq!(
+        snapshot.get_raw("counter").await?,
+        Some(99u32.to_be_bytes().to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn key_location_reports_layering() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    // Version 0, as in `simple_flow`.
+    let mut state_init = StateDelta::new(storage.latest_snapshot());
+    state_init.put_raw("test".to_owned(), b"test".to_vec());
+    state_init.put_raw("a/aa".to_owned(), b"aa".to_vec());
+    state_init.put_raw("a/aaa".to_owned(), b"aaa".to_vec());
+    state_init.put_raw("a/ab".to_owned(), b"ab".to_vec());
+    state_init.put_raw("a/z".to_owned(), b"z".to_vec());
+    storage.commit(state_init).await?;
+
+    let mut state0 = StateDelta::new(storage.latest_snapshot());
+    let mut tx10 = StateDelta::new(&mut state0);
+    tx10.delete("test".to_owned());
+    tx10.delete("a/aaa".to_owned());
+    tx10.put_raw("a/c".to_owned(), b"c".to_vec());
+    assert_eq!(tx10.key_location("test").await?, KeyLocation::OverlayDelete);
+    assert_eq!(tx10.key_location("a/c").await?, KeyLocation::OverlayPut);
+    assert_eq!(tx10.key_location("a/aa").await?, KeyLocation::StoreOnly);
+    assert_eq!(tx10.key_location("a/zz").await?, KeyLocation::Absent);
+    tx10.apply();
+
+    // From tx11, the changes applied to state0 are in the store.
+    let mut tx11 = StateDelta::new(&mut state0);
+    tx11.put_raw("a/ab".to_owned(), b"ab2".to_vec());
+    assert_eq!(
+        tx11.key_location("a/ab").await?,
+        KeyLocation::OverlayShadowingStore
+    );
+    assert_eq!(tx11.key_location("a/c").await?, KeyLocation::StoreOnly);
+    assert_eq!(tx11.key_location("test").await?, KeyLocation::Absent);
+    tx11.apply();
+
+    // From state0, everything since the snapshot is in the overlay.
+    assert_eq!(
+        state0.key_location("test").await?,
+        KeyLocation::OverlayDelete
+    );
+    assert_eq!(
+        state0.key_location("a/ab").await?,
+        KeyLocation::OverlayShadowingStore
+    );
+    assert_eq!(state0.key_location("a/c").await?, KeyLocation::OverlayPut);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_iterators_are_capped() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let options = StorageOptions {
+        max_open_iterators: Some(2),
+        open_iterator_timeout: Some(std::time::Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let storage = Storage::load_with_options(tmpdir.path().to_owned(), vec![], options).await?;
+
+    // Enough keys that an unconsumed stream keeps its iterator open.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..100u8 {
+        delta.put_raw(format!("k/{i:03}"), vec![i]);
+    }
+    storage.commit(delta).await?;
+    let snapshot = storage.latest_snapshot();
+
+    let first = snapshot.prefix_raw("k/");
+    let mut second = snapshot.prefix_raw("k/");
+    assert!(second.next().await.transpose()?.is_some());
+
+    // A third stream times out waiting for a slot.
+    let err = snapshot
+        .prefix_raw("k/")
+        .next()
+        .await
+        .expect("the stream yields an error")
+        .expect_err("no slot is available");
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::TooManyIterators { max: 2 })
+    ));
+
+    // Dropping a stream frees its slot for the next one.
+    drop(first);
+    let mut third = snapshot.prefix_raw("k/");
+    assert_eq!(
+        third.next().await.transpose()?,
+        Some(("k/000".to_string(), vec![0]))
+    );
+
+    // Streams release their slot once exhausted.
+    drop(second);
+    for _ in 0..3 {
+        assert_eq!(snapshot.prefix_keys("k/").count().await, 100);
+    }
+
+    drop(third);
+    Ok(())
+}
+
+#[tokio::test]
+async fn nonverifiable_range_by_index_decodes_indices() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    // Indices whose big-endian encoding spans several bytes, half committed
+    // and half in the overlay.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for index in (0..20u64).step_by(2) {
+        delta.nonverifiable_put_by_index("blocks/", index << 8, vec![index as u8]);
+    }
+    delta.nonverifiable_put_by_index("other/", 7 << 8, b"other".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for index in (1..20u64).step_by(2) {
+        delta.nonverifiable_put_by_index("blocks/", index << 8, vec![index as u8]);
+    }
+    delta.nonverifiable_delete(crate::read::index_key("blocks/", 6 << 8));
+
+    let indices: Vec<u64> = delta
+        .nonverifiable_range_by_index("blocks/", (5 << 8)..(10 << 8))?
+        .map(|entry| entry.map(|(index, _)| index >> 8))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(indices, vec![5, 7, 8, 9]);
+
+    let entries: Vec<_> = delta
+        .nonverifiable_range_by_index("blocks/", (17 << 8)..)?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(
+        entries,
+        vec![
+            (17 << 8, vec![17]),
+            (18 << 8, vec![18]),
+            (19 << 8, vec![19])
+        ]
+    );
+
+    let entries: Vec<_> = delta
+        .nonverifiable_range_by_index("blocks/", ..=(1 << 8))?
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(entries.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn commit_and_prune_keeps_recent_and_pinned_versions() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    for i in 0..5u8 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw("counter".to_string(), vec![i]);
+        storage.commit_and_prune(delta, 2).await?;
+    }
+    assert_eq!(storage.latest_version(), 4);
+    for version in 0..3 {
+        assert!(storage.snapshot(version).is_none());
+        assert!(matches!(
+            storage
+                .state_at_version(version)
+                .await
+                .unwrap_err()
+                .downcast_ref::<StorageError>(),
+            Some(StorageError::VersionPruned { oldest: 3, .. })
+        ));
+    }
+    for version in 3..5 {
+        assert!(storage.snapshot(version).is_some());
+    }
+    assert_eq!(
+        storage
+            .state_at_version(3)
+            .await?
+            .get_raw("counter")
+            .await?,
+        Some(vec![3])
+    );
+
+    // A pinned version survives pruning, along with every later version.
+    let archive = storage.latest_snapshot().into_archive();
+    for i in 0..3u8 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw("counter".to_string(), vec![i]);
+        storage.commit_and_prune(delta, 2).await?;
+    }
+    for version in 4..8 {
+        assert!(storage.snapshot(version).is_some());
+    }
+    assert_eq!(
+        storage
+            .snapshot(4)
+            .expect("pinned")
+            .get_raw("counter")
+            .await?,
+        Some(vec![4])
+    );
+
+    drop(archive);
+    let delta = StateDelta::new(storage.latest_snapshot());
+    storage.commit_and_prune(delta, 2).await?;
+    for version in 4..7 {
+        assert!(storage.snapshot(version).is_none());
+        assert!(storage.state_at_version(version).await.is_err());
+    }
+    assert!(storage.snapshot(7).is_some());
+    assert!(storage.snapshot(8).is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn multi_prefix_head_returns_each_head() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["a/1", "a/2", "a/3", "b/1", "c/1", "c/2"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+
+    // The overlay is merged into each head.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("a/1".to_string());
+    delta.put_raw("c/0".to_string(), b"c/0".to_vec());
+
+    let heads = delta
+        .multi_prefix_head(&[("a/".to_string(), 2), ("c/".to_string(), 1)])
+        .await?;
+    let expected: std::collections::BTreeMap<_, _> = [
+        (
+            "a/".to_string(),
+            vec![
+                ("a/2".to_string(), b"a/2".to_vec()),
+                ("a/3".to_string(), b"a/3".to_vec()),
+            ],
+        ),
+        ("c/".to_string(), vec![("c/0".to_string(), b"c/0".to_vec())]),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(heads, expected);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn range_raw_respects_bounds_and_overlay() -> anyhow::Result<()> {
+    use futures::TryStreamExt;
+    use std::ops::Bound;
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["book/1", "book/2", "book/3", "book/5", "other"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("book/2".to_string());
+    delta.put_raw("book/4".to_string(), b"new".to_vec());
+
+    let keys = |start: Bound<&str>, end: Bound<&str>| {
+        let stream = delta.range_raw(
+            start.map(|k| k.as_bytes().to_vec()),
+            end.map(|k| k.as_bytes().to_vec()),
+        );
+        async move {
+            let entries: Vec<_> = stream.try_collect().await?;
+            anyhow::Ok(
+                entries
+                    .into_iter()
+                    .map(|(key, _)| String::from_utf8(key).unwrap())
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+
+    assert_eq!(
+        keys(Bound::Included("book/1"), Bound::Included("book/4")).await?,
+        ["book/1", "book/3", "book/4"]
+    );
+    assert_eq!(
+        keys(Bound::Excluded("book/1"), Bound::Excluded("book/5")).await?,
+        ["book/3", "book/4"]
+    );
+    assert_eq!(
+        keys(Bound::Included("book/3"), Bound::Unbounded).await?,
+        ["book/3", "book/4", "book/5", "other"]
+    );
+    assert_eq!(
+        keys(Bound::Unbounded, Bound::Excluded("book/3")).await?,
+        ["book/1"]
+    );
+
+    // The overlay's value is returned.
+    let entries: Vec<_> = delta
+        .range_raw(
+            Bound::Included(b"book/4".to_vec()),
+            Bound::Included(b"book/4".to_vec()),
+        )
+        .try_collect()
+        .await?;
+    assert_eq!(entries, [(b"book/4".to_vec(), b"new".to_vec())]);
+
+    // Empty ranges yield nothing.
+    assert!(keys(Bound::Included("book/5"), Bound::Included("book/1"))
+        .await?
+        .is_empty());
+    assert!(keys(Bound::Excluded("book/3"), Bound::Included("book/3"))
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn range_raw_spans_substores() -> anyhow::Result<()> {
+    use futures::TryStreamExt;
+    use std::ops::Bound;
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(
+        tmpdir.path().to_owned(),
+        vec!["ibc".to_string(), "ibc/client".to_string()],
+    )
+    .await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["a", "ibc/a", "ibc/client/a", "ibc/z", "z"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("ibc/client/b".to_string(), b"ibc/client/b".to_vec());
+
+    let keys = |start: Bound<&str>, end: Bound<&str>| {
+        let stream = delta.range_raw(
+            start.map(|k| k.as_bytes().to_vec()),
+            end.map(|k| k.as_bytes().to_vec()),
+        );
+        async move {
+            let entries: Vec<_> = stream.try_collect().await?;
+            anyhow::Ok(
+                entries
+                    .into_iter()
+                    .map(|(key, _)| String::from_utf8(key).unwrap())
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+
+    // The main store also holds each substore's root hash, under its prefix.
+    assert_eq!(
+        keys(Bound::Unbounded, Bound::Unbounded).await?,
+        [
+            "a",
+            "ibc",
+            "ibc/a",
+            "ibc/client",
+            "ibc/client/a",
+            "ibc/client/b",
+            "ibc/z",
+            "z"
+        ]
+    );
+    assert_eq!(
+        keys(Bound::Included("ibc/a"), Bound::Unbounded).await?,
+        [
+            "ibc/a",
+            "ibc/client",
+            "ibc/client/a",
+            "ibc/client/b",
+            "ibc/z",
+            "z"
+        ]
+    );
+    assert_eq!(
+        keys(Bound::Excluded("ibc/a"), Bound::Excluded("ibc/z")).await?,
+        ["ibc/client", "ibc/client/a", "ibc/client/b"]
+    );
+
+    Ok(())
+}
+
+/// A helper that can only read from the transaction it is given.
+async fn read_balance<S: StateRead>(
+    tx: ReadTransaction<'_, S>,
+    key: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    tx.get_raw(key).await
+}
+
+#[tokio::test]
+async fn read_transaction_sees_overlay_and_base() -> anyhow::Result<()> {
+    use futures::TryStreamExt;
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("balance/alice".to_string(), vec![1]);
+    delta.put_raw("balance/bob".to_string(), vec![2]);
+    storage.commit(delta).await?;
+
+    let mut tx = StateDelta::new(storage.latest_snapshot());
+    tx.put_raw("balance/carol".to_string(), vec![3]);
+    tx.delete("balance/bob".to_string());
+
+    let read = tx.as_read();
+    assert_eq!(read_balance(read, "balance/alice").await?, Some(vec![1]));
+    assert_eq!(read_balance(read, "balance/carol").await?, Some(vec![3]));
+    assert!(read.contains_key("balance/alice").await?);
+    assert!(!read.contains_key("balance/bob").await?);
+    let keys: Vec<_> = read
+        .prefix_raw("balance/")
+        .map_ok(|(key, _)| key)
+        .try_collect()
+        .await?;
+    assert_eq!(keys, ["balance/alice", "balance/carol"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn namespaced_storages_do_not_collide() -> anyhow::Result<()> {
+    use futures::TryStreamExt;
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+    let alice = storage.with_namespace("alice/".to_string());
+    let bob = storage.with_namespace("bob/".to_string());
+
+    let mut delta = StateDelta::new(alice.latest_snapshot());
+    delta.put_raw("balance".to_string(), b"10".to_vec());
+    delta.nonverifiable_put_raw(b"index".to_vec(), b"a".to_vec());
+    alice.commit(delta).await?;
+    let mut delta = StateDelta::new(bob.latest_snapshot());
+    delta.put_raw("balance".to_string(), b"20".to_vec());
+    delta.put_raw("nonce".to_string(), b"1".to_vec());
+    bob.commit(delta).await?;
+
+    // Each view reads back only its own keys, without the namespace.
+    let alice_state = alice.latest_snapshot();
+    assert_eq!(alice_state.get_raw("balance").await?, Some(b"10".to_vec()));
+    assert_eq!(alice_state.get_raw("nonce").await?, None);
+    assert_eq!(
+        alice_state.nonverifiable_get_raw(b"index").await?,
+        Some(b"a".to_vec())
+    );
+    let bob_keys: Vec<String> = bob.latest_snapshot().prefix_keys("").try_collect().await?;
+    assert_eq!(bob_keys, vec!["balance".to_string(), "nonce".to_string()]);
+
+    // Both share the underlying storage, where the keys are namespaced.
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(storage.latest_version(), 1);
+    assert_eq!(
+        snapshot.get_raw("alice/balance").await?,
+        Some(b"10".to_vec())
+    );
+    assert_eq!(snapshot.get_raw("bob/balance").await?, Some(b"20".to_vec()));
+    assert_eq!(snapshot.get_raw("balance").await?, None);
+    assert_eq!(
+        snapshot.nonverifiable_get_raw(b"alice/index").await?,
+        Some(b"a".to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn deletions_are_reported_as_tombstones() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("test".to_string(), b"test".to_vec());
+    delta.put_raw("a/aaa".to_string(), b"aaa".to_vec());
+    storage.commit(delta).await?;
+    let base = storage.latest_version();
+
+    let mut changes_rx = storage.subscribe_changes();
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("test".to_string());
+    delta.put_raw("a/bbb".to_string(), b"bbb".to_vec());
+    // A key that was never present.
+    delta.delete("never".to_string());
+    storage.commit(delta).await?;
+
+    changes_rx.changed().await?;
+    let (version, changes) = changes_rx.borrow_and_update().clone();
+    assert_eq!(version, base + 1);
+    let unwritten = changes.unwritten_changes()?;
+    assert_eq!(unwritten.get("test"), Some(&None));
+    assert_eq!(unwritten.get("a/aaa"), None);
+
+    // A key written and deleted again after the base version is omitted.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("a/bbb".to_string());
+    storage.commit(delta).await?;
+
+    assert_eq!(
+        storage.changed_keys_since(base).await?,
+        vec![("test".to_string(), None)]
+    );
+    assert_eq!(
+        storage.changed_keys_since(base + 1).await?,
+        vec![("a/bbb".to_string(), None)]
+    );
+    assert!(storage.changed_keys_since(base + 2).await?.is_empty());
+    assert!(storage.changed_keys_since(base + 3).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn changeset_streams_the_writes_of_a_version() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("b".to_string(), b"b".to_vec());
+    delta.put_raw("a".to_string(), b"a".to_vec());
+    delta.nonverifiable_put_raw(b"index".to_vec(), b"index".to_vec());
+    let (first, _) = storage.commit(delta).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("a".to_string());
+    delta.put_raw("c".to_string(), b"c".to_vec());
+    let (second, _) = storage.commit(delta).await?;
+
+    let changes: Vec<_> = storage
+        .changeset(first)
+        .await?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(
+        changes,
+        vec![
+            (b"a".to_vec(), Some(b"a".to_vec())),
+            (b"b".to_vec(), Some(b"b".to_vec())),
+        ]
+    );
+
+    let changes: Vec<_> = storage
+        .changeset(second)
+        .await?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(
+        changes,
+        vec![(b"a".to_vec(), None), (b"c".to_vec(), Some(b"c".to_vec()))]
+    );
+
+    assert!(storage.changeset(second + 1).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn changeset_is_limited_to_the_retained_commits() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let options = StorageOptions {
+        change_log_retention: Some(2),
+        ..Default::default()
+    };
+    let storage = Storage::load_with_options(tmpdir.path().to_owned(), vec![], options).await?;
+
+    for i in 0..3u8 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw(format!("key_{i}"), vec![i]);
+        storage.commit(delta).await?;
+    }
+
+    let err = storage
+        .changeset(0)
+        .await
+        .err()
+        .expect("version 0 was evicted");
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::ChangesNotRetained { version: 0 })
+    ));
+    for version in 1..3u8 {
+        let changes: Vec<_> = storage
+            .changeset(version.into())
+            .await?
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<anyhow::Result<_>>()?;
+        assert_eq!(
+            changes,
+            vec![(format!("key_{version}").into_bytes(), Some(vec![version]))]
+        );
+    }
+
+    // Changes are not persisted across restarts.
+    storage.release().await;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+    assert!(storage.changeset(2).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn clear_empties_the_tree() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().join("cleared"), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["a/aaa", "a/aab", "b/bbb", "test"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    // A key written in the same transaction is cleared too, and one that was
+    // already deleted is not counted.
+    delta.put_raw("c/ccc".to_string(), b"ccc".to_vec());
+    delta.delete("test".to_string());
+    assert_eq!(delta.clear().await?, 4);
+    assert_eq!(delta.get_raw("c/ccc").await?, None);
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    for key in ["a/aaa", "a/aab", "b/bbb", "c/ccc", "test"] {
+        assert_eq!(snapshot.get_raw(key).await?, None);
+    }
+
+    // The root is that of a tree whose only key was deleted.
+    let reference = Storage::load(tmpdir.path().join("reference"), vec![]).await?;
+    let mut delta = StateDelta::new(reference.latest_snapshot());
+    delta.put_raw("x".to_string(), b"x".to_vec());
+    reference.commit(delta).await?;
+    let mut delta = StateDelta::new(reference.latest_snapshot());
+    delta.delete("x".to_string());
+    let (_, empty_root) = reference.commit(delta).await?;
+    assert_eq!(snapshot.root_hash().await?, empty_root);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn value_size_matches_value_length() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["sub".to_string()]).await?;
+
+    let keys = ["a", "b", "sub/c", "empty", "absent", "pending"];
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), vec![0; 300]);
+    delta.put_raw("b".to_string(), b"b".to_vec());
+    delta.put_raw("sub/c".to_string(), vec![1; 70_000]);
+    delta.put_raw("empty".to_string(), vec![]);
+    storage.commit(delta).await?;
+    // A value overwritten in a later version reports its latest length.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), vec![0; 3]);
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    for key in keys {
+        let expected = snapshot.get_raw(key).await?.map(|v| v.len());
+        assert_eq!(snapshot.value_size(key).await?, expected, "{key}");
+    }
+    assert_eq!(snapshot.value_size("a").await?, Some(3));
+    assert_eq!(snapshot.value_size("absent").await?, None);
+
+    let mut delta = StateDelta::new(snapshot);
+    delta.delete("b".to_string());
+    delta.put_raw("pending".to_string(), vec![2; 5]);
+    for key in keys {
+        let expected = delta.get_raw(key).await?.map(|v| v.len());
+        assert_eq!(delta.value_size(key).await?, expected, "{key}");
+    }
+    assert_eq!(delta.value_size("b").await?, None);
+    assert_eq!(delta.value_size("pending").await?, Some(5));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn contains_raw_honors_overlay_and_deletions() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["sub".to_string()]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), vec![0; 100_000]);
+    delta.put_raw("sub/b".to_string(), vec![1]);
+    delta.put_raw("gone".to_string(), vec![2]);
+    storage.commit(delta).await?;
+    // A key deleted in a later version is absent.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("gone".to_string());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert!(snapshot.contains_raw("a").await?);
+    assert!(snapshot.contains_raw("sub/b").await?);
+    assert!(!snapshot.contains_raw("gone").await?);
+    assert!(!snapshot.contains_raw("absent").await?);
+
+    let mut delta = StateDelta::new(snapshot);
+    delta.delete("a".to_string());
+    delta.put_raw("pending".to_string(), vec![]);
+    assert!(!delta.contains_raw("a").await?);
+    assert!(delta.contains_raw("pending").await?);
+    assert!(delta.contains_raw("sub/b").await?);
+    assert!(!delta.contains_raw("gone").await?);
+
+    Ok(())
+}
+
+#[async_trait::async_trait]
+trait CounterReadExt: StateRead {
+    async fn get_counter(&self) -> anyhow::Result<u64> {
+        let Some(bytes) = self.get_raw("counter").await? else {
+            return Ok(0);
+        };
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("counter is not a u64"))?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+impl<T: StateRead + ?Sized> CounterReadExt for T {}
+
+trait CounterWriteExt: StateWrite {
+    fn set_counter(&mut self, value: u64) {
+        self.put_raw("counter".to_string(), value.to_be_bytes().to_vec());
+    }
+}
+
+impl<T: StateWrite + ?Sized> CounterWriteExt for T {}
+
+#[tokio::test]
+async fn extension_traits_layer_on_state() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec![]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    assert_eq!(delta.get_counter().await?, 0);
+    delta.set_counter(41);
+    assert_eq!(delta.get_counter().await?, 41);
+
+    // The accessors are available on nested deltas, through references...
+    let mut tx = StateDelta::new(&mut delta);
+    let counter = tx.get_counter().await?;
+    tx.set_counter(counter + 1);
+    tx.apply();
+    storage.commit(delta).await?;
+
+    // ...and on snapshots, which are read-only.
+    assert_eq!(storage.latest_snapshot().get_counter().await?, 42);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn incremental_checkpoint_restores_latest_state() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let prefixes = vec!["sub".to_string()];
+    let storage = Storage::load(tmpdir.path().join("live"), prefixes.clone()).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"a".to_vec());
+    delta.put_raw("sub/b".to_string(), b"b".to_vec());
+    storage.commit(delta).await?;
+    storage.checkpoint(tmpdir.path().join("base")).await?;
+    let base_version = storage.latest_version();
+
+    for i in 0..3u8 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw(format!("sub/c{i}"), vec![i]);
+        delta.nonverifiable_put_raw(vec![i], vec![i]);
+        delta.delete("a".to_string());
+        storage.commit(delta).await?;
+    }
+    let incremental = tmpdir.path().join("incremental");
+    storage
+        .incremental_checkpoint(base_version, incremental.clone())
+        .await?;
+    let latest = storage.latest_snapshot();
+
+    // Once pruned, the base version is refused.
+    let delta = StateDelta::new(storage.latest_snapshot());
+    storage.commit_and_prune(delta, 1).await?;
+    assert!(storage
+        .incremental_checkpoint(base_version, tmpdir.path().join("pruned"))
+        .await
+        .is_err());
+
+    let restored = Storage::load(tmpdir.path().join("base"), prefixes).await?;
+    assert_eq!(restored.latest_version(), base_version);
+    let root_hash = restored.apply_incremental_checkpoint(incremental).await?;
+
+    let snapshot = restored.latest_snapshot();
+    assert_eq!(snapshot.version(), latest.version());
+    assert_eq!(root_hash, latest.root_hash().await?);
+    assert_eq!(
+        snapshot.prefix_root_hash("sub").await?,
+        latest.prefix_root_hash("sub").await?
+    );
+    assert_eq!(snapshot.get_raw("a").await?, None);
+    assert_eq!(snapshot.get_raw("sub/c2").await?, Some(vec![2]));
+    assert_eq!(snapshot.nonverifiable_get_raw(&[1]).await?, Some(vec![1]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn export_snapshot_restores_a_loadable_database() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let prefixes = vec!["sub".to_string(), "other".to_string()];
+    let storage = Storage::load(tmpdir.path().join("live"), prefixes.clone()).await?;
+
+    for i in 0..3u8 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw(format!("a{i}"), vec![i]);
+        delta.put_raw(format!("sub/b{i}"), vec![i]);
+        delta.nonverifiable_put_raw(vec![i], vec![i]);
+        storage.commit(delta).await?;
+    }
+    let exported = storage.latest_snapshot();
+    let export = tmpdir.path().join("export");
+    storage.export_snapshot(exported.version(), &export).await?;
+
+    // Commits go on after the export, and older versions are refused.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("sub/later".to_string(), b"later".to_vec());
+    storage.commit(delta).await?;
+    assert!(storage
+        .export_snapshot(exported.version(), &tmpdir.path().join("stale"))
+        .await
+        .is_err());
+    // An existing file is not overwritten.
+    assert!(storage
+        .export_snapshot(storage.latest_version(), &export)
+        .await
+        .is_err());
+
+    let restored_path = tmpdir.path().join("restored");
+    Storage::import_snapshot(&export, &restored_path).await?;
+    let restored = Storage::load(restored_path, prefixes).await?;
+    let snapshot = restored.latest_snapshot();
+    assert_eq!(snapshot.version(), exported.version());
+    assert_eq!(snapshot.root_hash().await?, exported.root_hash().await?);
+    assert_eq!(
+        restored.substore_versions().get("sub"),
+        Some(&exported.version())
+    );
+    assert_eq!(restored.substore_versions().get("other"), Some(&u64::MAX));
+    assert_eq!(snapshot.get_raw("sub/b2").await?, Some(vec![2]));
+    assert_eq!(snapshot.get_raw("sub/later").await?, None);
+    assert_eq!(snapshot.nonverifiable_get_raw(&[1]).await?, Some(vec![1]));
+
+    // A truncated export is refused, and leaves nothing behind.
+    let bytes = std::fs::read(&export)?;
+    let truncated = tmpdir.path().join("truncated");
+    std::fs::write(&truncated, &bytes[..bytes.len() / 2])?;
+    let dest = tmpdir.path().join("partial");
+    assert!(Storage::import_snapshot(&truncated, &dest).await.is_err());
+    assert!(!dest.exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rocksdb_config_is_applied_at_load() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let options = StorageOptions {
+        rocksdb: RocksDbConfig {
+            block_cache_size: 1 << 20,
+            write_buffer_size: 1 << 20,
+            max_open_files: 64,
+        },
+        ..Default::default()
+    };
+    let storage =
+        Storage::load_with_options(tmpdir.path().to_owned(), vec!["sub".to_string()], options)
+            .await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..100u32 {
+        delta.put_raw(format!("sub/{i}"), vec![0; 1024]);
+    }
+    let (_, root_hash) = storage.commit(delta).await?;
+    storage.release().await;
+
+    // Values RocksDB can't represent fall back to its defaults.
+    let options = StorageOptions {
+        rocksdb: RocksDbConfig {
+            max_open_files: u32::MAX,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let storage =
+        Storage::load_with_options(tmpdir.path().to_owned(), vec!["sub".to_string()], options)
+            .await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.root_hash().await?, root_hash);
+    assert_eq!(snapshot.get_raw("sub/99").await?, Some(vec![0; 1024]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn commit_caps_internal_nodes() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let options = StorageOptions {
+        max_internal_nodes_per_commit: Some(64),
+        ..Default::default()
+    };
+    let storage = Storage::load_with_options(tmpdir.path().to_owned(), vec![], options).await?;
+
+    // A small write fits under the cap, and the trace reports its fan-out.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), vec![1]);
+    delta.put_raw("b".to_string(), vec![2]);
+    let (_, _, trace) = storage.commit_with_trace(delta).await?;
+    assert!(trace.internal_nodes > 0 && trace.internal_nodes <= 64);
+    let version = storage.latest_version();
+
+    // Writes scattered across the keyspace exceed it, and nothing is written.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..1000u32 {
+        delta.put_raw(format!("scattered/{i}"), i.to_le_bytes().to_vec());
+    }
+    let err = storage.commit(delta).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<StorageError>(),
+        Some(StorageError::TreeUpdateTooWide { max: 64, .. })
+    ));
+    assert_eq!(storage.latest_version(), version);
+    assert_eq!(
+        storage.latest_snapshot().get_raw("scattered/0").await?,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn find_substore_prefers_the_longest_prefix() {
+    use crate::store::{multistore::MultistoreConfig, substore::SubstoreConfig};
+    use std::sync::Arc;
+
+    let substores: Vec<_> = ["ibc", "ibc/client", "dex"]
+        .into_iter()
+        .map(|prefix| Arc::new(SubstoreConfig::new(prefix)))
+        .collect();
+    let config = MultistoreConfig::try_new(Arc::new(SubstoreConfig::new("")), substores, true)
+        .expect("the prefixes are valid");
+
+    let (key, substore) = config.route_key_str("ibc/client/07-tendermint-0");
+    assert_eq!(
+        (key, substore.prefix.as_str()),
+        ("07-tendermint-0", "ibc/client")
+    );
+    let (key, substore) = config.route_key_str("ibc/channel/0");
+    assert_eq!((key, substore.prefix.as_str()), ("channel/0", "ibc"));
+    let (key, substore) = config.route_key_str("dex/pair");
+    assert_eq!((key, substore.prefix.as_str()), ("pair", "dex"));
+
+    // Keys matching no prefix fall back to the main store.
+    assert!(config.find_substore(b"governance/proposal").is_none());
+    let (key, substore) = config.route_key_str("governance/proposal");
+    assert_eq!((key, substore.prefix.as_str()), ("governance/proposal", ""));
+    assert_eq!(config.find_substore(b"").unwrap().prefix, "");
+}
+
+#[test]
+fn find_substore_routes_nested_prefixes_to_the_innermost() -> anyhow::Result<()> {
+    use crate::store::{multistore::MultistoreConfig, substore::SubstoreConfig};
+    use std::sync::Arc;
+
+    // Registration order does not matter.
+    let substores: Vec<_> = ["dex/lp", "dex"]
+        .into_iter()
+        .map(|prefix| Arc::new(SubstoreConfig::new(prefix)))
+        .collect();
+    let main_store = Arc::new(SubstoreConfig::new(""));
+    assert!(MultistoreConfig::try_new(main_store.clone(), substores.clone(), false).is_err());
+
+    for substores in [substores.clone(), substores.into_iter().rev().collect()] {
+        let config = MultistoreConfig::try_new(main_store.clone(), substores, true)?;
+        assert_eq!(
+            config.find_substore(b"dex/lp/foo").unwrap().prefix,
+            "dex/lp"
+        );
+        assert_eq!(config.find_substore(b"dex/lp").unwrap().prefix, "dex/lp");
+        assert_eq!(config.find_substore(b"dex/foo").unwrap().prefix, "dex");
+        // A prefix only matches at the delimiter.
+        assert_eq!(config.find_substore(b"dex/lpx").unwrap().prefix, "dex");
+        assert!(config.find_substore(b"dexfoo").is_none());
+
+        let (key, substore) = config.route_key_str("dex/lpx");
+        assert_eq!((key, substore.prefix.as_str()), ("lpx", "dex"));
+        let (key, substore) = config.route_key_str("dex/lp/x");
+        assert_eq!((key, substore.prefix.as_str()), ("x", "dex/lp"));
+        let (key, substore) = config.route_key_str("dexfoo");
+        assert_eq!((key, substore.prefix.as_str()), ("dexfoo", ""));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn nested_prefixes_must_be_allowed_explicitly() -> anyhow::Result<()> {
+    let prefixes = vec!["dex".to_string(), "dex/lp".to_string()];
+
+    let tmpdir = tempfile::tempdir()?;
+    assert!(Storage::load(tmpdir.path().to_owned(), prefixes.clone())
+        .await
+        .is_err());
+
+    let tmpdir = tempfile::tempdir()?;
+    let options = StorageOptions {
+        allow_nested_prefixes: true,
+        ..Default::default()
+    };
+    let storage = Storage::load_with_options(tmpdir.path().to_owned(), prefixes, options).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("dex/lp/position".to_string(), vec![1]);
+    delta.put_raw("dex/pair".to_string(), vec![2]);
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("dex/lp/position").await?, Some(vec![1]));
+    assert_eq!(snapshot.get_raw("dex/pair").await?, Some(vec![2]));
+    let roots = snapshot.substore_roots().await?;
+    assert!(roots.contains_key("dex") && roots.contains_key("dex/lp"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn key_history_lists_every_change() -> anyhow::Result<()> {
+    use futures::TryStreamExt;
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["sub".to_string()]).await?;
+
+    // Version 0: both keys are written.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("key".to_string(), vec![0]);
+    delta.put_raw("sub/key".to_string(), vec![0]);
+    storage.commit(delta).await?;
+    // Version 1: unrelated keys are written, in both stores.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("other".to_string(), vec![1]);
+    delta.put_raw("sub/other".to_string(), vec![1]);
+    storage.commit(delta).await?;
+    // Version 2: both keys change.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("key".to_string(), vec![2]);
+    delta.put_raw("sub/key".to_string(), vec![2]);
+    storage.commit(delta).await?;
+    // Version 3: both keys are deleted.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("key".to_string());
+    delta.delete("sub/key".to_string());
+    storage.commit(delta).await?;
+    // Version 4: only the main store changes.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("other".to_string(), vec![4]);
+    storage.commit(delta).await?;
+
+    let expected = vec![(0, Some(vec![0])), (2, Some(vec![2])), (3, None)];
+    for key in ["key", "sub/key"] {
+        let history: Vec<_> = storage.key_history(key).try_collect().await?;
+        assert_eq!(history, expected, "history of {key}");
+    }
+    let history: Vec<_> = storage.key_history("sub/missing").try_collect().await?;
+    assert!(history.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn multistore_config_rejects_invalid_prefixes() {
+    use crate::store::{multistore::MultistoreConfig, substore::SubstoreConfig};
+    use std::sync::Arc;
+
+    let try_new = |main: &str, prefixes: &[&str]| {
+        let substores = prefixes
+            .iter()
+            .map(|prefix| Arc::new(SubstoreConfig::new(prefix)))
+            .collect();
+        MultistoreConfig::try_new(Arc::new(SubstoreConfig::new(main)), substores, false)
+    };
+
+    assert!(try_new("", &[]).is_ok());
+    assert!(try_new("", &["ibc", "dex"]).is_ok());
+    assert!(try_new("main", &["ibc"]).is_err());
+    assert!(try_new("", &["ibc", ""]).is_err());
+    assert!(try_new("", &["ibc", "dex", "ibc"]).is_err());
+    assert!(try_new("", &["ibc/"]).is_err());
+    // Prefixes are only nested at the delimiter.
+    assert!(try_new("", &["ibc", "ibc/client"]).is_err());
+    assert!(try_new("", &["ibc", "ibcx"]).is_ok());
+
+    // The default config has no substores, and routes everything to the main store.
+    let config = MultistoreConfig::default();
+    let (key, substore) = config.route_key_str("ibc/key");
+    assert_eq!((key, substore.prefix.as_str()), ("ibc/key", ""));
+}
+
+#[tokio::test]
+async fn move_raw_across_substores() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(
+        tmpdir.path().to_owned(),
+        vec!["old".to_string(), "new".to_string()],
+    )
+    .await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("old/record".to_string(), b"committed".to_vec());
+    storage.commit(delta).await?;
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    // A committed value moves across substores.
+    assert!(state.move_raw("old/record", "new/record").await?);
+    // So does a value that was only written in this transaction.
+    state.put_raw("old/pending".to_string(), b"pending".to_vec());
+    assert!(state.move_raw("old/pending", "pending").await?);
+    // Nothing happens when there is nothing to move.
+    assert!(!state.move_raw("old/record", "new/other").await?);
+    assert!(state.move_raw("pending", "pending").await?);
+
+    assert_eq!(state.get_raw("old/record").await?, None);
+    assert_eq!(
+        state.get_raw("new/record").await?,
+        Some(b"committed".to_vec())
+    );
+    storage.commit(state).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("old/record").await?, None);
+    assert_eq!(snapshot.get_raw("old/pending").await?, None);
+    assert_eq!(snapshot.get_raw("new/other").await?, None);
+    assert_eq!(
+        snapshot.get_raw("new/record").await?,
+        Some(b"committed".to_vec())
+    );
+    assert_eq!(
+        snapshot.get_raw("pending").await?,
+        Some(b"pending".to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn root_hash_at_version_matches_commits() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["sub".to_string()]).await?;
+    assert_eq!(storage.root_hash_at_version(0).await?, None);
+
+    let mut roots = Vec::new();
+    for i in 0..3u8 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw(format!("sub/key_{i}"), vec![i]);
+        delta.put_raw(format!("key_{i}"), vec![i]);
+        roots.push(storage.commit(delta).await?.1);
+    }
+    storage.evict_snapshots(1);
+
+    // Roots are read from the database, even for versions no longer in memory.
+    assert!(storage.snapshot(0).is_none());
+    for (version, root) in roots.iter().enumerate() {
+        assert_eq!(
+            storage.root_hash_at_version(version as u64).await?,
+            Some(*root)
+        );
+    }
+    assert_eq!(storage.root_hash_at_version(3).await?, None);
+
+    // The main store holds the root of the substore under its prefix.
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.root_hash().await?, roots[2]);
+    let substore_root = snapshot.prefix_root_hash("sub").await?;
+    assert_eq!(
+        snapshot.get_raw("sub").await?,
+        Some(substore_root.0.to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn state_at_version_reads_past_commits() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(
+        tmpdir.path().to_owned(),
+        vec!["sub".to_string(), "other".to_string()],
+    )
+    .await?;
+
+    // v0 writes to `sub`, v1 leaves it untouched, and `other` is first
+    // written at v1.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"0".to_vec());
+    delta.put_raw("sub/x".to_string(), b"0".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"1".to_vec());
+    delta.put_raw("other/y".to_string(), b"1".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("a".to_string());
+    delta.put_raw("sub/x".to_string(), b"2".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("other/y".to_string(), b"3".to_vec());
+    storage.commit(delta).await?;
+    storage.evict_snapshots(1);
+    assert!(storage.snapshot(1).is_none());
+
+    let state = storage.state_at_version(0).await?;
+    assert_eq!(state.version(), 0);
+    assert_eq!(state.get_raw("a").await?, Some(b"0".to_vec()));
+    assert_eq!(state.get_raw("sub/x").await?, Some(b"0".to_vec()));
+    assert_eq!(state.get_raw("other/y").await?, None);
+
+    let state = storage.state_at_version(1).await?;
+    assert_eq!(state.get_raw("a").await?, Some(b"1".to_vec()));
+    assert_eq!(state.get_raw("sub/x").await?, Some(b"0".to_vec()));
+    assert_eq!(state.get_raw("other/y").await?, Some(b"1".to_vec()));
+    assert_eq!(
+        state.root_hash().await?,
+        storage.root_hash_at_version(1).await?.unwrap()
+    );
+
+    let state = storage.state_at_version(2).await?;
+    assert_eq!(state.get_raw("a").await?, None);
+    assert_eq!(state.get_raw("sub/x").await?, Some(b"2".to_vec()));
+    assert_eq!(state.get_raw("other/y").await?, Some(b"1".to_vec()));
+
+    // The key preimage index and the nonverifiable store are not versioned.
+    let scan: anyhow::Result<Vec<_>> = state
+        .prefix_raw("sub/")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect();
+    assert!(matches!(
+        scan.unwrap_err().downcast_ref::<StorageError>(),
+        Some(StorageError::UnversionedRead { version: 2 })
+    ));
+    assert!(state.nonverifiable_get_raw(b"n").await.is_err());
+
+    // The latest version is still in memory, so it supports every read.
+    let state = storage.state_at_version(3).await?;
+    let scan: Vec<_> = state.prefix_raw("other/").collect::<Vec<_>>().await;
+    assert_eq!(scan.len(), 1);
+    assert_eq!(scan[0].as_ref().unwrap().1, b"3".to_vec());
+
+    assert!(storage.state_at_version(4).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rollback_plan_restores_past_keyspace() -> anyhow::Result<()> {
+    use futures::TryStreamExt;
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["sub".to_string()]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"a0".to_vec());
+    delta.put_raw("b".to_string(), b"b0".to_vec());
+    delta.put_raw("sub/x".to_string(), b"x0".to_vec());
+    delta.nonverifiable_put_raw(b"n".to_vec(), b"n0".to_vec());
+    storage.commit(delta).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"a1".to_vec());
+    delta.put_raw("sub/y".to_string(), b"y1".to_vec());
+    let (v1, v1_root) = storage.commit(delta).await?;
+    let v1_snapshot = storage.latest_snapshot();
+
+    // v2 and v3 overwrite, delete, and add keys, and restore one to its v1 value.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"a2".to_vec());
+    delta.delete("b".to_string());
+    delta.delete("sub/x".to_string());
+    delta.put_raw("c".to_string(), b"c2".to_vec());
+    delta.nonverifiable_put_raw(b"n".to_vec(), b"n2".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"a1".to_vec());
+    delta.put_raw("sub/z".to_string(), b"z3".to_vec());
+    delta.nonverifiable_put_raw(b"m".to_vec(), b"m3".to_vec());
+    storage.commit(delta).await?;
+
+    let plan = storage.rollback_plan(v1).await?;
+    assert_eq!(
+        plan,
+        vec![
+            OverlayOp::Put {
+                key: "b".to_string(),
+                value: b"b0".to_vec()
+            },
+            OverlayOp::Delete {
+                key: "c".to_string()
+            },
+            OverlayOp::Put {
+                key: "sub/x".to_string(),
+                value: b"x0".to_vec()
+            },
+            OverlayOp::Delete {
+                key: "sub/z".to_string()
+            },
+            OverlayOp::NonverifiableDelete { key: b"m".to_vec() },
+            OverlayOp::NonverifiablePut {
+                key: b"n".to_vec(),
+                value: b"n0".to_vec()
+            },
+        ]
+    );
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.replay_ops(&plan);
+    assert_eq!(storage.commit(delta).await?.1, v1_root);
+
+    let snapshot = storage.latest_snapshot();
+    for prefix in ["", "sub/"] {
+        let expected: Vec<_> = v1_snapshot.prefix_raw(prefix).try_collect().await?;
+        let actual: Vec<_> = snapshot.prefix_raw(prefix).try_collect().await?;
+        assert_eq!(actual, expected);
+    }
+    let expected: Vec<_> = v1_snapshot
+        .nonverifiable_prefix_raw(b"")
+        .try_collect()
+        .await?;
+    let actual: Vec<_> = snapshot.nonverifiable_prefix_raw(b"").try_collect().await?;
+    assert_eq!(actual, expected);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rollback_plan_reads_evicted_versions_from_disk() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let options = StorageOptions {
+        change_log_retention: Some(1),
+        ..Default::default()
+    };
+    let storage =
+        Storage::load_with_options(tmpdir.path().to_owned(), vec!["sub".to_string()], options)
+            .await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"a0".to_vec());
+    delta.put_raw("sub/x".to_string(), b"x0".to_vec());
+    delta.nonverifiable_put_raw(b"n".to_vec(), b"n0".to_vec());
+    let (v0, v0_root) = storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a".to_string(), b"a1".to_vec());
+    delta.put_raw("sub/y".to_string(), b"y1".to_vec());
+    delta.nonverifiable_put_raw(b"n".to_vec(), b"n1".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("sub/x".to_string(), b"x2".to_vec());
+    storage.commit(delta).await?;
+    storage.evict_snapshots(1);
+
+    // The changes of v1 are no longer retained, so its nonverifiable write is
+    // left out of the plan.
+    let plan = storage.rollback_plan(v0).await?;
+    assert_eq!(
+        plan,
+        vec![
+            OverlayOp::Put {
+                key: "a".to_string(),
+                value: b"a0".to_vec()
+            },
+            OverlayOp::Put {
+                key: "sub/x".to_string(),
+                value: b"x0".to_vec()
+            },
+            OverlayOp::Delete {
+                key: "sub/y".to_string()
+            },
+        ]
+    );
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.replay_ops(&plan);
+    let (v3, root) = storage.commit(delta).await?;
+    assert_eq!(root, v0_root);
+
+    // A key deleted by a commit that is no longer retained can't be restored.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("a".to_string());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("b".to_string(), b"b5".to_vec());
+    storage.commit(delta).await?;
+    assert!(storage.rollback_plan(v3).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn prune_deletes_old_versions() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["sub".to_string()]).await?;
+
+    // Every version overwrites a key of each store, and only the first ones
+    // write to the substore.
+    let mut roots = Vec::new();
+    for i in 0..6u8 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw("a".to_string(), vec![i]);
+        delta.put_raw(format!("key_{i}"), vec![i]);
+        if i < 3 {
+            delta.put_raw("sub/x".to_string(), vec![i]);
+        }
+        roots.push(storage.commit(delta).await?.1);
+    }
+    assert_eq!(storage.oldest_available_version()?, None);
+    assert!(storage.check_integrity().await?.orphaned_nodes > 0);
+
+    storage.prune(3).await?;
+    assert_eq!(storage.oldest_available_version()?, Some(3));
+    assert!(storage.snapshot(2).is_none());
+
+    // The stale nodes are gone, and the retained trees are intact.
+    let report = storage.check_integrity().await?;
+    assert!(report.is_healthy());
+    assert_eq!(report.orphaned_nodes, 0);
+
+    let error = storage.state_at_version(2).await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<StorageError>(),
+        Some(StorageError::VersionPruned {
+            version: 2,
+            oldest: 3
+        })
+    ));
+
+    for version in 3..6u64 {
+        let state = storage.state_at_version(version).await?;
+        assert_eq!(state.get_raw("a").await?, Some(vec![version as u8]));
+        assert_eq!(state.get_raw("key_0").await?, Some(vec![0]));
+        assert_eq!(state.get_raw("sub/x").await?, Some(vec![2]));
+        assert_eq!(state.root_hash().await?, roots[version as usize]);
+
+        let snapshot = storage.snapshot(version).expect("version is retained");
+        for key in ["a", "key_1", "sub/x"] {
+            let (value, proof) = snapshot.get_with_jmt_proof(key.as_bytes().to_vec()).await?;
+            proof.verify(roots[version as usize], key.as_bytes(), value.as_deref())?;
+        }
+    }
+
+    // Pruning is idempotent.
+    storage.prune(3).await?;
+    assert_eq!(storage.oldest_available_version()?, Some(3));
+
+    Ok(())
+}
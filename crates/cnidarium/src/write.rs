@@ -1,4 +1,7 @@
-use crate::StateRead;
+use crate::{OverlayOp, StateRead, StorageError};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
 use std::{any::Any, collections::BTreeMap};
 use tendermint::abci;
 
@@ -10,6 +13,16 @@ pub trait StateWrite: StateRead + Send + Sync {
     /// Delete a key from the verifiable key-value store.
     fn delete(&mut self, key: String);
 
+    /// Delete every key under `prefix` from the verifiable key-value store.
+    ///
+    /// The deletion is recorded as a single change, without scanning the keys:
+    /// reads in this state see every key under `prefix` as gone, and a key
+    /// written under `prefix` afterwards is present again. The keys are only
+    /// listed when the changes are committed, from the substore that `prefix`
+    /// routes to and from the substores nested under it, so `prefix` should
+    /// either lie within a substore or cover whole substores.
+    fn prefix_delete(&mut self, prefix: String);
+
     /// Puts raw bytes into the non-verifiable key-value store with the given key.
     fn nonverifiable_put_raw(&mut self, key: Vec<u8>, value: Vec<u8>);
 
@@ -45,6 +58,10 @@ impl<'a, S: StateWrite + Send + Sync> StateWrite for &'a mut S {
         (**self).delete(key)
     }
 
+    fn prefix_delete(&mut self, prefix: String) {
+        (**self).prefix_delete(prefix)
+    }
+
     fn nonverifiable_delete(&mut self, key: Vec<u8>) {
         (**self).nonverifiable_delete(key)
     }
@@ -72,3 +89,216 @@ impl<'a, S: StateWrite + Send + Sync> StateWrite for &'a mut S {
         (**self).record(event)
     }
 }
+
+/// Extension trait providing higher-level write helpers on top of [`StateWrite`].
+#[async_trait]
+pub trait StateWriteExt: StateWrite {
+    /// Allocates the next identifier from the `u64` counter stored at `key`.
+    ///
+    /// Reads the current value of the counter (or `0` if it is missing),
+    /// returns it, and writes back the incremented value, so consecutive calls
+    /// on the same state return consecutive identifiers.
+    ///
+    /// # Errors
+    /// Returns [`StorageError::CounterOverflow`] if the counter is at `u64::MAX`,
+    /// rather than wrapping around, and an error if the stored value is not a
+    /// big-endian `u64`.
+    async fn next_id(&mut self, key: &str) -> Result<u64> {
+        let current = match self.get_raw(key).await? {
+            Some(bytes) => {
+                let bytes: [u8; 8] = bytes.as_slice().try_into().map_err(|_| {
+                    anyhow::anyhow!(
+                        "counter at {key} is not a u64 (found {} bytes)",
+                        bytes.len()
+                    )
+                })?;
+                u64::from_be_bytes(bytes)
+            }
+            None => 0,
+        };
+
+        let next = current
+            .checked_add(1)
+            .ok_or_else(|| StorageError::CounterOverflow {
+                key: key.to_string(),
+            })?;
+        self.put_raw(key.to_string(), next.to_be_bytes().to_vec());
+        Ok(current)
+    }
+
+    /// Deletes every verifiable key in the range `[start, end)` that is visible
+    /// in this state, returning the number of keys deleted.
+    ///
+    /// The keys are found by scanning the longest common prefix of `start` and
+    /// `end`, so both bounds should lie in the same substore, and the range
+    /// should be narrow enough for the scan to be cheap. Each key is deleted
+    /// individually, since every deletion must be reflected in the tree.
+    async fn delete_range(&mut self, start: &str, end: &str) -> Result<u64> {
+        anyhow::ensure!(
+            start <= end,
+            "invalid range: start ({start}) is greater than end ({end})"
+        );
+
+        let prefix_len = start
+            .char_indices()
+            .zip(end.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or_else(|| start.len().min(end.len()));
+        let prefix = &start[..prefix_len];
+
+        let mut keys = Vec::new();
+        let mut stream = std::pin::pin!(self.prefix_keys(prefix));
+        while let Some(key) = stream.next().await {
+            let key = key?;
+            if key.as_str() >= end {
+                break;
+            }
+            if key.as_str() >= start {
+                keys.push(key);
+            }
+        }
+
+        let count = keys.len() as u64;
+        for key in keys {
+            self.delete(key);
+        }
+        Ok(count)
+    }
+
+    /// Runs `init` on this state only if no verifiable key matches `prefix`,
+    /// returning whether it ran.
+    ///
+    /// This packages the common first-run pattern of a component that
+    /// initializes its defaults when its subtree is empty. Emptiness is checked
+    /// with [`StateReadExt::prefix_is_empty`](crate::StateReadExt::prefix_is_empty),
+    /// so writes and deletions pending in this state are taken into account.
+    async fn init_if_empty<F>(&mut self, prefix: &str, init: F) -> Result<bool>
+    where
+        F: FnOnce(&mut Self) + Send,
+    {
+        use crate::StateReadExt as _;
+
+        if !self.prefix_is_empty(prefix).await? {
+            return Ok(false);
+        }
+        init(self);
+        Ok(true)
+    }
+
+    /// Rewrites every verifiable value under `prefix` that is visible in this
+    /// state with `f`, returning the number of values processed.
+    ///
+    /// `f` is called with each key and its value, in key order, and returns
+    /// the new value to write, or `None` to delete the key. The entries are
+    /// all read before any of them is rewritten, so `f` sees each value as it
+    /// was before the call, exactly once, even if it rewrites keys that the
+    /// scan has not reached yet. The whole prefix is therefore held in memory.
+    async fn map_prefix<F>(&mut self, prefix: &str, mut f: F) -> Result<u64>
+    where
+        F: FnMut(&str, Vec<u8>) -> Option<Vec<u8>> + Send,
+    {
+        let entries: Vec<(String, Vec<u8>)> = self
+            .prefix_raw(prefix)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_>>()?;
+
+        let count = entries.len() as u64;
+        for (key, value) in entries {
+            match f(&key, value) {
+                Some(value) => self.put_raw(key, value),
+                None => self.delete(key),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Moves the verifiable value at `from` to `to`, returning whether there
+    /// was a value to move.
+    ///
+    /// The value is read from this state, so pending writes are taken into
+    /// account, and the two keys may route to different substores. The write
+    /// at `to` and the deletion of `from` are only applied once the value has
+    /// been read, so either both of them happen, or neither does. Any value at
+    /// `to` is overwritten. Moving a key to itself leaves it unchanged.
+    async fn move_raw(&mut self, from: &str, to: &str) -> Result<bool> {
+        let Some(value) = self.get_raw(from).await? else {
+            return Ok(false);
+        };
+        if from != to {
+            self.put_raw(to.to_string(), value);
+            self.delete(from.to_string());
+        }
+        Ok(true)
+    }
+
+    /// Puts an object into the ephemeral object store, and also writes its
+    /// borsh encoding to the non-verifiable store under the same key.
+    ///
+    /// Within this state, the object is served from memory by
+    /// [`StateRead::object_get`], without any decoding. The encoded copy is
+    /// applied and committed along with the rest of the non-verifiable writes,
+    /// so it outlives the ephemeral copy, and can be read back with
+    /// [`StateReadExt::object_get_persistent`](crate::StateReadExt::object_get_persistent).
+    ///
+    /// # Panics
+    ///
+    /// As for [`StateWrite::object_put`].
+    fn object_put_persistent<T>(&mut self, key: &'static str, value: T) -> Result<()>
+    where
+        T: borsh::BorshSerialize + Clone + Any + Send + Sync,
+    {
+        let bytes = borsh::to_vec(&value)?;
+        self.object_put(key, value);
+        self.nonverifiable_put_raw(key.as_bytes().to_vec(), bytes);
+        Ok(())
+    }
+
+    /// Deletes an object written by [`StateWriteExt::object_put_persistent`],
+    /// from both the ephemeral object store and the non-verifiable store.
+    fn object_delete_persistent(&mut self, key: &'static str) {
+        self.object_delete(key);
+        self.nonverifiable_delete(key.as_bytes().to_vec());
+    }
+
+    /// Appends `value` to the list stored under `list_key`, returning the index
+    /// of the new element.
+    ///
+    /// Each element is stored under its own key, `{list_key}/{index:020}`, and
+    /// the length of the list is stored under `list_key` as a counter (see
+    /// [`StateWriteExt::next_id`]), so appending does not read the existing
+    /// elements. The elements can be read back in order with
+    /// [`StateReadExt::list_iter`](crate::StateReadExt::list_iter).
+    async fn list_append(&mut self, list_key: &str, value: Vec<u8>) -> Result<u64> {
+        let index = self.next_id(list_key).await?;
+        self.put_raw(crate::read::list_element_key(list_key, index), value);
+        Ok(index)
+    }
+
+    /// Puts `value` in the nonverifiable store at `index` under `prefix`, to be
+    /// scanned by index with
+    /// [`StateReadExt::nonverifiable_range_by_index`](crate::StateReadExt::nonverifiable_range_by_index).
+    fn nonverifiable_put_by_index(&mut self, prefix: &str, index: u64, value: Vec<u8>) {
+        self.nonverifiable_put_raw(crate::read::index_key(prefix, index), value);
+    }
+
+    /// Applies `ops`, as recorded by [`StateDelta::overlay_ops`](crate::StateDelta::overlay_ops),
+    /// in order.
+    fn replay_ops(&mut self, ops: &[OverlayOp]) {
+        for op in ops {
+            match op {
+                OverlayOp::Put { key, value } => self.put_raw(key.clone(), value.clone()),
+                OverlayOp::Delete { key } => self.delete(key.clone()),
+                OverlayOp::PrefixDelete { prefix } => self.prefix_delete(prefix.clone()),
+                OverlayOp::NonverifiablePut { key, value } => {
+                    self.nonverifiable_put_raw(key.clone(), value.clone())
+                }
+                OverlayOp::NonverifiableDelete { key } => self.nonverifiable_delete(key.clone()),
+            }
+        }
+    }
+}
+
+impl<T: StateWrite + ?Sized> StateWriteExt for T {}
@@ -1,7 +1,6 @@
 use std::sync::Arc;
 
-// HashMap is okay here because we don't care about ordering of substore roots.
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use crate::{
     cache::Cache,
@@ -22,14 +21,18 @@ pub struct StagedWriteBatch {
     /// The root hash of the chain state corresponding to this set of changes.
     pub(crate) root_hash: RootHash,
     /// The configs, root hashes, and new versions of each substore
-    /// that was updated in this batch.
-    #[allow(clippy::disallowed_types)]
-    pub(crate) substore_roots: HashMap<Arc<SubstoreConfig>, (RootHash, u64)>,
+    /// that was updated in this batch, in ascending prefix order.
+    pub(crate) substore_roots: BTreeMap<Arc<SubstoreConfig>, (RootHash, u64)>,
     /// Whether or not to perform a migration.
     pub(crate) perform_migration: bool,
     /// A lightweight copy of the changeset, this is useful to provide
     /// a stream of changes to subscribers.
     pub(crate) changes: Arc<Cache>,
+    /// The number of internal tree nodes written, across all substores.
+    pub(crate) internal_nodes: usize,
+    /// How long it took to prepare this batch, reported as part of the commit
+    /// duration.
+    pub(crate) prepare_duration: std::time::Duration,
 }
 
 impl StagedWriteBatch {
@@ -43,6 +46,13 @@ impl StagedWriteBatch {
         &self.root_hash
     }
 
+    /// Returns the number of internal tree nodes written by this set of
+    /// changes, across all substores, which measures how widely it fans out
+    /// across the trees.
+    pub fn internal_nodes(&self) -> usize {
+        self.internal_nodes
+    }
+
     /// Returns the version of a substore in this batch, if it exists
     /// and `None` otherwise.
     pub fn substore_version(&self, prefix: &str) -> Option<jmt::Version> {
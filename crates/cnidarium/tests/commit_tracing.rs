@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use cnidarium::{StateDelta, StateWrite, Storage};
+use tracing::Subscriber;
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    Layer,
+};
+
+/// A layer that records the target of each event, along with the names of
+/// the spans it was recorded under, from innermost to outermost.
+#[derive(Clone, Default)]
+struct RecordScopes {
+    events: Arc<Mutex<Vec<(String, Vec<String>)>>>,
+}
+
+impl<S> Layer<S> for RecordScopes
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        let scope = ctx
+            .event_scope(event)
+            .map(|scope| scope.map(|span| span.name().to_string()).collect())
+            .unwrap_or_default();
+        self.events
+            .lock()
+            .expect("lock is not poisoned")
+            .push((event.metadata().target().to_string(), scope));
+    }
+}
+
+#[tokio::test]
+/// Test that the work performed by a commit on blocking threads is recorded
+/// under the span supplied by the caller.
+async fn test_commit_with_span() -> Result<()> {
+    let layer = RecordScopes::default();
+    // This needs to be the global default, since the commit emits events from
+    // blocking threads.
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer.clone()))?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["ibc".to_string()]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/aa".to_string(), b"aa".to_vec());
+    delta.put_raw("ibc/client".to_string(), b"client".to_vec());
+
+    // Drive the commit from a task that is not instrumented with the block span.
+    let block_span = tracing::info_span!("process_block", height = 0);
+    tokio::spawn({
+        let storage = storage.clone();
+        async move { storage.commit_with_span(delta, block_span).await }
+    })
+    .await??;
+
+    let events = layer.events.lock().expect("lock is not poisoned").clone();
+    let substore_events: Vec<_> = events
+        .iter()
+        .filter(|(target, _)| target.starts_with("cnidarium::store::substore"))
+        .collect();
+    assert!(
+        !substore_events.is_empty(),
+        "the commit should record events from the substores"
+    );
+    for (_, scope) in substore_events {
+        assert_eq!(
+            scope.iter().rev().take(2).collect::<Vec<_>>(),
+            vec!["process_block", "commit"],
+            "substore events should be recorded under the caller's span"
+        );
+    }
+
+    Ok(())
+}
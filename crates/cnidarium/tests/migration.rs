@@ -87,7 +87,7 @@ async fn test_simple_migration() -> anyhow::Result<()> {
         let key = format!("key_{i}");
         let value = format!("value_{i}").as_bytes().to_vec();
         delta.put_raw(key.clone(), value.clone());
-        let root_hash = storage.commit(delta).await?;
+        let (_, root_hash) = storage.commit(delta).await?;
 
         tracing::info!(%key, ?root_hash, version = %i, "committed key-value pair");
 
@@ -202,7 +202,7 @@ async fn test_simple_migration() -> anyhow::Result<()> {
         let key = format!("key_{i}");
         let value = format!("value_{i}").as_bytes().to_vec();
         delta.put_raw(key.clone(), value.clone());
-        let root_hash = storage.commit(delta).await?;
+        let (_, root_hash) = storage.commit(delta).await?;
 
         tracing::info!(%key, ?root_hash, version = %i, "committed key-value pair");
 
@@ -302,7 +302,7 @@ async fn test_substore_migration() -> anyhow::Result<()> {
             delta.put_raw(key.clone(), value.clone());
         }
 
-        let root_hash = storage.commit(delta).await?;
+        let (_, root_hash) = storage.commit(delta).await?;
         tracing::info!(?root_hash, version = %i, "committed key-value pair");
         counter += 1;
     }
@@ -560,7 +560,7 @@ async fn test_substore_migration() -> anyhow::Result<()> {
             delta.put_raw(key.clone(), value.clone());
         }
 
-        let root_hash = storage.commit(delta).await?;
+        let (_, root_hash) = storage.commit(delta).await?;
         tracing::info!(?root_hash, version = %i, "committed key-value pair");
         counter += 1;
     }
@@ -894,7 +894,7 @@ mod proptests {
             premigration_transcript,
         );
 
-        let premigration_root_hash = storage
+        let (_, premigration_root_hash) = storage
             .commit(premigration_delta)
             .await
             .expect("can commit premigration");
@@ -973,7 +973,7 @@ mod proptests {
             postmigration_transcript,
         );
 
-        let postmigration_root_hash = storage
+        let (_, postmigration_root_hash) = storage
             .commit(postmigration_delta)
             .await
             .expect("can commit postmigration");
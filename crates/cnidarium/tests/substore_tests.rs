@@ -154,7 +154,7 @@ async fn test_substore_simple() -> anyhow::Result<()> {
     delta.put_raw(key_root_1.clone(), value_root_1.clone());
 
     tracing::debug!("committing first batch of writes");
-    let global_root_hash_1 = storage.commit(delta).await?;
+    let (_, global_root_hash_1) = storage.commit(delta).await?;
 
     tracing::debug!("checking that we can read the values back out");
     // Check that we can read the values back out.
@@ -191,7 +191,7 @@ async fn test_substore_simple() -> anyhow::Result<()> {
     let value_a_2 = "value_2a".as_bytes().to_vec();
     let mut delta = StateDelta::new(storage.latest_snapshot());
     delta.put_raw(key_a_2.clone(), value_a_2.clone());
-    let global_root_hash_2 = storage.commit(delta).await?;
+    let (_, global_root_hash_2) = storage.commit(delta).await?;
 
     // CHeck that we can read the new value back out.
     let snapshot = storage.latest_snapshot();
@@ -258,7 +258,7 @@ async fn test_substore_prefix_queries() -> anyhow::Result<()> {
         kv_main.push((key_i, value_i));
     }
 
-    let _ = storage.commit(delta).await?;
+    let (_, _) = storage.commit(delta).await?;
 
     let snapshot = storage.latest_snapshot();
     let mut counter = 0;
@@ -370,7 +370,7 @@ async fn test_substore_prefix_keys() -> anyhow::Result<()> {
         kv_main.push((key_i, value_i));
     }
 
-    let _ = storage.commit(delta).await?;
+    let (_, _) = storage.commit(delta).await?;
 
     let snapshot = storage.latest_snapshot();
     let mut counter = 0;
@@ -483,7 +483,7 @@ async fn test_substore_nv_prefix() -> anyhow::Result<()> {
         kv_main.push((key_i, value_i));
     }
 
-    let _ = storage.commit(delta).await?;
+    let (_, _) = storage.commit(delta).await?;
 
     let snapshot = storage.latest_snapshot();
     let mut counter = 0;
@@ -618,7 +618,7 @@ async fn test_substore_nv_range_queries_main_store() -> anyhow::Result<()> {
         kv_d.push((key_d_i, value_d_i));
     }
 
-    let _ = storage.commit(delta).await?;
+    let (_, _) = storage.commit(delta).await?;
 
     let snapshot = storage.latest_snapshot();
 
@@ -706,7 +706,7 @@ async fn reproduction_bad_substore_cache_range() -> anyhow::Result<()> {
         substore_kvs.push(k)
     }
 
-    let _ = storage.commit(delta).await?;
+    let (_, _) = storage.commit(delta).await?;
     let snapshot = storage.latest_snapshot();
 
     // We can prefix range fine on a static snapshot.
@@ -749,3 +749,1385 @@ async fn reproduction_bad_substore_cache_range() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+/// Test that substores configured with different compression codecs read back
+/// their data correctly, including after the storage is reloaded.
+async fn test_substore_compression() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes: Vec<String> = vec!["ibc".to_string(), "nullifier".to_string()];
+
+    let mut options = cnidarium::StorageOptions::default();
+    options
+        .substore_compression
+        .insert("ibc".to_string(), cnidarium::Compression::Zstd);
+    options
+        .substore_compression
+        .insert("nullifier".to_string(), cnidarium::Compression::None);
+
+    let storage =
+        Storage::load_with_options(db_path.clone(), substore_prefixes.clone(), options.clone())
+            .await?;
+
+    let client_state = "{\"chain_id\":\"penumbra\"}".repeat(64).into_bytes();
+    let nullifier = [7u8; 32].to_vec();
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("ibc/client/1".to_string(), client_state.clone());
+    delta.put_raw("nullifier/nf/1".to_string(), nullifier.clone());
+    delta.nonverifiable_put_raw(b"ibc/index".to_vec(), client_state.clone());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(
+        snapshot.get_raw("ibc/client/1").await?,
+        Some(client_state.clone())
+    );
+    assert_eq!(
+        snapshot.get_raw("nullifier/nf/1").await?,
+        Some(nullifier.clone())
+    );
+    assert_eq!(
+        snapshot.nonverifiable_get_raw(b"ibc/index").await?,
+        Some(client_state.clone())
+    );
+    std::mem::drop(snapshot);
+    storage.release().await;
+
+    // Reload the storage, so that the data is read back from disk.
+    let storage = Storage::load_with_options(db_path, substore_prefixes, options).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("ibc/client/1").await?, Some(client_state));
+    assert_eq!(snapshot.get_raw("nullifier/nf/1").await?, Some(nullifier));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that keys are paired with the root of the substore they are routed to.
+async fn test_substore_root_for_key() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes = vec!["a", "b"].into_iter().map(|s| s.to_string()).collect();
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("a/key".to_string(), b"value_a".to_vec());
+    delta.put_raw("b/key".to_string(), b"value_b".to_vec());
+    delta.put_raw("main/key".to_string(), b"value_main".to_vec());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+
+    let (prefix, root) = snapshot.substore_root_for_key("a/key").await?;
+    assert_eq!(prefix, "a");
+    assert_eq!(root, snapshot.prefix_root_hash("a").await?);
+    assert_ne!(root, snapshot.prefix_root_hash("b").await?);
+    // The main store commits to the substore root under the substore prefix.
+    assert_eq!(snapshot.get_raw("a").await?, Some(root.0.to_vec()));
+
+    for key in ["main/key", "akey", "a"] {
+        let (prefix, root) = snapshot.substore_root_for_key(key).await?;
+        assert_eq!(prefix, "", "{key} should be routed to the main store");
+        assert_eq!(root, snapshot.root_hash().await?);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that the live key count of each substore tracks insertions and deletions.
+async fn test_substore_key_counts() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes = vec!["a", "b", "c"]
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..3 {
+        delta.put_raw(format!("a/key_{i}"), b"value".to_vec());
+    }
+    for i in 0..5 {
+        delta.put_raw(format!("b/key_{i}"), b"value".to_vec());
+    }
+    delta.put_raw("main/key_0".to_string(), b"value".to_vec());
+    delta.put_raw("main/key_1".to_string(), b"value".to_vec());
+    storage.commit(delta).await?;
+
+    let counts = storage.latest_snapshot().substore_key_counts().await?;
+    assert_eq!(counts.get("a"), Some(&3));
+    assert_eq!(counts.get("b"), Some(&5));
+    assert_eq!(counts.get("c"), Some(&0));
+    // The main store also holds the roots of substores `a` and `b`.
+    assert_eq!(counts.get(""), Some(&4));
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.delete("a/key_0".to_string());
+    delta.put_raw("a/key_1".to_string(), b"updated".to_vec());
+    storage.commit(delta).await?;
+
+    let counts = storage.latest_snapshot().substore_key_counts().await?;
+    assert_eq!(counts.get("a"), Some(&2));
+    assert_eq!(counts.get("b"), Some(&5));
+    assert_eq!(counts.get(""), Some(&4));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that a nonverifiable TTL configures the column family for expiry,
+/// without affecting reads of fresh entries.
+async fn test_substore_nonverifiable_ttl() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes: Vec<String> = vec!["cache".to_string(), "ibc".to_string()];
+
+    let mut options = cnidarium::StorageOptions::default();
+    options
+        .nonverifiable_ttl
+        .insert("cache".to_string(), std::time::Duration::from_secs(3600));
+    let storage = Storage::load_with_options(db_path.clone(), substore_prefixes, options).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.nonverifiable_put_raw(b"cache/entry".to_vec(), b"fresh".to_vec());
+    delta.nonverifiable_put_raw(b"ibc/entry".to_vec(), b"durable".to_vec());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(
+        snapshot.nonverifiable_get_raw(b"cache/entry").await?,
+        Some(b"fresh".to_vec())
+    );
+    assert_eq!(
+        snapshot.nonverifiable_get_raw(b"ibc/entry").await?,
+        Some(b"durable".to_vec())
+    );
+    std::mem::drop(snapshot);
+    storage.release().await;
+
+    // RocksDB records the options of every column family in an OPTIONS file.
+    let options_file = std::fs::read_dir(&db_path)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("OPTIONS-"))
+        })
+        .max()
+        .expect("rocksdb writes an OPTIONS file");
+    let recorded = std::fs::read_to_string(options_file)?;
+    let cf_options = |cf: &str| -> String {
+        let header = format!("[CFOptions \"{cf}\"]");
+        let start = recorded.find(&header).expect("column family is recorded");
+        let section = &recorded[start + header.len()..];
+        let end = section.find("\n[").unwrap_or(section.len());
+        section[..end].to_string()
+    };
+    let has_option =
+        |section: &str, option: &str| section.lines().any(|line| line.trim() == option);
+
+    let cache = cf_options("substore-cache-nonverifiable");
+    assert!(has_option(&cache, "ttl=3600"));
+    assert!(has_option(&cache, "compaction_style=kCompactionStyleFIFO"));
+    let ibc = cf_options("substore-ibc-nonverifiable");
+    assert!(!has_option(&ibc, "compaction_style=kCompactionStyleFIFO"));
+    let cache_jmt = cf_options("substore-cache-jmt");
+    assert!(!has_option(
+        &cache_jmt,
+        "compaction_style=kCompactionStyleFIFO"
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that batched membership checks account for cached writes and deletions,
+/// across substores.
+async fn test_substore_contains_keys() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes = vec!["nullifier", "ibc"]
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("nullifier/committed".to_string(), vec![1]);
+    delta.put_raw("nullifier/deleted".to_string(), vec![1]);
+    delta.put_raw("ibc/committed".to_string(), vec![1]);
+    delta.put_raw("main".to_string(), vec![1]);
+    storage.commit(delta).await?;
+
+    let keys: Vec<String> = [
+        "nullifier/committed",
+        "nullifier/absent",
+        "nullifier/written",
+        "nullifier/deleted",
+        "ibc/committed",
+        "ibc/absent",
+        "main",
+        "nullifier/committed",
+    ]
+    .into_iter()
+    .map(|s| s.to_string())
+    .collect();
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(
+        snapshot.contains_keys(&keys).await?,
+        vec![true, false, false, true, true, false, true, true]
+    );
+
+    let mut state = StateDelta::new(snapshot);
+    state.put_raw("nullifier/written".to_string(), vec![1]);
+    let mut tx = state.fork();
+    tx.delete("nullifier/deleted".to_string());
+    assert_eq!(
+        tx.contains_keys(&keys).await?,
+        vec![true, false, true, false, true, false, true, true]
+    );
+    assert!(tx.contains_keys(&[]).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that batched reads return values aligned with the requested keys,
+/// accounting for cached writes and deletions, across substores.
+async fn test_substore_multi_get_raw() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes = vec!["nullifier", "ibc"]
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("nullifier/committed".to_string(), vec![1]);
+    delta.put_raw("nullifier/deleted".to_string(), vec![2]);
+    delta.put_raw("ibc/committed".to_string(), vec![3]);
+    delta.put_raw("main".to_string(), vec![4]);
+    storage.commit(delta).await?;
+
+    let keys: Vec<String> = [
+        "ibc/committed",
+        "nullifier/absent",
+        "nullifier/written",
+        "nullifier/deleted",
+        "main",
+        "nullifier/committed",
+        "ibc/committed",
+    ]
+    .into_iter()
+    .map(|s| s.to_string())
+    .collect();
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(
+        snapshot.multi_get_raw(&keys).await?,
+        vec![
+            Some(vec![3]),
+            None,
+            None,
+            Some(vec![2]),
+            Some(vec![4]),
+            Some(vec![1]),
+            Some(vec![3])
+        ]
+    );
+
+    let mut state = StateDelta::new(snapshot);
+    state.put_raw("nullifier/written".to_string(), vec![5]);
+    state.put_raw("main".to_string(), vec![6]);
+    let mut tx = state.fork();
+    tx.delete("nullifier/deleted".to_string());
+    assert_eq!(
+        tx.multi_get_raw(&keys).await?,
+        vec![
+            Some(vec![3]),
+            None,
+            Some(vec![5]),
+            None,
+            Some(vec![6]),
+            Some(vec![1]),
+            Some(vec![3])
+        ]
+    );
+    assert!(tx.multi_get_raw(&[]).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that proofs generated through a small node cache, which evicts nodes
+/// while proofs are generated, verify against the substore roots.
+async fn test_substore_proofs_with_node_cache() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes = vec!["ibc".to_string()];
+    let options = cnidarium::StorageOptions {
+        node_cache_capacity: 16,
+        ..Default::default()
+    };
+    let storage = Storage::load_with_options(db_path, substore_prefixes, options).await?;
+
+    pub static PENUMBRA_PROOF_SPECS: Lazy<Vec<ics23::ProofSpec>> =
+        Lazy::new(|| vec![cnidarium::ics23_spec(), cnidarium::ics23_spec()]);
+
+    // Spread the keys over several versions, and overwrite some of them, so
+    // that proofs traverse nodes written at different versions.
+    for version in 0..4u64 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        for i in 0..32u64 {
+            if i % 4 == version || (version == 3 && i % 2 == 0) {
+                delta.put_raw(
+                    format!("ibc/key_{i}"),
+                    format!("{i}@{version}").into_bytes(),
+                );
+            }
+        }
+        storage.commit(delta).await?;
+    }
+
+    let old_snapshot = storage.snapshot(1).expect("version 1 is still cached");
+    for snapshot in [storage.latest_snapshot(), old_snapshot] {
+        let merkle_root = MerkleRoot {
+            hash: snapshot.root_hash().await?.0.to_vec(),
+        };
+        // Each proof is generated twice, the second time mostly from the cache.
+        for _ in 0..2 {
+            for i in 0..32u64 {
+                let key = format!("ibc/key_{i}");
+                let (value, proof) = snapshot.get_with_proof(key.clone().into_bytes()).await?;
+                let expected = snapshot.get_raw(&key).await?;
+                assert_eq!(value, expected);
+                let merkle_path = MerklePath {
+                    key_path: vec!["ibc".to_string(), format!("key_{i}")],
+                };
+                match value {
+                    Some(value) => proof.verify_membership(
+                        &PENUMBRA_PROOF_SPECS,
+                        merkle_root.clone(),
+                        merkle_path,
+                        value,
+                        0,
+                    )?,
+                    None => proof.verify_non_membership(
+                        &PENUMBRA_PROOF_SPECS,
+                        merkle_root.clone(),
+                        merkle_path,
+                    )?,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that a single substore can be exported and imported into another
+/// storage, reproducing its contents and root.
+async fn test_substore_export_import() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let source_dir = tempfile::tempdir()?;
+    let substore_prefixes: Vec<String> = vec!["dex".to_string(), "ibc".to_string()];
+    let source = Storage::load(source_dir.path().to_owned(), substore_prefixes.clone()).await?;
+
+    let mut delta = StateDelta::new(source.latest_snapshot());
+    for i in 0..50u64 {
+        delta.put_raw(format!("dex/pool/{i}"), i.to_be_bytes().to_vec());
+    }
+    delta.put_raw("ibc/client".to_string(), b"client".to_vec());
+    delta.put_raw("main".to_string(), b"main".to_vec());
+    source.commit(delta).await?;
+
+    let source_snapshot = source.latest_snapshot();
+    let exported: Vec<(String, Vec<u8>)> = source_snapshot
+        .export_substore("dex")?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(exported.len(), 50);
+    assert!(exported.iter().all(|(key, _)| key.starts_with("pool/")));
+    assert!(source_snapshot.export_substore("ghost").is_err());
+    assert!(source_snapshot.export_substore("").is_err());
+
+    // The destination has stale data in its `dex` substore, which is replaced.
+    let dest_dir = tempfile::tempdir()?;
+    let dest = Storage::load(dest_dir.path().to_owned(), substore_prefixes).await?;
+    let mut delta = StateDelta::new(dest.latest_snapshot());
+    delta.put_raw("dex/stale".to_string(), b"stale".to_vec());
+    delta.put_raw("ibc/other".to_string(), b"other".to_vec());
+    dest.commit(delta).await?;
+
+    let mut delta = StateDelta::new(dest.latest_snapshot());
+    let imported = delta
+        .import_substore("dex", tokio_stream::iter(exported.into_iter().map(Ok)))
+        .await?;
+    assert_eq!(imported, 50);
+    dest.commit(delta).await?;
+
+    let dest_snapshot = dest.latest_snapshot();
+    assert_eq!(
+        dest_snapshot.prefix_root_hash("dex").await?,
+        source_snapshot.prefix_root_hash("dex").await?
+    );
+    assert_eq!(
+        dest_snapshot.get_raw("dex/pool/7").await?,
+        Some(7u64.to_be_bytes().to_vec())
+    );
+    assert_eq!(dest_snapshot.get_raw("dex/stale").await?, None);
+    assert_eq!(
+        dest_snapshot.get_raw("ibc/other").await?,
+        Some(b"other".to_vec())
+    );
+    assert_eq!(dest_snapshot.get_raw("ibc/client").await?, None);
+
+    let mut delta = StateDelta::new(dest.latest_snapshot());
+    assert!(delta
+        .import_substore("ghost", tokio_stream::iter(Vec::new()))
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that strict scans reject prefixes that don't designate a substore,
+/// instead of falling through to the main store.
+async fn test_substore_prefix_raw_strict() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes = vec!["ibc".to_string()];
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("ibc/client/1".to_string(), b"client".to_vec());
+    // A main store key that a mistyped prefix would silently match.
+    delta.put_raw("ghost/key".to_string(), b"ghost".to_vec());
+    storage.commit(delta).await?;
+
+    let is_unknown_substore = |err: &anyhow::Error| {
+        matches!(
+            err.downcast_ref::<cnidarium::StorageError>(),
+            Some(cnidarium::StorageError::UnknownSubstore { .. })
+        )
+    };
+
+    let snapshot = storage.latest_snapshot();
+    for prefix in ["ibc", "ibc/", "ibc/client/"] {
+        let entries: Vec<_> = snapshot.prefix_raw_strict(prefix)?.collect().await;
+        assert_eq!(entries.len(), 1, "prefix {prefix} scans the ibc substore");
+    }
+    for prefix in ["ghost/", "ibcx", ""] {
+        let err = snapshot
+            .prefix_raw_strict(prefix)
+            .err()
+            .expect("prefix is rejected");
+        assert!(is_unknown_substore(&err), "prefix {prefix}: {err}");
+    }
+    // The lenient scan falls through to the main store.
+    let entries: Vec<_> = snapshot.prefix_raw("ghost/").collect().await;
+    assert_eq!(entries.len(), 1);
+
+    let mut delta = StateDelta::new(snapshot);
+    delta.put_raw("ibc/client/2".to_string(), b"client".to_vec());
+    let entries: Vec<_> = delta.prefix_raw_strict("ibc/")?.collect().await;
+    assert_eq!(entries.len(), 2);
+    let err = delta
+        .prefix_raw_strict("ghost/")
+        .err()
+        .expect("prefix is rejected");
+    assert!(is_unknown_substore(&err));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that commit traces list the hashed writes and deletions of each
+/// substore, and are identical for identical commits.
+async fn test_substore_commit_trace() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let substore_prefixes: Vec<String> = vec!["ibc".to_string(), "dex".to_string()];
+
+    let mut traces = Vec::new();
+    for _ in 0..2 {
+        let tmpdir = tempfile::tempdir()?;
+        let storage = Storage::load(tmpdir.path().to_owned(), substore_prefixes.clone()).await?;
+
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw("ibc/b".to_string(), b"ibc_b".to_vec());
+        delta.put_raw("ibc/a".to_string(), b"ibc_a".to_vec());
+        delta.put_raw("main".to_string(), b"main".to_vec());
+        delta.nonverifiable_put_raw(b"ibc/index".to_vec(), b"untraced".to_vec());
+        storage.commit(delta).await?;
+
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        delta.put_raw("ibc/c".to_string(), b"ibc_c".to_vec());
+        delta.delete("ibc/a".to_string());
+        let (version, root_hash, trace) = storage.commit_with_trace(delta).await?;
+
+        assert_eq!(version, 1);
+        assert_eq!(trace.version, 1);
+        assert_eq!(trace.root_hash, root_hash);
+        // Only the updated substore and the main store are traced.
+        let prefixes: Vec<_> = trace.substores.iter().map(|s| s.prefix.as_str()).collect();
+        assert_eq!(prefixes, vec!["", "ibc"]);
+
+        let snapshot = storage.latest_snapshot();
+        let main = &trace.substores[0];
+        assert_eq!(main.root_hash, root_hash);
+        let ibc_root = snapshot.prefix_root_hash("ibc").await?;
+        assert_eq!(
+            main.writes,
+            vec![("ibc".to_string(), *blake3::hash(&ibc_root.0).as_bytes())]
+        );
+        assert!(main.deletions.is_empty());
+
+        let ibc = &trace.substores[1];
+        assert_eq!(ibc.root_hash, ibc_root);
+        assert_eq!(
+            ibc.writes,
+            vec![("ibc/c".to_string(), *blake3::hash(b"ibc_c").as_bytes())]
+        );
+        assert_eq!(ibc.deletions, vec!["ibc/a".to_string()]);
+
+        traces.push(trace);
+        std::mem::drop(snapshot);
+        storage.release().await;
+    }
+    assert_eq!(traces[0], traces[1]);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that batched proofs each verify against the root, for keys across
+/// several substores, the main store, and missing keys.
+async fn test_substore_multi_get_with_proof() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes = vec!["ibc".to_string(), "dex".to_string()];
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    pub static PENUMBRA_PROOF_SPECS: Lazy<Vec<ics23::ProofSpec>> =
+        Lazy::new(|| vec![cnidarium::ics23_spec(), cnidarium::ics23_spec()]);
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..20u64 {
+        delta.put_raw(format!("ibc/balance/{i}"), i.to_be_bytes().to_vec());
+        delta.put_raw(format!("dex/pool/{i}"), i.to_be_bytes().to_vec());
+    }
+    delta.put_raw("main_key".to_string(), b"main".to_vec());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    let mut keys: Vec<Vec<u8>> = (0..20u64)
+        .flat_map(|i| [format!("ibc/balance/{i}"), format!("dex/pool/{i}")])
+        .map(String::into_bytes)
+        .collect();
+    keys.push(b"ibc/balance/missing".to_vec());
+    keys.push(b"main_key".to_vec());
+
+    let results = snapshot.multi_get_with_proof(&keys).await?;
+    assert_eq!(results.len(), keys.len());
+
+    let merkle_root = MerkleRoot {
+        hash: snapshot.root_hash().await?.0.to_vec(),
+    };
+    for (key, (value, proof)) in keys.iter().zip(results) {
+        let key = String::from_utf8(key.clone())?;
+        assert_eq!(value, snapshot.get_raw(&key).await?);
+
+        // Single-store proofs only have one layer.
+        let (specs, key_path) = match key.split_once('/') {
+            Some((prefix, rest)) => (
+                PENUMBRA_PROOF_SPECS.clone(),
+                vec![prefix.to_string(), rest.to_string()],
+            ),
+            None => (vec![cnidarium::ics23_spec()], vec![key.clone()]),
+        };
+        let merkle_path = MerklePath { key_path };
+        match value {
+            Some(value) => {
+                proof.verify_membership(&specs, merkle_root.clone(), merkle_path, value, 0)?
+            }
+            None => proof.verify_non_membership(&specs, merkle_root.clone(), merkle_path)?,
+        }
+    }
+
+    assert!(snapshot.multi_get_with_proof(&[Vec::new()]).await.is_err());
+    assert!(snapshot.multi_get_with_proof(&[]).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that a substore-level prefix scan only returns the matching keys of
+/// that substore, relative to it.
+async fn test_substore_prefix_raw() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.into_path();
+    let substore_prefixes = vec!["ibc".to_string(), "dex".to_string()];
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("ibc/clients/a".to_string(), b"a".to_vec());
+    delta.put_raw("ibc/clients/b".to_string(), b"b".to_vec());
+    delta.put_raw("ibc/connections/a".to_string(), b"c".to_vec());
+    delta.put_raw("dex/clients/a".to_string(), b"d".to_vec());
+    delta.put_raw("clients/a".to_string(), b"e".to_vec());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    let entries: Vec<_> = snapshot
+        .substore_prefix_raw("ibc", "clients/")?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(
+        entries,
+        vec![
+            ("clients/a".to_string(), b"a".to_vec()),
+            ("clients/b".to_string(), b"b".to_vec()),
+        ]
+    );
+
+    // An empty inner prefix scans the whole substore.
+    let keys: Vec<String> = snapshot
+        .substore_prefix_raw("dex", "")?
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(keys, vec!["clients/a".to_string()]);
+
+    // The main store and unregistered prefixes are rejected.
+    for prefix in ["", "ib", "nope"] {
+        let err = snapshot
+            .substore_prefix_raw(prefix, "clients/")
+            .err()
+            .expect("the prefix is not a registered substore");
+        assert!(matches!(
+            err.downcast_ref::<cnidarium::StorageError>(),
+            Some(cnidarium::StorageError::UnknownSubstore { .. })
+        ));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that the main root and the substore roots are identical after a reload.
+async fn test_substore_roots_survive_reload() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes: Vec<String> = vec!["prefix_a", "prefix_b", "prefix_c"]
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
+    let options = cnidarium::StorageOptions {
+        verify_roots_on_load: true,
+        ..Default::default()
+    };
+
+    let storage =
+        Storage::load_with_options(db_path.clone(), substore_prefixes.clone(), options.clone())
+            .await?;
+    // Substores are written at different versions, and `prefix_c` never is.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a".to_vec());
+    delta.put_raw("prefix_b/key".to_string(), b"b".to_vec());
+    delta.put_raw("key".to_string(), b"main".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a2".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("key".to_string(), b"main2".to_vec());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    let root_hash = snapshot.root_hash().await?;
+    let substore_roots = snapshot.substore_roots().await?;
+    assert_eq!(substore_roots.len(), 3);
+    drop(snapshot);
+    storage.release().await;
+
+    let storage = Storage::load_with_options(db_path, substore_prefixes, options).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.version(), 2);
+    assert_eq!(snapshot.root_hash().await?, root_hash);
+    assert_eq!(snapshot.substore_roots().await?, substore_roots);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that every column family is listed with its role.
+async fn test_column_families_describe_substores() -> anyhow::Result<()> {
+    use cnidarium::ColumnFamilyRole;
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec!["prefix_a".to_string(), "prefix_b".to_string()];
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a".to_vec());
+    delta.put_raw("key".to_string(), b"main".to_vec());
+    storage.commit(delta).await?;
+
+    let families = storage.column_families()?;
+    let count = |role: ColumnFamilyRole| families.iter().filter(|cf| cf.role == role).count();
+
+    // Each substore has its five column families.
+    assert_eq!(count(ColumnFamilyRole::Substore("prefix_a".to_string())), 5);
+    assert_eq!(count(ColumnFamilyRole::Substore("prefix_b".to_string())), 5);
+    // The main store has its nodes, its nonverifiable data and three indexes.
+    assert_eq!(count(ColumnFamilyRole::JmtNodes), 1);
+    assert_eq!(count(ColumnFamilyRole::NonVerifiable), 1);
+    assert_eq!(count(ColumnFamilyRole::MainStore), 3);
+    assert_eq!(count(ColumnFamilyRole::Metadata), 1);
+    assert_eq!(count(ColumnFamilyRole::Unknown), 0);
+    // All the configured column families are open.
+    assert!(families.iter().all(|cf| cf.approximate_size.is_some()));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that a substore hashing its values keeps them out of the tree, while
+/// reads resolve them and proofs verify their hash.
+async fn test_substore_hash_values() -> anyhow::Result<()> {
+    use sha2::Digest;
+
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec!["blobs".to_string(), "plain".to_string()];
+    let options = cnidarium::StorageOptions {
+        hash_values: ["blobs".to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+    let storage =
+        Storage::load_with_options(db_path.clone(), substore_prefixes.clone(), options.clone())
+            .await?;
+
+    let large = vec![7u8; 1 << 20];
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("blobs/large".to_string(), large.clone());
+    delta.put_raw("blobs/small".to_string(), b"small".to_vec());
+    delta.put_raw("plain/large".to_string(), large.clone());
+    storage.commit(delta).await?;
+
+    // Reads resolve the values.
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("blobs/large").await?, Some(large.clone()));
+    assert_eq!(snapshot.value_size("blobs/large").await?, Some(large.len()));
+    assert!(snapshot.contains_raw("blobs/small").await?);
+    let values: Vec<_> = snapshot
+        .prefix_raw("blobs/")
+        .map(|entry| entry.map(|(_, value)| value))
+        .collect::<Result<_, _>>()
+        .await?;
+    assert_eq!(values, vec![large.clone(), b"small".to_vec()]);
+
+    // The tree only holds the hash of the value, which the proof verifies.
+    pub static PENUMBRA_PROOF_SPECS: Lazy<Vec<ics23::ProofSpec>> =
+        Lazy::new(|| vec![cnidarium::ics23_spec(), cnidarium::ics23_spec()]);
+    let root = snapshot.root_hash().await?;
+    let (value_hash, proof) = snapshot.get_with_proof(b"blobs/large".to_vec()).await?;
+    let value_hash = value_hash.expect("key is present");
+    assert_eq!(value_hash, sha2::Sha256::digest(&large).to_vec());
+    proof.verify_membership(
+        &PENUMBRA_PROOF_SPECS,
+        MerkleRoot {
+            hash: root.0.to_vec(),
+        },
+        MerklePath {
+            key_path: vec!["blobs".to_string(), "large".to_string()],
+        },
+        value_hash,
+        0,
+    )?;
+    // Other substores are unaffected.
+    let (value, _) = snapshot.get_with_proof(b"plain/large".to_vec()).await?;
+    assert_eq!(value, Some(large.clone()));
+
+    // Overwrites and deletions go through the tree as usual, and the values
+    // survive a reload.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("blobs/large".to_string(), b"shrunk".to_vec());
+    delta.delete("blobs/small".to_string());
+    let (_, root) = storage.commit(delta).await?;
+    storage.release().await;
+
+    // The setting is recorded, and cannot change once the substore exists.
+    assert!(Storage::load(db_path.clone(), substore_prefixes.clone())
+        .await
+        .is_err());
+    let mismatched = cnidarium::StorageOptions {
+        hash_values: ["plain".to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+    assert!(
+        Storage::load_with_options(db_path.clone(), substore_prefixes.clone(), mismatched)
+            .await
+            .is_err()
+    );
+
+    let storage = Storage::load_with_options(db_path.clone(), substore_prefixes, options).await?;
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.root_hash().await?, root);
+    assert_eq!(
+        snapshot.get_raw("blobs/large").await?,
+        Some(b"shrunk".to_vec())
+    );
+    assert_eq!(snapshot.get_raw("blobs/small").await?, None);
+
+    // Read-only instances use the recorded setting.
+    let read_only = Storage::load_read_only(db_path).await?;
+    assert_eq!(
+        read_only.latest_snapshot().get_raw("blobs/large").await?,
+        Some(b"shrunk".to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that the root hash does not depend on the order in which substores are registered.
+async fn test_substore_registration_order_does_not_affect_roots() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let prefixes = ["ibc", "dex", "governance", "shielded_pool"];
+
+    let mut storages = Vec::new();
+    for order in [[0, 1, 2, 3], [3, 1, 0, 2]] {
+        let tmpdir = tempfile::tempdir()?;
+        let substore_prefixes = order.iter().map(|&i| prefixes[i].to_string()).collect();
+        let storage = Storage::load(tmpdir.path().to_owned(), substore_prefixes).await?;
+        storages.push((tmpdir, storage));
+    }
+
+    let mut roots = Vec::new();
+    for (_, storage) in &storages {
+        let mut storage_roots = Vec::new();
+        for height in 0..3u8 {
+            let mut delta = StateDelta::new(storage.latest_snapshot());
+            for prefix in prefixes {
+                delta.put_raw(format!("{prefix}/key_{height}"), vec![height]);
+            }
+            delta.put_raw(format!("main_{height}"), vec![height]);
+            storage_roots.push(storage.commit(delta).await?.1);
+        }
+        roots.push((
+            storage_roots,
+            storage.latest_snapshot().substore_roots().await?,
+        ));
+    }
+
+    assert_eq!(roots[0], roots[1]);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that JMT proofs of keys in the main store and in substores verify against the root hash.
+async fn test_substore_jmt_proofs() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let storage = Storage::load(tmpdir.path().to_owned(), vec!["ibc".to_string()]).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("main_key".to_string(), b"main_value".to_vec());
+    delta.put_raw("ibc/client".to_string(), b"client_state".to_vec());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    let root_hash = snapshot.root_hash().await?;
+
+    // Inclusion proofs, for a key in the main store and one in a substore.
+    for (key, expected) in [
+        (&b"main_key"[..], &b"main_value"[..]),
+        (b"ibc/client", b"client_state"),
+    ] {
+        let (value, proof) = snapshot.get_with_jmt_proof(key.to_vec()).await?;
+        assert_eq!(value.as_deref(), Some(expected));
+        proof.verify(root_hash, key, Some(expected))?;
+        assert!(proof.verify(root_hash, key, Some(b"forged")).is_err());
+        assert!(proof.verify(root_hash, key, None).is_err());
+    }
+
+    // Exclusion proofs.
+    for key in [&b"missing"[..], b"ibc/missing"] {
+        let (value, proof) = snapshot.get_with_jmt_proof(key.to_vec()).await?;
+        assert_eq!(value, None);
+        proof.verify(root_hash, key, None)?;
+        assert!(proof.verify(root_hash, key, Some(b"forged")).is_err());
+    }
+
+    // The proofs are rooted at the snapshot's version.
+    let (_, proof) = snapshot.get_with_jmt_proof(b"ibc/client".to_vec()).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("ibc/client".to_string(), b"updated".to_vec());
+    let (_, new_root_hash) = storage.commit(delta).await?;
+    assert!(proof
+        .verify(new_root_hash, b"ibc/client", Some(b"client_state"))
+        .is_err());
+    proof.verify(root_hash, b"ibc/client", Some(b"client_state"))?;
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that the status reports the latest version, the combined root and the
+/// version and root of each substore.
+async fn test_status_reports_substores() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec![
+        "prefix_a".to_string(),
+        "prefix_b".to_string(),
+        "prefix_c".to_string(),
+    ];
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let status = storage.status().await?;
+    assert_eq!(status.latest_version, u64::MAX);
+    assert_eq!(status.root_hash, None);
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a".to_vec());
+    delta.put_raw("prefix_b/key".to_string(), b"b".to_vec());
+    storage.commit(delta).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a2".to_vec());
+    let (_, root_hash) = storage.commit(delta).await?;
+
+    let status = storage.status().await?;
+    assert_eq!(status.latest_version, 1);
+    assert_eq!(status.root_hash, Some(root_hash));
+    assert_eq!(status.oldest_available_version, 0);
+
+    let snapshot = storage.latest_snapshot();
+    let prefixes: Vec<_> = status.substores.iter().map(|s| s.prefix.as_str()).collect();
+    assert_eq!(prefixes, vec!["prefix_a", "prefix_b", "prefix_c"]);
+    let versions: Vec<_> = status.substores.iter().map(|s| s.version).collect();
+    assert_eq!(versions, vec![1, 0, u64::MAX]);
+    for substore in &status.substores[..2] {
+        assert_eq!(
+            substore.root_hash,
+            Some(snapshot.prefix_root_hash(&substore.prefix).await?)
+        );
+    }
+    assert_eq!(status.substores[2].root_hash, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that a commit that fails after some substores were written, or that is
+/// interrupted before its batch is written, leaves every substore at the same
+/// version once the storage is reloaded.
+async fn test_failed_commit_leaves_substores_consistent() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec!["prefix_a".to_string(), "prefix_b".to_string()];
+    let mut options = cnidarium::StorageOptions::default();
+    options.max_internal_nodes_per_commit = Some(64);
+    let storage =
+        Storage::load_with_options(db_path.clone(), substore_prefixes.clone(), options).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a".to_vec());
+    delta.put_raw("prefix_b/key".to_string(), b"b".to_vec());
+    delta.put_raw("key".to_string(), b"main".to_vec());
+    let (version, root_hash) = storage.commit(delta).await?;
+
+    // Both substore trees are added to the batch before the commit is found
+    // to exceed the cap on the main store, so the failure happens after the
+    // substores were written.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for i in 0..1000u32 {
+        delta.put_raw(format!("prefix_a/{i}"), i.to_le_bytes().to_vec());
+        delta.put_raw(format!("prefix_b/{i}"), i.to_le_bytes().to_vec());
+    }
+    delta.put_raw("key".to_string(), b"failed".to_vec());
+    assert!(storage.commit(delta).await.is_err());
+
+    // A commit whose batch was staged for every substore but never written,
+    // as if the process was killed before the write.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"staged".to_vec());
+    delta.put_raw("prefix_b/key".to_string(), b"staged".to_vec());
+    let batch = storage.prepare_commit(delta).await?;
+    assert_eq!(batch.substore_version("prefix_a"), Some(version + 1));
+    std::mem::drop(batch);
+    storage.release().await;
+
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+    let status = storage.status().await?;
+    assert_eq!(status.latest_version, version);
+    assert_eq!(status.root_hash, Some(root_hash));
+    let versions: Vec<_> = status.substores.iter().map(|s| s.version).collect();
+    assert_eq!(versions, vec![version, version]);
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("prefix_a/key").await?, Some(b"a".to_vec()));
+    assert_eq!(snapshot.get_raw("prefix_b/key").await?, Some(b"b".to_vec()));
+    assert_eq!(snapshot.get_raw("prefix_a/0").await?, None);
+    assert_eq!(snapshot.get_raw("key").await?, Some(b"main".to_vec()));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that the substore versions reported by the storage only advance for
+/// the substores that were written to.
+async fn test_substore_versions() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec!["prefix_a".to_string(), "prefix_b".to_string()];
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let versions = storage.substore_versions();
+    assert_eq!(versions.len(), 3);
+    assert!(versions.values().all(|version| *version == u64::MAX));
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a2".to_vec());
+    delta.put_raw("prefix_b/key".to_string(), b"b".to_vec());
+    storage.commit(delta).await?;
+    let delta = StateDelta::new(storage.latest_snapshot());
+    storage.commit(delta).await?;
+
+    let versions = storage.substore_versions();
+    assert_eq!(versions.get(""), Some(&2));
+    assert_eq!(versions.get("prefix_a"), Some(&1));
+    assert_eq!(versions.get("prefix_b"), Some(&0));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that a substore registered on a live storage is created when the
+/// storage is reloaded, starts out empty, and can then be written to.
+async fn test_register_substore() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec!["prefix_a".to_string()];
+    let storage = Storage::load(db_path.clone(), substore_prefixes.clone()).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/key".to_string(), b"a".to_vec());
+    delta.put_raw("main/key".to_string(), b"main".to_vec());
+    let (version, root_hash) = storage.commit(delta).await?;
+
+    // Prefixes that are nested in a registered substore, or that hold keys
+    // of the main store, are rejected.
+    assert!(storage.register_substore("").await.is_err());
+    assert!(storage.register_substore("prefix_a").await.is_err());
+    assert!(storage.register_substore("prefix_a/nested").await.is_err());
+    assert!(storage.register_substore("main").await.is_err());
+
+    storage.register_substore("prefix_b").await?;
+    assert!(storage.register_substore("prefix_b").await.is_err());
+    assert!(storage.register_substore("prefix_b/nested").await.is_err());
+    // Prefixes only overlap at a delimiter.
+    storage.register_substore("prefix").await?;
+
+    // Until the storage is reloaded, commits that write under the new
+    // prefixes are rejected, rather than routed to the main store.
+    let pending = |result: anyhow::Result<_>| {
+        matches!(
+            result
+                .unwrap_err()
+                .downcast_ref::<cnidarium::StorageError>(),
+            Some(cnidarium::StorageError::PendingSubstore { .. })
+        )
+    };
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_b/key".to_string(), b"b".to_vec());
+    assert!(pending(storage.commit(delta).await));
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_b".to_string(), b"b".to_vec());
+    assert!(pending(storage.commit(delta).await));
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.nonverifiable_put_raw(b"prefix_b/key".to_vec(), b"b".to_vec());
+    assert!(pending(storage.commit(delta).await));
+    assert_eq!(storage.latest_version(), version);
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_bc/key".to_string(), b"bc".to_vec());
+    let (version, root_hash) = storage.commit(delta).await?;
+
+    // Until the storage is reloaded, read-only instances ignore the new
+    // substore, which has no column families yet.
+    let read_only = Storage::load_read_only(db_path.clone()).await?;
+    let snapshot = read_only.latest_snapshot();
+    assert_eq!(snapshot.version(), version);
+    assert_eq!(snapshot.get_raw("main/key").await?, Some(b"main".to_vec()));
+    drop(snapshot);
+    drop(read_only);
+    storage.release().await;
+
+    // The new substore is loaded from the configuration, and is empty.
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+    let versions = storage.substore_versions();
+    assert_eq!(versions.get("prefix_b"), Some(&u64::MAX));
+    assert_eq!(versions.get("prefix"), Some(&u64::MAX));
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.version(), version);
+    assert_eq!(snapshot.root_hash().await?, root_hash);
+    assert_eq!(snapshot.get_raw("prefix_b/key").await?, None);
+    assert!(snapshot.prefix_keys("prefix_b/").next().await.is_none());
+
+    let mut delta = StateDelta::new(snapshot);
+    delta.put_raw("prefix_b/key".to_string(), b"b".to_vec());
+    storage.commit(delta).await?;
+    assert_eq!(storage.substore_versions().get("prefix_b"), Some(&0));
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(snapshot.get_raw("prefix_b/key").await?, Some(b"b".to_vec()));
+    assert_eq!(snapshot.get_raw("prefix_a/key").await?, Some(b"a".to_vec()));
+    assert_eq!(
+        snapshot.get_raw("prefix_bc/key").await?,
+        Some(b"bc".to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that reads served by a small value cache, which evicts values while
+/// they are read, return the value at the version of each snapshot.
+async fn test_reads_with_value_cache() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec!["ibc".to_string()];
+    let options = cnidarium::StorageOptions {
+        value_cache_capacity: 4,
+        ..Default::default()
+    };
+    let storage = Storage::load_with_options(db_path, substore_prefixes, options).await?;
+
+    // Before the first commit, nothing is read or cached.
+    assert_eq!(storage.latest_snapshot().get_raw("ibc/key_0").await?, None);
+
+    for version in 0..4u64 {
+        let mut delta = StateDelta::new(storage.latest_snapshot());
+        for i in 0..4u64 {
+            if i <= version {
+                delta.put_raw(
+                    format!("ibc/key_{i}"),
+                    format!("{i}@{version}").into_bytes(),
+                );
+            }
+        }
+        delta.put_raw("params".to_string(), format!("@{version}").into_bytes());
+        // Deleting a key must not be hidden by a cached value either.
+        if version == 3 {
+            delta.delete("ibc/key_0".to_string());
+        }
+        storage.commit(delta).await?;
+
+        // Each value is read twice, the second time from the cache.
+        for _ in 0..2 {
+            for old in 0..=version {
+                let snapshot = storage.snapshot(old).expect("version is still cached");
+                assert_eq!(
+                    snapshot.get_raw("params").await?,
+                    Some(format!("@{old}").into_bytes())
+                );
+                for i in 0..4u64 {
+                    let expected = if i == 0 && old == 3 {
+                        None
+                    } else {
+                        (i <= old).then(|| format!("{i}@{old}").into_bytes())
+                    };
+                    assert_eq!(snapshot.get_raw(&format!("ibc/key_{i}")).await?, expected);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that a global prefix scan merges the main store and every substore
+/// under the prefix into a single stream in key order.
+async fn test_prefix_raw_global() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec!["ibc".to_string(), "prefix_b".to_string()];
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    for key in ["zz", "ibc/x", "a", "prefix_b/y", "ibca"] {
+        delta.put_raw(key.to_string(), key.as_bytes().to_vec());
+    }
+    storage.commit(delta).await?;
+    let snapshot = storage.latest_snapshot();
+
+    // The main store holds the root of each substore under its prefix.
+    let keys: Vec<String> = snapshot
+        .prefix_raw_global("")
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(
+        keys,
+        vec!["a", "ibc", "ibc/x", "ibca", "prefix_b", "prefix_b/y", "zz"]
+    );
+
+    // `prefix_raw` only scans the main store.
+    let main_keys: Vec<String> = snapshot
+        .prefix_raw("")
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(main_keys, vec!["a", "ibc", "ibca", "prefix_b", "zz"]);
+
+    let entries: Vec<(String, Vec<u8>)> = snapshot
+        .prefix_raw_global("ib")
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[1], ("ibc/x".to_string(), b"ibc/x".to_vec()));
+    assert_eq!(entries[2], ("ibca".to_string(), b"ibca".to_vec()));
+
+    let keys: Vec<String> = snapshot
+        .prefix_raw_global("prefix_b/")
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(keys, vec!["prefix_b/y"]);
+
+    // Pending writes and deletions of a delta are merged in.
+    let mut delta = StateDelta::new(snapshot);
+    delta.put_raw("ibc/w".to_string(), b"w".to_vec());
+    delta.delete("ibca".to_string());
+    let keys: Vec<String> = delta
+        .prefix_raw_global("ib")
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    assert_eq!(keys, vec!["ibc", "ibc/w", "ibc/x"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Test that versioned reads report the version of the chain state at which
+/// each value was last written, including for keys in substores, which are
+/// versioned independently.
+async fn test_get_raw_versioned() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let tmpdir = tempfile::tempdir()?;
+    let db_path = tmpdir.path().to_owned();
+    let substore_prefixes = vec!["prefix_a".to_string()];
+    let storage = Storage::load(db_path, substore_prefixes).await?;
+
+    // Version 0 writes to both stores, version 1 only to the main store, and
+    // versions 2 and 3 only to the substore.
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/k".to_string(), b"k0".to_vec());
+    delta.put_raw("main".to_string(), b"main".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("other".to_string(), b"other".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/j".to_string(), b"j".to_vec());
+    storage.commit(delta).await?;
+    let mut delta = StateDelta::new(storage.latest_snapshot());
+    delta.put_raw("prefix_a/k".to_string(), b"k3".to_vec());
+    delta.delete("other".to_string());
+    storage.commit(delta).await?;
+
+    let snapshot = storage.latest_snapshot();
+    assert_eq!(
+        snapshot.get_raw_versioned("prefix_a/k").await?,
+        Some((b"k3".to_vec(), 3))
+    );
+    assert_eq!(
+        snapshot.get_raw_versioned("prefix_a/j").await?,
+        Some((b"j".to_vec(), 2))
+    );
+    assert_eq!(
+        snapshot.get_raw_versioned("main").await?,
+        Some((b"main".to_vec(), 0))
+    );
+    assert_eq!(snapshot.get_raw_versioned("other").await?, None);
+    assert_eq!(snapshot.get_raw_versioned("missing").await?, None);
+
+    let past = storage.snapshot(2).expect("version 2 is retained");
+    assert_eq!(
+        past.get_raw_versioned("prefix_a/k").await?,
+        Some((b"k0".to_vec(), 0))
+    );
+    assert_eq!(
+        past.get_raw_versioned("other").await?,
+        Some((b"other".to_vec(), 1))
+    );
+
+    // Writes pending in a delta are reported as uncommitted.
+    let mut delta = StateDelta::new(snapshot);
+    delta.put_raw("prefix_a/j".to_string(), b"j2".to_vec());
+    delta.delete("main".to_string());
+    assert_eq!(
+        delta.get_raw_versioned("prefix_a/j").await?,
+        Some((b"j2".to_vec(), u64::MAX))
+    );
+    assert_eq!(delta.get_raw_versioned("main").await?, None);
+    assert_eq!(
+        delta.get_raw_versioned("prefix_a/k").await?,
+        Some((b"k3".to_vec(), 3))
+    );
+
+    Ok(())
+}
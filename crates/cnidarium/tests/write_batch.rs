@@ -110,10 +110,11 @@ pub async fn test_write_batch_stale_version_substores() -> Result<()> {
     }
 
     /* Persist `write_batch_1` and check that the two other (stale) deltas cannot be applied. */
-    let final_root = storage
+    let (final_version, final_root) = storage
         .commit_batch(write_batch_1)
         .expect("committing batch 3 should work");
     let final_snapshot = storage.latest_snapshot();
+    assert_eq!(version_1, final_version);
     assert_eq!(root_hash_1.0, final_root.0);
     assert_eq!(root_hash_1.0, final_snapshot.root_hash().await?.0);
     assert_eq!(version_1, final_snapshot.version());
@@ -196,7 +197,7 @@ pub async fn test_two_empty_writes() -> Result<()> {
         )
     }
 
-    let block_1_root = storage
+    let (_, block_1_root) = storage
         .commit_batch(write_batch_1)
         .expect("committing batch 3 should work");
     let block_1_snapshot = storage.latest_snapshot();
@@ -217,7 +218,7 @@ pub async fn test_two_empty_writes() -> Result<()> {
     assert_eq!(version_3, block_1_version.wrapping_add(1));
 
     /* Check that we can apply `write_batch_3` */
-    let block_2_root = storage
+    let (_, block_2_root) = storage
         .commit_batch(write_batch_3)
         .expect("committing batch 3 should work");
     let block_2_snapshot = storage.latest_snapshot();
@@ -288,7 +289,7 @@ pub async fn test_batch_substore() -> Result<()> {
         assert_eq!(state_snapshot.version(), prev_version);
         assert_eq!(state_snapshot.root_hash().await?.0, prev_root.0);
 
-        let block_root = storage
+        let (_, block_root) = storage
             .commit_batch(write_batch)
             .expect("committing batch 3 should work");
         let block_snapshot = storage.latest_snapshot();
@@ -0,0 +1,198 @@
+//! Human-readable descriptions of the actions in a transaction, for previewing
+//! a transaction before signing it.
+
+use penumbra_transaction::Action;
+
+/// A presentation-level description of a single [`Action`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActionDescription {
+    /// The name of the `Action` variant, e.g. `"Spend"`.
+    pub variant: &'static str,
+    /// A short, human-readable summary of what the action does.
+    pub summary: &'static str,
+    /// The components whose state executing the action touches.
+    pub touches: &'static [&'static str],
+}
+
+/// Describes each of `actions`, in order.
+///
+/// This is a read-only presentation helper: it only looks at the actions
+/// themselves, and does not consult the chain state.
+pub fn describe_actions(actions: &[Action]) -> Vec<ActionDescription> {
+    actions.iter().map(describe_action).collect()
+}
+
+fn describe_action(action: &Action) -> ActionDescription {
+    match action {
+        Action::Output(_) => describe("Output", "Create a new note", &["shielded_pool", "sct"]),
+        Action::Spend(_) => describe("Spend", "Spend an existing note", &["shielded_pool", "sct"]),
+        Action::ValidatorDefinition(_) => describe(
+            "ValidatorDefinition",
+            "Define or update a validator",
+            &["stake"],
+        ),
+        Action::IbcRelay(_) => describe("IbcRelay", "Relay an IBC message", &["ibc"]),
+        Action::Swap(_) => describe("Swap", "Swap assets on the DEX", &["dex", "sct"]),
+        Action::SwapClaim(_) => describe(
+            "SwapClaim",
+            "Claim the outputs of a swap",
+            &["dex", "shielded_pool", "sct"],
+        ),
+        Action::ProposalSubmit(_) => describe(
+            "ProposalSubmit",
+            "Submit a governance proposal",
+            &["governance"],
+        ),
+        Action::ProposalWithdraw(_) => describe(
+            "ProposalWithdraw",
+            "Withdraw a governance proposal",
+            &["governance"],
+        ),
+        Action::DelegatorVote(_) => describe(
+            "DelegatorVote",
+            "Vote on a proposal as a delegator",
+            &["governance", "sct"],
+        ),
+        Action::ValidatorVote(_) => describe(
+            "ValidatorVote",
+            "Vote on a proposal as a validator",
+            &["governance"],
+        ),
+        Action::ProposalDepositClaim(_) => describe(
+            "ProposalDepositClaim",
+            "Claim the deposit of a finished proposal",
+            &["governance"],
+        ),
+        Action::PositionOpen(_) => describe("PositionOpen", "Open a liquidity position", &["dex"]),
+        Action::PositionClose(_) => {
+            describe("PositionClose", "Close a liquidity position", &["dex"])
+        }
+        Action::PositionWithdraw(_) => describe(
+            "PositionWithdraw",
+            "Withdraw the reserves of a closed liquidity position",
+            &["dex"],
+        ),
+        Action::Delegate(_) => describe("Delegate", "Delegate to a validator", &["stake"]),
+        Action::Undelegate(_) => describe("Undelegate", "Undelegate from a validator", &["stake"]),
+        Action::UndelegateClaim(_) => describe(
+            "UndelegateClaim",
+            "Claim undelegated tokens once unbonded",
+            &["stake"],
+        ),
+        Action::Ics20Withdrawal(_) => describe(
+            "Ics20Withdrawal",
+            "Withdraw tokens to another chain over IBC",
+            &["shielded_pool", "ibc"],
+        ),
+        Action::CommunityPoolSpend(_) => describe(
+            "CommunityPoolSpend",
+            "Spend funds from the community pool",
+            &["community_pool"],
+        ),
+        Action::CommunityPoolOutput(_) => describe(
+            "CommunityPoolOutput",
+            "Create a note from community pool funds",
+            &["community_pool", "shielded_pool", "sct"],
+        ),
+        Action::CommunityPoolDeposit(_) => describe(
+            "CommunityPoolDeposit",
+            "Deposit funds into the community pool",
+            &["community_pool"],
+        ),
+        Action::ActionDutchAuctionSchedule(_) => describe(
+            "ActionDutchAuctionSchedule",
+            "Schedule a Dutch auction",
+            &["auction", "dex"],
+        ),
+        Action::ActionDutchAuctionEnd(_) => describe(
+            "ActionDutchAuctionEnd",
+            "End a Dutch auction",
+            &["auction", "dex"],
+        ),
+        Action::ActionDutchAuctionWithdraw(_) => describe(
+            "ActionDutchAuctionWithdraw",
+            "Withdraw the reserves of an ended Dutch auction",
+            &["auction"],
+        ),
+    }
+}
+
+fn describe(
+    variant: &'static str,
+    summary: &'static str,
+    touches: &'static [&'static str],
+) -> ActionDescription {
+    ActionDescription {
+        variant,
+        summary,
+        touches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Deref;
+
+    use anyhow::Result;
+    use penumbra_asset::{Value, STAKING_TOKEN_ASSET_ID};
+    use penumbra_fee::Fee;
+    use penumbra_keys::test_keys;
+    use penumbra_shielded_pool::{Note, OutputPlan, SpendPlan};
+    use penumbra_tct as tct;
+    use penumbra_transaction::{plan::TransactionPlan, TransactionParameters, WitnessData};
+    use rand_core::OsRng;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn describes_spend_and_output() -> Result<()> {
+        let value = Value {
+            amount: 100u64.into(),
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let note = Note::generate(&mut OsRng, &test_keys::ADDRESS_0, value);
+        let mut sct = tct::Tree::new();
+        sct.insert(tct::Witness::Keep, note.commit())
+            .expect("can insert into the tree");
+        let auth_path = sct.witness(note.commit()).expect("note was just inserted");
+
+        let plan = TransactionPlan {
+            transaction_parameters: TransactionParameters {
+                expiry_height: 0,
+                fee: Fee::default(),
+                chain_id: "".into(),
+            },
+            actions: vec![
+                SpendPlan::new(&mut OsRng, note.clone(), auth_path.position()).into(),
+                OutputPlan::new(&mut OsRng, value, test_keys::ADDRESS_1.deref().clone()).into(),
+            ],
+            detection_data: None,
+            memo: None,
+        };
+        let auth_data = plan.authorize(OsRng, &test_keys::SPEND_KEY)?;
+        let witness_data = WitnessData {
+            anchor: sct.root(),
+            state_commitment_proofs: [(note.commit(), auth_path)].into_iter().collect(),
+        };
+        let tx = plan
+            .build_concurrent(&test_keys::FULL_VIEWING_KEY, &witness_data, &auth_data)
+            .await?;
+
+        let descriptions = describe_actions(&tx.transaction_body().actions);
+        assert_eq!(
+            descriptions
+                .iter()
+                .map(|d| (d.variant, d.summary))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Spend", "Spend an existing note"),
+                ("Output", "Create a new note"),
+            ]
+        );
+        assert!(descriptions
+            .iter()
+            .all(|d| d.touches.contains(&"shielded_pool")));
+
+        Ok(())
+    }
+}
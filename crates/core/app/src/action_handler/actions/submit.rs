@@ -27,7 +27,9 @@ use penumbra_transaction::{AuthorizationData, Transaction, TransactionPlan, Witn
 
 use crate::app::StateReadExt;
 use crate::community_pool_ext::CommunityPoolStateWriteExt;
-use crate::{action_handler::AppActionHandler, params::change::ParameterChangeExt as _};
+use crate::{
+    action_handler::AppActionHandler, params::change::ParameterChangeExt as _, VerificationPool,
+};
 
 // IMPORTANT: these length limits are enforced by consensus! Changing them will change which
 // transactions are accepted by the network, and so they *cannot* be changed without a network
@@ -186,9 +188,11 @@ impl AppActionHandler for ProposalSubmit {
                 let tx = build_community_pool_transaction(parsed_transaction_plan.clone())
                     .await
                     .context("failed to build submitted Community Pool spend transaction plan")?;
-                tx.check_stateless(()).await.context(
-                    "submitted Community Pool spend transaction failed stateless checks",
-                )?;
+                tx.check_stateless(VerificationPool::shared())
+                    .await
+                    .context(
+                        "submitted Community Pool spend transaction failed stateless checks",
+                    )?;
                 /*
                 // We skip stateful checks rather than doing them in simulation. Partly this is
                 // because it's easier to not check, but also it avoids having to reason about whether
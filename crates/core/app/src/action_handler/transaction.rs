@@ -11,6 +11,7 @@ use tokio::task::JoinSet;
 use tracing::{instrument, Instrument};
 
 use super::AppActionHandler;
+use crate::VerificationPool;
 
 mod stateful;
 mod stateless;
@@ -25,11 +26,11 @@ use stateless::{
 
 #[async_trait]
 impl AppActionHandler for Transaction {
-    type CheckStatelessContext = ();
+    type CheckStatelessContext = VerificationPool;
 
     // We only instrument the top-level `check_stateless`, so we get one span for each transaction.
-    #[instrument(skip(self, _context))]
-    async fn check_stateless(&self, _context: ()) -> Result<()> {
+    #[instrument(skip(self, pool))]
+    async fn check_stateless(&self, pool: VerificationPool) -> Result<()> {
         // This check should be done first, and complete before all other
         // stateless checks, like proof verification.  In addition to proving
         // that value balances, the binding signature binds the proofs to the
@@ -51,20 +52,24 @@ impl AppActionHandler for Transaction {
 
         let context = self.context();
 
-        // Currently, we need to clone the component actions so that the spawned
-        // futures can have 'static lifetimes. In the future, we could try to
-        // use the yoke crate, but cloning is almost certainly not a big deal
-        // for now.
-        let mut action_checks = JoinSet::new();
-        for (i, action) in self.actions().cloned().enumerate() {
+        // Currently, we need to clone the component actions so that the
+        // verification jobs can have 'static lifetimes. In the future, we could
+        // try to use the yoke crate, but cloning is almost certainly not a big
+        // deal for now.
+        //
+        // Stateless checks don't perform any I/O, so each one is driven to
+        // completion on a thread of the verification pool, which bounds the
+        // number of proofs verified at once across all transactions.
+        let action_checks = self.actions().cloned().enumerate().map(|(i, action)| {
             let context2 = context.clone();
             let span = action.create_span(i);
-            action_checks
-                .spawn(async move { action.check_stateless(context2).await }.instrument(span));
-        }
+            pool.run(move || {
+                futures::executor::block_on(action.check_stateless(context2).instrument(span))
+            })
+        });
         // Now check if any component action failed verification.
-        while let Some(check) = action_checks.join_next().await {
-            check??;
+        for check in futures::future::try_join_all(action_checks).await? {
+            check?;
         }
 
         Ok(())
@@ -147,21 +152,26 @@ impl AppActionHandler for Transaction {
 
 #[cfg(test)]
 mod tests {
-    use std::ops::Deref;
+    use std::{ops::Deref, sync::Arc};
 
     use anyhow::Result;
+    use cnidarium::TempStorage;
     use penumbra_asset::{Value, STAKING_TOKEN_ASSET_ID};
     use penumbra_fee::Fee;
     use penumbra_keys::test_keys;
+    use penumbra_proto::DomainType;
     use penumbra_shielded_pool::{Note, OutputPlan, SpendPlan};
     use penumbra_tct as tct;
     use penumbra_transaction::{
         plan::{CluePlan, DetectionDataPlan, TransactionPlan},
-        TransactionParameters, WitnessData,
+        Transaction, TransactionParameters, WitnessData,
     };
     use rand_core::OsRng;
 
-    use crate::AppActionHandler;
+    use crate::{
+        app::{App, StatelessLimits},
+        AppActionHandler, VerificationPool,
+    };
 
     #[tokio::test]
     async fn check_stateless_succeeds_on_valid_spend() -> Result<()> {
@@ -302,9 +312,113 @@ mod tests {
         tx.anchor = wrong_root;
 
         // On the verifier side, perform stateless verification.
-        let result = tx.check_stateless(()).await;
+        let result = tx.check_stateless(VerificationPool::shared()).await;
         assert!(result.is_err());
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn stateless_limits_reject_before_verification() -> Result<()> {
+        let tx_bytes = spend_transaction(false).await?.encode_to_vec();
+
+        let storage = TempStorage::new().await?;
+        let pool = VerificationPool::new(1)?;
+        let mut app = App::new(storage.latest_snapshot()).with_verification_pool(pool.clone());
+
+        // The transaction has a spend and an output, one more action than
+        // allowed, so it is rejected without verifying any proof.
+        let limits = StatelessLimits {
+            max_actions: 1,
+            ..Default::default()
+        };
+        assert!(app.check_tx_bytes(&tx_bytes, &limits).await.is_err());
+        assert_eq!(pool.jobs_started(), 0);
+
+        // Within the limits, both actions are verified, whether or not the
+        // transaction is accepted afterwards.
+        let _ = app
+            .check_tx_bytes(&tx_bytes, &StatelessLimits::default())
+            .await;
+        assert_eq!(pool.jobs_started(), 2);
+
+        // The limits are a mempool policy, which delivering a transaction in a
+        // block doesn't apply.
+        let _ = app.deliver_tx_bytes(&tx_bytes).await;
+        assert_eq!(pool.jobs_started(), 4);
+
+        Ok(())
+    }
+
+    /// Builds a transaction with a spend and an output, whose spend proof
+    /// fails to verify if `wrong_anchor` is set.
+    async fn spend_transaction(wrong_anchor: bool) -> Result<Transaction> {
+        let value = Value {
+            amount: 100u64.into(),
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let note = Note::generate(&mut OsRng, &test_keys::ADDRESS_0, value);
+
+        let mut sct = tct::Tree::new();
+        let empty_root = sct.root();
+        sct.insert(tct::Witness::Keep, note.commit()).unwrap();
+        let auth_path = sct.witness(note.commit()).unwrap();
+
+        let plan = TransactionPlan {
+            transaction_parameters: TransactionParameters {
+                expiry_height: 0,
+                fee: Fee::default(),
+                chain_id: "".into(),
+            },
+            actions: vec![
+                SpendPlan::new(&mut OsRng, note, auth_path.position()).into(),
+                OutputPlan::new(&mut OsRng, value, test_keys::ADDRESS_1.deref().clone()).into(),
+            ],
+            detection_data: None,
+            memo: None,
+        };
+
+        let fvk = &test_keys::FULL_VIEWING_KEY;
+        let sk = &test_keys::SPEND_KEY;
+        let auth_data = plan.authorize(OsRng, sk)?;
+        let witness_data = WitnessData {
+            anchor: sct.root(),
+            state_commitment_proofs: plan
+                .spend_plans()
+                .map(|spend| {
+                    (
+                        spend.note.commit(),
+                        sct.witness(spend.note.commit()).unwrap(),
+                    )
+                })
+                .collect(),
+        };
+        let mut tx = plan
+            .build_concurrent(fvk, &witness_data, &auth_data)
+            .await?;
+        if wrong_anchor {
+            tx.anchor = empty_root;
+        }
+        Ok(tx)
+    }
+
+    #[tokio::test]
+    async fn check_stateless_is_independent_of_pool_size() -> Result<()> {
+        let valid = Arc::new(spend_transaction(false).await?);
+        let invalid = Arc::new(spend_transaction(true).await?);
+        let batch = [valid.clone(), invalid, valid];
+
+        for threads in [1, 2, 4] {
+            let pool = VerificationPool::new(threads)?;
+            assert_eq!(pool.threads(), threads);
+            let results =
+                futures::future::join_all(batch.iter().map(|tx| tx.check_stateless(pool.clone())))
+                    .await;
+            // The single bad proof fails its transaction, and only that one.
+            let passed: Vec<bool> = results.iter().map(Result::is_ok).collect();
+            assert_eq!(passed, [true, false, true], "with {threads} threads");
+        }
+
+        Ok(())
+    }
 }
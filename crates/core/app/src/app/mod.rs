@@ -4,7 +4,10 @@ use std::time::Duration;
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use cnidarium::{ArcStateDeltaExt, Snapshot, StateDelta, StateRead, StateWrite, Storage};
+use cnidarium::{
+    ArcStateDeltaExt, OverlayOp, Snapshot, StateDelta, StateRead, StateWrite, StateWriteExt as _,
+    Storage,
+};
 use cnidarium_component::Component;
 use ibc_types::core::connection::ChainId;
 use jmt::RootHash;
@@ -25,13 +28,15 @@ use penumbra_proto::core::app::v1::TransactionsByHeightResponse;
 use penumbra_proto::DomainType;
 use penumbra_sct::component::clock::EpochRead;
 use penumbra_sct::component::sct::Sct;
+use penumbra_sct::component::source::SourceContext as _;
 use penumbra_sct::component::{StateReadExt as _, StateWriteExt as _};
 use penumbra_sct::epoch::Epoch;
+use penumbra_sct::CommitmentSource;
 use penumbra_shielded_pool::component::{ShieldedPool, StateReadExt as _, StateWriteExt as _};
 use penumbra_stake::component::{
     stake::ConsensusUpdateRead, Staking, StateReadExt as _, StateWriteExt as _,
 };
-use penumbra_transaction::Transaction;
+use penumbra_transaction::{Action, Transaction};
 use prost::Message as _;
 use tendermint::abci::{self, Event};
 
@@ -44,7 +49,7 @@ use crate::action_handler::AppActionHandler;
 use crate::genesis::AppState;
 use crate::params::change::ParameterChangeExt as _;
 use crate::params::AppParameters;
-use crate::{CommunityPoolStateReadExt, PenumbraHost};
+use crate::{CommunityPoolStateReadExt, PenumbraHost, VerificationPool};
 
 pub mod state_key;
 
@@ -60,6 +65,53 @@ pub const MAX_TRANSACTION_SIZE_BYTES: usize = 96 * 1024;
 /// The maximum size of the evidence portion of a block (30KB).
 pub const MAX_EVIDENCE_SIZE_BYTES: usize = 30 * 1024;
 
+/// Bounds on the shape of a transaction, checked by [`App::check_tx_bytes`]
+/// before any of its proofs are verified.
+///
+/// These are a cheap guard against transactions crafted to consume verification
+/// time. They are a local mempool policy, set by each node, so they are never
+/// applied to the transactions of a block. By default, the action counts are
+/// unbounded and the size is capped at [`MAX_TRANSACTION_SIZE_BYTES`], so no
+/// transaction that could be included in a block is rejected.
+#[derive(Clone, Debug)]
+pub struct StatelessLimits {
+    /// The maximum number of actions in a transaction.
+    pub max_actions: usize,
+    /// The maximum size of the encoded transaction, in bytes.
+    pub max_encoded_bytes: usize,
+    /// The maximum number of `Spend` actions in a transaction.
+    pub max_spends: usize,
+    /// The maximum number of `Output` actions in a transaction.
+    pub max_outputs: usize,
+    /// The maximum number of `Swap` actions in a transaction.
+    pub max_swaps: usize,
+}
+
+impl Default for StatelessLimits {
+    fn default() -> Self {
+        Self {
+            max_actions: usize::MAX,
+            max_encoded_bytes: MAX_TRANSACTION_SIZE_BYTES,
+            max_spends: usize::MAX,
+            max_outputs: usize::MAX,
+            max_swaps: usize::MAX,
+        }
+    }
+}
+
+/// The effects of the actions executed by [`App::simulate_to_root`].
+#[derive(Clone, Debug, Default)]
+pub struct ExecutionSummary {
+    /// The number of actions that were executed.
+    pub actions: usize,
+    /// The number of keys written or deleted in the verifiable store.
+    pub verifiable_writes: usize,
+    /// The number of keys written or deleted in the nonverifiable store.
+    pub nonverifiable_writes: usize,
+    /// The events emitted while executing the actions.
+    pub events: Vec<Event>,
+}
+
 /// The Penumbra application, written as a bundle of [`Component`]s.
 ///
 /// The [`App`] is not a [`Component`], but
@@ -67,6 +119,7 @@ pub const MAX_EVIDENCE_SIZE_BYTES: usize = 30 * 1024;
 /// commits the changes to the persistent storage and resets its subcomponents.
 pub struct App {
     state: InterBlockState,
+    verification_pool: VerificationPool,
 }
 
 impl App {
@@ -80,7 +133,17 @@ impl App {
         // there should be no unexpected copies elsewhere.
         let state = Arc::new(StateDelta::new(snapshot));
 
-        Self { state }
+        Self {
+            state,
+            verification_pool: VerificationPool::shared(),
+        }
+    }
+
+    /// Sets the pool on which the proofs of delivered transactions are
+    /// verified, instead of the [shared](VerificationPool::shared) one.
+    pub fn with_verification_pool(mut self, pool: VerificationPool) -> Self {
+        self.verification_pool = pool;
+        self
     }
 
     /// Returns whether the application is ready to start.
@@ -101,14 +164,18 @@ impl App {
     // Invariant: `state_tx` and `self.state` are the only two references to the
     // inter-block state.
     fn apply(&mut self, state_tx: StateDelta<InterBlockState>) -> Vec<Event> {
-        let (state2, mut cache) = state_tx.flatten();
+        let (state2, mut cache) = state_tx
+            .flatten()
+            .expect("must be able to read back spilled state changes");
         std::mem::drop(state2);
         // Now there is only one reference to the inter-block state: self.state
 
         let events = cache.take_events();
-        cache.apply_to(
-            Arc::get_mut(&mut self.state).expect("no other references to inter-block state"),
-        );
+        cache
+            .apply_to(
+                Arc::get_mut(&mut self.state).expect("no other references to inter-block state"),
+            )
+            .expect("must be able to read back spilled state changes");
 
         events
     }
@@ -391,6 +458,27 @@ impl App {
             .context("failed to deliver transaction")
     }
 
+    /// Checks a transaction submitted to the mempool, by delivering it like
+    /// [`Self::deliver_tx_bytes`].
+    ///
+    /// Transactions exceeding the `limits` are rejected upfront, before
+    /// spending any time on verifying their proofs. The limits are only
+    /// enforced here: blocks are checked with [`Self::deliver_tx`], which
+    /// doesn't apply them, so that nodes with different limits agree on the
+    /// validity of a block.
+    pub async fn check_tx_bytes(
+        &mut self,
+        tx_bytes: &[u8],
+        limits: &StatelessLimits,
+    ) -> Result<Vec<abci::Event>> {
+        let tx = Arc::new(Transaction::decode(tx_bytes).context("decoding transaction")?);
+        self.check_stateless_limits(&tx, limits)
+            .context("transaction exceeds stateless limits")?;
+        self.deliver_tx(tx)
+            .await
+            .context("failed to deliver transaction")
+    }
+
     pub async fn deliver_tx(&mut self, tx: Arc<Transaction>) -> Result<Vec<abci::Event>> {
         // Ensure that any normally-delivered transaction (originating from a user) does not contain
         // any Community Pool spends or outputs; the only place those are permitted is transactions originating
@@ -419,8 +507,9 @@ impl App {
         // We spawn tasks for each set of checks, to do CPU-bound stateless checks
         // and I/O-bound stateful checks at the same time.
         let tx2 = tx.clone();
+        let pool = self.verification_pool.clone();
         let stateless = tokio::spawn(
-            async move { tx2.check_stateless(()).await }.instrument(tracing::Span::current()),
+            async move { tx2.check_stateless(pool).await }.instrument(tracing::Span::current()),
         );
         let tx2 = tx.clone();
         let state2 = self.state.clone();
@@ -462,6 +551,90 @@ impl App {
         Ok(state_tx.apply().1)
     }
 
+    /// Executes `actions` in order on top of the pending state of the
+    /// application, and returns the root hash that committing the result to
+    /// `storage` would produce, along with a summary of the execution.
+    ///
+    /// Execution happens in a throwaway copy of the pending state, which is
+    /// discarded: neither `storage` nor the application state are modified.
+    /// Only the actions' stateful execution is performed, without their
+    /// stateless or historical checks, and without a transaction to pay fees
+    /// or provide a commitment source: new commitments are attributed to an
+    /// anonymous transaction.
+    ///
+    /// Ephemeral objects written by earlier transactions of the block are not
+    /// carried into the copy. The pending state must be on top of the latest
+    /// version of `storage`, as for [`App::commit`].
+    pub async fn simulate_to_root(
+        &self,
+        storage: &Storage,
+        actions: &[Action],
+    ) -> Result<(RootHash, ExecutionSummary)> {
+        let mut working = StateDelta::new(storage.latest_snapshot());
+        working.replay_ops(&self.state.overlay_ops()?);
+
+        let mut state_tx = StateDelta::new(&mut working);
+        state_tx.put_current_source(Some(CommitmentSource::Transaction { id: None }));
+        for (i, action) in actions.iter().enumerate() {
+            action
+                .check_and_execute(&mut state_tx)
+                .await
+                .with_context(|| format!("executing action {i}"))?;
+        }
+        state_tx.put_current_source(None);
+
+        let ops = state_tx.overlay_ops()?;
+        let verifiable_writes = ops
+            .iter()
+            .filter(|op| matches!(op, OverlayOp::Put { .. } | OverlayOp::Delete { .. }))
+            .count();
+        let summary = ExecutionSummary {
+            actions: actions.len(),
+            verifiable_writes,
+            nonverifiable_writes: ops.len() - verifiable_writes,
+            events: state_tx.apply().1,
+        };
+
+        let root_hash = storage
+            .compute_root(&working)
+            .await
+            .context("computing the simulated root")?;
+        Ok((root_hash, summary))
+    }
+
+    /// Checks that the transaction's shape is within the supplied `limits`.
+    ///
+    /// This only inspects the transaction, and is cheap enough to run before
+    /// any proof verification.
+    pub fn check_stateless_limits(&self, tx: &Transaction, limits: &StatelessLimits) -> Result<()> {
+        let num_actions = tx.actions().count();
+        anyhow::ensure!(
+            num_actions <= limits.max_actions,
+            "transaction has {num_actions} actions, exceeding the limit of {}",
+            limits.max_actions
+        );
+
+        let encoded_bytes = tx.encode_to_vec().len();
+        anyhow::ensure!(
+            encoded_bytes <= limits.max_encoded_bytes,
+            "transaction is {encoded_bytes} bytes, exceeding the limit of {}",
+            limits.max_encoded_bytes
+        );
+
+        for (name, count, limit) in [
+            ("spend", tx.spends().count(), limits.max_spends),
+            ("output", tx.outputs().count(), limits.max_outputs),
+            ("swap", tx.swaps().count(), limits.max_swaps),
+        ] {
+            anyhow::ensure!(
+                count <= limit,
+                "transaction has {count} {name} actions, exceeding the limit of {limit}"
+            );
+        }
+
+        Ok(())
+    }
+
     #[tracing::instrument(skip_all, fields(height = %end_block.height))]
     pub async fn end_block(&mut self, end_block: &request::EndBlock) -> Vec<abci::Event> {
         let state_tx = StateDelta::new(self.state.clone());
@@ -610,7 +783,7 @@ impl App {
         }
 
         // Commit the pending writes, clearing the state.
-        let jmt_root = storage
+        let (_, jmt_root) = storage
             .commit(state)
             .await
             .expect("must be able to successfully commit to storage");
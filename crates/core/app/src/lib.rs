@@ -13,6 +13,9 @@ pub static SUBSTORE_PREFIXES: Lazy<Vec<String>> = Lazy::new(|| {
 /// The substore prefix used for storing historical CometBFT block data.
 pub static COMETBFT_SUBSTORE_PREFIX: &'static str = "cometbft-data";
 
+pub mod action_description;
+pub use action_description::{describe_actions, ActionDescription};
+
 pub mod app_version;
 pub use app_version::APP_VERSION;
 
@@ -29,11 +32,12 @@ cfg_if::cfg_if! {
         mod action_handler;
         mod community_pool_ext;
         mod penumbra_host_chain;
+        mod verification_pool;
 
         pub use crate::{
             action_handler::AppActionHandler, app::StateWriteExt,
             community_pool_ext::CommunityPoolStateReadExt, metrics::register_metrics,
-            penumbra_host_chain::PenumbraHost,
+            penumbra_host_chain::PenumbraHost, verification_pool::VerificationPool,
         };
 
         /// Temporary compat wrapper for duplicate trait impls
@@ -10,12 +10,16 @@ use tokio::sync::mpsc;
 use tower_actor::Message;
 use tracing::Instrument;
 
-use crate::{app::App, metrics};
+use crate::{
+    app::{App, StatelessLimits},
+    metrics,
+};
 
 /// A mempool service that applies transaction checks against an isolated application fork.
 pub struct Mempool {
     queue: mpsc::Receiver<Message<Request, Response, tower::BoxError>>,
     storage: Storage,
+    stateless_limits: StatelessLimits,
 }
 
 impl Mempool {
@@ -23,7 +27,18 @@ impl Mempool {
         storage: Storage,
         queue: mpsc::Receiver<Message<Request, Response, tower::BoxError>>,
     ) -> Self {
-        Self { queue, storage }
+        Self {
+            queue,
+            storage,
+            stateless_limits: StatelessLimits::default(),
+        }
+    }
+
+    /// Sets the limits that transactions are checked against before their
+    /// proofs are verified, see [`App::check_tx_bytes`].
+    pub fn with_stateless_limits(mut self, limits: StatelessLimits) -> Self {
+        self.stateless_limits = limits;
+        self
     }
 
     pub async fn check_tx(&mut self, req: Request) -> Result<Response, tower::BoxError> {
@@ -39,7 +54,10 @@ impl Mempool {
 
         let mut app = App::new(self.storage.latest_snapshot());
 
-        match app.deliver_tx_bytes(tx_bytes.as_ref()).await {
+        match app
+            .check_tx_bytes(tx_bytes.as_ref(), &self.stateless_limits)
+            .await
+        {
             Ok(events) => {
                 let elapsed = start.elapsed();
                 tracing::info!(?elapsed, "tx accepted");
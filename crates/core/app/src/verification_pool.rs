@@ -0,0 +1,107 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use anyhow::{Context as _, Result};
+use once_cell::sync::OnceCell;
+
+/// The pool used by default, see [`VerificationPool::shared`].
+static SHARED: OnceCell<VerificationPool> = OnceCell::new();
+
+/// A fixed-size pool of threads on which the stateless checks of actions,
+/// including the verification of their proofs, are run.
+///
+/// Every action of every transaction being checked is a separate job, so
+/// running them on a bounded pool, rather than on the async runtime, schedules
+/// the verification work of a whole block on a fixed set of cores, however many
+/// proofs it holds.
+///
+/// Cloning a pool is cheap, and clones share the same threads.
+#[derive(Clone, Debug)]
+pub struct VerificationPool {
+    pool: Arc<rayon::ThreadPool>,
+    /// The number of jobs started on the pool, see [`VerificationPool::jobs_started`].
+    jobs_started: Arc<AtomicU64>,
+}
+
+impl VerificationPool {
+    /// Creates a pool with `threads` threads.
+    pub fn new(threads: usize) -> Result<Self> {
+        anyhow::ensure!(
+            threads > 0,
+            "the verification pool needs at least one thread"
+        );
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("proof-verification-{i}"))
+            .build()
+            .context("failed to build the proof verification pool")?;
+        Ok(Self {
+            pool: Arc::new(pool),
+            jobs_started: Arc::default(),
+        })
+    }
+
+    /// Sets the number of threads of the [shared](VerificationPool::shared) pool.
+    ///
+    /// This must be called at most once, before the shared pool is first used.
+    pub fn init_shared(threads: usize) -> Result<()> {
+        SHARED
+            .set(Self::new(threads)?)
+            .map_err(|_| anyhow::anyhow!("the shared verification pool is already initialized"))
+    }
+
+    /// Returns the pool used by [`App`](crate::app::App) instances that were not
+    /// given one with [`App::with_verification_pool`](crate::app::App::with_verification_pool).
+    ///
+    /// Unless it was sized with [`VerificationPool::init_shared`], it has one
+    /// thread per available core.
+    pub fn shared() -> Self {
+        SHARED
+            .get_or_init(|| {
+                let threads = std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1);
+                Self::new(threads).expect("can build the shared verification pool")
+            })
+            .clone()
+    }
+
+    /// Returns the number of threads of the pool.
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Returns the number of jobs started on the pool. Each job runs the
+    /// stateless checks of a single action, including verifying its proof.
+    pub fn jobs_started(&self) -> u64 {
+        self.jobs_started.load(Ordering::Relaxed)
+    }
+
+    /// Runs `f` on the pool, and returns its result.
+    ///
+    /// When called from one of the pool's own threads, `f` runs in place, so
+    /// that a job never waits for another one queued behind it.
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.jobs_started.fetch_add(1, Ordering::Relaxed);
+        if self.pool.current_thread_index().is_some() {
+            return Ok(f());
+        }
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.pool.spawn(move || {
+            let _ = tx.send(f());
+        });
+        rx.await.context("proof verification job was dropped")
+    }
+}
+
+impl Default for VerificationPool {
+    fn default() -> Self {
+        Self::shared()
+    }
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use self::common::TempStorageExt;
+use cnidarium::{StateDelta, TempStorage};
+use penumbra_app::{app::App, AppActionHandler};
+use penumbra_asset::{Value, STAKING_TOKEN_ASSET_ID};
+use penumbra_keys::{symmetric::PayloadKey, test_keys};
+use penumbra_sct::{component::source::SourceContext, CommitmentSource};
+use penumbra_shielded_pool::OutputPlan;
+use penumbra_transaction::Action;
+use rand_core::OsRng;
+use std::ops::Deref;
+
+/// Simulating a set of actions yields the root that executing and committing
+/// them produces, without modifying the storage.
+#[tokio::test]
+async fn app_simulate_to_root_matches_commit() -> anyhow::Result<()> {
+    let storage = TempStorage::new_with_penumbra_prefixes()
+        .await?
+        .apply_default_genesis()
+        .await?;
+    let version = storage.latest_version();
+
+    let actions: Vec<Action> = (0..2)
+        .map(|amount| {
+            let value = Value {
+                amount: (amount + 1u64).into(),
+                asset_id: *STAKING_TOKEN_ASSET_ID,
+            };
+            let plan = OutputPlan::new(&mut OsRng, value, test_keys::ADDRESS_1.deref().clone());
+            Action::Output(plan.output(
+                test_keys::FULL_VIEWING_KEY.outgoing(),
+                &PayloadKey::random_key(&mut OsRng),
+            ))
+        })
+        .collect();
+
+    let app = App::new(storage.latest_snapshot());
+    let (simulated_root, summary) = app.simulate_to_root(&storage, &actions).await?;
+    assert_eq!(summary.actions, 2);
+    assert_eq!(summary.events.len(), 2);
+    assert_eq!(storage.latest_version(), version);
+
+    let mut state = StateDelta::new(storage.latest_snapshot());
+    state.put_current_source(Some(CommitmentSource::Transaction { id: None }));
+    for action in &actions {
+        action.check_and_execute(&mut state).await?;
+    }
+    state.put_current_source(None);
+    let (_, committed_root) = storage.commit(state).await?;
+
+    assert_eq!(simulated_root, committed_root);
+
+    Ok(())
+}
@@ -104,11 +104,11 @@ pub trait Arbitrage: StateWrite + Sized {
         let (self2, cache) = Arc::try_unwrap(this)
             .map_err(|_| ())
             .expect("no more outstanding refs to state after routing")
-            .flatten();
+            .flatten()?;
         std::mem::drop(self2);
         // Now there is only one reference to self again
         let mut self_mut = Arc::get_mut(self).expect("self was unique ref");
-        cache.apply_to(&mut self_mut);
+        cache.apply_to(&mut self_mut)?;
 
         // Finally, record the arb execution in the state:
         let height = self_mut.get_block_height().await?;
@@ -5,6 +5,7 @@ use std::{
 };
 
 use anyhow::{Context as _, Result};
+use cnidarium::DecodedCache;
 use pin_project::pin_project;
 use prost::Message;
 
@@ -15,15 +16,24 @@ use crate::DomainType;
 pub struct ProtoFuture<P, F> {
     #[pin]
     pub(super) inner: F,
+    /// The key being read, reported if the value cannot be decoded.
+    pub(super) key: String,
     pub(super) _marker: std::marker::PhantomData<P>,
 }
 
 /// A future that resolves to a domain type.
 #[pin_project]
 pub struct DomainFuture<D, F> {
+    /// The pending read, or `None` if the value was found in the cache.
     #[pin]
-    pub(super) inner: F,
-    pub(super) _marker: std::marker::PhantomData<D>,
+    pub(super) inner: Option<F>,
+    /// The key being read, reported if the value cannot be decoded.
+    pub(super) key: String,
+    /// The value found in the state's decoded cache, if any.
+    pub(super) cached: Option<D>,
+    /// The cache to insert the decoded value into, along with its generation
+    /// when the read was issued.
+    pub(super) cache: Option<(DecodedCache, u64)>,
 }
 
 impl<F, P> Future for ProtoFuture<P, F>
@@ -36,7 +46,9 @@ where
         let this = self.project();
         match this.inner.poll(cx) {
             Poll::Ready(Ok(Some(bytes))) => {
-                let v = P::decode(&*bytes).context("could not decode proto from bytes")?;
+                let v = P::decode(&*bytes).with_context(|| {
+                    format!("could not decode proto from bytes at key {}", this.key)
+                })?;
                 Poll::Ready(Ok(Some(v)))
             }
             Poll::Ready(Ok(None)) => Poll::Ready(Ok(None)),
@@ -49,18 +61,28 @@ where
 impl<D, F> Future for DomainFuture<D, F>
 where
     F: Future<Output = Result<Option<Vec<u8>>>>,
-    D: DomainType,
+    D: DomainType + Send + Sync + 'static,
     anyhow::Error: From<<D as TryFrom<D::Proto>>::Error>,
 {
     type Output = Result<Option<D>>;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        match this.inner.poll(cx) {
+        let Some(inner) = this.inner.as_pin_mut() else {
+            return Poll::Ready(Ok(this.cached.take()));
+        };
+        match inner.poll(cx) {
             Poll::Ready(Ok(Some(bytes))) => {
-                let v = D::Proto::decode(&*bytes).context("could not decode proto from bytes")?;
+                let v = D::Proto::decode(&*bytes).with_context(|| {
+                    format!("could not decode proto from bytes at key {}", this.key)
+                })?;
                 let v = D::try_from(v)
                     .map_err(anyhow::Error::from)
-                    .context("could not parse domain type from proto")?;
+                    .with_context(|| {
+                        format!("could not parse domain type from proto at key {}", this.key)
+                    })?;
+                if let Some((cache, generation)) = this.cache.take() {
+                    cache.insert(this.key, v.clone(), generation);
+                }
                 Poll::Ready(Ok(Some(v)))
             }
             Poll::Ready(Ok(None)) => Poll::Ready(Ok(None)),
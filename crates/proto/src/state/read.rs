@@ -15,14 +15,36 @@ pub trait StateReadProto: StateRead + Send + Sync {
     /// * `Ok(Some(v))` if the value is present and parseable as a domain type `D`;
     /// * `Ok(None)` if the value is missing;
     /// * `Err(_)` if the value is present but not parseable as a domain type `D`, or if an underlying storage error occurred.
+    ///
+    /// When the state has a [decoded cache](cnidarium::StateRead::decoded_cache),
+    /// e.g. in a [`StateDelta`](cnidarium::StateDelta), the decoded value is
+    /// kept there, so that reading the same key again as a `D` does not decode
+    /// it again until the key is written.
     fn get<D>(&self, key: &str) -> DomainFuture<D, Self::GetRawFut>
     where
-        D: DomainType + std::fmt::Debug,
+        D: DomainType + std::fmt::Debug + Send + Sync + 'static,
         anyhow::Error: From<<D as TryFrom<D::Proto>>::Error>,
     {
+        let cache = self.decoded_cache();
+        if let Some(value) = cache.as_ref().and_then(|cache| cache.get::<D>(key)) {
+            return DomainFuture {
+                inner: None,
+                key: key.to_string(),
+                cached: Some(value),
+                cache: None,
+            };
+        }
+        // The generation is read before the read is issued, so that a write
+        // made while it is pending prevents caching its result.
+        let cache = cache.map(|cache| {
+            let generation = cache.generation();
+            (cache, generation)
+        });
         DomainFuture {
-            inner: self.get_raw(key),
-            _marker: std::marker::PhantomData,
+            inner: Some(self.get_raw(key)),
+            key: key.to_string(),
+            cached: None,
+            cache,
         }
     }
 
@@ -35,12 +57,14 @@ pub trait StateReadProto: StateRead + Send + Sync {
     /// * `Err(_)` if the value is present but not parseable as a domain type `D`, or if an underlying storage error occurred.
     fn nonverifiable_get<D>(&self, key: &[u8]) -> DomainFuture<D, Self::GetRawFut>
     where
-        D: DomainType + std::fmt::Debug,
+        D: DomainType + std::fmt::Debug + Send + Sync + 'static,
         anyhow::Error: From<<D as TryFrom<D::Proto>>::Error>,
     {
         DomainFuture {
-            inner: self.nonverifiable_get_raw(key),
-            _marker: std::marker::PhantomData,
+            inner: Some(self.nonverifiable_get_raw(key)),
+            key: format!("{:?}", cnidarium::EscapedByteSlice(key)),
+            cached: None,
+            cache: None,
         }
     }
 
@@ -57,6 +81,7 @@ pub trait StateReadProto: StateRead + Send + Sync {
     {
         ProtoFuture {
             inner: self.get_raw(key),
+            key: key.to_string(),
             _marker: std::marker::PhantomData,
         }
     }
@@ -74,6 +99,7 @@ pub trait StateReadProto: StateRead + Send + Sync {
     {
         ProtoFuture {
             inner: self.nonverifiable_get_raw(key),
+            key: format!("{:?}", cnidarium::EscapedByteSlice(key)),
             _marker: std::marker::PhantomData,
         }
     }
@@ -157,3 +183,75 @@ pub trait StateReadProto: StateRead + Send + Sync {
     }
 }
 impl<T: StateRead + ?Sized> StateReadProto for T {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use cnidarium::{StateDelta, StateWrite};
+
+    use super::*;
+    use crate::core::num::v1::Amount;
+
+    /// The number of [`Counted`] values decoded so far.
+    static DECODES: AtomicUsize = AtomicUsize::new(0);
+
+    /// A domain type that counts how many times it is decoded.
+    #[derive(Clone, Debug, PartialEq)]
+    struct Counted(u64);
+
+    impl DomainType for Counted {
+        type Proto = Amount;
+    }
+
+    impl From<Counted> for Amount {
+        fn from(value: Counted) -> Self {
+            Amount { lo: value.0, hi: 0 }
+        }
+    }
+
+    impl TryFrom<Amount> for Counted {
+        type Error = anyhow::Error;
+
+        fn try_from(proto: Amount) -> Result<Self> {
+            DECODES.fetch_add(1, Ordering::SeqCst);
+            Ok(Counted(proto.lo))
+        }
+    }
+
+    #[tokio::test]
+    async fn get_decodes_once_until_written() -> Result<()> {
+        let mut state = StateDelta::new(());
+        state.put_raw(
+            "params".to_string(),
+            Amount::from(Counted(1)).encode_to_vec(),
+        );
+        let decodes = DECODES.load(Ordering::SeqCst);
+
+        // Reading the same key twice only decodes it once.
+        assert_eq!(state.get::<Counted>("params").await?, Some(Counted(1)));
+        assert_eq!(state.get::<Counted>("params").await?, Some(Counted(1)));
+        assert_eq!(DECODES.load(Ordering::SeqCst), decodes + 1);
+
+        // Writing the key evicts the decoded value.
+        state.put_raw(
+            "params".to_string(),
+            Amount::from(Counted(2)).encode_to_vec(),
+        );
+        assert_eq!(state.get::<Counted>("params").await?, Some(Counted(2)));
+        assert_eq!(state.get::<Counted>("params").await?, Some(Counted(2)));
+        assert_eq!(DECODES.load(Ordering::SeqCst), decodes + 2);
+
+        // A read issued before a write does not cache its stale result.
+        state.put_raw(
+            "params".to_string(),
+            Amount::from(Counted(4)).encode_to_vec(),
+        );
+        let pending = state.get::<Counted>("params");
+        state.delete("params".to_string());
+        assert_eq!(pending.await?, Some(Counted(4)));
+        assert_eq!(state.get::<Counted>("params").await?, None);
+
+        Ok(())
+    }
+}
@@ -2,8 +2,13 @@ use crate::{event::ProtoEvent, DomainType, Message};
 
 use std::fmt::Debug;
 
+use anyhow::Result;
+use async_trait::async_trait;
 use cnidarium::StateWrite;
 
+use super::read::StateReadProto;
+
+#[async_trait]
 pub trait StateWriteProto: StateWrite + Send + Sync {
     /// Puts a domain type into the verifiable key-value store with the given key.
     fn put<D>(&mut self, key: String, value: D)
@@ -14,6 +19,29 @@ pub trait StateWriteProto: StateWrite + Send + Sync {
         self.put_proto(key, D::Proto::from(value));
     }
 
+    /// Updates the domain type stored in the verifiable key-value store at
+    /// the given key, by applying `f` to its current value, or to `default`
+    /// if it is missing, and writing back the result.
+    ///
+    /// The current value is read from this state, so successive updates of the
+    /// same key within a transaction build on one another.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the current value is present but not parseable as a
+    /// domain type `D`, or if an underlying storage error occurred. In that
+    /// case, nothing is written.
+    async fn update<D, F>(&mut self, key: &str, default: D, f: F) -> Result<()>
+    where
+        D: DomainType + Debug + Send + Sync + 'static,
+        F: FnOnce(D) -> D + Send,
+        anyhow::Error: From<<D as TryFrom<D::Proto>>::Error>,
+    {
+        let current = self.get::<D>(key).await?.unwrap_or(default);
+        self.put(key.to_string(), f(current));
+        Ok(())
+    }
+
     /// Puts a proto type into the verifiable key-value store with the given key.
     fn put_proto<P>(&mut self, key: String, value: P)
     where
@@ -48,3 +76,33 @@ pub trait StateWriteProto: StateWrite + Send + Sync {
     }
 }
 impl<T: StateWrite + ?Sized> StateWriteProto for T {}
+
+#[cfg(test)]
+mod tests {
+    use cnidarium::StateDelta;
+    use ibc_types::core::client::Height;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn update_merges_within_a_transaction() -> Result<()> {
+        let mut state = StateDelta::new(());
+        let default = Height::new(0, 1)?;
+
+        // An absent key starts from the default.
+        state.update("height", default, Height::increment).await?;
+        assert_eq!(
+            state.get::<Height>("height").await?,
+            Some(Height::new(0, 2)?)
+        );
+
+        // A second update sees the result of the first.
+        state.update("height", default, |h| h.add(10)).await?;
+        assert_eq!(
+            state.get::<Height>("height").await?,
+            Some(Height::new(0, 12)?)
+        );
+
+        Ok(())
+    }
+}